@@ -0,0 +1,187 @@
+// Common Crate - auth.rs
+// my-actix-system/common/src/auth.rs
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use regex::Regex;
+use std::fmt;
+use crate::config::Config;
+
+/// Credentials extracted from an `Authorization` header
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthorizationHeader {
+    /// `Bearer <token>`
+    Bearer(String),
+    /// A bare token without a scheme, accepted for backward compatibility
+    Bare(String),
+}
+
+/// Reasons an `Authorization` header can be rejected
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthHeaderError {
+    /// Header present but empty
+    Empty,
+    /// Header contains characters that are not visible ASCII
+    InvalidEncoding,
+    /// Scheme given without a token, e.g. `Bearer `
+    MissingToken,
+    /// Scheme other than `Bearer`
+    UnsupportedScheme(String),
+    /// Token contains whitespace or other unexpected structure
+    Malformed,
+}
+
+impl fmt::Display for AuthHeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthHeaderError::Empty => write!(f, "Authorization header is empty"),
+            AuthHeaderError::InvalidEncoding => write!(f, "Authorization header is not valid ASCII"),
+            AuthHeaderError::MissingToken => write!(f, "Authorization header is missing a token"),
+            AuthHeaderError::UnsupportedScheme(scheme) => {
+                write!(f, "Unsupported authorization scheme: {}", scheme)
+            },
+            AuthHeaderError::Malformed => write!(f, "Authorization header is malformed"),
+        }
+    }
+}
+
+impl std::error::Error for AuthHeaderError {}
+
+impl AuthorizationHeader {
+    /// Parse a raw header value into its credentials
+    pub fn parse(value: &str) -> Result<Self, AuthHeaderError> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(AuthHeaderError::Empty);
+        }
+
+        match value.split_once(char::is_whitespace) {
+            Some((scheme, rest)) => {
+                if !scheme.eq_ignore_ascii_case("bearer") {
+                    return Err(AuthHeaderError::UnsupportedScheme(scheme.to_string()));
+                }
+
+                let token = rest.trim();
+                if token.is_empty() {
+                    Err(AuthHeaderError::MissingToken)
+                } else if token.contains(char::is_whitespace) {
+                    Err(AuthHeaderError::Malformed)
+                } else {
+                    Ok(AuthorizationHeader::Bearer(token.to_string()))
+                }
+            },
+            None if value.eq_ignore_ascii_case("bearer") => Err(AuthHeaderError::MissingToken),
+            None => Ok(AuthorizationHeader::Bare(value.to_string())),
+        }
+    }
+
+    /// Parse an HTTP header value into its credentials
+    pub fn from_header(header: &HeaderValue) -> Result<Self, AuthHeaderError> {
+        let value = header.to_str().map_err(|_| AuthHeaderError::InvalidEncoding)?;
+        Self::parse(value)
+    }
+
+    /// The token, regardless of how it was presented
+    pub fn token(&self) -> &str {
+        match self {
+            AuthorizationHeader::Bearer(token) | AuthorizationHeader::Bare(token) => token,
+        }
+    }
+}
+
+/// Allowlist of WebSocket clients by a request header, usually
+/// `User-Agent`. Built once from the config; with no patterns it accepts
+/// every connection.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionFilter {
+    allow: Option<(HeaderName, Vec<Regex>)>,
+}
+
+impl ConnectionFilter {
+    /// Invalid patterns are logged and skipped, so a filter whose patterns
+    /// are all invalid refuses everything rather than nothing
+    pub fn from_config(config: &Config) -> Self {
+        if config.connection_filter_patterns.is_empty() {
+            return Self::default();
+        }
+
+        let header = match HeaderName::from_bytes(config.connection_filter_header.as_bytes()) {
+            Ok(header) => header,
+            Err(_) => {
+                tracing::error!("Invalid connection filter header {:?}, filtering on User-Agent",
+                    config.connection_filter_header);
+                actix_web::http::header::USER_AGENT
+            }
+        };
+        let patterns = config.connection_filter_patterns.iter()
+            .filter_map(|pattern| Regex::new(pattern)
+                .map_err(|e| tracing::error!("Ignoring invalid connection filter pattern {:?}: {}", pattern, e))
+                .ok())
+            .collect();
+
+        Self { allow: Some((header, patterns)) }
+    }
+
+    /// Whether a connection with these request headers may proceed
+    pub fn allows(&self, headers: &HeaderMap) -> bool {
+        let Some((header, patterns)) = &self.allow else {
+            return true;
+        };
+        headers.get(header)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| patterns.iter().any(|pattern| pattern.is_match(value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bearer_token() {
+        let header = AuthorizationHeader::parse("Bearer dev_token").unwrap();
+        assert_eq!(header, AuthorizationHeader::Bearer("dev_token".to_string()));
+        assert_eq!(header.token(), "dev_token");
+
+        // Scheme is case-insensitive
+        let header = AuthorizationHeader::parse("bearer dev_token").unwrap();
+        assert_eq!(header.token(), "dev_token");
+    }
+
+    #[test]
+    fn test_parse_bare_token() {
+        let header = AuthorizationHeader::parse("dev_token").unwrap();
+        assert_eq!(header, AuthorizationHeader::Bare("dev_token".to_string()));
+        assert_eq!(header.token(), "dev_token");
+    }
+
+    #[test]
+    fn test_parse_malformed_header() {
+        assert_eq!(AuthorizationHeader::parse(""), Err(AuthHeaderError::Empty));
+        assert_eq!(AuthorizationHeader::parse("Bearer "), Err(AuthHeaderError::MissingToken));
+        assert_eq!(AuthorizationHeader::parse("Bearer a b"), Err(AuthHeaderError::Malformed));
+        assert_eq!(
+            AuthorizationHeader::parse("Basic dXNlcjpwYXNz"),
+            Err(AuthHeaderError::UnsupportedScheme("Basic".to_string()))
+        );
+
+        let invalid = HeaderValue::from_bytes(b"Bearer \xfftoken").unwrap();
+        assert_eq!(AuthorizationHeader::from_header(&invalid), Err(AuthHeaderError::InvalidEncoding));
+    }
+
+    #[test]
+    fn test_connection_filter_by_user_agent() {
+        let headers = |user_agent: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(actix_web::http::header::USER_AGENT, HeaderValue::from_str(user_agent).unwrap());
+            headers
+        };
+
+        let mut config = Config::default();
+        assert!(ConnectionFilter::from_config(&config).allows(&HeaderMap::new()));
+
+        config.connection_filter_patterns = vec!["^AgentBridge/\\d+".to_string(), "[".to_string()];
+        let filter = ConnectionFilter::from_config(&config);
+        assert!(filter.allows(&headers("AgentBridge/2.1 (linux)")));
+        assert!(!filter.allows(&headers("curl/8.5.0")));
+        assert!(!filter.allows(&HeaderMap::new()));
+    }
+}
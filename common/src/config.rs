@@ -1,20 +1,316 @@
 // common/src/config.rs
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::env;
 use std::path::PathBuf;
+use std::time::Duration;
 use config::{Config as ConfigFile, File, Environment};
+use jsonwebtoken::{encode, DecodingKey, EncodingKey, Header};
+use jsonwebtoken::errors::{Error as JwtError, ErrorKind as JwtErrorKind};
+use uuid::Uuid;
+use crate::utils::{validate_jwt_claims_with_key, JwtAlgorithm, JwtClaims, JWT_LEEWAY_SECS};
+
+/// Id given to agents connecting with the pre-shared key unless configured
+/// otherwise, and so the default agent client messages are routed to
+pub const DEFAULT_AGENT_ID: &str = "agent1";
 
 /// Central configuration for both services
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Config {
     pub websocket_server_addr: String,
+    // Further websocket-servers the web-server proxy fails over to when the
+    // one it is using goes down
+    #[serde(default)]
+    pub websocket_server_backup_addrs: Vec<String>,
     pub web_server_addr: String,
     pub agent_token: String,  // Pre-shared key for agent authentication
+    // Read the agent token from this file instead (e.g. a Kubernetes secret mount)
+    #[serde(default)]
+    pub agent_token_file: Option<String>,
+    // Bearer token for the admin routes on both servers. Agent tokens don't
+    // grant admin access
+    #[serde(default = "default_admin_token")]
+    pub admin_token: String,
+    #[serde(default)]
+    pub admin_token_file: Option<String>,
+    // Pre-shared key the web-server proxy presents to the websocket-server,
+    // vouching for the client id and wallet it forwards
+    #[serde(default = "default_proxy_token")]
+    pub proxy_token: String,
+    #[serde(default)]
+    pub proxy_token_file: Option<String>,
+    
+    // Secret used to sign and validate client JWTs
+    #[serde(default = "default_jwt_secret")]
+    pub jwt_secret: String,
+    // Read the JWT secret from this file instead
+    #[serde(default)]
+    pub jwt_secret_file: Option<String>,
+    
+    // "HS256" signs client JWTs with jwt_secret; "RS256" signs with
+    // jwt_private_key and validates with jwt_public_key (both PEM), so
+    // services that only verify tokens never hold the signing key
+    #[serde(default)]
+    pub jwt_algorithm: JwtAlgorithm,
+    #[serde(default)]
+    pub jwt_private_key: Option<String>,
+    #[serde(default)]
+    pub jwt_private_key_file: Option<String>,
+    #[serde(default)]
+    pub jwt_public_key: Option<String>,
+    #[serde(default)]
+    pub jwt_public_key_file: Option<String>,
+    // Seconds past expiry a client JWT is still accepted, for clock skew
+    // between the services that issue and check tokens
+    #[serde(default = "default_jwt_leeway_secs")]
+    pub jwt_leeway_secs: u64,
+    
+    // Id agents connecting with the pre-shared key register under
+    #[serde(default = "default_agent_name")]
+    pub agent_id: String,
+    // Further agents, by id, each authenticating with its own token
+    #[serde(default)]
+    pub agents: HashMap<String, String>,
+    // Agent that receives client messages by default, `agent_id` unless set.
+    // Set empty to offer client messages to every connected agent instead
+    #[serde(default)]
+    pub default_agent_id: Option<String>,
+    
+    // How client messages are spread across agents:
+    // "default_agent", "round_robin" or "broadcast"
+    #[serde(default = "default_routing_strategy")]
+    pub routing_strategy: String,
+    // Router shards routing client messages in parallel, each on its own
+    // thread; a client's messages always go to the same shard
+    #[serde(default = "default_router_shards")]
+    pub router_shards: usize,
+    
+    // Per-agent inbound message rate limit (token bucket). A rate of 0 disables it
+    #[serde(default = "default_agent_rate_limit_per_sec")]
+    pub agent_rate_limit_per_sec: u32,
+    #[serde(default = "default_agent_rate_limit_burst")]
+    pub agent_rate_limit_burst: u32,
+    
+    // Per-IP limit on client creation at the web-server (token bucket)
+    #[serde(default = "default_client_rate_limit_per_min")]
+    pub client_rate_limit_per_min: u32,
+    #[serde(default = "default_client_rate_limit_burst")]
+    pub client_rate_limit_burst: u32,
+    // What client creation is limited by: "ip" or "session_then_ip", which
+    // uses the session cookie when present so clients behind one NAT don't
+    // share a bucket
+    #[serde(default = "default_client_rate_limit_key")]
+    pub client_rate_limit_key: String,
+    
+    // Concurrent sessions a single client may hold; the oldest is evicted beyond this
+    #[serde(default = "default_max_sessions_per_client")]
+    pub max_sessions_per_client: usize,
+    
+    // What the web-server proxy does when a session that already has an open
+    // WebSocket connects again
+    #[serde(default)]
+    pub duplicate_connection_policy: DuplicateConnectionPolicy,
+    
+    // Client ID mismatches from one IP, within the ban period, after which
+    // the web-server refuses that IP for the ban period. 0 never bans
+    #[serde(default)]
+    pub client_id_mismatch_ban_threshold: u32,
+    #[serde(default = "default_client_id_mismatch_ban_secs")]
+    pub client_id_mismatch_ban_secs: u64,
+    
+    // Force acknowledgement tracking for every agent -> client message
+    #[serde(default)]
+    pub require_message_acks: bool,
+    
+    // Characters allowed in a single client or agent message's content,
+    // checked after parsing. 0 means no limit
+    #[serde(default)]
+    pub max_content_len: usize,
+    
+    // Largest text or binary frame accepted from a client, by the
+    // websocket-server and the web-server proxy. Larger frames close the
+    // connection with a policy violation
+    #[serde(default = "default_max_frame_bytes")]
+    pub max_frame_bytes: usize,
+    
+    // Text frames larger than this are deflated for clients that enable
+    // compression. 0 turns compression off
+    #[serde(default = "default_compression_threshold_bytes")]
+    pub compression_threshold_bytes: usize,
+    
+    // Handling a single message for longer than this logs a warning with
+    // its type and size. 0 turns the check off
+    #[serde(default = "default_slow_message_threshold_ms")]
+    pub slow_message_threshold_ms: u64,
+    
+    // Stamp agent -> client deliveries with the server's epoch millis
+    #[serde(default)]
+    pub include_server_timestamp: bool,
+    
+    // Unacknowledged messages a client may have outstanding before the router
+    // holds further messages for it until acks catch up. 0 disables
+    #[serde(default)]
+    pub max_unacked_messages: usize,
+    
+    // Require client connections to the websocket-server to present a JWT for
+    // their client id, or the pre-shared key the web-server proxy sends.
+    // Leave off when only the proxy can reach the websocket-server
+    #[serde(default)]
+    pub require_client_auth: bool,
+    
+    // Accept WebSocket connections only when this request header matches
+    // one of the patterns (regexes). No patterns accepts every connection
+    #[serde(default = "default_connection_filter_header")]
+    pub connection_filter_header: String,
+    #[serde(default)]
+    pub connection_filter_patterns: Vec<String>,
+    
+    // Whether the session cookie is marked Secure. Unset, it is Secure only
+    // for requests that arrived over HTTPS
+    #[serde(default)]
+    pub secure_cookies: Option<bool>,
+    
+    // Redis the web-server keeps client sessions in, so they survive a
+    // restart. Unset keeps them in memory; needs the `redis` feature
+    #[serde(default)]
+    pub redis_url: Option<String>,
+    
+    // How long the web-server waits for proxied connections to close after
+    // asking them to on shutdown
+    #[serde(default = "default_shutdown_grace_secs")]
+    pub shutdown_grace_secs: u64,
+    
+    // Most verbose tracing level logged: "error", "warn", "info", "debug" or
+    // "trace". The web-server can change it at runtime via /api/log-level
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    
+    // websocket-server connection monitoring and session retention
+    #[serde(default)]
+    pub state_manager: StateManagerConfig,
+    
+    // Cross-origin access to the web-server's /api routes
+    #[serde(default)]
+    pub cors: CorsConfig,
+    
+    // Serve HTTPS/WSS from both servers with this certificate; unset serves
+    // plaintext, leaving TLS to a proxy in front
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
     
     // Static file serving configuration
     pub static_files: StaticFilesConfig,
 }
 
+/// Handling of a second WebSocket connection for a session that already has one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateConnectionPolicy {
+    /// Close the existing connection; the last connection wins
+    #[default]
+    CloseExisting,
+    /// Refuse the new connection with a 409, leaving the existing one alone
+    RejectNew,
+    /// Keep both connections open
+    AllowBoth,
+}
+
+/// Timeouts and intervals used by the websocket-server's StateManagerActor
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StateManagerConfig {
+    pub client_timeout_secs: u64,
+    // Seconds without a message after which a connected client counts as
+    // idle rather than active, though its heartbeats keep it connected. 0 disables
+    pub idle_threshold_secs: u64,
+    pub agent_timeout_secs: u64,
+    pub cleanup_interval_secs: u64,
+    pub metrics_interval_secs: u64,
+    // The metrics summary is logged at info every this many metrics
+    // intervals, and at debug the rest of the time. 0 keeps it at debug
+    pub metrics_log_every: u32,
+    pub max_reconnect_attempts: u32,
+    pub session_ttl_secs: u64,
+    // Messages buffered per client while its socket is down; extras are dropped
+    pub client_buffer_size: usize,
+    // Messages per second replayed from a client's buffer after it reconnects
+    pub buffer_flush_rate: u32,
+    // Seconds a buffered or unacknowledged message stays deliverable; older
+    // ones are discarded rather than replayed
+    pub buffered_message_ttl_secs: u64,
+    // File the lifetime message and byte counters are saved to every metrics
+    // interval and restored from on start. Unset keeps them in memory only
+    pub metrics_file: Option<String>,
+    // How often the router's registrations are reconciled against the live
+    // set, evicting entries whose unregister was lost
+    pub router_reconcile_interval_secs: u64,
+    // File client sessions are written to on shutdown and reloaded from on
+    // start. Unset keeps them in memory only
+    pub sessions_file: Option<String>,
+    // Reconnecting clients allowed to have a session restore outstanding with
+    // the state manager at once; the rest wait their turn. 0 is unlimited
+    pub max_concurrent_restores: usize,
+    // Keep a disconnected or timed-out agent's subscriptions, hello and
+    // undelivered messages, handing them back when it reconnects
+    pub persist_agent_sessions: bool,
+}
+
+impl Default for StateManagerConfig {
+    fn default() -> Self {
+        Self {
+            client_timeout_secs: 60,
+            idle_threshold_secs: 300,
+            agent_timeout_secs: 120,
+            cleanup_interval_secs: 30,
+            metrics_interval_secs: 5,
+            metrics_log_every: 12,
+            max_reconnect_attempts: 10,
+            session_ttl_secs: 3600,
+            client_buffer_size: 100,
+            buffer_flush_rate: 100,
+            buffered_message_ttl_secs: 300,
+            metrics_file: None,
+            router_reconcile_interval_secs: 60,
+            sessions_file: None,
+            max_concurrent_restores: 64,
+            persist_agent_sessions: false,
+        }
+    }
+}
+
+/// Cross-origin requests the web-server accepts on its /api routes
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    // Origins allowed to call the API, e.g. "http://localhost:3000", or "*"
+    // for any. Empty allows any origin in development and none otherwise
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    // Whether browsers send the session cookie with cross-origin requests
+    pub allow_credentials: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: ["GET", "POST", "PUT", "DELETE"].map(String::from).to_vec(),
+            allow_credentials: true,
+        }
+    }
+}
+
+/// PEM files the servers terminate TLS with. The web-server proxy also
+/// trusts this certificate when connecting to the websocket-server over WSS,
+/// so a self-signed one works, as long as it names the host in
+/// `websocket_server_addr`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    // Certificate chain, leaf first
+    pub cert_path: String,
+    pub key_path: String,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct StaticFilesConfig {
     pub path: String,
@@ -30,12 +326,147 @@ pub struct CacheConfig {
     pub must_revalidate: bool,
 }
 
+// Development-only default admin and proxy credentials, distinct from the
+// agent token
+fn default_admin_token() -> String {
+    "dev_admin_token".to_string()
+}
+
+fn default_proxy_token() -> String {
+    "dev_proxy_token".to_string()
+}
+
+// Development-only default, matching the previously hardcoded secret
+fn default_jwt_secret() -> String {
+    "your_jwt_secret_key_here".to_string()
+}
+
+fn default_max_sessions_per_client() -> usize {
+    5
+}
+
+fn default_compression_threshold_bytes() -> usize {
+    1024
+}
+
+fn default_agent_name() -> String {
+    DEFAULT_AGENT_ID.to_string()
+}
+
+fn default_routing_strategy() -> String {
+    "default_agent".to_string()
+}
+
+fn default_router_shards() -> usize {
+    1
+}
+
+fn default_agent_rate_limit_per_sec() -> u32 {
+    50
+}
+
+fn default_agent_rate_limit_burst() -> u32 {
+    100
+}
+
+fn default_client_rate_limit_per_min() -> u32 {
+    3
+}
+
+fn default_client_rate_limit_burst() -> u32 {
+    3
+}
+
+fn default_client_rate_limit_key() -> String {
+    "ip".to_string()
+}
+
+fn default_jwt_leeway_secs() -> u64 {
+    JWT_LEEWAY_SECS
+}
+
+fn default_connection_filter_header() -> String {
+    "user-agent".to_string()
+}
+
+fn default_max_frame_bytes() -> usize {
+    64 * 1024
+}
+
+fn default_slow_message_threshold_ms() -> u64 {
+    50
+}
+
+fn default_client_id_mismatch_ban_secs() -> u64 {
+    900
+}
+
+fn default_shutdown_grace_secs() -> u64 {
+    10
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+// Read a secret from a file, trimming surrounding whitespace and newlines
+fn read_secret_file(path: &str) -> std::io::Result<String> {
+    std::fs::read_to_string(path)
+        .map(|contents| contents.trim().to_string())
+        .map_err(|e| std::io::Error::new(e.kind(), format!("secret file {}: {}", path, e)))
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             websocket_server_addr: "127.0.0.1:8080".to_string(),
+            websocket_server_backup_addrs: Vec::new(),
             web_server_addr: "127.0.0.1:8081".to_string(),
             agent_token: "dev_token".to_string(),
+            agent_token_file: None,
+            admin_token: default_admin_token(),
+            admin_token_file: None,
+            proxy_token: default_proxy_token(),
+            proxy_token_file: None,
+            jwt_secret: default_jwt_secret(),
+            jwt_secret_file: None,
+            jwt_algorithm: JwtAlgorithm::default(),
+            jwt_private_key: None,
+            jwt_private_key_file: None,
+            jwt_public_key: None,
+            jwt_public_key_file: None,
+            jwt_leeway_secs: default_jwt_leeway_secs(),
+            agent_id: default_agent_name(),
+            agents: HashMap::new(),
+            default_agent_id: Some(default_agent_name()),
+            routing_strategy: default_routing_strategy(),
+            router_shards: default_router_shards(),
+            agent_rate_limit_per_sec: default_agent_rate_limit_per_sec(),
+            agent_rate_limit_burst: default_agent_rate_limit_burst(),
+            client_rate_limit_per_min: default_client_rate_limit_per_min(),
+            client_rate_limit_burst: default_client_rate_limit_burst(),
+            client_rate_limit_key: default_client_rate_limit_key(),
+            max_sessions_per_client: default_max_sessions_per_client(),
+            duplicate_connection_policy: DuplicateConnectionPolicy::default(),
+            client_id_mismatch_ban_threshold: 0,
+            client_id_mismatch_ban_secs: default_client_id_mismatch_ban_secs(),
+            require_message_acks: false,
+            max_content_len: 0,
+            max_frame_bytes: default_max_frame_bytes(),
+            compression_threshold_bytes: default_compression_threshold_bytes(),
+            slow_message_threshold_ms: default_slow_message_threshold_ms(),
+            include_server_timestamp: false,
+            max_unacked_messages: 0,
+            require_client_auth: false,
+            connection_filter_header: default_connection_filter_header(),
+            connection_filter_patterns: Vec::new(),
+            secure_cookies: None,
+            redis_url: None,
+            shutdown_grace_secs: default_shutdown_grace_secs(),
+            log_level: default_log_level(),
+            state_manager: StateManagerConfig::default(),
+            cors: CorsConfig::default(),
+            tls: None,
             
             static_files: StaticFilesConfig {
                 path: "./static".to_string(),
@@ -52,10 +483,23 @@ impl Default for Config {
 }
 
 impl Config {
+    /// The `RUN_MODE` picking the environment config file, "development" by default
+    pub fn run_mode() -> String {
+        env::var("RUN_MODE").unwrap_or_else(|_| "development".into())
+    }
+    
     /// Load configuration from file and environment
     pub fn load() -> Result<Self, config::ConfigError> {
-        // Get the run mode, defaulting to "development"
-        let run_mode = env::var("RUN_MODE").unwrap_or_else(|_| "development".into());
+        let mut config = Self::load_files()?;
+        config.resolve_secret_files()
+            .map_err(|e| config::ConfigError::Message(format!("Failed to read secret file: {}", e)))?;
+        Ok(config)
+    }
+    
+    // Configuration from the config files and APP__ variables, with any
+    // secret files not yet read
+    fn load_files() -> Result<Self, config::ConfigError> {
+        let run_mode = Self::run_mode();
         
         // Locate the config directory
         let config_dir = env::var("CONFIG_DIR")
@@ -73,7 +517,7 @@ impl Config {
         tracing::info!("Using run mode: {}", run_mode);
         
         // Build configuration
-        let config = ConfigFile::builder()
+        ConfigFile::builder()
             // Start with defaults
             .add_source(File::from(config_dir.join("default.toml")).required(false))
             // Add environment specific config
@@ -84,15 +528,116 @@ impl Config {
             .add_source(Environment::with_prefix("APP").separator("__"))
             // Build and deserialize
             .build()?
-            .try_deserialize()?;
-            
-        Ok(config)
+            .try_deserialize()
+            .map(|mut config: Self| {
+                config.resolve_default_agent();
+                config
+            })
+    }
+    
+    // An unset `default_agent_id` is `agent_id`; an empty one means none
+    fn resolve_default_agent(&mut self) {
+        match self.default_agent_id.as_deref() {
+            None => self.default_agent_id = Some(self.agent_id.clone()),
+            Some("") => self.default_agent_id = None,
+            Some(_) => {},
+        }
+    }
+    
+    /// Every websocket-server the proxy may connect to, primary first
+    pub fn websocket_server_addrs(&self) -> Vec<String> {
+        std::iter::once(&self.websocket_server_addr)
+            .chain(&self.websocket_server_backup_addrs)
+            .cloned()
+            .collect()
+    }
+    
+    /// The agent a token authenticates: one of `agents`, or `agent_id` for
+    /// the pre-shared `agent_token`
+    pub fn agent_id_for_token(&self, token: &str) -> Option<&str> {
+        self.agents.iter()
+            .find(|(_, agent_token)| *agent_token == token)
+            .map(|(agent_id, _)| agent_id.as_str())
+            .or_else(|| (token == self.agent_token).then_some(self.agent_id.as_str()))
+    }
+    
+    /// Whether tokens are signed with the development default JWT secret,
+    /// which anyone can use to mint valid tokens
+    pub fn has_default_jwt_secret(&self) -> bool {
+        self.jwt_algorithm == JwtAlgorithm::Hs256 && self.jwt_secret == default_jwt_secret()
+    }
+    
+    /// Sign a client JWT, expiring `ttl` from now, with the configured algorithm
+    /// and key. Returns the token and the claims it carries.
+    pub fn sign_jwt(&self, client_id: &Uuid, wallet_address: &str, ttl: Duration) -> Result<(String, JwtClaims), JwtError> {
+        let key = match self.jwt_algorithm {
+            JwtAlgorithm::Hs256 => EncodingKey::from_secret(self.jwt_secret.as_bytes()),
+            JwtAlgorithm::Rs256 => {
+                let pem = self.jwt_private_key.as_deref()
+                    .ok_or(JwtErrorKind::InvalidRsaKey("jwt_private_key is not configured"))?;
+                EncodingKey::from_rsa_pem(pem.as_bytes())?
+            },
+        };
+        let claims = JwtClaims::new(client_id, wallet_address, ttl);
+        let token = encode(&Header::new(self.jwt_algorithm.into()), &claims, &key)?;
+        Ok((token, claims))
     }
     
-    /// Load from environment variables directly (backward compatibility)
-    pub fn from_env() -> Self {
+    /// Validate a client JWT with the configured algorithm and key, accepting
+    /// it up to `leeway_secs` after it expired
+    pub fn validate_jwt(&self, token: &str, leeway_secs: u64) -> Result<JwtClaims, JwtError> {
+        let key = match self.jwt_algorithm {
+            JwtAlgorithm::Hs256 => DecodingKey::from_secret(self.jwt_secret.as_bytes()),
+            JwtAlgorithm::Rs256 => {
+                let pem = self.jwt_public_key.as_deref()
+                    .ok_or(JwtErrorKind::InvalidRsaKey("jwt_public_key is not configured"))?;
+                DecodingKey::from_rsa_pem(pem.as_bytes())?
+            },
+        };
+        validate_jwt_claims_with_key(token, &key, self.jwt_algorithm, leeway_secs)
+    }
+    
+    /// Replace inline secrets with the contents of their `*_file` counterparts, when set
+    pub fn resolve_secret_files(&mut self) -> std::io::Result<()> {
+        if let Some(path) = &self.agent_token_file {
+            self.agent_token = read_secret_file(path)?;
+            tracing::info!("Agent token loaded from {}", path);
+        }
+        
+        if let Some(path) = &self.admin_token_file {
+            self.admin_token = read_secret_file(path)?;
+            tracing::info!("Admin token loaded from {}", path);
+        }
+        
+        if let Some(path) = &self.proxy_token_file {
+            self.proxy_token = read_secret_file(path)?;
+            tracing::info!("Proxy token loaded from {}", path);
+        }
+        
+        if let Some(path) = &self.jwt_secret_file {
+            self.jwt_secret = read_secret_file(path)?;
+            tracing::info!("JWT secret loaded from {}", path);
+        }
+        
+        if let Some(path) = &self.jwt_private_key_file {
+            self.jwt_private_key = Some(read_secret_file(path)?);
+            tracing::info!("JWT private key loaded from {}", path);
+        }
+        
+        if let Some(path) = &self.jwt_public_key_file {
+            self.jwt_public_key = Some(read_secret_file(path)?);
+            tracing::info!("JWT public key loaded from {}", path);
+        }
+        
+        Ok(())
+    }
+    
+    /// Load from the config files, falling back to environment variables
+    /// alone (backward compatibility). An unreadable secret file is an error
+    /// either way, rather than leaving the development defaults in place
+    pub fn from_env() -> std::io::Result<Self> {
         // Try to load from file first
-        match Self::load() {
+        let mut config = match Self::load_files() {
             Ok(config) => {
                 tracing::info!("Configuration loaded from files and environment");
                 config
@@ -105,11 +650,225 @@ impl Config {
                 let websocket_server_addr = env::var("WEBSOCKET_SERVER_ADDR")
                     .unwrap_or_else(|_| "127.0.0.1:8080".to_string());
                     
+                // Comma-separated, e.g. "10.0.0.2:8080,10.0.0.3:8080"
+                let websocket_server_backup_addrs = env::var("WEBSOCKET_SERVER_BACKUP_ADDRS")
+                    .map(|v| v.split(',').map(str::trim).filter(|addr| !addr.is_empty()).map(str::to_string).collect())
+                    .unwrap_or_default();
+                    
                 let web_server_addr = env::var("WEB_SERVER_ADDR")
                     .unwrap_or_else(|_| "127.0.0.1:8081".to_string());
                     
                 let agent_token = env::var("AGENT_TOKEN")
                     .unwrap_or_else(|_| "dev_token".to_string());
+                    
+                let agent_token_file = env::var("AGENT_TOKEN_FILE").ok();
+                
+                let admin_token = env::var("ADMIN_TOKEN")
+                    .unwrap_or_else(|_| default_admin_token());
+                    
+                let admin_token_file = env::var("ADMIN_TOKEN_FILE").ok();
+                
+                let proxy_token = env::var("PROXY_TOKEN")
+                    .unwrap_or_else(|_| default_proxy_token());
+                    
+                let proxy_token_file = env::var("PROXY_TOKEN_FILE").ok();
+                
+                let jwt_secret = env::var("JWT_SECRET")
+                    .unwrap_or_else(|_| default_jwt_secret());
+                    
+                let jwt_secret_file = env::var("JWT_SECRET_FILE").ok();
+                
+                let jwt_algorithm = match env::var("JWT_ALGORITHM") {
+                    Ok(v) if v.eq_ignore_ascii_case("RS256") => JwtAlgorithm::Rs256,
+                    _ => JwtAlgorithm::Hs256,
+                };
+                
+                let jwt_private_key_file = env::var("JWT_PRIVATE_KEY_FILE").ok();
+                let jwt_public_key_file = env::var("JWT_PUBLIC_KEY_FILE").ok();
+                
+                let jwt_leeway_secs = env::var("JWT_LEEWAY_SECS")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or_else(default_jwt_leeway_secs);
+                    
+                let agent_id = env::var("AGENT_ID")
+                    .ok()
+                    .filter(|v| !v.is_empty())
+                    .unwrap_or_else(default_agent_name);
+                    
+                // Comma-separated id=token pairs, e.g. "agent2=token2,agent3=token3"
+                let agents = env::var("AGENT_TOKENS")
+                    .map(|v| v.split(',')
+                        .filter_map(|pair| pair.split_once('='))
+                        .map(|(id, token)| (id.trim().to_string(), token.trim().to_string()))
+                        .filter(|(id, token)| !id.is_empty() && !token.is_empty())
+                        .collect())
+                    .unwrap_or_default();
+                    
+                // Resolved like the config files' value below
+                let default_agent_id = env::var("DEFAULT_AGENT_ID").ok();
+                    
+                let routing_strategy = env::var("ROUTING_STRATEGY")
+                    .unwrap_or_else(|_| default_routing_strategy());
+                    
+                let router_shards = env::var("ROUTER_SHARDS")
+                    .ok()
+                    .and_then(|v| v.parse::<usize>().ok())
+                    .unwrap_or_else(default_router_shards);
+                    
+                let agent_rate_limit_per_sec = env::var("AGENT_RATE_LIMIT_PER_SEC")
+                    .ok()
+                    .and_then(|v| v.parse::<u32>().ok())
+                    .unwrap_or_else(default_agent_rate_limit_per_sec);
+                    
+                let agent_rate_limit_burst = env::var("AGENT_RATE_LIMIT_BURST")
+                    .ok()
+                    .and_then(|v| v.parse::<u32>().ok())
+                    .unwrap_or_else(default_agent_rate_limit_burst);
+                    
+                let client_rate_limit_per_min = env::var("CLIENT_RATE_LIMIT_PER_MIN")
+                    .ok()
+                    .and_then(|v| v.parse::<u32>().ok())
+                    .unwrap_or_else(default_client_rate_limit_per_min);
+                    
+                let client_rate_limit_burst = env::var("CLIENT_RATE_LIMIT_BURST")
+                    .ok()
+                    .and_then(|v| v.parse::<u32>().ok())
+                    .unwrap_or_else(default_client_rate_limit_burst);
+                    
+                let client_rate_limit_key = env::var("CLIENT_RATE_LIMIT_KEY")
+                    .unwrap_or_else(|_| default_client_rate_limit_key());
+                    
+                let max_sessions_per_client = env::var("MAX_SESSIONS_PER_CLIENT")
+                    .ok()
+                    .and_then(|v| v.parse::<usize>().ok())
+                    .unwrap_or_else(default_max_sessions_per_client);
+                    
+                let duplicate_connection_policy = match env::var("DUPLICATE_CONNECTION_POLICY").as_deref() {
+                    Ok("reject_new") => DuplicateConnectionPolicy::RejectNew,
+                    Ok("allow_both") => DuplicateConnectionPolicy::AllowBoth,
+                    _ => DuplicateConnectionPolicy::CloseExisting,
+                };
+                    
+                let client_id_mismatch_ban_threshold = env::var("CLIENT_ID_MISMATCH_BAN_THRESHOLD")
+                    .ok()
+                    .and_then(|v| v.parse::<u32>().ok())
+                    .unwrap_or(0);
+                    
+                let client_id_mismatch_ban_secs = env::var("CLIENT_ID_MISMATCH_BAN_SECS")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or_else(default_client_id_mismatch_ban_secs);
+                    
+                let require_message_acks = env::var("REQUIRE_MESSAGE_ACKS")
+                    .map(|v| v.to_lowercase() == "true")
+                    .unwrap_or(false);
+                    
+                let max_content_len = env::var("MAX_CONTENT_LEN")
+                    .ok()
+                    .and_then(|v| v.parse::<usize>().ok())
+                    .unwrap_or(0);
+                    
+                let max_frame_bytes = env::var("MAX_FRAME_BYTES")
+                    .ok()
+                    .and_then(|v| v.parse::<usize>().ok())
+                    .unwrap_or_else(default_max_frame_bytes);
+                    
+                let compression_threshold_bytes = env::var("COMPRESSION_THRESHOLD_BYTES")
+                    .ok()
+                    .and_then(|v| v.parse::<usize>().ok())
+                    .unwrap_or_else(default_compression_threshold_bytes);
+                    
+                let slow_message_threshold_ms = env::var("SLOW_MESSAGE_THRESHOLD_MS")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or_else(default_slow_message_threshold_ms);
+                    
+                let include_server_timestamp = env::var("INCLUDE_SERVER_TIMESTAMP")
+                    .map(|v| v.to_lowercase() == "true")
+                    .unwrap_or(false);
+                    
+                let max_unacked_messages = env::var("MAX_UNACKED_MESSAGES")
+                    .ok()
+                    .and_then(|v| v.parse::<usize>().ok())
+                    .unwrap_or(0);
+                    
+                let require_client_auth = env::var("REQUIRE_CLIENT_AUTH")
+                    .map(|v| v.to_lowercase() == "true")
+                    .unwrap_or(false);
+                    
+                let connection_filter_header = env::var("CONNECTION_FILTER_HEADER")
+                    .unwrap_or_else(|_| default_connection_filter_header());
+                    
+                // Comma-separated; patterns that need a comma belong in the config file
+                let connection_filter_patterns = env::var("CONNECTION_FILTER_PATTERNS")
+                    .map(|v| v.split(',').map(str::trim).filter(|p| !p.is_empty()).map(str::to_string).collect())
+                    .unwrap_or_default();
+                    
+                let secure_cookies = env::var("SECURE_COOKIES")
+                    .ok()
+                    .map(|v| v.to_lowercase() == "true");
+                    
+                let redis_url = env::var("REDIS_URL").ok();
+                    
+                let shutdown_grace_secs = env::var("SHUTDOWN_GRACE_SECS")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or_else(default_shutdown_grace_secs);
+                    
+                let log_level = env::var("LOG_LEVEL")
+                    .unwrap_or_else(|_| default_log_level());
+                
+                // Comma-separated, like the connection filter patterns
+                let comma_list = |v: String| v.split(',').map(str::trim).filter(|s| !s.is_empty()).map(str::to_string).collect();
+                let cors_defaults = CorsConfig::default();
+                let cors = CorsConfig {
+                    allowed_origins: env::var("CORS_ALLOWED_ORIGINS")
+                        .map(comma_list)
+                        .unwrap_or(cors_defaults.allowed_origins),
+                    allowed_methods: env::var("CORS_ALLOWED_METHODS")
+                        .map(comma_list)
+                        .unwrap_or(cors_defaults.allowed_methods),
+                    allow_credentials: env::var("CORS_ALLOW_CREDENTIALS")
+                        .map(|v| v.to_lowercase() == "true")
+                        .unwrap_or(cors_defaults.allow_credentials),
+                };
+                
+                // Each StateManager setting from STATE_MANAGER_<FIELD>, keeping
+                // the default when unset or unparseable
+                fn state_manager_var<T: std::str::FromStr>(field: &str, default: T) -> T {
+                    env::var(format!("STATE_MANAGER_{}", field))
+                        .ok()
+                        .and_then(|v| v.parse::<T>().ok())
+                        .unwrap_or(default)
+                }
+                let state_manager_defaults = StateManagerConfig::default();
+                let state_manager = StateManagerConfig {
+                    client_timeout_secs: state_manager_var("CLIENT_TIMEOUT_SECS", state_manager_defaults.client_timeout_secs),
+                    idle_threshold_secs: state_manager_var("IDLE_THRESHOLD_SECS", state_manager_defaults.idle_threshold_secs),
+                    agent_timeout_secs: state_manager_var("AGENT_TIMEOUT_SECS", state_manager_defaults.agent_timeout_secs),
+                    cleanup_interval_secs: state_manager_var("CLEANUP_INTERVAL_SECS", state_manager_defaults.cleanup_interval_secs),
+                    metrics_interval_secs: state_manager_var("METRICS_INTERVAL_SECS", state_manager_defaults.metrics_interval_secs),
+                    metrics_log_every: state_manager_var("METRICS_LOG_EVERY", state_manager_defaults.metrics_log_every),
+                    max_reconnect_attempts: state_manager_var("MAX_RECONNECT_ATTEMPTS", state_manager_defaults.max_reconnect_attempts),
+                    session_ttl_secs: state_manager_var("SESSION_TTL_SECS", state_manager_defaults.session_ttl_secs),
+                    client_buffer_size: state_manager_var("CLIENT_BUFFER_SIZE", state_manager_defaults.client_buffer_size),
+                    buffer_flush_rate: state_manager_var("BUFFER_FLUSH_RATE", state_manager_defaults.buffer_flush_rate),
+                    buffered_message_ttl_secs: state_manager_var("BUFFERED_MESSAGE_TTL_SECS", state_manager_defaults.buffered_message_ttl_secs),
+                    metrics_file: env::var("STATE_MANAGER_METRICS_FILE").ok(),
+                    router_reconcile_interval_secs: state_manager_var("ROUTER_RECONCILE_INTERVAL_SECS", state_manager_defaults.router_reconcile_interval_secs),
+                    sessions_file: env::var("STATE_MANAGER_SESSIONS_FILE").ok(),
+                    max_concurrent_restores: state_manager_var("MAX_CONCURRENT_RESTORES", state_manager_defaults.max_concurrent_restores),
+                    persist_agent_sessions: env::var("STATE_MANAGER_PERSIST_AGENT_SESSIONS")
+                        .map(|v| v.to_lowercase() == "true")
+                        .unwrap_or(state_manager_defaults.persist_agent_sessions),
+                };
+                
+                // TLS needs both the certificate and its key
+                let tls = match (env::var("TLS_CERT_PATH"), env::var("TLS_KEY_PATH")) {
+                    (Ok(cert_path), Ok(key_path)) => Some(TlsConfig { cert_path, key_path }),
+                    _ => None,
+                };
                 
                 // Static file serving configuration
                 let static_files_path = env::var("STATIC_FILES_PATH")
@@ -135,10 +894,55 @@ impl Config {
                     .map(|v| v.to_lowercase() == "true")
                     .unwrap_or(true);
                 
-                Self {
+                let mut config = Self {
                     websocket_server_addr,
+                    websocket_server_backup_addrs,
                     web_server_addr,
                     agent_token,
+                    agent_token_file,
+                    admin_token,
+                    admin_token_file,
+                    proxy_token,
+                    proxy_token_file,
+                    jwt_secret,
+                    jwt_secret_file,
+                    jwt_algorithm,
+                    jwt_private_key: None,
+                    jwt_private_key_file,
+                    jwt_public_key: None,
+                    jwt_public_key_file,
+                    jwt_leeway_secs,
+                    agent_id,
+                    agents,
+                    default_agent_id,
+                    routing_strategy,
+                    router_shards,
+                    agent_rate_limit_per_sec,
+                    agent_rate_limit_burst,
+                    client_rate_limit_per_min,
+                    client_rate_limit_burst,
+                    client_rate_limit_key,
+                    max_sessions_per_client,
+                    duplicate_connection_policy,
+                    client_id_mismatch_ban_threshold,
+                    client_id_mismatch_ban_secs,
+                    require_message_acks,
+                    max_content_len,
+                    max_frame_bytes,
+                    compression_threshold_bytes,
+                    slow_message_threshold_ms,
+                    include_server_timestamp,
+                    max_unacked_messages,
+                    require_client_auth,
+                    connection_filter_header,
+                    connection_filter_patterns,
+                    secure_cookies,
+                    redis_url,
+                    shutdown_grace_secs,
+                    log_level,
+                    state_manager,
+                    cors,
+                    tls,
                     static_files: StaticFilesConfig {
                         path: static_files_path,
                         index: static_files_index,
@@ -149,8 +953,240 @@ impl Config {
                             must_revalidate: cache_must_revalidate,
                         },
                     },
-                }
+                };
+                config.resolve_default_agent();
+                config
             }
+        };
+        
+        config.resolve_secret_files()?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn write_temp_secret(contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("agent-bridge-secret-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+    
+    #[test]
+    fn test_secret_files_override_inline_values() {
+        let token_path = write_temp_secret("  mounted_token\n");
+        let secret_path = write_temp_secret("mounted_jwt_secret\n");
+        
+        let mut config = Config {
+            agent_token_file: Some(token_path.to_string_lossy().into_owned()),
+            jwt_secret_file: Some(secret_path.to_string_lossy().into_owned()),
+            ..Config::default()
+        };
+        config.resolve_secret_files().unwrap();
+        
+        assert_eq!(config.agent_token, "mounted_token");
+        assert_eq!(config.jwt_secret, "mounted_jwt_secret");
+        
+        std::fs::remove_file(token_path).unwrap();
+        std::fs::remove_file(secret_path).unwrap();
+    }
+    
+    #[test]
+    fn test_missing_secret_file_is_an_error() {
+        let mut config = Config {
+            agent_token_file: Some("/nonexistent/agent-token".to_string()),
+            ..Config::default()
+        };
+        
+        assert!(config.resolve_secret_files().is_err());
+        assert_eq!(config.agent_token, "dev_token");
+    }
+    
+    // Tests that set environment variables hold this, as the environment is
+    // shared by every test thread
+    static ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    
+    #[test]
+    fn test_from_env_refuses_unreadable_secret_file() {
+        let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let config_dir = env::temp_dir().join(format!("agent-bridge-config-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&config_dir).unwrap();
+        std::fs::copy(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/../config/default.toml"),
+            config_dir.join("default.toml"),
+        ).unwrap();
+        env::set_var("CONFIG_DIR", &config_dir);
+        
+        // Loaded from the config files
+        env::set_var("APP__AGENT_TOKEN_FILE", "/nonexistent/agent-token");
+        assert!(Config::load_files().is_ok());
+        assert!(Config::from_env().is_err());
+        env::remove_var("APP__AGENT_TOKEN_FILE");
+        
+        // And from the environment alone, once the config files fail to parse
+        std::fs::write(config_dir.join("default.toml"), "not toml [").unwrap();
+        env::set_var("JWT_SECRET_FILE", "/nonexistent/jwt-secret");
+        assert!(Config::from_env().is_err());
+        env::remove_var("JWT_SECRET_FILE");
+        assert!(Config::from_env().is_ok());
+        
+        env::remove_var("CONFIG_DIR");
+        std::fs::remove_dir_all(config_dir).unwrap();
+    }
+    
+    #[test]
+    fn test_default_agent_follows_agent_id_in_every_path() {
+        let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let config_dir = env::temp_dir().join(format!("agent-bridge-config-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&config_dir).unwrap();
+        env::set_var("CONFIG_DIR", &config_dir);
+        let load_file = |contents: &str| {
+            std::fs::write(config_dir.join("default.toml"), contents).unwrap();
+            Config::from_env().unwrap()
+        };
+        
+        let custom = include_str!("../../config/default.toml")
+            .replace("\nagent_id = \"agent1\"", "\nagent_id = \"custom-agent\"");
+        let config = load_file(&custom);
+        assert_eq!(config.default_agent_id.as_deref(), Some("custom-agent"));
+        let config = load_file(&custom.replace("# default_agent_id = \"\"", "default_agent_id = \"\""));
+        assert_eq!(config.default_agent_id, None);
+        
+        // Unparseable config files leave only the environment
+        env::set_var("AGENT_ID", "custom-agent");
+        let config = load_file("not toml [");
+        env::remove_var("AGENT_ID");
+        env::remove_var("CONFIG_DIR");
+        std::fs::remove_dir_all(&config_dir).unwrap();
+        assert_eq!(config.default_agent_id.as_deref(), Some("custom-agent"));
+        
+        let config = Config::default();
+        assert_eq!(config.default_agent_id, Some(config.agent_id));
+    }
+    
+    #[test]
+    fn test_from_env_reads_state_manager_settings() {
+        let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // Unparseable config files leave only the environment
+        let config_dir = env::temp_dir().join(format!("agent-bridge-config-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&config_dir).unwrap();
+        std::fs::write(config_dir.join("default.toml"), "not toml [").unwrap();
+        env::set_var("CONFIG_DIR", &config_dir);
+        
+        let vars = [
+            ("STATE_MANAGER_CLIENT_TIMEOUT_SECS", "15"),
+            ("STATE_MANAGER_CLEANUP_INTERVAL_SECS", "5"),
+            ("STATE_MANAGER_CLIENT_BUFFER_SIZE", "20"),
+            ("STATE_MANAGER_SESSIONS_FILE", "/tmp/sessions.json"),
+            ("STATE_MANAGER_PERSIST_AGENT_SESSIONS", "true"),
+            ("STATE_MANAGER_AGENT_TIMEOUT_SECS", "not a number"),
+        ];
+        for (name, value) in vars {
+            env::set_var(name, value);
+        }
+        let config = Config::from_env();
+        for (name, _) in vars {
+            env::remove_var(name);
         }
+        env::remove_var("CONFIG_DIR");
+        std::fs::remove_dir_all(config_dir).unwrap();
+        
+        let state_manager = config.unwrap().state_manager;
+        let defaults = StateManagerConfig::default();
+        assert_eq!(state_manager, StateManagerConfig {
+            client_timeout_secs: 15,
+            cleanup_interval_secs: 5,
+            client_buffer_size: 20,
+            sessions_file: Some("/tmp/sessions.json".to_string()),
+            persist_agent_sessions: true,
+            // Unparseable values keep the default
+            agent_timeout_secs: defaults.agent_timeout_secs,
+            ..defaults
+        });
+    }
+    
+    #[test]
+    fn test_default_jwt_secret_is_detected() {
+        assert!(Config::default().has_default_jwt_secret());
+        
+        let config = Config {
+            jwt_secret: "a_deployment_specific_secret".to_string(),
+            ..Config::default()
+        };
+        assert!(!config.has_default_jwt_secret());
+        
+        // The secret is unused when tokens are signed with a key pair
+        let config = Config { jwt_algorithm: JwtAlgorithm::Rs256, ..Config::default() };
+        assert!(!config.has_default_jwt_secret());
+    }
+    
+    #[test]
+    fn test_rs256_config_signs_and_validates_with_key_pair() {
+        let signer = Config {
+            jwt_algorithm: JwtAlgorithm::Rs256,
+            jwt_private_key: Some(include_str!("../testdata/jwt_rs256_private.pem").to_string()),
+            jwt_public_key: Some(include_str!("../testdata/jwt_rs256_public.pem").to_string()),
+            ..Config::default()
+        };
+        let client_id = Uuid::new_v4();
+        let (token, signed) = signer.sign_jwt(&client_id, "0xwallet", Duration::from_secs(60)).unwrap();
+        
+        // A verifying service needs only the public key
+        let verifier = Config { jwt_private_key: None, ..signer.clone() };
+        let claims = verifier.validate_jwt(&token, 0).unwrap();
+        assert_eq!(claims.client_id().unwrap(), client_id);
+        assert_eq!((claims.jti, claims.exp), (signed.jti, signed.exp));
+        assert!(verifier.sign_jwt(&client_id, "0xwallet", Duration::from_secs(60)).is_err());
+        
+        // An HS256 deployment rejects the token
+        assert!(Config::default().validate_jwt(&token, 0).is_err());
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_jwt_leeway_tolerates_small_clock_skew() {
+        use jsonwebtoken::{encode, EncodingKey, Header};
+        
+        let config = Config { jwt_leeway_secs: 30, ..Config::default() };
+        let expired_ago = |secs: usize| {
+            let now = chrono::Utc::now().timestamp() as usize;
+            let claims = JwtClaims {
+                sub: uuid::Uuid::new_v4().to_string(),
+                wallet: "0xwallet".to_string(),
+                iat: now - 3600,
+                exp: now - secs,
+                jti: uuid::Uuid::new_v4().to_string(),
+            };
+            encode(&Header::default(), &claims, &EncodingKey::from_secret(config.jwt_secret.as_bytes())).unwrap()
+        };
+        
+        assert!(config.validate_jwt(&expired_ago(10), config.jwt_leeway_secs).is_ok());
+        let err = config.validate_jwt(&expired_ago(600), config.jwt_leeway_secs).unwrap_err();
+        assert_eq!(err.kind(), &JwtErrorKind::ExpiredSignature);
+    }
+    
+    #[test]
+    fn test_token_without_id_is_rejected() {
+        use jsonwebtoken::{encode, EncodingKey, Header};
+        
+        // Revocation is by token id, so a token without one could never be revoked
+        let config = Config::default();
+        let now = chrono::Utc::now().timestamp() as usize;
+        let claims = serde_json::json!({
+            "sub": uuid::Uuid::new_v4().to_string(),
+            "wallet": "0xwallet",
+            "iat": now,
+            "exp": now + 60,
+        });
+        let key = EncodingKey::from_secret(config.jwt_secret.as_bytes());
+        let without_id = encode(&Header::default(), &claims, &key).unwrap();
+        let err = config.validate_jwt(&without_id, 0).unwrap_err();
+        assert_eq!(err.kind(), &JwtErrorKind::InvalidToken);
+        
+        let mut claims = claims;
+        claims["jti"] = "".into();
+        let empty_id = encode(&Header::default(), &claims, &key).unwrap();
+        assert!(config.validate_jwt(&empty_id, 0).is_err());
+    }
+}
@@ -2,8 +2,13 @@ pub mod messages;
 pub mod config;
 pub mod utils;
 pub mod models;
+pub mod auth;
+pub mod tls;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
 
 pub use messages::*;
 pub use config::*;
 pub use utils::*;
-pub use models::*;
\ No newline at end of file
+pub use models::*;
+pub use auth::*;
\ No newline at end of file
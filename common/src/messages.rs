@@ -2,6 +2,7 @@
 // my-actix-system/common/src/messages.rs
 use actix::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
 /// Message from client to agent
@@ -21,11 +22,115 @@ pub struct ClientMessage {
     pub session_id: Option<String>,
     #[serde(default)]
     pub requires_ack: bool,
+    // Agent that should handle this message; None routes to the default agent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_agent_id: Option<String>,
+    // Payload of a binary frame, base64-encoded in JSON; `content` is empty when set
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "base64_bytes")]
+    pub content_binary: Option<Vec<u8>>,
+    // Tenant of the sending client; only agents of the same tenant receive it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    // Pub/sub topic; the message goes to every agent subscribed to a matching topic
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+}
+
+impl ClientMessage {
+    /// Text message from `client_id`, stamped with the current time. Optional
+    /// fields start unset; fill them in with the `with_*` setters.
+    pub fn new(client_id: Uuid, content: impl Into<String>) -> Self {
+        Self {
+            client_id,
+            content: content.into(),
+            authenticated: false,
+            wallet_address: None,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            message_id: None,
+            session_id: None,
+            requires_ack: false,
+            target_agent_id: None,
+            content_binary: None,
+            tenant_id: None,
+            topic: None,
+        }
+    }
+
+    /// Binary message from `client_id`; `content` stays empty
+    pub fn binary(client_id: Uuid, data: Vec<u8>) -> Self {
+        Self {
+            content_binary: Some(data),
+            ..Self::new(client_id, String::new())
+        }
+    }
+
+    pub fn with_authentication(mut self, authenticated: bool, wallet_address: Option<String>) -> Self {
+        self.authenticated = authenticated;
+        self.wallet_address = wallet_address;
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn with_message_id(mut self, message_id: u64) -> Self {
+        self.message_id = Some(message_id);
+        self
+    }
+
+    pub fn with_session_id(mut self, session_id: Option<String>) -> Self {
+        self.session_id = session_id;
+        self
+    }
+
+    pub fn with_requires_ack(mut self, requires_ack: bool) -> Self {
+        self.requires_ack = requires_ack;
+        self
+    }
+
+    pub fn with_target_agent_id(mut self, target_agent_id: Option<String>) -> Self {
+        self.target_agent_id = target_agent_id;
+        self
+    }
+
+    pub fn with_tenant_id(mut self, tenant_id: Option<String>) -> Self {
+        self.tenant_id = tenant_id;
+        self
+    }
+
+    pub fn with_topic(mut self, topic: Option<String>) -> Self {
+        self.topic = topic;
+        self
+    }
+}
+
+// Serializes optional binary payloads as base64 strings
+mod base64_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => serializer.serialize_str(&base64::encode(bytes)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|encoded| base64::decode(encoded).map_err(serde::de::Error::custom))
+            .transpose()
+    }
 }
 
-/// Message from agent to client(s)
+/// Message from agent to client(s). Broadcasts that require an ack resolve
+/// to a `BroadcastResult` describing their reach.
 #[derive(Debug, Clone, Serialize, Deserialize, Message)]
-#[rtype(result = "()")]
+#[rtype(result = "Option<BroadcastResult>")]
 pub struct AgentMessage {
     pub target_client_id: Option<Uuid>, // None means broadcast
     pub content: String,
@@ -39,6 +144,294 @@ pub struct AgentMessage {
     // Added field for message type classification (optional)
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub message_type: Option<String>,
+    // Agent that sent the message, filled in by the server so delivery
+    // acknowledgements find their way back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_agent_id: Option<String>,
+    // Epoch millis at which the server routed the message, when enabled, so
+    // clients can measure latency. Omitted otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_timestamp: Option<u64>,
+    // Which clients a broadcast reaches; unset reaches every client. Ignored
+    // for messages to a single client, and removed before delivery
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audience: Option<Audience>,
+}
+
+impl AgentMessage {
+    /// Text message for `client_id`, stamped with the current time. Optional
+    /// fields start unset; fill them in with struct update syntax.
+    pub fn to_client(client_id: Uuid, content: impl Into<String>) -> Self {
+        Self {
+            target_client_id: Some(client_id),
+            ..Self::broadcast(content)
+        }
+    }
+
+    /// Text message for every client, stamped with the current time
+    pub fn broadcast(content: impl Into<String>) -> Self {
+        Self {
+            target_client_id: None,
+            content: content.into(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            message_id: None,
+            requires_ack: false,
+            message_type: None,
+            source_agent_id: None,
+            server_timestamp: None,
+            audience: None,
+        }
+    }
+}
+
+/// Clients an agent broadcast is meant for, e.g. `"AuthenticatedOnly"` or
+/// `{"WalletList":["0xabc"]}`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum Audience {
+    All,
+    AuthenticatedOnly,
+    /// Authenticated clients with one of these wallets, compared case-insensitively
+    WalletList(Vec<String>),
+}
+
+impl Audience {
+    /// Whether a client with this authentication state is in the audience
+    pub fn includes(&self, authenticated: bool, wallet_address: Option<&str>) -> bool {
+        match self {
+            Audience::All => true,
+            Audience::AuthenticatedOnly => authenticated,
+            Audience::WalletList(wallets) => authenticated && wallet_address.is_some_and(|wallet_address| {
+                wallets.iter().any(|wallet| wallet.eq_ignore_ascii_case(wallet_address))
+            }),
+        }
+    }
+}
+
+/// Reach of a broadcast, returned to the sending agent when the broadcast
+/// requires an ack, e.g.
+/// `{"type":"broadcast_result","message_id":7,"attempted":3,"delivered":2}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "broadcast_result")]
+pub struct BroadcastResult {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<u64>,
+    pub attempted: usize, // Clients registered when the broadcast was sent
+    pub delivered: usize, // Clients whose mailbox accepted it
+}
+
+/// Error reported to a client on its connection, e.g.
+/// `{"type":"error","code":1000,"message":"Router not configured"}`.
+/// Clients should branch on `code`; `message` is for people and may change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "error")]
+pub struct ErrorFrame {
+    pub code: u32,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<serde_json::Value>,
+}
+
+impl ErrorFrame {
+    /// No router to hand the client's message to
+    pub const ROUTER_UNAVAILABLE: u32 = 1000;
+    /// The router could not accept the message, e.g. its mailbox is full
+    pub const ROUTING_FAILED: u32 = 1001;
+    /// Message content over `max_content_len`; detail carries the limit
+    pub const CONTENT_TOO_LONG: u32 = 1002;
+    /// Frame over `max_frame_bytes`; the connection is closed after this
+    pub const FRAME_TOO_LARGE: u32 = 1003;
+    /// The client asked for compression but the server has it turned off
+    pub const COMPRESSION_UNAVAILABLE: u32 = 1004;
+
+    pub fn new(code: u32, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), detail: None }
+    }
+
+    pub fn with_detail(mut self, detail: serde_json::Value) -> Self {
+        self.detail = Some(detail);
+        self
+    }
+
+    /// The frame as sent to the client
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("error frames always serialize")
+    }
+}
+
+/// Sent to a client as it connects, e.g. `{"type":"session","resume_token":"..."}`.
+/// Reconnecting with `?resume_token=` resumes the session, buffered
+/// messages included; without it the client starts afresh.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "session")]
+pub struct SessionFrame {
+    pub resume_token: String,
+}
+
+impl SessionFrame {
+    /// The frame as sent to the client
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("session frames always serialize")
+    }
+
+    /// The session frame `text` holds, if it is one
+    pub fn parse(text: &str) -> Option<Self> {
+        match serde_json::from_str(text).ok()? {
+            TaggedFrame::Session(frame) => Some(frame),
+        }
+    }
+}
+
+// Frames told apart by their "type". Serde checks the tag of an enum variant,
+// but not of a tagged struct like `SessionFrame` on its own
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TaggedFrame {
+    Session(SessionFrame),
+}
+
+/// Header the web-server uses to pass a client's tenant to the websocket-server
+pub const TENANT_ID_HEADER: &str = "X-Tenant-Id";
+
+/// Header the web-server uses to pass the wallet a client authenticated with.
+/// Only trusted alongside the proxy's pre-shared key.
+pub const WALLET_ADDRESS_HEADER: &str = "X-Wallet-Address";
+
+/// Version of the message envelope, negotiated as a WebSocket subprotocol so
+/// the format can change without breaking clients built against an older one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    #[default]
+    V1,
+}
+
+impl ProtocolVersion {
+    /// Every version served, in order of preference
+    pub const SUPPORTED: &'static [ProtocolVersion] = &[ProtocolVersion::V1];
+
+    /// The `Sec-WebSocket-Protocol` name of this version
+    pub fn subprotocol(self) -> &'static str {
+        match self {
+            ProtocolVersion::V1 => "agent-bridge.v1",
+        }
+    }
+
+    /// The version for a connection requesting the comma-separated
+    /// `requested` subprotocols: the first of them that is supported. None
+    /// when nothing was requested, in which case the default applies and no
+    /// protocol is echoed; Err with the request when nothing in it is supported.
+    pub fn negotiate(requested: Option<&str>) -> Result<Option<ProtocolVersion>, String> {
+        let Some(requested) = requested.filter(|requested| !requested.trim().is_empty()) else {
+            return Ok(None);
+        };
+        requested.split(',')
+            .map(str::trim)
+            .find_map(|name| Self::SUPPORTED.iter().copied().find(|version| version.subprotocol() == name))
+            .map(Some)
+            .ok_or_else(|| requested.to_string())
+    }
+}
+
+impl std::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.subprotocol())
+    }
+}
+
+/// Handshake an agent sends after connecting to declare its tenant, and
+/// whether it serves only authenticated clients, e.g.
+/// `{"type":"agent_hello","tenant_id":"acme","require_authenticated_clients":true}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "agent_hello")]
+pub struct AgentHello {
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+    #[serde(default)]
+    pub require_authenticated_clients: bool,
+}
+
+/// Frame an agent sends to reach another agent through the router, e.g.
+/// `{"type":"agent_to_agent","target_agent_id":"agent2","content":"..."}`.
+/// The sender's id is filled in by the server before delivery.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Message)]
+#[rtype(result = "()")]
+#[serde(tag = "type", rename = "agent_to_agent")]
+pub struct AgentToAgent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_agent_id: Option<String>,
+    pub target_agent_id: String,
+    pub content: String,
+}
+
+/// Frame an agent sends to start or stop following a client's connection
+/// lifecycle, e.g. `{"type":"subscribe_presence","client_id":"..."}`.
+/// Subscribed agents receive that client's `SystemMessage`s.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PresenceRequest {
+    SubscribePresence { client_id: Uuid },
+    UnsubscribePresence { client_id: Uuid },
+}
+
+impl PresenceRequest {
+    /// Parse a presence frame, returning None for any other message
+    pub fn parse(text: &str) -> Option<Self> {
+        serde_json::from_str(text).ok()
+    }
+}
+
+/// Frame an agent sends to start or stop receiving client messages on a
+/// topic, e.g. `{"type":"subscribe_topic","topic":"orders.*"}`. A `*` in
+/// the topic matches any run of characters.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TopicRequest {
+    SubscribeTopic { topic: String },
+    UnsubscribeTopic { topic: String },
+}
+
+impl TopicRequest {
+    /// Parse a topic frame, returning None for any other message
+    pub fn parse(text: &str) -> Option<Self> {
+        serde_json::from_str(text).ok()
+    }
+}
+
+// Agent control frames, distinguished by their `type` tag. Deserializing the
+// tagged structs directly would accept frames with the tag missing.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AgentControlFrame {
+    AgentHello(AgentHello),
+    AgentToAgent(AgentToAgent),
+}
+
+impl AgentControlFrame {
+    fn parse(text: &str) -> Option<Self> {
+        serde_json::from_str(text).ok()
+    }
+}
+
+impl AgentHello {
+    /// Parse a hello frame, returning None for any other message
+    pub fn parse(text: &str) -> Option<Self> {
+        match AgentControlFrame::parse(text)? {
+            AgentControlFrame::AgentHello(hello) => Some(hello),
+            _ => None,
+        }
+    }
+}
+
+impl AgentToAgent {
+    /// Parse an agent-to-agent frame, returning None for any other message
+    pub fn parse(text: &str) -> Option<Self> {
+        match AgentControlFrame::parse(text)? {
+            AgentControlFrame::AgentToAgent(frame) => Some(frame),
+            _ => None,
+        }
+    }
 }
 
 /// New message acknowledgement type
@@ -121,6 +514,15 @@ impl MessageSize for ClientMessage {
         if self.message_id.is_some() {
             size += 8; // message_id
         }
+        if let Some(ref agent_id) = self.target_agent_id {
+            size += agent_id.len();
+        }
+        if let Some(ref binary) = self.content_binary {
+            size += binary.len();
+        }
+        if let Some(ref tenant_id) = self.tenant_id {
+            size += tenant_id.len();
+        }
         size
     }
 }
@@ -136,6 +538,12 @@ impl MessageSize for AgentMessage {
         if let Some(ref msg_type) = self.message_type {
             size += msg_type.len();
         }
+        if let Some(ref source_agent_id) = self.source_agent_id {
+            size += source_agent_id.len();
+        }
+        if self.server_timestamp.is_some() {
+            size += 8; // server_timestamp
+        }
         if self.message_id.is_some() {
             size += 8; // message_id
         }
@@ -157,4 +565,79 @@ impl MessageSize for MessageAcknowledgement {
         }
         size
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_negotiation_picks_first_supported() {
+        assert_eq!(ProtocolVersion::negotiate(None), Ok(None));
+        assert_eq!(ProtocolVersion::negotiate(Some("agent-bridge.v1")), Ok(Some(ProtocolVersion::V1)));
+        assert_eq!(ProtocolVersion::negotiate(Some("agent-bridge.v9, agent-bridge.v1")), Ok(Some(ProtocolVersion::V1)));
+        assert_eq!(ProtocolVersion::negotiate(Some("agent-bridge.v9")), Err("agent-bridge.v9".to_string()));
+    }
+
+    #[test]
+    fn test_builder_defaults_optional_fields() {
+        let client_id = Uuid::new_v4();
+        let message = ClientMessage::new(client_id, "hello");
+
+        assert_eq!(message.client_id, client_id);
+        assert_eq!(message.content, "hello");
+        assert!(message.timestamp > 0);
+        assert!(!message.authenticated && !message.requires_ack);
+        assert_eq!(message.wallet_address, None);
+        assert_eq!(message.message_id, None);
+        assert_eq!(message.session_id, None);
+        assert_eq!(message.target_agent_id, None);
+        assert_eq!(message.content_binary, None);
+        assert_eq!(message.tenant_id, None);
+
+        // Unset fields stay out of the wire format
+        let json = serde_json::to_value(&message).unwrap();
+        assert!(json.get("target_agent_id").is_none() && json.get("tenant_id").is_none());
+    }
+
+    #[test]
+    fn test_builder_sets_fields() {
+        let message = ClientMessage::new(Uuid::new_v4(), "hello")
+            .with_authentication(true, Some("0xabc".to_string()))
+            .with_timestamp(42)
+            .with_message_id(7)
+            .with_session_id(Some("session-1".to_string()))
+            .with_requires_ack(true)
+            .with_target_agent_id(Some("agent2".to_string()))
+            .with_tenant_id(Some("acme".to_string()));
+
+        assert!(message.authenticated);
+        assert_eq!(message.wallet_address.as_deref(), Some("0xabc"));
+        assert_eq!(message.timestamp, 42);
+        assert_eq!(message.message_id, Some(7));
+        assert_eq!(message.session_id.as_deref(), Some("session-1"));
+        assert!(message.requires_ack);
+        assert_eq!(message.target_agent_id.as_deref(), Some("agent2"));
+        assert_eq!(message.tenant_id.as_deref(), Some("acme"));
+
+        let binary = ClientMessage::binary(Uuid::new_v4(), vec![0, 1, 2]);
+        assert!(binary.content.is_empty());
+        assert_eq!(binary.content_binary, Some(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn test_session_frame_parse() {
+        let frame = SessionFrame { resume_token: "abc".to_string() };
+        assert_eq!(SessionFrame::parse(&frame.to_json()), Some(frame.clone()));
+
+        // However the JSON is laid out
+        let pretty = serde_json::to_string_pretty(&frame).unwrap();
+        assert!(pretty.contains('\n'));
+        assert_eq!(SessionFrame::parse(&pretty), Some(frame));
+
+        // Other frames carrying a resume token are not session frames
+        assert_eq!(SessionFrame::parse(r#"{"type":"error","resume_token":"abc"}"#), None);
+        assert_eq!(SessionFrame::parse(r#"{"resume_token":"abc"}"#), None);
+        assert_eq!(SessionFrame::parse("hello"), None);
+    }
+}
@@ -23,6 +23,9 @@ pub struct ClientSession {
     pub metadata: HashMap<String, String>,
     /// JWT token for authenticated clients
     pub jwt_token: Option<String>,
+    /// Tenant the client belongs to; its messages only reach that tenant's agents
+    #[serde(default)]
+    pub tenant_id: Option<String>,
 }
 
 impl ClientSession {
@@ -38,6 +41,7 @@ impl ClientSession {
             wallet_address: None,
             metadata: HashMap::new(),
             jwt_token: None,
+            tenant_id: None,
         }
     }
     
@@ -60,6 +64,11 @@ impl ClientSession {
         self.update_activity();
     }
     
+    /// Assign the session to a tenant
+    pub fn set_tenant(&mut self, tenant_id: String) {
+        self.tenant_id = Some(tenant_id);
+    }
+    
     /// Add or update metadata value
     pub fn set_metadata(&mut self, key: String, value: String) {
         self.metadata.insert(key, value);
@@ -69,13 +78,14 @@ impl ClientSession {
     pub fn get_metadata(&self, key: &str) -> Option<&String> {
         self.metadata.get(key)
     }
-    /// Generate the JWT token from the wallet sign in 
-    pub fn generate_auth_token(&mut self, jwt_secret: &[u8]) -> Result<String, jsonwebtoken::errors::Error> {
+    /// Generate the JWT token from the wallet sign in, returning it with its claims
+    pub fn generate_auth_token(&mut self, config: &crate::Config) -> Result<(String, crate::utils::JwtClaims), jsonwebtoken::errors::Error> {
         if let Some(wallet) = &self.wallet_address {
-            let token = crate::utils::generate_jwt_token(&self.client_id, wallet, jwt_secret)?;
+            let ttl = std::time::Duration::from_secs(crate::utils::JWT_TTL_SECS);
+            let (token, claims) = config.sign_jwt(&self.client_id, wallet, ttl)?;
             self.jwt_token = Some(token.clone());
             self.update_activity();
-            Ok(token)
+            Ok((token, claims))
         } else {
             Err(jsonwebtoken::errors::ErrorKind::InvalidSubject.into())
         }
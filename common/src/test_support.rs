@@ -0,0 +1,170 @@
+// common/src/test_support.rs
+// Helpers shared by the services' tests, behind the `test-support` feature:
+// captured logs, and driving WebSocket actors without an HTTP upgrade
+use actix::{Actor, Addr, StreamHandler};
+use actix_web::error::PayloadError;
+use actix_web::web::Bytes;
+use actix_web_actors::ws;
+use futures::StreamExt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Collects formatted log output when handed to a test's tracing subscriber
+/// as its writer, e.g. `with_writer(move || log.clone())`
+#[derive(Clone, Default)]
+pub struct CapturedLog(Arc<Mutex<Vec<u8>>>);
+
+impl CapturedLog {
+    /// Everything logged so far
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for CapturedLog {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Start a WebSocket actor on an idle input stream. Returns the actor address
+// and a channel carrying the raw frames the actor writes to its socket.
+pub fn start_ws_actor<A>(actor: A) -> (Addr<A>, mpsc::UnboundedReceiver<Bytes>)
+where
+    A: Actor<Context = ws::WebsocketContext<A>> + StreamHandler<Result<ws::Message, ws::ProtocolError>>,
+{
+    start_ws_actor_with_input(actor, Vec::new())
+}
+
+// Like `start_ws_actor`, but feeds `frames` to the actor as if sent by the peer.
+// The input stays open afterwards so the actor is not stopped by end-of-stream.
+pub fn start_ws_actor_with_input<A>(actor: A, frames: Vec<Bytes>) -> (Addr<A>, mpsc::UnboundedReceiver<Bytes>)
+where
+    A: Actor<Context = ws::WebsocketContext<A>> + StreamHandler<Result<ws::Message, ws::ProtocolError>>,
+{
+    let input = futures::stream::iter(frames.into_iter().map(Ok::<Bytes, PayloadError>))
+        .chain(futures::stream::pending());
+    let (addr, output) = ws::WebsocketContext::create_with_addr(actor, input);
+    let (tx, rx) = mpsc::unbounded_channel();
+    
+    // The context only makes progress while its output stream is polled
+    actix::spawn(async move {
+        let mut output = Box::pin(output);
+        while let Some(Ok(frame)) = output.next().await {
+            if tx.send(frame).is_err() {
+                break;
+            }
+        }
+    });
+    
+    (addr, rx)
+}
+
+// Like `start_ws_actor`, but also returns a sender for feeding frames to the
+// actor as if sent by the peer, at any point after it starts
+pub fn start_ws_actor_with_sender<A>(actor: A) -> (Addr<A>, mpsc::UnboundedSender<Bytes>, mpsc::UnboundedReceiver<Bytes>)
+where
+    A: Actor<Context = ws::WebsocketContext<A>> + StreamHandler<Result<ws::Message, ws::ProtocolError>>,
+{
+    let (input_tx, input_rx) = mpsc::unbounded_channel();
+    let input = tokio_stream::wrappers::UnboundedReceiverStream::new(input_rx).map(Ok::<Bytes, PayloadError>);
+    let (addr, output) = ws::WebsocketContext::create_with_addr(actor, input);
+    let (tx, rx) = mpsc::unbounded_channel();
+    
+    actix::spawn(async move {
+        let mut output = Box::pin(output);
+        while let Some(Ok(frame)) = output.next().await {
+            if tx.send(frame).is_err() {
+                break;
+            }
+        }
+    });
+    
+    (addr, input_tx, rx)
+}
+
+// Collect every raw frame written within `window`
+pub async fn collect_raw_frames(frames: &mut mpsc::UnboundedReceiver<Bytes>, window: Duration) -> Vec<Bytes> {
+    let mut collected = Vec::new();
+    let deadline = tokio::time::Instant::now() + window;
+    while let Ok(Some(frame)) = tokio::time::timeout_at(deadline, frames.recv()).await {
+        collected.push(frame);
+    }
+    collected
+}
+
+// Collect every frame written within `window`, decoded lossily as text.
+// Server frames are unmasked, so text payloads appear verbatim.
+pub async fn collect_frames(frames: &mut mpsc::UnboundedReceiver<Bytes>, window: Duration) -> Vec<String> {
+    collect_raw_frames(frames, window).await
+        .iter()
+        .map(|frame| String::from_utf8_lossy(frame).into_owned())
+        .collect()
+}
+
+// Split the raw bytes a server wrote into (opcode, payload) frames. Server
+// frames are unmasked; writes may be coalesced, so frames are parsed in sequence.
+pub fn decode_server_frames(mut raw: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    let mut frames = Vec::new();
+    while raw.len() >= 2 {
+        let opcode = raw[0] & 0x0f;
+        let (length, header) = match raw[1] & 0x7f {
+            126 => (u16::from_be_bytes([raw[2], raw[3]]) as usize, 4),
+            127 => (u64::from_be_bytes(raw[2..10].try_into().unwrap()) as usize, 10),
+            length => (length as usize, 2),
+        };
+        frames.push((opcode, raw[header..header + length].to_vec()));
+        raw = &raw[header + length..];
+    }
+    frames
+}
+
+// Encode a close frame as a client would send it. Client frames must be masked;
+// an all-zero mask leaves the payload unchanged.
+pub fn client_close_frame(code: Option<u16>, reason: &str) -> Bytes {
+    let mut payload = Vec::new();
+    if let Some(code) = code {
+        payload.extend_from_slice(&code.to_be_bytes());
+        payload.extend_from_slice(reason.as_bytes());
+    }
+    assert!(payload.len() < 126, "close payloads are limited to 125 bytes");
+    let mut frame = vec![0x88, 0x80 | payload.len() as u8, 0, 0, 0, 0];
+    frame.extend_from_slice(&payload);
+    Bytes::from(frame)
+}
+
+// Encode a text frame as a client would send it, masked with zeros
+pub fn client_text_frame(text: &str) -> Bytes {
+    client_frame(0x81, text.as_bytes())
+}
+
+// Encode a binary frame as a client would send it
+pub fn client_binary_frame(payload: &[u8]) -> Bytes {
+    client_frame(0x82, payload)
+}
+
+// Encode a pong as a client would send it
+pub fn client_pong_frame(payload: &[u8]) -> Bytes {
+    client_frame(0x8A, payload)
+}
+
+fn client_frame(opcode: u8, payload: &[u8]) -> Bytes {
+    let length = u16::try_from(payload.len()).expect("64-bit payload lengths are not supported");
+    let mut frame = vec![opcode];
+    if length < 126 {
+        frame.push(0x80 | length as u8);
+    } else {
+        frame.push(0x80 | 126);
+        frame.extend_from_slice(&length.to_be_bytes());
+    }
+    frame.extend_from_slice(&[0, 0, 0, 0]);
+    frame.extend_from_slice(payload);
+    Bytes::from(frame)
+}
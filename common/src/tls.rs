@@ -0,0 +1,78 @@
+// common/src/tls.rs
+// Loading the configured certificate for serving HTTPS/WSS, and for the
+// web-server proxy to trust when it connects to the websocket-server
+use std::fs::File;
+use std::io::{self, BufReader};
+use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig};
+use crate::config::TlsConfig;
+
+/// Server-side TLS settings for `HttpServer::bind_rustls`
+pub fn server_config(tls: &TlsConfig) -> io::Result<ServerConfig> {
+    let certs = load_certs(&tls.cert_path)?;
+    let key = load_key(&tls.key_path)?;
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", tls.cert_path, e)))
+}
+
+/// Client-side TLS settings trusting the configured certificate chain, for
+/// reaching a server that presents it even when it is self-signed
+pub fn client_config(tls: &TlsConfig) -> io::Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(&tls.cert_path)? {
+        roots.add(&cert)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", tls.cert_path, e)))?;
+    }
+    Ok(ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+fn load_certs(path: &str) -> io::Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?;
+    if certs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("no certificates in {}", path)));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+// The first private key in the file, in any of the PEM encodings rustls reads
+fn load_key(path: &str) -> io::Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)?);
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {},
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, format!("no private key in {}", path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn localhost() -> TlsConfig {
+        TlsConfig {
+            cert_path: concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls_localhost.crt").to_string(),
+            key_path: concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls_localhost.key").to_string(),
+        }
+    }
+
+    #[test]
+    fn test_self_signed_certificate_loads() {
+        assert!(server_config(&localhost()).is_ok());
+        assert!(client_config(&localhost()).is_ok());
+
+        // A certificate where the key should be is refused, not served
+        let swapped = TlsConfig { key_path: localhost().cert_path, ..localhost() };
+        assert!(server_config(&swapped).is_err());
+        let missing = TlsConfig { cert_path: "/nonexistent/server.crt".to_string(), ..localhost() };
+        assert!(server_config(&missing).is_err());
+    }
+}
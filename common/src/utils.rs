@@ -1,20 +1,160 @@
 // Common Crate - utils.rs 
 // my-actix-system/common/src/utils.rs
-use tracing::Level;
-use tracing_subscriber::FmtSubscriber;
+use tracing::{Level, Subscriber};
+use tracing_subscriber::{filter::LevelFilter, fmt::MakeWriter, prelude::*, reload, Registry};
 use jsonwebtoken::{encode, decode, Header, Algorithm, Validation, EncodingKey, DecodingKey};
+pub use jsonwebtoken::errors::Error as JwtError;
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-/// Setup tracing for consistent logging across services
-pub fn setup_tracing() {
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(Level::INFO)
-        .finish();
-    
+/// Setup tracing for consistent logging across services, at INFO until the
+/// returned handle applies the configured level
+pub fn setup_tracing() -> LogLevelHandle {
+    let (subscriber, handle) = reloadable_subscriber(LevelFilter::INFO, std::io::stdout);
     tracing::subscriber::set_global_default(subscriber)
         .expect("Failed to set tracing subscriber");
+    handle
+}
+
+/// A formatting subscriber writing to `make_writer` whose maximum level can
+/// be changed while it is in use, through the returned handle
+pub fn reloadable_subscriber<W>(level: LevelFilter, make_writer: W) -> (impl Subscriber + Send + Sync, LogLevelHandle)
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let (filter, handle) = reload::Layer::new(level);
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(make_writer));
+    (subscriber, LogLevelHandle(handle))
+}
+
+/// Changes the level of a subscriber made by `reloadable_subscriber`
+#[derive(Clone)]
+pub struct LogLevelHandle(reload::Handle<LevelFilter, Registry>);
+
+impl LogLevelHandle {
+    /// Switch to `level` ("error", "warn", "info", "debug" or "trace", in any
+    /// case), returning the level now in force
+    pub fn set(&self, level: &str) -> Result<Level, String> {
+        let level: Level = level.trim().parse()
+            .map_err(|_| format!("Unknown log level '{}'", level))?;
+        self.0.reload(LevelFilter::from_level(level))
+            .map_err(|e| e.to_string())?;
+        Ok(level)
+    }
+}
+
+/// Exponential reconnection backoff (2^attempts seconds) capped at `max_secs`.
+/// Saturates instead of overflowing for large attempt counts.
+pub fn reconnect_backoff_secs(attempts: u32, max_secs: u64) -> u64 {
+    2u64.checked_pow(attempts)
+        .map_or(max_secs, |secs| secs.min(max_secs))
+}
+
+/// Reconnection backoff with full jitter: a uniformly random delay between
+/// zero and `reconnect_backoff_secs(attempts, cap_secs)`, so clients dropped
+/// by the same outage spread their reconnects instead of arriving together.
+pub fn backoff_with_jitter(attempts: u32, cap_secs: u64) -> Duration {
+    let max_millis = reconnect_backoff_secs(attempts, cap_secs).saturating_mul(1000);
+    Duration::from_millis(rand::Rng::gen_range(&mut rand::thread_rng(), 0..=max_millis))
+}
+
+/// Whether `content` is longer than `max_chars` characters; 0 means no limit.
+/// Counts characters rather than bytes, so multi-byte text gets the same allowance.
+pub fn content_exceeds(content: &str, max_chars: usize) -> bool {
+    max_chars > 0 && content.chars().nth(max_chars).is_some()
+}
+
+/// Run `handle` and warn if it took longer than `threshold`, naming the
+/// handler and the message's type and size. A zero threshold skips the check.
+pub fn time_message<T>(handler: &str, message_type: &str, size: usize, threshold: Duration, handle: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = handle();
+    let elapsed = started.elapsed();
+    if !threshold.is_zero() && elapsed > threshold {
+        tracing::warn!(
+            handler, message_type, size,
+            "Slow message handling: {} took {:?} for a {} byte {} (threshold {:?})",
+            handler, elapsed, size, message_type, threshold
+        );
+    }
+    result
+}
+
+/// Token bucket rate limiter: allows bursts of up to `burst` actions and
+/// refills at `rate_per_sec` tokens per second.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate_per_sec: u32, burst: u32) -> Self {
+        Self::with_refill_rate(f64::from(rate_per_sec), burst)
+    }
+
+    /// Like `new`, for rates below one token per second
+    pub fn with_refill_rate(refill_per_sec: f64, burst: u32) -> Self {
+        let capacity = f64::from(burst.max(1));
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_per_sec,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take a token if one is available
+    pub fn try_acquire(&mut self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    /// Take a token if one is available, refilling up to `now`
+    pub fn try_acquire_at(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Lifetime of client JWTs issued after sign-in (24 hours)
+pub const JWT_TTL_SECS: u64 = 86400;
+
+/// Default clock skew tolerated when checking a JWT's expiry (the jsonwebtoken
+/// default); services take theirs from `Config::jwt_leeway_secs`
+pub const JWT_LEEWAY_SECS: u64 = 60;
+
+/// Signing algorithm for client JWTs. HS256 shares one secret between every
+/// service; RS256 signs with a private key so other services only need the
+/// public key to verify.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JwtAlgorithm {
+    #[default]
+    #[serde(rename = "HS256")]
+    Hs256,
+    #[serde(rename = "RS256")]
+    Rs256,
+}
+
+impl From<JwtAlgorithm> for Algorithm {
+    fn from(algorithm: JwtAlgorithm) -> Self {
+        match algorithm {
+            JwtAlgorithm::Hs256 => Algorithm::HS256,
+            JwtAlgorithm::Rs256 => Algorithm::RS256,
+        }
+    }
 }
 
 // JWT Claims structure
@@ -24,41 +164,230 @@ pub struct JwtClaims {
     pub wallet: String,    // wallet_address 
     pub exp: usize,        // expiration time
     pub iat: usize,        // issued at time
+    #[serde(default)]
+    pub jti: String,       // unique token id, used to revoke the token
+}
+
+impl JwtClaims {
+    /// Claims for a new token with a fresh id, expiring `ttl` from now
+    pub fn new(client_id: &Uuid, wallet_address: &str, ttl: Duration) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs() as usize;
+        
+        Self {
+            sub: client_id.to_string(),
+            wallet: wallet_address.to_string(),
+            iat: now,
+            exp: now + ttl.as_secs() as usize,
+            jti: Uuid::new_v4().to_string(),
+        }
+    }
+    
+    /// The client the token was issued to
+    pub fn client_id(&self) -> Result<Uuid, jsonwebtoken::errors::Error> {
+        Uuid::parse_str(&self.sub)
+            .map_err(|_| jsonwebtoken::errors::ErrorKind::InvalidSubject.into())
+    }
 }
 
 // Generate JWT token from client_id and wallet_address
 pub fn generate_jwt_token(client_id: &Uuid, wallet_address: &str, secret: &[u8]) -> Result<String, jsonwebtoken::errors::Error> {
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_secs() as usize;
-    
-    let claims = JwtClaims {
-        sub: client_id.to_string(),
-        wallet: wallet_address.to_string(),
-        iat: now,
-        exp: now + 86400, // 24 hours expiration
-    };
-    
+    generate_jwt_token_with_ttl(client_id, wallet_address, secret, Duration::from_secs(JWT_TTL_SECS))
+}
+
+// Generate JWT token that expires `ttl` from now
+pub fn generate_jwt_token_with_ttl(
+    client_id: &Uuid,
+    wallet_address: &str,
+    secret: &[u8],
+    ttl: Duration,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    generate_jwt_token_with_key(client_id, wallet_address, &EncodingKey::from_secret(secret), JwtAlgorithm::Hs256, ttl)
+}
+
+// Generate JWT token signed with an RSA private key (PEM)
+pub fn generate_jwt_token_rs256(client_id: &Uuid, wallet_address: &str, private_key_pem: &[u8]) -> Result<String, jsonwebtoken::errors::Error> {
+    let key = EncodingKey::from_rsa_pem(private_key_pem)?;
+    generate_jwt_token_with_key(client_id, wallet_address, &key, JwtAlgorithm::Rs256, Duration::from_secs(JWT_TTL_SECS))
+}
+
+// Generate JWT token that expires `ttl` from now, signed with `key` using `algorithm`
+pub fn generate_jwt_token_with_key(
+    client_id: &Uuid,
+    wallet_address: &str,
+    key: &EncodingKey,
+    algorithm: JwtAlgorithm,
+    ttl: Duration,
+) -> Result<String, jsonwebtoken::errors::Error> {
     encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret)
+        &Header::new(algorithm.into()),
+        &JwtClaims::new(client_id, wallet_address, ttl),
+        key
     )
 }
 
 // Validate JWT token and extract client_id and wallet_address
 pub fn validate_jwt_token(token: &str, secret: &[u8]) -> Result<(Uuid, String), jsonwebtoken::errors::Error> {
-    let validation = Validation::new(Algorithm::HS256);
-    
-    let token_data = decode::<JwtClaims>(
-        token,
-        &DecodingKey::from_secret(secret),
-        &validation
-    )?;
-    
-    let uuid = Uuid::parse_str(&token_data.claims.sub)
-        .map_err(|_| jsonwebtoken::errors::ErrorKind::InvalidSubject)?;
+    validate_jwt_token_with_leeway(token, secret, JWT_LEEWAY_SECS)
+}
+
+// Validate JWT token, still accepting it up to `leeway_secs` after it expired
+pub fn validate_jwt_token_with_leeway(token: &str, secret: &[u8], leeway_secs: u64) -> Result<(Uuid, String), jsonwebtoken::errors::Error> {
+    let claims = validate_jwt_claims(token, secret, leeway_secs)?;
+    Ok((claims.client_id()?, claims.wallet))
+}
+
+// Validate RS256 JWT token against an RSA public key (PEM) and extract
+// client_id and wallet_address
+pub fn validate_jwt_token_rs256(token: &str, public_key_pem: &[u8]) -> Result<(Uuid, String), jsonwebtoken::errors::Error> {
+    let key = DecodingKey::from_rsa_pem(public_key_pem)?;
+    let claims = validate_jwt_claims_with_key(token, &key, JwtAlgorithm::Rs256, JWT_LEEWAY_SECS)?;
+    Ok((claims.client_id()?, claims.wallet))
+}
+
+// Validate JWT token and return all of its claims, including the token id
+pub fn validate_jwt_claims(token: &str, secret: &[u8], leeway_secs: u64) -> Result<JwtClaims, jsonwebtoken::errors::Error> {
+    validate_jwt_claims_with_key(token, &DecodingKey::from_secret(secret), JwtAlgorithm::Hs256, leeway_secs)
+}
+
+// Validate JWT token against `key`, accepting only tokens signed with `algorithm`.
+// Tokens without an id are rejected, since they could never be revoked.
+pub fn validate_jwt_claims_with_key(
+    token: &str,
+    key: &DecodingKey,
+    algorithm: JwtAlgorithm,
+    leeway_secs: u64,
+) -> Result<JwtClaims, jsonwebtoken::errors::Error> {
+    let mut validation = Validation::new(algorithm.into());
+    validation.leeway = leeway_secs;
     
-    Ok((uuid, token_data.claims.wallet))
-}
\ No newline at end of file
+    let token_data = decode::<JwtClaims>(token, key, &validation)?;
+    if token_data.claims.jti.is_empty() {
+        return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into());
+    }
+    Ok(token_data.claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_limit_counts_characters() {
+        assert!(!content_exceeds("hello", 5));
+        assert!(content_exceeds("hello!", 5));
+        // Five characters, ten bytes
+        assert!(!content_exceeds("ééééé", 5));
+        assert!(!content_exceeds(&"x".repeat(10_000), 0));
+    }
+
+    #[test]
+    fn test_backoff_with_jitter_stays_within_cap_and_varies() {
+        let delays: Vec<Duration> = (0..50).map(|_| backoff_with_jitter(10, 60)).collect();
+        assert!(delays.iter().all(|delay| *delay <= Duration::from_secs(60)));
+        assert!(delays.iter().any(|delay| *delay != delays[0]), "jitter should vary: {:?}", delays);
+
+        // Bounded by 2^attempts before the cap, and never overflows
+        assert!((0..50).all(|_| backoff_with_jitter(1, 60) <= Duration::from_secs(2)));
+        assert!(backoff_with_jitter(u32::MAX, 60) <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_slow_message_handling_is_logged() {
+        let log = crate::test_support::CapturedLog::default();
+        let writer = log.clone();
+        let subscriber = tracing_subscriber::FmtSubscriber::builder().with_writer(move || writer.clone()).finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            // An artificially slow handler crosses the threshold...
+            let result = time_message("router", "client_message", 512, Duration::from_millis(10), || {
+                std::thread::sleep(Duration::from_millis(30));
+                "routed"
+            });
+            assert_eq!(result, "routed");
+            // ...while a fast one, or any with the check disabled, stays quiet
+            time_message("router", "agent_message", 64, Duration::from_millis(500), || ());
+            time_message("router", "agent_to_agent", 64, Duration::ZERO, || std::thread::sleep(Duration::from_millis(5)));
+        });
+
+        let output = log.contents();
+        assert_eq!(output.matches("Slow message handling").count(), 1, "{}", output);
+        assert!(output.contains("WARN"));
+        assert!(output.contains("512 byte client_message"));
+    }
+
+    #[test]
+    fn test_token_bucket_throttles_and_refills() {
+        let mut bucket = TokenBucket::new(2, 3);
+        let start = bucket.last_refill;
+
+        // Full burst is available immediately, then the bucket is empty
+        for _ in 0..3 {
+            assert!(bucket.try_acquire_at(start));
+        }
+        assert!(!bucket.try_acquire_at(start));
+
+        // Half a second at 2 tokens/sec refills exactly one token
+        let later = start + Duration::from_millis(500);
+        assert!(bucket.try_acquire_at(later));
+        assert!(!bucket.try_acquire_at(later));
+
+        // Refill never exceeds the burst size
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(bucket.try_acquire_at(much_later));
+        }
+        assert!(!bucket.try_acquire_at(much_later));
+    }
+
+    #[test]
+    fn test_jwt_expiry_follows_ttl_and_leeway() {
+        let client_id = Uuid::new_v4();
+        let token = generate_jwt_token_with_ttl(&client_id, "0xwallet", b"secret", Duration::from_secs(300)).unwrap();
+        let claims = decode::<JwtClaims>(&token, &DecodingKey::from_secret(b"secret"), &Validation::new(Algorithm::HS256))
+            .unwrap()
+            .claims;
+        assert_eq!(claims.exp - claims.iat, 300);
+        assert!(Uuid::parse_str(&claims.jti).is_ok());
+
+        // Expired ten minutes ago: only a wide enough leeway still accepts it
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as usize;
+        let claims = JwtClaims {
+            sub: client_id.to_string(),
+            wallet: "0xwallet".to_string(),
+            iat: now - 3600,
+            exp: now - 600,
+            jti: Uuid::new_v4().to_string(),
+        };
+        let expired = encode(&Header::default(), &claims, &EncodingKey::from_secret(b"secret")).unwrap();
+        assert!(validate_jwt_token(&expired, b"secret").is_err());
+        assert!(validate_jwt_token_with_leeway(&expired, b"secret", 60).is_err());
+        assert_eq!(
+            validate_jwt_token_with_leeway(&expired, b"secret", 3600).unwrap(),
+            (client_id, "0xwallet".to_string())
+        );
+    }
+
+    const TEST_RSA_PRIVATE_KEY: &str = include_str!("../testdata/jwt_rs256_private.pem");
+    const TEST_RSA_PUBLIC_KEY: &str = include_str!("../testdata/jwt_rs256_public.pem");
+
+    #[test]
+    fn test_rs256_token_validates_with_public_key_only() {
+        let client_id = Uuid::new_v4();
+        let token = generate_jwt_token_rs256(&client_id, "0xwallet", TEST_RSA_PRIVATE_KEY.as_bytes()).unwrap();
+
+        let header = jsonwebtoken::decode_header(&token).unwrap();
+        assert_eq!(header.alg, Algorithm::RS256);
+        assert_eq!(
+            validate_jwt_token_rs256(&token, TEST_RSA_PUBLIC_KEY.as_bytes()).unwrap(),
+            (client_id, "0xwallet".to_string())
+        );
+
+        // Algorithms are not interchangeable
+        assert!(validate_jwt_token(&token, TEST_RSA_PUBLIC_KEY.as_bytes()).is_err());
+        let hs256 = generate_jwt_token(&client_id, "0xwallet", b"secret").unwrap();
+        assert!(validate_jwt_token_rs256(&hs256, TEST_RSA_PUBLIC_KEY.as_bytes()).is_err());
+    }
+}
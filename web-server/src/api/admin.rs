@@ -0,0 +1,184 @@
+// web-server/src/api/admin.rs
+use actix::Addr;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use common::{AuthorizationHeader, Config, LogLevelHandle};
+use serde::Deserialize;
+use serde_json::json;
+use crate::client_registry::{ClientRegistryActor, GetAllClientIds};
+use crate::security::SecurityMonitor;
+
+// Admin routes are protected with the admin token, as on the websocket-server
+fn is_admin_authorized(req: &HttpRequest, config: &Config) -> bool {
+    req.headers().get("Authorization")
+        .and_then(|header| AuthorizationHeader::from_header(header).ok())
+        .map(|auth| auth.token() == config.admin_token)
+        .unwrap_or(false)
+}
+
+// Client ids holding a live session, so ops tooling can spot sessions with
+// no websocket connection behind them
+#[get("/admin/client-ids")]
+pub async fn get_client_ids(
+    req: HttpRequest,
+    registry: web::Data<Addr<ClientRegistryActor>>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    if !is_admin_authorized(&req, &config) {
+        tracing::warn!("Unauthorized client id listing");
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    match registry.send(GetAllClientIds).await {
+        Ok(client_ids) => HttpResponse::Ok().json(json!({ "client_ids": client_ids })),
+        Err(e) => {
+            tracing::error!("Failed to list client ids: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "error": "Internal server error"
+            }))
+        }
+    }
+}
+
+// Security event counters, banned IPs and the recent audit trail
+#[get("/admin/security")]
+pub async fn get_security_events(
+    req: HttpRequest,
+    security: web::Data<SecurityMonitor>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    if !is_admin_authorized(&req, &config) {
+        tracing::warn!("Unauthorized security event listing");
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    HttpResponse::Ok().json(json!({
+        "client_id_mismatches": security.client_id_mismatches(),
+        "banned_ips": security.banned_ips(),
+        "audit_log": security.audit_events(),
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct LogLevelRequest {
+    level: String,
+}
+
+// Change how verbosely the web-server logs without restarting it, e.g. to
+// debug a live issue; the configured level returns on the next start
+#[post("/log-level")]
+pub async fn set_log_level(
+    req: HttpRequest,
+    body: web::Json<LogLevelRequest>,
+    log_level: web::Data<LogLevelHandle>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    if !is_admin_authorized(&req, &config) {
+        tracing::warn!("Unauthorized log level change");
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    match log_level.set(&body.level) {
+        Ok(level) => {
+            tracing::info!("Log level set to {}", level);
+            HttpResponse::Ok().json(json!({ "level": level.to_string() }))
+        },
+        Err(e) => HttpResponse::BadRequest().json(json!({ "error": e })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::Actor;
+    use actix_web::{test, App};
+    use std::collections::HashSet;
+    use uuid::Uuid;
+    use crate::client_registry::{CreateClientSession, InvalidateClientSession, RegisterAnonymousClient};
+
+    #[actix_web::test]
+    async fn test_client_ids_match_live_sessions() {
+        let config = Config::default();
+        let registry = ClientRegistryActor::new().start();
+        let (first, _) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let (second, _) = registry.send(RegisterAnonymousClient).await.unwrap();
+        // A second session for the same client is listed once
+        registry.send(CreateClientSession { client_id: first }).await.unwrap();
+        // A logged out client is no longer listed
+        let (_, logged_out) = registry.send(RegisterAnonymousClient).await.unwrap();
+        registry.send(InvalidateClientSession { session_token: logged_out }).await.unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(registry))
+                .app_data(web::Data::new(config.clone()))
+                .service(get_client_ids)
+        ).await;
+
+        let req = test::TestRequest::get().uri("/admin/client-ids").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+        // An agent's token is not an admin credential
+        let req = test::TestRequest::get()
+            .uri("/admin/client-ids")
+            .insert_header(("Authorization", format!("Bearer {}", config.agent_token)))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+        let req = test::TestRequest::get()
+            .uri("/admin/client-ids")
+            .insert_header(("Authorization", format!("Bearer {}", config.admin_token)))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let client_ids: Vec<Uuid> = serde_json::from_value(body["client_ids"].clone()).unwrap();
+        assert_eq!(client_ids.len(), 2);
+        assert_eq!(client_ids.into_iter().collect::<HashSet<_>>(), HashSet::from([first, second]));
+    }
+
+    fn debug_probe(name: &str) {
+        tracing::debug_span!("probe").in_scope(|| tracing::debug!("{}", name));
+    }
+
+    #[actix_web::test]
+    async fn test_log_level_endpoint_turns_on_debug_output() {
+        let log = common::test_support::CapturedLog::default();
+        let writer = log.clone();
+        let (subscriber, handle) = common::reloadable_subscriber(
+            tracing::level_filters::LevelFilter::INFO,
+            move || writer.clone(),
+        );
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let config = Config::default();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(handle))
+                .app_data(web::Data::new(config.clone()))
+                .service(set_log_level)
+        ).await;
+        let change = |level: &str, authorized: bool| {
+            let mut req = test::TestRequest::post()
+                .uri("/log-level")
+                .set_json(json!({ "level": level }));
+            if authorized {
+                req = req.insert_header(("Authorization", format!("Bearer {}", config.admin_token)));
+            }
+            req.to_request()
+        };
+
+        debug_probe("before-change");
+        assert!(!log.contents().contains("before-change"));
+
+        // Only admins may change the level, and only to a real level
+        assert_eq!(test::call_service(&app, change("debug", false)).await.status(), 401);
+        assert_eq!(test::call_service(&app, change("loud", true)).await.status(), 400);
+        debug_probe("after-rejected");
+        assert!(!log.contents().contains("after-rejected"));
+
+        let body: serde_json::Value = test::call_and_read_body_json(&app, change("DEBUG", true)).await;
+        assert_eq!(body["level"], "DEBUG");
+        debug_probe("after-change");
+        let output = log.contents();
+        let line = output.lines().find(|line| line.contains("after-change")).expect(&output);
+        assert!(line.contains("DEBUG") && line.contains("probe"), "{}", line);
+    }
+}
@@ -1,14 +1,47 @@
 // web-server/src/api/mod.rs
+pub mod admin;
 pub mod sessions;
 
-pub fn configure(cfg: &mut actix_web::web::ServiceConfig) {
+use actix_cors::Cors;
+use actix_web::{error::{InternalError, JsonPayloadError}, middleware::Condition, mime, web, HttpResponse};
+use serde_json::json;
+
+// JSON bodies are also taken as text/plain, which is what fetch sends for a
+// string body by default. Other content types get an error naming the
+// expected one rather than a bare 400.
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        .content_type(|mime| mime.type_() == mime::TEXT && mime.subtype() == mime::PLAIN)
+        .error_handler(|err, _req| {
+            let response = match &err {
+                JsonPayloadError::ContentType => HttpResponse::UnsupportedMediaType().json(json!({
+                    "error": "Expected Content-Type: application/json"
+                })),
+                _ => HttpResponse::BadRequest().json(json!({
+                    "error": format!("Invalid JSON body: {}", err)
+                })),
+            };
+            InternalError::from_response(err, response).into()
+        })
+}
+
+// `cors` applies to the /api routes only; None leaves them without CORS headers
+pub fn configure(cfg: &mut actix_web::web::ServiceConfig, cors: Option<Cors>) {
     cfg.service(
         actix_web::web::scope("/api")
+            .wrap(Condition::new(cors.is_some(), cors.unwrap_or_default()))
+            .app_data(json_config())
             .service(sessions::api_index)
             .service(sessions::create_client)
             .service(sessions::get_client_info)
             .service(sessions::invalidate_session)
+            .service(sessions::siwe_challenge)
             .service(sessions::upgrade_session)
+            .service(sessions::refresh_session)
             .service(sessions::protected_endpoint)
+            .service(sessions::list_my_sessions)
+            .service(admin::get_client_ids)
+            .service(admin::get_security_events)
+            .service(admin::set_log_level)
     );
-}
\ No newline at end of file
+}
@@ -2,60 +2,107 @@
 use actix::Addr;
 use actix_web::{get, post, delete, web, HttpRequest, HttpResponse, Responder, cookie::{Cookie, SameSite}};
 use actix_web::cookie::time::Duration as CookieDuration;
+use common::{AuthorizationHeader, Config};
+use common::utils::JwtClaims;
 use common::models::session::{ClientSessionResponse, SessionResult};
 use serde_json::json;
 use uuid::Uuid;
 use serde::{Deserialize, Serialize};
 use jsonwebtoken::errors::Error as JwtError;
+use crate::auth::siwe;
+use crate::security::{client_ip, SecurityMonitor};
 use crate::client_registry::{
     ClientRegistryActor, 
     RegisterAnonymousClient, 
     GetClientSession,
     GetClientSessionById,
+    GetClientSessionByTokenId,
+    GetSessionsByWallet,
     InvalidateClientSession,
-    UpdateClientSession
+    UpdateClientSession,
+    IssueChallenge,
+    ConsumeChallenge,
+    RecordIssuedToken,
+    IsTokenRevoked
 };
 
 // Cookie name for session tracking
-const SESSION_COOKIE_NAME: &str = "sploots_session";
+pub(crate) const SESSION_COOKIE_NAME: &str = "sploots_session";
 // Cookie max age in seconds (24 hours)
 const COOKIE_MAX_AGE: i64 = 86400;
-// Add JWT secret to configuration
-// This should be loaded from environment or config file
-const JWT_SECRET: &[u8] = b"your_jwt_secret_key_here";
-// ****************************
-// In web-server/src/main.rs or config.rs: 
-// pub fn get_jwt_secret() -> Vec<u8> {
-//     std::env::var("JWT_SECRET")
-//         .unwrap_or_else(|_| "insecure_default_only_for_development".to_string())
-//         .into_bytes()
-// }
-
-// // Then in sessions.rs
-// let jwt_secret = get_jwt_secret();
-// *****************************
-
-
+// How long after expiry a JWT can still be exchanged for a fresh one (1 hour)
+const REFRESH_GRACE_SECS: u64 = 3600;
 
 // Request structure for session upgrade
 #[derive(Deserialize)]
 pub struct UpgradeRequest {
     pub wallet_address: String,
+    // Hex-encoded personal_sign signature over the challenge message
+    pub signature: String,
+    // Nonce of the challenge that was signed
+    pub nonce: String,
 }
 
-// Response structure for successful upgrade
+// Response structure for successful upgrade or refresh
 #[derive(Serialize)]
 pub struct UpgradeResponse {
     pub status: String,
     pub token: String,
 }
 
+// Query parameters for a sign-in challenge
+#[derive(Deserialize)]
+pub struct ChallengeQuery {
+    pub address: String,
+    #[serde(default = "default_chain_id")]
+    pub chain_id: u64,
+}
+
+// Ethereum mainnet
+fn default_chain_id() -> u64 {
+    1
+}
+
+// Response structure for a sign-in challenge
+#[derive(Serialize)]
+pub struct ChallengeResponse {
+    pub message: String,
+    pub nonce: String,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
 #[get("/")]
 pub async fn api_index() -> impl Responder {
-    HttpResponse::Ok().json(json!({
+    HttpResponse::Ok().json(api_info())
+}
+
+// Service name and build information reported by the API index
+fn api_info() -> serde_json::Value {
+    // Empty when the build script could not run git (e.g. building from a tarball)
+    let git_hash = Some(env!("GIT_HASH")).filter(|hash| !hash.is_empty());
+
+    json!({
         "name": "Agent Bridge Platform API",
-        "version": "0.1.0"
-    }))
+        "version": env!("CARGO_PKG_VERSION"),
+        "build": {
+            "git_hash": git_hash,
+        }
+    })
+}
+
+// Session cookie carrying `session_token`. Secure when configured, otherwise
+// only when the request came over HTTPS, so plain-HTTP local development
+// still gets a cookie the browser will keep
+fn session_cookie(req: &HttpRequest, config: &Config, session_token: String) -> Cookie<'static> {
+    let secure = config.secure_cookies
+        .unwrap_or_else(|| req.connection_info().scheme() == "https");
+    Cookie::build(SESSION_COOKIE_NAME, session_token)
+        .path("/")
+        .secure(secure)
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .max_age(CookieDuration::seconds(COOKIE_MAX_AGE))
+        .finish()
 }
 
 // Create a new client session or return existing one
@@ -63,6 +110,7 @@ pub async fn api_index() -> impl Responder {
 pub async fn create_client(
     req: HttpRequest,
     registry: web::Data<Addr<ClientRegistryActor>>,
+    config: web::Data<Config>,
 ) -> impl Responder {
     // Check for existing session cookie
     if let Some(cookie) = req.cookie(SESSION_COOKIE_NAME) {
@@ -82,6 +130,10 @@ pub async fn create_client(
                 tracing::info!("Session expired, creating new client");
                 // Fall through to create new session
             },
+            Ok(SessionResult::Invalid) => {
+                tracing::warn!("Malformed or tampered session cookie, creating new client");
+                // Fall through to create new session
+            },
             Ok(_) => {
                 tracing::info!("Session not found or invalid, creating new client");
                 // Fall through to create new session
@@ -99,13 +151,7 @@ pub async fn create_client(
     match registry.send(RegisterAnonymousClient).await {
         Ok((client_id, session_token)) => {
             // Create session cookie
-            let cookie = Cookie::build(SESSION_COOKIE_NAME, session_token)
-                .path("/")
-                .secure(true)
-                .http_only(true)
-                .same_site(SameSite::Strict)
-                .max_age(CookieDuration::seconds(COOKIE_MAX_AGE))
-                .finish();
+            let cookie = session_cookie(&req, &config, session_token);
             
             // Create response
             let response = json!({
@@ -138,6 +184,8 @@ pub async fn get_client_info(
     path: web::Path<(String,)>,
     req: HttpRequest,
     registry: web::Data<Addr<ClientRegistryActor>>,
+    config: web::Data<Config>,
+    security: web::Data<SecurityMonitor>,
 ) -> impl Responder {
     let client_id_str = &path.0;
     
@@ -151,6 +199,13 @@ pub async fn get_client_info(
         }
     };
     
+    let ip = client_ip(&req);
+    if security.is_banned(&ip) {
+        return HttpResponse::Forbidden().json(json!({
+            "error": "Access denied"
+        }));
+    }
+    
     // Check for session cookie
     if let Some(cookie) = req.cookie(SESSION_COOKIE_NAME) {
         let session_token = cookie.value().to_string();
@@ -164,6 +219,7 @@ pub async fn get_client_info(
                         "Client ID mismatch: requested {}, session has {}", 
                         client_id, session.client_id
                     );
+                    security.record_client_id_mismatch(&ip, client_id, session.client_id, "session");
                     return HttpResponse::Forbidden().json(json!({
                         "error": "Access denied"
                     }));
@@ -178,6 +234,11 @@ pub async fn get_client_info(
                     "error": "Session expired"
                 }));
             },
+            Ok(SessionResult::Invalid) => {
+                return HttpResponse::Unauthorized().json(json!({
+                    "error": "Malformed or tampered session token"
+                }));
+            },
             Ok(_) => {
                 return HttpResponse::Unauthorized().json(json!({
                     "error": "Invalid session"
@@ -199,13 +260,7 @@ pub async fn get_client_info(
             tracing::warn!("Session found for client {} but cookie is missing", client_id);
             
             // Create new cookie
-            let cookie = Cookie::build(SESSION_COOKIE_NAME, session.session_token.clone())
-                .path("/")
-                .secure(true)
-                .http_only(true)
-                .same_site(SameSite::Strict)
-                .max_age(CookieDuration::seconds(COOKIE_MAX_AGE))
-                .finish();
+            let cookie = session_cookie(&req, &config, session.session_token.clone());
             
             // Return client info with cookie
             let response = ClientSessionResponse::from(&session);
@@ -280,18 +335,112 @@ pub async fn invalidate_session(
     }
 }
 
+// Sign-In With Ethereum challenge: a message for the wallet to sign,
+// bound to a single-use nonce stored against the caller's session
+#[get("/sessions/challenge")]
+pub async fn siwe_challenge(
+    req: HttpRequest,
+    query: web::Query<ChallengeQuery>,
+    registry: web::Data<Addr<ClientRegistryActor>>,
+) -> impl Responder {
+    let Some(cookie) = req.cookie(SESSION_COOKIE_NAME) else {
+        return HttpResponse::Unauthorized().json(json!({
+            "error": "No session cookie found"
+        }));
+    };
+    
+    if !siwe::is_valid_address(&query.address) {
+        return HttpResponse::BadRequest().json(json!({
+            "error": "Invalid Ethereum address"
+        }));
+    }
+    
+    let (domain, uri) = {
+        let connection = req.connection_info();
+        (
+            connection.host().to_string(),
+            format!("{}://{}", connection.scheme(), connection.host()),
+        )
+    };
+    
+    match registry.send(IssueChallenge {
+        session_token: cookie.value().to_string(),
+        domain,
+        uri,
+        address: query.address.clone(),
+        chain_id: query.chain_id,
+    }).await {
+        Ok(Some(challenge)) => HttpResponse::Ok().json(ChallengeResponse {
+            message: challenge.to_string(),
+            nonce: challenge.nonce,
+            expires_at: challenge.expiration_time,
+        }),
+        Ok(None) => HttpResponse::Unauthorized().json(json!({
+            "error": "Invalid session"
+        })),
+        Err(e) => {
+            tracing::error!("Error issuing sign-in challenge: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "error": "Internal server error"
+            }))
+        }
+    }
+}
+
 // Session upgrade endpoint
 #[post("/sessions/upgrade")]
 pub async fn upgrade_session(
     req: HttpRequest,
     data: web::Json<UpgradeRequest>,
     registry: web::Data<Addr<ClientRegistryActor>>,
+    config: web::Data<Config>,
 ) -> impl Responder {
     // 1. Extract client ID from existing session cookie
-    if let Some(cookie) = req.cookie("sploots_session") {
+    if let Some(cookie) = req.cookie(SESSION_COOKIE_NAME) {
         let session_token = cookie.value().to_string();
         
-        // 2. Upgrade session with wallet address n  NEED TO CHECK THIS UPDATE AS IT SEESM THAT IT IS NOT CORRECTLY UTILIZING THE ALREADY DEFINED TYPES 
+        // 2. Prove wallet ownership: the signature must recover to the wallet
+        // address over the challenge issued to this session
+        let challenge = match registry.send(ConsumeChallenge {
+            session_token: session_token.clone(),
+            nonce: data.nonce.clone(),
+        }).await {
+            Ok(Some(challenge)) => challenge,
+            Ok(None) => {
+                return HttpResponse::Unauthorized().json(json!({
+                    "error": "Invalid or expired nonce"
+                }));
+            },
+            Err(e) => {
+                tracing::error!("Error consuming sign-in challenge: {}", e);
+                return HttpResponse::InternalServerError().json(json!({
+                    "error": "Internal server error"
+                }));
+            }
+        };
+        
+        if !challenge.address.eq_ignore_ascii_case(&data.wallet_address) {
+            return HttpResponse::Unauthorized().json(json!({
+                "error": "Wallet address does not match challenge"
+            }));
+        }
+        
+        match challenge.verify(&data.signature) {
+            Ok(true) => {},
+            Ok(false) => {
+                return HttpResponse::Unauthorized().json(json!({
+                    "error": "Signature does not match wallet address"
+                }));
+            },
+            Err(e) => {
+                tracing::debug!("Rejected sign-in signature: {}", e);
+                return HttpResponse::Unauthorized().json(json!({
+                    "error": "Invalid signature"
+                }));
+            }
+        }
+        
+        // 3. Upgrade session with wallet address n  NEED TO CHECK THIS UPDATE AS IT SEESM THAT IT IS NOT CORRECTLY UTILIZING THE ALREADY DEFINED TYPES 
         match registry.send(UpdateClientSession {
             session_token,
             is_authenticated: Some(true),
@@ -300,10 +449,12 @@ pub async fn upgrade_session(
             extend_ttl: true,
         }).await {
             Ok(SessionResult::Success(mut session)) => {
-                // 3. Generate JWT for WebSocket auth
-                match session.generate_auth_token(JWT_SECRET) {
-                    Ok(token) => {
-                        // 4. Return token
+                // 4. Generate JWT for WebSocket auth
+                match session.generate_auth_token(&config) {
+                    Ok((token, claims)) => {
+                        record_issued_token(&registry, &session.session_token, claims);
+                        
+                        // 5. Return token
                         return HttpResponse::Ok().json(UpgradeResponse {
                             status: "success".to_string(),
                             token,
@@ -321,6 +472,11 @@ pub async fn upgrade_session(
                     "error": "Session expired"
                 }));
             },
+            Ok(SessionResult::Invalid) => {
+                return HttpResponse::Unauthorized().json(json!({
+                    "error": "Malformed or tampered session token"
+                }));
+            },
             Ok(_) => {
                 return HttpResponse::Unauthorized().json(json!({
                     "error": "Invalid session"
@@ -340,24 +496,159 @@ pub async fn upgrade_session(
     }))
 }
 
+// Issue a fresh JWT for an authenticated session without re-signing. Takes the
+// current JWT, even shortly after it expired, or else the session cookie
+#[post("/sessions/refresh")]
+pub async fn refresh_session(
+    req: HttpRequest,
+    registry: web::Data<Addr<ClientRegistryActor>>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let bearer = req.headers().get("Authorization")
+        .and_then(|header| match AuthorizationHeader::from_header(header) {
+            Ok(AuthorizationHeader::Bearer(token)) => Some(token),
+            _ => None,
+        });
+    
+    // 1. Find the session behind the token or cookie
+    let (lookup, token_owner) = if let Some(token) = bearer {
+        let claims = match config.validate_jwt(&token, REFRESH_GRACE_SECS) {
+            Ok(claims) => claims,
+            Err(e) => {
+                tracing::warn!("Rejected JWT refresh: {}", e);
+                return HttpResponse::Unauthorized().json(json!({
+                    "error": "Invalid token"
+                }));
+            }
+        };
+        if let Err(response) = check_not_revoked(&registry, &claims.jti).await {
+            return response;
+        }
+        let Ok(client_id) = claims.client_id() else {
+            return HttpResponse::Unauthorized().json(json!({
+                "error": "Invalid token"
+            }));
+        };
+        // The session that issued the token, not just the client's newest one
+        let lookup = registry.send(GetClientSessionByTokenId { jti: claims.jti }).await;
+        (lookup, Some((client_id, claims.wallet)))
+    } else if let Some(cookie) = req.cookie(SESSION_COOKIE_NAME) {
+        let session_token = cookie.value().to_string();
+        (registry.send(GetClientSession { session_token }).await, None)
+    } else {
+        return HttpResponse::Unauthorized().json(json!({
+            "error": "No token or session cookie found"
+        }));
+    };
+    
+    let session = match lookup {
+        Ok(SessionResult::Success(session)) => session,
+        Ok(SessionResult::Expired) => {
+            return HttpResponse::Unauthorized().json(json!({
+                "error": "Session expired"
+            }));
+        },
+        Ok(SessionResult::Invalid) => {
+            return HttpResponse::Unauthorized().json(json!({
+                "error": "Malformed or tampered session token"
+            }));
+        },
+        Ok(_) => {
+            return HttpResponse::Unauthorized().json(json!({
+                "error": "Invalid session"
+            }));
+        },
+        Err(e) => {
+            tracing::error!("Error retrieving session for refresh: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "error": "Internal server error"
+            }));
+        }
+    };
+    
+    // 2. Only sessions still signed in, by the client and wallet the token was issued for
+    let wallet_address = match &session.wallet_address {
+        Some(wallet) if session.is_authenticated
+            && token_owner.as_ref().is_none_or(|(client_id, token_wallet)| {
+                *client_id == session.client_id && token_wallet.eq_ignore_ascii_case(wallet)
+            }) => wallet,
+        _ => {
+            tracing::info!("Refused token refresh for unauthenticated client {}", session.client_id);
+            return HttpResponse::Unauthorized().json(json!({
+                "error": "Session is not authenticated"
+            }));
+        }
+    };
+    
+    // 3. Issue the new token
+    let ttl = std::time::Duration::from_secs(common::utils::JWT_TTL_SECS);
+    match config.sign_jwt(&session.client_id, wallet_address, ttl) {
+        Ok((token, claims)) => {
+            record_issued_token(&registry, &session.session_token, claims);
+            HttpResponse::Ok().json(UpgradeResponse {
+                status: "success".to_string(),
+                token,
+            })
+        },
+        Err(_) => HttpResponse::InternalServerError().json(json!({
+            "error": "Failed to generate authentication token"
+        })),
+    }
+}
+
+// Track a freshly issued JWT against its session, so logging out revokes it
+fn record_issued_token(registry: &Addr<ClientRegistryActor>, session_token: &str, claims: JwtClaims) {
+    let expires_at = chrono::DateTime::from_timestamp(claims.exp as i64, 0).unwrap_or_else(chrono::Utc::now);
+    
+    registry.do_send(RecordIssuedToken {
+        session_token: session_token.to_string(),
+        jti: claims.jti,
+        expires_at,
+    });
+}
+
+// Reject tokens revoked by logging out
+async fn check_not_revoked(registry: &Addr<ClientRegistryActor>, jti: &str) -> Result<(), HttpResponse> {
+    match registry.send(IsTokenRevoked { jti: jti.to_string() }).await {
+        Ok(false) => Ok(()),
+        Ok(true) => {
+            tracing::warn!("Rejected revoked token: {}", jti);
+            Err(HttpResponse::Unauthorized().json(json!({
+                "error": "Token revoked"
+            })))
+        },
+        Err(e) => {
+            tracing::error!("Error checking token revocation: {}", e);
+            Err(HttpResponse::InternalServerError().json(json!({
+                "error": "Internal server error"
+            })))
+        }
+    }
+}
+
 // Add to web-server/src/api/sessions.rs
 
 // JWT validation middleware
-fn validate_jwt(req: &HttpRequest) -> Result<(Uuid, String), HttpResponse> {
+async fn validate_jwt(
+    req: &HttpRequest,
+    config: &Config,
+    registry: &Addr<ClientRegistryActor>,
+) -> Result<(Uuid, String), HttpResponse> {
     if let Some(auth_header) = req.headers().get("Authorization") {
-        if let Ok(auth_str) = auth_header.to_str() {
-            if auth_str.starts_with("Bearer ") {
-                let token = &auth_str[7..]; // Skip "Bearer "
-                match common::utils::validate_jwt_token(token, JWT_SECRET) {
-                    Ok((client_id, wallet_address)) => {
-                        return Ok((client_id, wallet_address));
-                    },
-                    Err(e) => {
-                        tracing::warn!("JWT validation failed: {}", e);
-                        return Err(HttpResponse::Unauthorized().json(json!({
-                            "error": "Invalid token"
-                        })));
-                    }
+        if let Ok(AuthorizationHeader::Bearer(token)) = AuthorizationHeader::from_header(auth_header) {
+            match config.validate_jwt(&token, config.jwt_leeway_secs) {
+                Ok(claims) => {
+                    check_not_revoked(registry, &claims.jti).await?;
+                    let client_id = claims.client_id().map_err(|_| HttpResponse::Unauthorized().json(json!({
+                        "error": "Invalid token"
+                    })))?;
+                    return Ok((client_id, claims.wallet));
+                },
+                Err(e) => {
+                    tracing::warn!("JWT validation failed: {}", e);
+                    return Err(HttpResponse::Unauthorized().json(json!({
+                        "error": "Invalid token"
+                    })));
                 }
             }
         }
@@ -372,8 +663,10 @@ fn validate_jwt(req: &HttpRequest) -> Result<(Uuid, String), HttpResponse> {
 #[get("/protected")]
 pub async fn protected_endpoint(
     req: HttpRequest,
+    registry: web::Data<Addr<ClientRegistryActor>>,
+    config: web::Data<Config>,
 ) -> impl Responder {
-    match validate_jwt(&req) {
+    match validate_jwt(&req, &config, &registry).await {
         Ok((client_id, wallet_address)) => {
             HttpResponse::Ok().json(json!({
                 "status": "success",
@@ -384,4 +677,599 @@ pub async fn protected_endpoint(
         },
         Err(response) => response
     }
-}
\ No newline at end of file
+}
+
+// The caller's logged-in sessions, found by the wallet in their JWT, so
+// nobody can list another wallet's sessions
+#[get("/me/sessions")]
+pub async fn list_my_sessions(
+    req: HttpRequest,
+    registry: web::Data<Addr<ClientRegistryActor>>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let wallet_address = match validate_jwt(&req, &config, &registry).await {
+        Ok((_, wallet_address)) => wallet_address,
+        Err(response) => return response,
+    };
+    
+    match registry.send(GetSessionsByWallet { wallet_address: wallet_address.clone() }).await {
+        Ok(sessions) => HttpResponse::Ok().json(json!({
+            "wallet_address": wallet_address,
+            "sessions": sessions
+        })),
+        Err(e) => {
+            tracing::error!("Error listing sessions for wallet: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "error": "Internal server error"
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::Actor;
+    use actix_web::{test, App};
+    use crate::client_registry::CreateClientSession;
+    use crate::test_support::{key_one, personal_sign, KEY_ONE_ADDRESS};
+    
+    const ADDRESS: &str = "0x0000000000000000000000000000000000000001";
+
+    #[actix_web::test]
+    async fn test_api_index_reports_crate_version() {
+        let app = test::init_service(App::new().service(api_index)).await;
+        let req = test::TestRequest::get().uri("/").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert!(body["build"].get("git_hash").is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_malformed_session_cookie_gets_its_own_401() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(ClientRegistryActor::new().start()))
+                .app_data(web::Data::new(Config::default()))
+                .app_data(web::Data::new(SecurityMonitor::new()))
+                .service(get_client_info)
+        ).await;
+        let error_for = |session_token: &str| {
+            let req = test::TestRequest::get()
+                .uri(&format!("/client/{}", Uuid::new_v4()))
+                .cookie(Cookie::new(SESSION_COOKIE_NAME, session_token.to_string()))
+                .to_request();
+            let app = &app;
+            async move {
+                let res = test::call_service(app, req).await;
+                assert_eq!(res.status(), 401);
+                let body: serde_json::Value = test::read_body_json(res).await;
+                body["error"].as_str().unwrap().to_string()
+            }
+        };
+
+        assert_eq!(error_for("../../etc/passwd").await, "Malformed or tampered session token");
+        assert_eq!(error_for("unknown").await, "Invalid session");
+    }
+
+    #[actix_web::test]
+    async fn test_client_id_mismatches_are_counted_audited_and_banned() {
+        let registry = ClientRegistryActor::new().start();
+        let (owner, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let security = web::Data::new(SecurityMonitor::new().with_mismatch_ban(3, 60));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(registry))
+                .app_data(web::Data::new(Config::default()))
+                .app_data(security.clone())
+                .service(get_client_info)
+        ).await;
+        let request = |client_id: Uuid| test::TestRequest::get()
+            .uri(&format!("/client/{}", client_id))
+            .peer_addr("10.0.0.7:40000".parse().unwrap())
+            .cookie(Cookie::new(SESSION_COOKIE_NAME, session_token.clone()))
+            .to_request();
+        
+        // Probing other clients with this session is refused and recorded
+        let probed: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for client_id in &probed {
+            assert_eq!(test::call_service(&app, request(*client_id)).await.status(), 403);
+        }
+        assert_eq!(security.client_id_mismatches(), 3);
+        let mismatches: Vec<_> = security.audit_events().into_iter()
+            .filter(|event| event.event == "client_id_mismatch")
+            .collect();
+        assert_eq!(mismatches.len(), 3);
+        for (event, client_id) in mismatches.iter().zip(&probed) {
+            assert_eq!(event.ip, "10.0.0.7");
+            assert!(event.detail.contains(&client_id.to_string()));
+            assert!(event.detail.contains(&owner.to_string()));
+        }
+        
+        // The third mismatch banned the IP, so even its own client is refused
+        assert!(security.audit_events().iter().any(|event| event.event == "ip_banned"));
+        assert_eq!(test::call_service(&app, request(owner)).await.status(), 403);
+        assert_eq!(security.client_id_mismatches(), 3);
+    }
+    
+    #[actix_web::test]
+    async fn test_challenge_returns_a_unique_nonce_each_call() {
+        let registry = ClientRegistryActor::new().start();
+        let (_, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(registry)).service(siwe_challenge)
+        ).await;
+        
+        let mut nonces = Vec::new();
+        for _ in 0..2 {
+            let req = test::TestRequest::get()
+                .uri(&format!("/sessions/challenge?address={}", ADDRESS))
+                .cookie(Cookie::new(SESSION_COOKIE_NAME, session_token.clone()))
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            
+            let nonce = body["nonce"].as_str().unwrap().to_string();
+            assert!(nonce.chars().all(|c| c.is_ascii_alphanumeric()));
+            let message = body["message"].as_str().unwrap();
+            assert!(message.contains(ADDRESS));
+            assert!(message.contains(&format!("Nonce: {}", nonce)));
+            nonces.push(nonce);
+        }
+        assert_ne!(nonces[0], nonces[1]);
+    }
+    
+    #[actix_web::test]
+    async fn test_challenge_requires_session_and_valid_address() {
+        let registry = ClientRegistryActor::new().start();
+        let (_, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(registry)).service(siwe_challenge)
+        ).await;
+        
+        let req = test::TestRequest::get()
+            .uri(&format!("/sessions/challenge?address={}", ADDRESS))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+        
+        let req = test::TestRequest::get()
+            .uri("/sessions/challenge?address=not-an-address")
+            .cookie(Cookie::new(SESSION_COOKIE_NAME, session_token))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+    
+    fn challenge_request(session_token: &str) -> test::TestRequest {
+        test::TestRequest::get()
+            .uri(&format!("/sessions/challenge?address={}", KEY_ONE_ADDRESS))
+            .cookie(Cookie::new(SESSION_COOKIE_NAME, session_token.to_string()))
+    }
+    
+    fn upgrade_request(session_token: &str, signature: &str, nonce: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/sessions/upgrade")
+            .cookie(Cookie::new(SESSION_COOKIE_NAME, session_token.to_string()))
+            .set_json(json!({
+                "wallet_address": KEY_ONE_ADDRESS,
+                "signature": signature,
+                "nonce": nonce,
+            }))
+    }
+    
+    #[actix_web::test]
+    async fn test_upgrade_with_valid_signature_issues_token() {
+        let registry = ClientRegistryActor::new().start();
+        let (client_id, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(registry))
+                .app_data(web::Data::new(Config::default()))
+                .service(siwe_challenge)
+                .service(upgrade_session)
+        ).await;
+        
+        let challenge: serde_json::Value =
+            test::call_and_read_body_json(&app, challenge_request(&session_token).to_request()).await;
+        let nonce = challenge["nonce"].as_str().unwrap();
+        let signature = personal_sign(&key_one(), challenge["message"].as_str().unwrap());
+        let req = upgrade_request(&session_token, &signature, nonce).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        
+        let token = body["token"].as_str().unwrap();
+        let (token_client, wallet) = common::utils::validate_jwt_token(token, Config::default().jwt_secret.as_bytes()).unwrap();
+        assert_eq!(token_client, client_id);
+        assert!(wallet.eq_ignore_ascii_case(KEY_ONE_ADDRESS));
+        
+        // The nonce is spent, so the same signature cannot be replayed
+        let req = upgrade_request(&session_token, &signature, nonce).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+    }
+    
+    #[actix_web::test]
+    async fn test_upgrade_with_invalid_signature_is_rejected() {
+        let registry = ClientRegistryActor::new().start();
+        let (client_id, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(registry.clone()))
+                .app_data(web::Data::new(Config::default()))
+                .service(siwe_challenge)
+                .service(upgrade_session)
+        ).await;
+        
+        // Signed by the right key but over another message, and not a signature at all
+        let signatures = [personal_sign(&key_one(), "something else"), "0xdeadbeef".to_string()];
+        for signature in signatures {
+            let challenge: serde_json::Value =
+                test::call_and_read_body_json(&app, challenge_request(&session_token).to_request()).await;
+            let req = upgrade_request(&session_token, &signature, challenge["nonce"].as_str().unwrap()).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 401);
+        }
+        
+        // The session was never upgraded
+        match registry.send(GetClientSessionById { client_id }).await.unwrap() {
+            SessionResult::Success(session) => assert!(!session.is_authenticated),
+            _ => panic!("session should still exist"),
+        }
+    }
+    
+    #[actix_web::test]
+    async fn test_upgrade_body_content_types() {
+        let registry = ClientRegistryActor::new().start();
+        let (_, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(registry))
+                .app_data(web::Data::new(Config::default()))
+                .app_data(crate::api::json_config())
+                .service(siwe_challenge)
+                .service(upgrade_session)
+        ).await;
+        let upgrade = |content_type: &str, body: String| {
+            test::TestRequest::post()
+                .uri("/sessions/upgrade")
+                .cookie(Cookie::new(SESSION_COOKIE_NAME, session_token.clone()))
+                .insert_header(("Content-Type", content_type))
+                .set_payload(body)
+                .to_request()
+        };
+        
+        // A JSON body sent as text/plain, as fetch does for a string body, is accepted
+        let challenge: serde_json::Value =
+            test::call_and_read_body_json(&app, challenge_request(&session_token).to_request()).await;
+        let body = json!({
+            "wallet_address": KEY_ONE_ADDRESS,
+            "signature": personal_sign(&key_one(), challenge["message"].as_str().unwrap()),
+            "nonce": challenge["nonce"],
+        });
+        let res = test::call_service(&app, upgrade("text/plain;charset=UTF-8", body.to_string())).await;
+        assert_eq!(res.status(), 200);
+        
+        // Other content types are told what is expected
+        let res = test::call_service(&app, upgrade("application/x-www-form-urlencoded", body.to_string())).await;
+        assert_eq!(res.status(), 415);
+        let error: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(error["error"], "Expected Content-Type: application/json");
+        
+        // A malformed body names the problem
+        let res = test::call_service(&app, upgrade("application/json", "{\"nonce\":".to_string())).await;
+        assert_eq!(res.status(), 400);
+        let error: serde_json::Value = test::read_body_json(res).await;
+        assert!(error["error"].as_str().unwrap().starts_with("Invalid JSON body"));
+    }
+    
+    // Sign a registered session in as KEY_ONE_ADDRESS, returning its JWT
+    async fn authenticate(registry: &Addr<ClientRegistryActor>, session_token: &str) -> String {
+        match registry.send(UpdateClientSession {
+            session_token: session_token.to_string(),
+            is_authenticated: Some(true),
+            wallet_address: Some(Some(KEY_ONE_ADDRESS.to_string())),
+            metadata: None,
+            extend_ttl: true,
+        }).await.unwrap() {
+            SessionResult::Success(mut session) => {
+                let (token, claims) = session.generate_auth_token(&Config::default()).unwrap();
+                record_issued_token(registry, session_token, claims);
+                token
+            },
+            _ => panic!("session should exist"),
+        }
+    }
+    
+    fn refresh_request(token: Option<&str>, session_token: Option<&str>) -> test::TestRequest {
+        let mut req = test::TestRequest::post().uri("/sessions/refresh");
+        if let Some(token) = token {
+            req = req.insert_header(("Authorization", format!("Bearer {}", token)));
+        }
+        if let Some(session_token) = session_token {
+            req = req.cookie(Cookie::new(SESSION_COOKIE_NAME, session_token.to_string()));
+        }
+        req
+    }
+    
+    #[actix_web::test]
+    async fn test_refresh_issues_token_for_authenticated_session() {
+        let registry = ClientRegistryActor::new().start();
+        let (client_id, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let (_, anonymous_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let token = authenticate(&registry, &session_token).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(registry.clone()))
+                .app_data(web::Data::new(Config::default()))
+                .service(refresh_session)
+        ).await;
+        
+        // An hour-old token that expired ten minutes ago is still within the grace window
+        let now = chrono::Utc::now().timestamp() as usize;
+        let claims = common::utils::JwtClaims {
+            sub: client_id.to_string(),
+            wallet: KEY_ONE_ADDRESS.to_string(),
+            iat: now - 3600,
+            exp: now - 600,
+            jti: Uuid::new_v4().to_string(),
+        };
+        let expired = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(Config::default().jwt_secret.as_bytes()),
+        ).unwrap();
+        registry.send(RecordIssuedToken {
+            session_token: session_token.clone(),
+            jti: claims.jti,
+            expires_at: chrono::DateTime::from_timestamp(claims.exp as i64, 0).unwrap(),
+        }).await.unwrap();
+        
+        for req in [refresh_request(Some(&token), None), refresh_request(Some(&expired), None), refresh_request(None, Some(&session_token))] {
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req.to_request()).await;
+            let refreshed = body["token"].as_str().unwrap();
+            let (token_client, wallet) = common::utils::validate_jwt_token(refreshed, Config::default().jwt_secret.as_bytes()).unwrap();
+            assert_eq!(token_client, client_id);
+            assert_eq!(wallet, KEY_ONE_ADDRESS);
+        }
+        
+        // Anonymous sessions have nothing to refresh
+        let req = refresh_request(None, Some(&anonymous_token)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+    }
+    
+        #[actix_web::test]
+    async fn test_refresh_is_tracked_against_the_issuing_session() {
+        let registry = ClientRegistryActor::new().start();
+        let (client_id, first_session) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let second_session = registry.send(CreateClientSession { client_id }).await.unwrap();
+        let token = authenticate(&registry, &first_session).await;
+        authenticate(&registry, &second_session).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(registry))
+                .app_data(web::Data::new(Config::default()))
+                .service(invalidate_session)
+                .service(refresh_session)
+                .service(protected_endpoint)
+        ).await;
+        
+        // Refreshed through the older session, while a newer one is signed in too
+        let body: serde_json::Value = test::call_and_read_body_json(&app, refresh_request(Some(&token), None).to_request()).await;
+        let refreshed = body["token"].as_str().unwrap().to_string();
+        
+        let req = test::TestRequest::delete()
+            .uri("/client/session")
+            .cookie(Cookie::new(SESSION_COOKIE_NAME, first_session))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        
+        // Logging out of the issuing session revokes the refreshed token too
+        let req = test::TestRequest::get()
+            .uri("/protected")
+            .insert_header(("Authorization", format!("Bearer {}", refreshed)))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 401);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"], "Token revoked");
+    }
+    
+#[actix_web::test]
+    async fn test_wallet_lists_only_its_own_sessions() {
+        let registry = ClientRegistryActor::new().start();
+        let mut tokens = Vec::new();
+        let mut client_ids = Vec::new();
+        for _ in 0..2 {
+            let (client_id, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+            tokens.push(authenticate(&registry, &session_token).await);
+            client_ids.push(client_id);
+        }
+        // Someone else's wallet, and a session that never signed in
+        let (_, other_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        registry.send(UpdateClientSession {
+            session_token: other_token,
+            is_authenticated: Some(true),
+            wallet_address: Some(Some(ADDRESS.to_string())),
+            metadata: None,
+            extend_ttl: true,
+        }).await.unwrap();
+        registry.send(RegisterAnonymousClient).await.unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(registry))
+                .app_data(web::Data::new(Config::default()))
+                .service(list_my_sessions)
+        ).await;
+        
+        let req = test::TestRequest::get()
+            .uri("/me/sessions")
+            .insert_header(("Authorization", format!("Bearer {}", tokens[1])))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let sessions = body["sessions"].as_array().unwrap();
+        assert_eq!(sessions.len(), 2);
+        for (session, client_id) in sessions.iter().zip(&client_ids) {
+            assert_eq!(session["client_id"], client_id.to_string());
+            assert_eq!(session["wallet_address"], KEY_ONE_ADDRESS);
+        }
+        
+        let req = test::TestRequest::get().uri("/me/sessions").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+    }
+    
+    #[actix_web::test]
+    async fn test_logged_out_session_cannot_be_refreshed() {
+        let registry = ClientRegistryActor::new().start();
+        let (_, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let token = authenticate(&registry, &session_token).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(registry))
+                .app_data(web::Data::new(Config::default()))
+                .service(invalidate_session)
+                .service(refresh_session)
+        ).await;
+        
+        let req = test::TestRequest::delete()
+            .uri("/client/session")
+            .cookie(Cookie::new(SESSION_COOKIE_NAME, session_token.clone()))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        
+        for req in [refresh_request(Some(&token), None), refresh_request(None, Some(&session_token))] {
+            assert_eq!(test::call_service(&app, req.to_request()).await.status(), 401);
+        }
+    }
+    
+    #[actix_web::test]
+    async fn test_logout_revokes_issued_token() {
+        let registry = ClientRegistryActor::new().start();
+        let (_, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let token = authenticate(&registry, &session_token).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(registry))
+                .app_data(web::Data::new(Config::default()))
+                .service(invalidate_session)
+                .service(protected_endpoint)
+        ).await;
+        let protected = || test::TestRequest::get()
+            .uri("/protected")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+        
+        assert_eq!(test::call_service(&app, protected()).await.status(), 200);
+        
+        let req = test::TestRequest::delete()
+            .uri("/client/session")
+            .cookie(Cookie::new(SESSION_COOKIE_NAME, session_token))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        
+        let res = test::call_service(&app, protected()).await;
+        assert_eq!(res.status(), 401);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"], "Token revoked");
+    }
+    
+    #[actix_web::test]
+    async fn test_logout_revokes_token_signed_with_private_key_only() {
+        // A signing-only RS256 deployment cannot read its own tokens back
+        let config = Config {
+            jwt_algorithm: common::utils::JwtAlgorithm::Rs256,
+            jwt_private_key: Some(include_str!("../../../common/testdata/jwt_rs256_private.pem").to_string()),
+            jwt_public_key: None,
+            ..Config::default()
+        };
+        let verifier = Config {
+            jwt_private_key: None,
+            jwt_public_key: Some(include_str!("../../../common/testdata/jwt_rs256_public.pem").to_string()),
+            ..config.clone()
+        };
+        let registry = ClientRegistryActor::new().start();
+        let (_, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        authenticate(&registry, &session_token).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(registry.clone()))
+                .app_data(web::Data::new(config))
+                .service(invalidate_session)
+                .service(refresh_session)
+        ).await;
+        
+        let body: serde_json::Value = test::call_and_read_body_json(&app, refresh_request(None, Some(&session_token)).to_request()).await;
+        let claims = verifier.validate_jwt(body["token"].as_str().unwrap(), 0).unwrap();
+        
+        let req = test::TestRequest::delete()
+            .uri("/client/session")
+            .cookie(Cookie::new(SESSION_COOKIE_NAME, session_token))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        assert!(registry.send(IsTokenRevoked { jti: claims.jti }).await.unwrap());
+    }
+    
+    #[actix_web::test]
+    async fn test_token_is_rejected_under_a_different_secret() {
+        let signing_config = Config { jwt_secret: "first_secret".to_string(), ..Config::default() };
+        let registry = ClientRegistryActor::new().start();
+        let (_, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(registry))
+                .app_data(web::Data::new(signing_config.clone()))
+                .service(siwe_challenge)
+                .service(upgrade_session)
+        ).await;
+        
+        let challenge: serde_json::Value =
+            test::call_and_read_body_json(&app, challenge_request(&session_token).to_request()).await;
+        let signature = personal_sign(&key_one(), challenge["message"].as_str().unwrap());
+        let req = upgrade_request(&session_token, &signature, challenge["nonce"].as_str().unwrap()).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let token = body["token"].as_str().unwrap().to_string();
+        
+        let other_config = Config { jwt_secret: "second_secret".to_string(), ..Config::default() };
+        for (config, expected) in [(signing_config, 200), (other_config, 401)] {
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(ClientRegistryActor::new().start()))
+                    .app_data(web::Data::new(config))
+                    .service(protected_endpoint)
+            ).await;
+            let req = test::TestRequest::get()
+                .uri("/protected")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), expected);
+        }
+    }
+    
+    // Create a client under `config`, returning whether the session cookie is Secure
+    async fn session_cookie_secure(config: Config, forwarded_proto: Option<&str>) -> bool {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(ClientRegistryActor::new().start()))
+                .app_data(web::Data::new(config))
+                .service(create_client)
+        ).await;
+        let mut req = test::TestRequest::post().uri("/client");
+        if let Some(proto) = forwarded_proto {
+            req = req.insert_header(("X-Forwarded-Proto", proto));
+        }
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), 200);
+        let cookie = res.response().cookies().find(|c| c.name() == SESSION_COOKIE_NAME).unwrap();
+        cookie.secure().unwrap_or(false)
+    }
+    
+    #[actix_web::test]
+    async fn test_session_cookie_is_secure_only_over_https() {
+        assert!(!session_cookie_secure(Config::default(), None).await);
+        assert!(!session_cookie_secure(Config::default(), Some("http")).await);
+        assert!(session_cookie_secure(Config::default(), Some("https")).await);
+    }
+    
+    #[actix_web::test]
+    async fn test_explicit_secure_cookies_setting_wins() {
+        let always = Config { secure_cookies: Some(true), ..Config::default() };
+        let never = Config { secure_cookies: Some(false), ..Config::default() };
+        assert!(session_cookie_secure(always, None).await);
+        assert!(!session_cookie_secure(never, Some("https")).await);
+    }
+}
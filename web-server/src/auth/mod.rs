@@ -1,4 +1,4 @@
-// Web Server - auth/mod.rs (empty placeholder for Phase 1)
+// Web Server - auth/mod.rs
 // my-actix-system/web-server/src/auth/mod.rs
-// Phase 1 doesn't implement authentication yet
-// This is a placeholder for Phase 3
\ No newline at end of file
+// Phase 3 authentication: Sign-In With Ethereum
+pub mod siwe;
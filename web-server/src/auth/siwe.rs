@@ -0,0 +1,190 @@
+// web-server/src/auth/siwe.rs
+// Sign-In With Ethereum (EIP-4361) messages
+use chrono::{DateTime, SecondsFormat, Utc};
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use sha3::{Digest, Keccak256};
+use std::fmt;
+
+/// Statement shown to the user in their wallet when signing in
+pub const SIGN_IN_STATEMENT: &str = "Sign in to Agent Bridge Platform";
+
+/// A SIWE message for a wallet to sign
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiweMessage {
+    pub domain: String,
+    pub address: String,
+    pub statement: String,
+    pub uri: String,
+    pub chain_id: u64,
+    pub nonce: String,
+    pub issued_at: DateTime<Utc>,
+    pub expiration_time: DateTime<Utc>,
+}
+
+/// Reasons a signature cannot be attributed to an address
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum SignatureError {
+    #[error("signature is not 65 hex-encoded bytes")]
+    Malformed,
+    #[error("signature recovery id is invalid")]
+    InvalidRecoveryId,
+    #[error("no public key recovers from this signature")]
+    RecoveryFailed,
+}
+
+/// Whether `address` is a 0x-prefixed, 20-byte hex Ethereum address
+pub fn is_valid_address(address: &str) -> bool {
+    address.strip_prefix("0x")
+        .is_some_and(|hex| hex.len() == 40 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Hash a message the way `personal_sign` does (EIP-191)
+pub fn personal_message_hash(message: &str) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.update(format!("\x19Ethereum Signed Message:\n{}", message.len()));
+    hasher.update(message);
+    hasher.finalize().into()
+}
+
+/// Ethereum address of a public key: the last 20 bytes of the keccak hash of
+/// its uncompressed encoding, lowercase hex
+pub fn address_of(key: &VerifyingKey) -> String {
+    let point = key.to_encoded_point(false);
+    let hash = Keccak256::digest(&point.as_bytes()[1..]);
+    format!("0x{}", hex::encode(&hash[12..]))
+}
+
+/// Recover the address that produced a `personal_sign` signature of `message`.
+/// The signature is 65 bytes `r || s || v`, hex-encoded with or without 0x;
+/// `v` may be 27/28 or 0/1.
+pub fn recover_address(message: &str, signature: &str) -> Result<String, SignatureError> {
+    let bytes = hex::decode(signature.trim_start_matches("0x"))
+        .map_err(|_| SignatureError::Malformed)?;
+    if bytes.len() != 65 {
+        return Err(SignatureError::Malformed);
+    }
+    
+    let signature = Signature::from_slice(&bytes[..64]).map_err(|_| SignatureError::Malformed)?;
+    let v = bytes[64];
+    let recovery_id = RecoveryId::from_byte(if v >= 27 { v - 27 } else { v })
+        .ok_or(SignatureError::InvalidRecoveryId)?;
+    
+    let key = VerifyingKey::recover_from_prehash(&personal_message_hash(message), &signature, recovery_id)
+        .map_err(|_| SignatureError::RecoveryFailed)?;
+    Ok(address_of(&key))
+}
+
+impl SiweMessage {
+    /// Whether the message's validity window has passed
+    pub fn is_expired(&self) -> bool {
+        Utc::now() >= self.expiration_time
+    }
+    
+    /// Whether `signature` is this message signed by the message's address
+    pub fn verify(&self, signature: &str) -> Result<bool, SignatureError> {
+        let signer = recover_address(&self.to_string(), signature)?;
+        Ok(signer.eq_ignore_ascii_case(&self.address))
+    }
+}
+
+// EIP-4361 timestamps are RFC 3339
+fn timestamp(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+impl fmt::Display for SiweMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} wants you to sign in with your Ethereum account:", self.domain)?;
+        writeln!(f, "{}", self.address)?;
+        writeln!(f)?;
+        writeln!(f, "{}", self.statement)?;
+        writeln!(f)?;
+        writeln!(f, "URI: {}", self.uri)?;
+        writeln!(f, "Version: 1")?;
+        writeln!(f, "Chain ID: {}", self.chain_id)?;
+        writeln!(f, "Nonce: {}", self.nonce)?;
+        writeln!(f, "Issued At: {}", timestamp(&self.issued_at))?;
+        write!(f, "Expiration Time: {}", timestamp(&self.expiration_time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::test_support::{key_one, personal_sign, KEY_ONE_ADDRESS};
+
+    #[test]
+    fn test_message_follows_eip_4361_layout() {
+        let issued_at = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        let message = SiweMessage {
+            domain: "example.com".to_string(),
+            address: "0x0000000000000000000000000000000000000001".to_string(),
+            statement: SIGN_IN_STATEMENT.to_string(),
+            uri: "https://example.com".to_string(),
+            chain_id: 1,
+            nonce: "abc123XYZ".to_string(),
+            issued_at,
+            expiration_time: issued_at + chrono::Duration::minutes(5),
+        };
+
+        assert_eq!(message.to_string(), "\
+example.com wants you to sign in with your Ethereum account:
+0x0000000000000000000000000000000000000001
+
+Sign in to Agent Bridge Platform
+
+URI: https://example.com
+Version: 1
+Chain ID: 1
+Nonce: abc123XYZ
+Issued At: 2024-01-02T03:04:05Z
+Expiration Time: 2024-01-02T03:09:05Z");
+    }
+
+    #[test]
+    fn test_recovers_signer_address() {
+        let key = key_one();
+        assert!(address_of(key.verifying_key()).eq_ignore_ascii_case(KEY_ONE_ADDRESS));
+
+        let signature = personal_sign(&key, "hello");
+        let recovered = recover_address("hello", &signature).unwrap();
+        assert!(recovered.eq_ignore_ascii_case(KEY_ONE_ADDRESS));
+    }
+
+    #[test]
+    fn test_signature_over_other_message_does_not_verify() {
+        let issued_at = Utc::now();
+        let message = SiweMessage {
+            domain: "example.com".to_string(),
+            address: KEY_ONE_ADDRESS.to_string(),
+            statement: SIGN_IN_STATEMENT.to_string(),
+            uri: "https://example.com".to_string(),
+            chain_id: 1,
+            nonce: "abc123XYZ".to_string(),
+            issued_at,
+            expiration_time: issued_at + chrono::Duration::minutes(5),
+        };
+
+        assert_eq!(message.verify(&personal_sign(&key_one(), &message.to_string())), Ok(true));
+        assert_eq!(message.verify(&personal_sign(&key_one(), "something else")), Ok(false));
+    }
+
+    #[test]
+    fn test_malformed_signatures_are_rejected() {
+        assert_eq!(recover_address("hello", "0x1234"), Err(SignatureError::Malformed));
+        assert_eq!(recover_address("hello", "not hex"), Err(SignatureError::Malformed));
+
+        let mut signature = personal_sign(&key_one(), "hello");
+        signature.replace_range(signature.len() - 2.., "05");
+        assert_eq!(recover_address("hello", &signature), Err(SignatureError::InvalidRecoveryId));
+    }
+
+    #[test]
+    fn test_address_validation() {
+        assert!(is_valid_address("0xAbCdEf0123456789abcdef0123456789ABCDEF01"));
+        assert!(!is_valid_address("AbCdEf0123456789abcdef0123456789ABCDEF01"));
+        assert!(!is_valid_address("0x1234"));
+        assert!(!is_valid_address("0xZZCdEf0123456789abcdef0123456789ABCDEF01"));
+    }
+}
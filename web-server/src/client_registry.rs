@@ -1,21 +1,35 @@
 // web-server/src/client_registry.rs
 use actix::{Actor, Context, Handler, Message, Addr, AsyncContext, MessageResult};
-use chrono::Utc;
-use common::models::session::{ClientSession, SessionResult};
+use chrono::{DateTime, Utc};
+use common::models::session::{ClientSession, ClientSessionResponse, SessionResult};
 use dashmap::DashMap;
 use std::sync::Arc;
 use std::time::Duration;
 use uuid::Uuid;
-use crate::utils::token::create_session_token;
+use crate::auth::siwe::{SiweMessage, SIGN_IN_STATEMENT};
+use crate::session_store::{InMemorySessionStore, SessionStore};
+use crate::utils::token::{create_session_token, generate_nonce, is_well_formed_session_token};
 
 // Default session TTL in seconds (24 hours)
 const DEFAULT_SESSION_TTL: i64 = 86400;
+// Default number of concurrent sessions a single client may hold
+const DEFAULT_MAX_SESSIONS_PER_CLIENT: usize = 5;
+// Default lifetime of a sign-in nonce in seconds (5 minutes)
+const DEFAULT_NONCE_TTL: i64 = 300;
 
 /// Actor message: Register a new anonymous client
 #[derive(Message)]
 #[rtype(result = "(Uuid, String)")]
 pub struct RegisterAnonymousClient;
 
+/// Actor message: Create an additional session for an existing client.
+/// Returns the new session token.
+#[derive(Message)]
+#[rtype(result = "String")]
+pub struct CreateClientSession {
+    pub client_id: Uuid,
+}
+
 /// Actor message: Get a client session by session token
 #[derive(Message)]
 #[rtype(result = "SessionResult")]
@@ -30,6 +44,14 @@ pub struct GetClientSessionById {
     pub client_id: Uuid,
 }
 
+/// Actor message: Get the client session a JWT was issued for, by the
+/// token's id
+#[derive(Message)]
+#[rtype(result = "SessionResult")]
+pub struct GetClientSessionByTokenId {
+    pub jti: String,
+}
+
 /// Actor message: Update client session activity
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -37,7 +59,8 @@ pub struct UpdateSessionActivity {
     pub session_token: String,
 }
 
-/// Actor message: Invalidate/remove a client session
+/// Actor message: Invalidate/remove a client session, revoking the JWTs
+/// issued for it
 #[derive(Message)]
 #[rtype(result = "bool")]
 pub struct InvalidateClientSession {
@@ -55,11 +78,65 @@ pub struct UpdateClientSession {
     pub extend_ttl: bool,
 }
 
+/// Actor message: Issue a SIWE sign-in challenge with a fresh nonce for a
+/// session, replacing any outstanding one. Returns None when the session is
+/// missing or expired.
+#[derive(Message)]
+#[rtype(result = "Option<SiweMessage>")]
+pub struct IssueChallenge {
+    pub session_token: String,
+    pub domain: String,
+    pub uri: String,
+    pub address: String,
+    pub chain_id: u64,
+}
+
+/// Actor message: Consume a session's sign-in challenge. Returns the challenge
+/// when `nonce` is its nonce and it has not expired; either way it cannot be
+/// used again.
+#[derive(Message)]
+#[rtype(result = "Option<SiweMessage>")]
+pub struct ConsumeChallenge {
+    pub session_token: String,
+    pub nonce: String,
+}
+
+/// Actor message: Remember a JWT issued for a session, so invalidating the
+/// session revokes it
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RecordIssuedToken {
+    pub session_token: String,
+    pub jti: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Actor message: Whether the JWT with id `jti` has been revoked
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct IsTokenRevoked {
+    pub jti: String,
+}
+
 /// Actor message: Clean up expired sessions
 #[derive(Message)]
 #[rtype(result = "usize")]
 pub struct CleanupExpiredSessions;
 
+/// Actor message: Client ids holding at least one unexpired session, for
+/// reconciling against the websocket-server's live connections
+#[derive(Message)]
+#[rtype(result = "Vec<Uuid>")]
+pub struct GetAllClientIds;
+
+/// Actor message: Unexpired sessions signed in with a wallet, oldest first,
+/// so a user can see where they are logged in
+#[derive(Message)]
+#[rtype(result = "Vec<ClientSessionResponse>")]
+pub struct GetSessionsByWallet {
+    pub wallet_address: String,
+}
+
 /// Actor message: Get session metrics
 #[derive(Message)]
 #[rtype(result = "SessionMetrics")]
@@ -77,12 +154,24 @@ pub struct SessionMetrics {
 
 /// ClientRegistryActor for managing client sessions
 pub struct ClientRegistryActor {
-    // Map from session token to session data
-    sessions: Arc<DashMap<String, ClientSession>>,
-    // Map from client ID to session token
-    client_lookup: Arc<DashMap<Uuid, String>>,
+    // Session data by session token
+    sessions: Box<dyn SessionStore>,
+    // Map from client ID to its session tokens, oldest first
+    client_lookup: Arc<DashMap<Uuid, Vec<String>>>,
+    // Outstanding sign-in challenge per session token
+    challenges: DashMap<String, SiweMessage>,
+    // JWT ids (and expiry) issued per session token
+    issued_tokens: DashMap<String, Vec<(String, DateTime<Utc>)>>,
+    // Session token each tracked JWT id was issued for
+    token_sessions: DashMap<String, String>,
+    // Revoked JWT ids, kept until the token would have expired anyway
+    revoked_tokens: DashMap<String, DateTime<Utc>>,
     // Session TTL in seconds
     session_ttl: i64,
+    // Sign-in nonce TTL in seconds
+    nonce_ttl: i64,
+    // Maximum concurrent sessions per client; the oldest is evicted beyond this
+    max_sessions_per_client: usize,
     // Cleanup interval in seconds
     cleanup_interval: u64,
     // Metrics
@@ -98,9 +187,15 @@ impl Default for ClientRegistryActor {
 impl ClientRegistryActor {
     pub fn new() -> Self {
         Self {
-            sessions: Arc::new(DashMap::new()),
+            sessions: Box::new(InMemorySessionStore::new()),
             client_lookup: Arc::new(DashMap::new()),
+            challenges: DashMap::new(),
+            issued_tokens: DashMap::new(),
+            token_sessions: DashMap::new(),
+            revoked_tokens: DashMap::new(),
             session_ttl: DEFAULT_SESSION_TTL,
+            nonce_ttl: DEFAULT_NONCE_TTL,
+            max_sessions_per_client: DEFAULT_MAX_SESSIONS_PER_CLIENT,
             cleanup_interval: 3600, // Run cleanup every hour
             metrics: SessionMetrics {
                 total_sessions: 0,
@@ -122,14 +217,131 @@ impl ClientRegistryActor {
         self
     }
     
+    pub fn with_max_sessions_per_client(mut self, max_sessions: usize) -> Self {
+        self.max_sessions_per_client = max_sessions.max(1);
+        self
+    }
+    
+    /// Keep sessions in `store`. Sessions it already holds, e.g. from before a
+    /// restart, are indexed by client, oldest first.
+    pub fn with_session_store(mut self, mut store: Box<dyn SessionStore>) -> Self {
+        let mut existing = store.values();
+        existing.sort_by_key(|session| session.created_at);
+        self.client_lookup.clear();
+        for session in existing {
+            self.client_lookup.entry(session.client_id).or_default().push(session.session_token);
+        }
+        self.sessions = store;
+        self.update_metrics();
+        self
+    }
+    
+    /// Create a new anonymous session for `client_id`, evicting the client's
+    /// oldest sessions if it would exceed the per-client limit
+    fn add_session(&mut self, client_id: Uuid) -> String {
+        // Use the secure token generator
+        let session_token = create_session_token();
+        let session = ClientSession::new_anonymous(client_id, session_token.clone());
+        
+        // Store session data
+        self.sessions.insert(session, self.session_ttl);
+        
+        let evicted: Vec<String> = {
+            let mut tokens = self.client_lookup.entry(client_id).or_default();
+            tokens.push(session_token.clone());
+            let excess = tokens.len().saturating_sub(self.max_sessions_per_client);
+            tokens.drain(..excess).collect()
+        };
+        
+        // Update metrics
+        self.metrics.anonymous_sessions += 1;
+        self.metrics.total_sessions += 1;
+        
+        for token in evicted {
+            tracing::info!("Client {} exceeded {} sessions, evicting oldest", client_id, self.max_sessions_per_client);
+            self.remove_session(&token);
+        }
+        
+        session_token
+    }
+    
+    /// Remove a session and its client lookup entry, revoking the JWTs
+    /// issued for it and updating metrics
+    fn remove_session(&mut self, session_token: &str) -> Option<ClientSession> {
+        let session = self.sessions.remove(session_token)?;
+        self.forget_token(&session.client_id, session_token);
+        self.challenges.remove(session_token);
+        self.revoke_issued_tokens(session_token);
+        
+        if session.is_authenticated {
+            self.metrics.authenticated_sessions -= 1;
+        } else {
+            self.metrics.anonymous_sessions -= 1;
+        }
+        self.metrics.total_sessions -= 1;
+        
+        Some(session)
+    }
+    
+    /// Revoke every JWT issued for a session, so none outlives it
+    fn revoke_issued_tokens(&self, session_token: &str) {
+        if let Some((_, issued)) = self.issued_tokens.remove(session_token) {
+            for (jti, expires_at) in issued {
+                self.token_sessions.remove(&jti);
+                self.revoked_tokens.insert(jti, expires_at);
+            }
+        }
+    }
+    
+    /// Drop a token from a client's lookup entry, removing the entry once empty
+    fn forget_token(&self, client_id: &Uuid, session_token: &str) {
+        if let Some(mut tokens) = self.client_lookup.get_mut(client_id) {
+            tokens.retain(|token| token != session_token);
+        }
+        self.client_lookup.remove_if(client_id, |_, tokens| tokens.is_empty());
+    }
+    
+    /// Find the session a client's token refers to. A token we could not
+    /// have issued, or one whose stored session names a different token
+    /// (a tampered or corrupt store entry, which is dropped), is `Invalid`
+    fn find_session(&mut self, session_token: &str) -> SessionResult {
+        if !is_well_formed_session_token(session_token) {
+            tracing::warn!("Rejected malformed session token");
+            return SessionResult::Invalid;
+        }
+        let Some(session) = self.sessions.get(session_token) else {
+            tracing::debug!("Session not found for token: {}", session_token);
+            return SessionResult::NotFound;
+        };
+        if session.session_token != session_token {
+            tracing::warn!("Stored session for client {} does not match its token, dropping it", session.client_id);
+            self.remove_session(session_token);
+            return SessionResult::Invalid;
+        }
+        SessionResult::Success(session)
+    }
+    
+    /// Record activity on a looked up session, unless it has expired
+    fn touch(&mut self, mut session: ClientSession) -> SessionResult {
+        if session.is_expired(self.session_ttl) {
+            tracing::debug!("Session expired: {}", session.client_id);
+            return SessionResult::Expired;
+        }
+        
+        session.update_activity();
+        self.sessions.insert(session.clone(), self.session_ttl);
+        
+        tracing::debug!("Retrieved session for client: {}", session.client_id);
+        SessionResult::Success(session)
+    }
+    
     /// Update session metrics
     fn update_metrics(&mut self) {
         let mut anonymous_count = 0;
         let mut authenticated_count = 0;
         let mut age_sum = 0.0;
         
-        for entry in self.sessions.iter() {
-            let session = entry.value();
+        for session in self.sessions.values() {
             if session.is_authenticated {
                 authenticated_count += 1;
             } else {
@@ -158,26 +370,47 @@ impl ClientRegistryActor {
         let mut expired_count = 0;
         
         // Collect expired session tokens
-        let expired_tokens: Vec<String> = self.sessions.iter()
-            .filter_map(|entry| {
-                let session = entry.value();
-                let age = now.signed_duration_since(session.last_active);
-                if age.num_seconds() > self.session_ttl {
-                    Some(session.session_token.clone())
-                } else {
-                    None
-                }
-            })
+        let expired_tokens: Vec<String> = self.sessions.iter_expired(self.session_ttl)
+            .map(|session| session.session_token)
             .collect();
         
         // Remove expired sessions
         for token in expired_tokens {
-            if let Some(session) = self.sessions.remove(&token) {
-                self.client_lookup.remove(&session.1.client_id);
+            if self.remove_session(&token).is_some() {
                 expired_count += 1;
             }
         }
         
+        // A store may expire sessions on its own, so drop tokens it no longer
+        // has and revoke the JWTs issued for them
+        let sessions = &mut self.sessions;
+        self.client_lookup.retain(|_, tokens| {
+            tokens.retain(|token| sessions.get(token).is_some());
+            !tokens.is_empty()
+        });
+        let orphaned: Vec<String> = self.issued_tokens.iter()
+            .map(|entry| entry.key().clone())
+            .filter(|token| self.sessions.get(token).is_none())
+            .collect();
+        for token in orphaned {
+            self.revoke_issued_tokens(&token);
+        }
+        
+        // Drop challenges that were never answered
+        self.challenges.retain(|_, challenge| !challenge.is_expired());
+        
+        // Expired JWTs are rejected regardless, so stop tracking them
+        for mut tokens in self.issued_tokens.iter_mut() {
+            tokens.retain(|(jti, expires_at)| {
+                let live = *expires_at > now;
+                if !live {
+                    self.token_sessions.remove(jti);
+                }
+                live
+            });
+        }
+        self.revoked_tokens.retain(|_, expires_at| *expires_at > now);
+        
         // Update metrics
         self.metrics.expired_count += expired_count;
         self.update_metrics();
@@ -216,18 +449,7 @@ impl Handler<RegisterAnonymousClient> for ClientRegistryActor {
     
     fn handle(&mut self, _msg: RegisterAnonymousClient, _ctx: &mut Self::Context) -> Self::Result {
         let client_id = Uuid::new_v4();
-        // Use the secure token generator
-        let session_token = create_session_token();
-        
-        let session = ClientSession::new_anonymous(client_id, session_token.clone());
-        
-        // Store session data
-        self.sessions.insert(session_token.clone(), session);
-        self.client_lookup.insert(client_id, session_token.clone());
-        
-        // Update metrics
-        self.metrics.anonymous_sessions += 1;
-        self.metrics.total_sessions += 1;
+        let session_token = self.add_session(client_id);
         
         tracing::info!("Registered new anonymous client: {}", client_id);
         
@@ -235,28 +457,25 @@ impl Handler<RegisterAnonymousClient> for ClientRegistryActor {
     }
 }
 
+// Handle creation of an additional session for an existing client
+impl Handler<CreateClientSession> for ClientRegistryActor {
+    type Result = String;
+    
+    fn handle(&mut self, msg: CreateClientSession, _ctx: &mut Self::Context) -> Self::Result {
+        let session_token = self.add_session(msg.client_id);
+        tracing::info!("Created additional session for client: {}", msg.client_id);
+        session_token
+    }
+}
+
 // Handle retrieval of a client session by token
 impl Handler<GetClientSession> for ClientRegistryActor {
     type Result = MessageResult<GetClientSession>;
     
     fn handle(&mut self, msg: GetClientSession, _ctx: &mut Self::Context) -> Self::Result {
-        let result = if let Some(mut entry) = self.sessions.get_mut(&msg.session_token) {
-            let session = entry.value_mut();
-            
-            // Check if session has expired
-            if session.is_expired(self.session_ttl) {
-                tracing::debug!("Session expired: {}", session.client_id);
-                SessionResult::Expired
-            } else {
-                // Update activity timestamp
-                session.update_activity();
-                
-                tracing::debug!("Retrieved session for client: {}", session.client_id);
-                SessionResult::Success(session.clone())
-            }
-        } else {
-            tracing::debug!("Session not found for token: {}", msg.session_token);
-            SessionResult::NotFound
+        let result = match self.find_session(&msg.session_token) {
+            SessionResult::Success(session) => self.touch(session),
+            other => other,
         };
         
         MessageResult(result)
@@ -268,23 +487,13 @@ impl Handler<GetClientSessionById> for ClientRegistryActor {
     type Result = MessageResult<GetClientSessionById>;
     
     fn handle(&mut self, msg: GetClientSessionById, _ctx: &mut Self::Context) -> Self::Result {
-        let result = if let Some(token_entry) = self.client_lookup.get(&msg.client_id) {
-            let token = token_entry.value();
-            
-            if let Some(mut session_entry) = self.sessions.get_mut(token) {
-                let session = session_entry.value_mut();
-                
-                // Check if session has expired
-                if session.is_expired(self.session_ttl) {
-                    tracing::debug!("Session expired: {}", session.client_id);
-                    SessionResult::Expired
-                } else {
-                    // Update activity timestamp
-                    session.update_activity();
-                    
-                    tracing::debug!("Retrieved session for client: {}", session.client_id);
-                    SessionResult::Success(session.clone())
-                }
+        // The most recent session represents the client
+        let newest_token = self.client_lookup.get(&msg.client_id)
+            .and_then(|tokens| tokens.last().cloned());
+        
+        let result = if let Some(token) = newest_token {
+            if let Some(session) = self.sessions.get(&token) {
+                self.touch(session)
             } else {
                 SessionResult::NotFound
             }
@@ -297,14 +506,37 @@ impl Handler<GetClientSessionById> for ClientRegistryActor {
     }
 }
 
+// Handle retrieval of the client session a JWT was issued for
+impl Handler<GetClientSessionByTokenId> for ClientRegistryActor {
+    type Result = MessageResult<GetClientSessionByTokenId>;
+    
+    fn handle(&mut self, msg: GetClientSessionByTokenId, _ctx: &mut Self::Context) -> Self::Result {
+        let session_token = self.token_sessions.get(&msg.jti).map(|entry| entry.value().clone());
+        
+        let result = match session_token {
+            Some(session_token) => match self.find_session(&session_token) {
+                SessionResult::Success(session) => self.touch(session),
+                other => other,
+            },
+            None => {
+                tracing::debug!("No session tracked for token: {}", msg.jti);
+                SessionResult::NotFound
+            },
+        };
+        
+        MessageResult(result)
+    }
+}
+
 // Handle session activity updates
 impl Handler<UpdateSessionActivity> for ClientRegistryActor {
     type Result = ();
     
     fn handle(&mut self, msg: UpdateSessionActivity, _ctx: &mut Self::Context) -> Self::Result {
-        if let Some(mut entry) = self.sessions.get_mut(&msg.session_token) {
-            entry.value_mut().update_activity();
-            tracing::trace!("Updated activity for session: {}", entry.value().client_id);
+        if let Some(mut session) = self.sessions.get(&msg.session_token) {
+            session.update_activity();
+            tracing::trace!("Updated activity for session: {}", session.client_id);
+            self.sessions.insert(session, self.session_ttl);
         }
     }
 }
@@ -314,17 +546,7 @@ impl Handler<InvalidateClientSession> for ClientRegistryActor {
     type Result = MessageResult<InvalidateClientSession>;
     
     fn handle(&mut self, msg: InvalidateClientSession, _ctx: &mut Self::Context) -> Self::Result {
-        let result = if let Some((_, session)) = self.sessions.remove(&msg.session_token) {
-            self.client_lookup.remove(&session.client_id);
-            
-            // Update metrics
-            if session.is_authenticated {
-                self.metrics.authenticated_sessions -= 1;
-            } else {
-                self.metrics.anonymous_sessions -= 1;
-            }
-            self.metrics.total_sessions -= 1;
-            
+        let result = if let Some(session) = self.remove_session(&msg.session_token) {
             tracing::info!("Invalidated session for client: {}", session.client_id);
             true
         } else {
@@ -340,14 +562,13 @@ impl Handler<UpdateClientSession> for ClientRegistryActor {
     type Result = MessageResult<UpdateClientSession>;
     
     fn handle(&mut self, msg: UpdateClientSession, _ctx: &mut Self::Context) -> Self::Result {
-        let result = if let Some(mut entry) = self.sessions.get_mut(&msg.session_token) {
-            let session = entry.value_mut();
-            
+        let result = match self.find_session(&msg.session_token) {
             // Check if session has expired
-            if session.is_expired(self.session_ttl) {
+            SessionResult::Success(session) if session.is_expired(self.session_ttl) => {
                 tracing::debug!("Session expired: {}", session.client_id);
                 SessionResult::Expired
-            } else {
+            },
+            SessionResult::Success(mut session) => {
                 // Track authentication status change for metrics
                 let was_authenticated = session.is_authenticated;
                 
@@ -381,17 +602,80 @@ impl Handler<UpdateClientSession> for ClientRegistryActor {
                 }
                 
                 tracing::debug!("Updated session for client: {}", session.client_id);
-                SessionResult::Success(session.clone())
-            }
-        } else {
-            tracing::debug!("Session not found for token: {}", msg.session_token);
-            SessionResult::NotFound
+                self.sessions.insert(session.clone(), self.session_ttl);
+                SessionResult::Success(session)
+            },
+            other => other,
         };
         
         MessageResult(result)
     }
 }
 
+// Handle sign-in challenge issuance
+impl Handler<IssueChallenge> for ClientRegistryActor {
+    type Result = Option<SiweMessage>;
+    
+    fn handle(&mut self, msg: IssueChallenge, _ctx: &mut Self::Context) -> Self::Result {
+        let session = self.sessions.get(&msg.session_token)?;
+        if session.is_expired(self.session_ttl) {
+            tracing::debug!("Refusing challenge for expired session: {}", session.client_id);
+            return None;
+        }
+        
+        let issued_at = Utc::now();
+        let challenge = SiweMessage {
+            domain: msg.domain,
+            address: msg.address,
+            statement: SIGN_IN_STATEMENT.to_string(),
+            uri: msg.uri,
+            chain_id: msg.chain_id,
+            // SIWE nonces are alphanumeric, so drop the separator
+            nonce: generate_nonce().replace('-', ""),
+            issued_at,
+            expiration_time: issued_at + chrono::Duration::seconds(self.nonce_ttl),
+        };
+        self.challenges.insert(msg.session_token, challenge.clone());
+        
+        tracing::debug!("Issued sign-in challenge for client: {}", session.client_id);
+        Some(challenge)
+    }
+}
+
+// Handle sign-in challenge consumption
+impl Handler<ConsumeChallenge> for ClientRegistryActor {
+    type Result = Option<SiweMessage>;
+    
+    fn handle(&mut self, msg: ConsumeChallenge, _ctx: &mut Self::Context) -> Self::Result {
+        // Removed whether or not it matches, so a challenge gets a single attempt
+        let (_, challenge) = self.challenges.remove(&msg.session_token)?;
+        (challenge.nonce == msg.nonce && !challenge.is_expired()).then_some(challenge)
+    }
+}
+
+// Handle recording of an issued JWT
+impl Handler<RecordIssuedToken> for ClientRegistryActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: RecordIssuedToken, _ctx: &mut Self::Context) -> Self::Result {
+        if self.sessions.get(&msg.session_token).is_none() {
+            tracing::warn!("Issued token for unknown session, not tracked for revocation");
+            return;
+        }
+        self.token_sessions.insert(msg.jti.clone(), msg.session_token.clone());
+        self.issued_tokens.entry(msg.session_token).or_default().push((msg.jti, msg.expires_at));
+    }
+}
+
+// Handle revocation checks
+impl Handler<IsTokenRevoked> for ClientRegistryActor {
+    type Result = bool;
+    
+    fn handle(&mut self, msg: IsTokenRevoked, _ctx: &mut Self::Context) -> Self::Result {
+        self.revoked_tokens.contains_key(&msg.jti)
+    }
+}
+
 // Handle session cleanup
 impl Handler<CleanupExpiredSessions> for ClientRegistryActor {
     type Result = MessageResult<CleanupExpiredSessions>;
@@ -403,6 +687,35 @@ impl Handler<CleanupExpiredSessions> for ClientRegistryActor {
     }
 }
 
+impl Handler<GetAllClientIds> for ClientRegistryActor {
+    type Result = MessageResult<GetAllClientIds>;
+    
+    fn handle(&mut self, _msg: GetAllClientIds, _ctx: &mut Self::Context) -> Self::Result {
+        let (sessions, session_ttl) = (&mut self.sessions, self.session_ttl);
+        let client_ids = self.client_lookup.iter()
+            .filter(|entry| entry.value().iter().any(|token| {
+                sessions.get(token).is_some_and(|session| !session.is_expired(session_ttl))
+            }))
+            .map(|entry| *entry.key())
+            .collect();
+        MessageResult(client_ids)
+    }
+}
+
+impl Handler<GetSessionsByWallet> for ClientRegistryActor {
+    type Result = MessageResult<GetSessionsByWallet>;
+    
+    fn handle(&mut self, msg: GetSessionsByWallet, _ctx: &mut Self::Context) -> Self::Result {
+        let mut sessions: Vec<ClientSession> = self.sessions.values().into_iter()
+            .filter(|session| session.is_authenticated && !session.is_expired(self.session_ttl))
+            .filter(|session| session.wallet_address.as_ref()
+                .is_some_and(|wallet| wallet.eq_ignore_ascii_case(&msg.wallet_address)))
+            .collect();
+        sessions.sort_by_key(|session| session.created_at);
+        MessageResult(sessions.iter().map(ClientSessionResponse::from).collect())
+    }
+}
+
 // Handle metrics request
 impl Handler<GetSessionMetrics> for ClientRegistryActor {
     type Result = MessageResult<GetSessionMetrics>;
@@ -412,4 +725,189 @@ impl Handler<GetSessionMetrics> for ClientRegistryActor {
         self.update_metrics();
         MessageResult(self.metrics.clone())
     }
-}   
\ No newline at end of file
+}   
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix::test]
+    async fn test_sessions_per_client_are_capped() {
+        let registry = ClientRegistryActor::new().with_max_sessions_per_client(2).start();
+        let (client_id, first) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let second = registry.send(CreateClientSession { client_id }).await.unwrap();
+        let third = registry.send(CreateClientSession { client_id }).await.unwrap();
+
+        // The oldest session is evicted once the cap is exceeded
+        let lookup = |token: &String| registry.send(GetClientSession { session_token: token.clone() });
+        assert!(matches!(lookup(&first).await.unwrap(), SessionResult::NotFound));
+        assert!(matches!(lookup(&second).await.unwrap(), SessionResult::Success(_)));
+        assert!(matches!(lookup(&third).await.unwrap(), SessionResult::Success(_)));
+
+        // Lookup by client resolves to the newest session
+        match registry.send(GetClientSessionById { client_id }).await.unwrap() {
+            SessionResult::Success(session) => assert_eq!(session.session_token, third),
+            _ => panic!("expected the newest session"),
+        }
+
+        let metrics = registry.send(GetSessionMetrics).await.unwrap();
+        assert_eq!(metrics.total_sessions, 2);
+    }
+
+    fn issue_challenge(session_token: &str) -> IssueChallenge {
+        IssueChallenge {
+            session_token: session_token.to_string(),
+            domain: "localhost".to_string(),
+            uri: "http://localhost".to_string(),
+            address: "0x0000000000000000000000000000000000000001".to_string(),
+            chain_id: 1,
+        }
+    }
+
+    fn consume_challenge(session_token: &str, nonce: &str) -> ConsumeChallenge {
+        ConsumeChallenge { session_token: session_token.to_string(), nonce: nonce.to_string() }
+    }
+
+    #[actix::test]
+    async fn test_challenge_is_single_use() {
+        let registry = ClientRegistryActor::new().start();
+        let (_, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let issued = registry.send(issue_challenge(&session_token)).await.unwrap().unwrap();
+
+        let consumed = registry.send(consume_challenge(&session_token, &issued.nonce)).await.unwrap();
+        assert_eq!(consumed, Some(issued.clone()));
+        assert!(registry.send(consume_challenge(&session_token, &issued.nonce)).await.unwrap().is_none());
+    }
+
+    #[actix::test]
+    async fn test_wrong_or_expired_nonce_is_rejected() {
+        let mut registry = ClientRegistryActor::new();
+        registry.nonce_ttl = 0;
+        let registry = registry.start();
+        let (_, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+
+        let expired = registry.send(issue_challenge(&session_token)).await.unwrap().unwrap();
+        assert!(registry.send(consume_challenge(&session_token, &expired.nonce)).await.unwrap().is_none());
+
+        // A wrong guess also burns the outstanding challenge
+        let registry = ClientRegistryActor::new().start();
+        let (_, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let issued = registry.send(issue_challenge(&session_token)).await.unwrap().unwrap();
+        assert!(registry.send(consume_challenge(&session_token, "guess")).await.unwrap().is_none());
+        assert!(registry.send(consume_challenge(&session_token, &issued.nonce)).await.unwrap().is_none());
+    }
+
+    #[actix::test]
+    async fn test_challenge_requires_a_session() {
+        let registry = ClientRegistryActor::new().start();
+        assert!(registry.send(issue_challenge("missing")).await.unwrap().is_none());
+    }
+
+    #[actix::test]
+    async fn test_revoked_tokens_are_pruned_after_expiry() {
+        let registry = ClientRegistryActor::new().start();
+        let (_, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        for (jti, expires_at) in [("live", Utc::now() + chrono::Duration::hours(1)), ("stale", Utc::now() - chrono::Duration::seconds(1))] {
+            registry.send(RecordIssuedToken {
+                session_token: session_token.clone(),
+                jti: jti.to_string(),
+                expires_at,
+            }).await.unwrap();
+        }
+
+        assert!(registry.send(InvalidateClientSession { session_token }).await.unwrap());
+        let is_revoked = |jti: &str| registry.send(IsTokenRevoked { jti: jti.to_string() });
+        assert!(is_revoked("live").await.unwrap());
+        assert!(is_revoked("stale").await.unwrap());
+
+        registry.send(CleanupExpiredSessions).await.unwrap();
+        assert!(is_revoked("live").await.unwrap());
+        assert!(!is_revoked("stale").await.unwrap());
+    }
+
+    #[actix::test]
+    async fn test_invalidating_one_session_keeps_the_others() {
+        let registry = ClientRegistryActor::new().start();
+        let (client_id, first) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let second = registry.send(CreateClientSession { client_id }).await.unwrap();
+
+        assert!(registry.send(InvalidateClientSession { session_token: second }).await.unwrap());
+
+        // The client is still reachable through its remaining session
+        match registry.send(GetClientSessionById { client_id }).await.unwrap() {
+            SessionResult::Success(session) => assert_eq!(session.session_token, first),
+            _ => panic!("expected the remaining session"),
+        }
+    }
+
+    #[actix::test]
+    async fn test_sessions_in_the_store_survive_a_restart() {
+        // Sessions left behind by a previous process
+        let client_id = Uuid::new_v4();
+        let mut store = InMemorySessionStore::new();
+        let mut older = ClientSession::new_anonymous(client_id, "older".to_string());
+        older.created_at -= chrono::Duration::seconds(10);
+        store.insert(older, DEFAULT_SESSION_TTL);
+        store.insert(ClientSession::new_anonymous(client_id, "newer".to_string()), DEFAULT_SESSION_TTL);
+
+        let registry = ClientRegistryActor::new().with_session_store(Box::new(store)).start();
+        assert!(matches!(
+            registry.send(GetClientSession { session_token: "older".to_string() }).await.unwrap(),
+            SessionResult::Success(_)
+        ));
+        match registry.send(GetClientSessionById { client_id }).await.unwrap() {
+            SessionResult::Success(session) => assert_eq!(session.session_token, "newer"),
+            _ => panic!("expected the newest stored session"),
+        }
+        assert_eq!(registry.send(GetSessionMetrics).await.unwrap().total_sessions, 2);
+    }
+
+    // Sessions keyed however the test likes, as a tampered backend might hold them
+    struct RawStore(std::collections::HashMap<String, ClientSession>);
+
+    impl SessionStore for RawStore {
+        fn get(&mut self, session_token: &str) -> Option<ClientSession> {
+            self.0.get(session_token).cloned()
+        }
+        fn insert(&mut self, session: ClientSession, _ttl_seconds: i64) {
+            self.0.insert(session.session_token.clone(), session);
+        }
+        fn remove(&mut self, session_token: &str) -> Option<ClientSession> {
+            self.0.remove(session_token)
+        }
+        fn iter_expired(&mut self, _ttl_seconds: i64) -> Box<dyn Iterator<Item = ClientSession> + '_> {
+            Box::new(std::iter::empty())
+        }
+        fn values(&mut self) -> Vec<ClientSession> {
+            self.0.values().cloned().collect()
+        }
+    }
+
+    #[actix::test]
+    async fn test_malformed_or_tampered_tokens_are_invalid() {
+        // A stored session filed under a token other than its own
+        let session = ClientSession::new_anonymous(Uuid::new_v4(), "original".to_string());
+        let store = RawStore([
+            ("original".to_string(), session.clone()),
+            ("forged".to_string(), session),
+        ].into());
+        let registry = ClientRegistryActor::new().with_session_store(Box::new(store)).start();
+        let lookup = |token: &str| registry.send(GetClientSession { session_token: token.to_string() });
+
+        assert!(matches!(lookup("original").await.unwrap(), SessionResult::Success(_)));
+        assert!(matches!(lookup("not a token").await.unwrap(), SessionResult::Invalid));
+        assert!(matches!(lookup("unknown").await.unwrap(), SessionResult::NotFound));
+
+        let update = UpdateClientSession {
+            session_token: "forged".to_string(),
+            is_authenticated: Some(true),
+            wallet_address: None,
+            metadata: None,
+            extend_ttl: false,
+        };
+        assert!(matches!(registry.send(update).await.unwrap(), SessionResult::Invalid));
+        // The tampered entry is dropped
+        assert!(matches!(lookup("forged").await.unwrap(), SessionResult::NotFound));
+        assert_eq!(registry.send(GetSessionMetrics).await.unwrap().total_sessions, 1);
+    }
+}
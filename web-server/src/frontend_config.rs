@@ -0,0 +1,112 @@
+// web-server/src/frontend_config.rs
+// Runtime configuration for the frontend, served as a script the SPA loads
+// before its bundle, so one static build works against any deployment
+use actix_web::{get, http::header, web, HttpRequest, HttpResponse, Responder};
+use common::{Config, ProtocolVersion};
+use serde::Serialize;
+
+// What the frontend may know about the deployment. Only settings that shape
+// client behaviour belong here; this is served to anyone
+#[derive(Serialize)]
+struct FrontendConfig {
+    // Base of the proxied WebSocket route; clients append their client id
+    websocket_url: String,
+    protocols: Vec<&'static str>,
+    require_message_acks: bool,
+    max_content_len: usize,
+    max_frame_bytes: usize,
+    compression: bool,
+}
+
+impl FrontendConfig {
+    fn new(config: &Config, req: &HttpRequest) -> Self {
+        // The host the browser reached us on, so this works behind a proxy
+        let info = req.connection_info();
+        let scheme = if info.scheme() == "https" { "wss" } else { "ws" };
+        Self {
+            websocket_url: format!("{}://{}/ws", scheme, info.host()),
+            protocols: ProtocolVersion::SUPPORTED.iter().map(|version| version.subprotocol()).collect(),
+            require_message_acks: config.require_message_acks,
+            max_content_len: config.max_content_len,
+            max_frame_bytes: config.max_frame_bytes,
+            compression: config.compression_threshold_bytes > 0,
+        }
+    }
+}
+
+// Never cached, so a config change reaches clients on their next load
+#[get("/config.js")]
+pub async fn config_js(req: HttpRequest, config: web::Data<Config>) -> impl Responder {
+    let frontend = FrontendConfig::new(&config, &req);
+    let json = serde_json::to_string(&frontend).expect("frontend config always serializes");
+    HttpResponse::Ok()
+        .content_type("application/javascript; charset=utf-8")
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .body(format!("window.__APP_CONFIG__ = {};\n", json))
+}
+
+// Registered ahead of the static files, which would otherwise serve a
+// config.js left in the build or fall through to the SPA index
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(config_js);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use crate::static_files::{self, StaticFilesConfig};
+
+    #[actix_web::test]
+    async fn test_config_js_exposes_public_settings_only() {
+        let config = Config {
+            agent_token: "agent-token-secret".to_string(),
+            admin_token: "admin-token-secret".to_string(),
+            proxy_token: "proxy-token-secret".to_string(),
+            jwt_secret: "jwt-secret-value".to_string(),
+            redis_url: Some("redis://:redis-password@cache/".to_string()),
+            require_message_acks: true,
+            max_frame_bytes: 4096,
+            ..Config::default()
+        };
+        // A stale config.js in the build must not shadow the endpoint
+        let root = std::env::temp_dir().join(format!("frontend-config-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("config.js"), "window.__APP_CONFIG__ = {\"stale\":true};").unwrap();
+        let static_config = StaticFilesConfig {
+            root_path: root.clone(),
+            enable_compression: false,
+            ..StaticFilesConfig::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .configure(configure)
+                .configure(|cfg| static_files::configure(cfg, static_config.clone()))
+        ).await;
+
+        let req = test::TestRequest::get()
+            .uri("/config.js")
+            .insert_header((header::HOST, "bridge.example.com"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers().get(header::CONTENT_TYPE).unwrap().to_str().unwrap().starts_with("application/javascript"));
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let json = body.strip_prefix("window.__APP_CONFIG__ = ")
+            .and_then(|rest| rest.strip_suffix(";\n"))
+            .unwrap_or_else(|| panic!("not a config assignment: {}", body));
+        let frontend: serde_json::Value = serde_json::from_str(json).unwrap();
+        assert_eq!(frontend["websocket_url"], "ws://bridge.example.com/ws");
+        assert_eq!(frontend["protocols"], serde_json::json!(["agent-bridge.v1"]));
+        assert_eq!(frontend["require_message_acks"], true);
+        assert_eq!(frontend["max_frame_bytes"], 4096);
+        assert!(frontend.get("stale").is_none());
+
+        for secret in ["agent-token-secret", "admin-token-secret", "proxy-token-secret", "jwt-secret-value", "redis-password"] {
+            assert!(!body.contains(secret), "config.js leaks {}: {}", secret, body);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+// web-server/src/health.rs
+use actix_web::{get, web, HttpResponse, Responder};
+use common::Config;
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use crate::proxy::ActiveConnections;
+
+// How long the deep check waits for the websocket-server to accept a connection
+const DOWNSTREAM_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// When the process started, kept in app data for uptime reporting
+#[derive(Clone, Copy)]
+pub struct StartTime(pub Instant);
+
+impl StartTime {
+    pub fn now() -> Self {
+        Self(Instant::now())
+    }
+}
+
+// Liveness of the web-server itself
+#[get("/health")]
+pub async fn health() -> impl Responder {
+    HttpResponse::Ok().json(json!({ "web": "ok" }))
+}
+
+// Liveness plus reachability of the websocket-server the proxy depends on
+#[get("/health/deep")]
+pub async fn health_deep(config: web::Data<Config>) -> impl Responder {
+    let websocket_reachable = is_reachable(&config.websocket_server_addr).await;
+    let body = json!({
+        "web": "ok",
+        "websocket": if websocket_reachable { "ok" } else { "unreachable" },
+    });
+
+    if websocket_reachable {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+// Readiness for load balancers: live proxy connections and uptime, and 503
+// while connections drain for shutdown. Outside the rate-limited /api/client paths
+#[get("/api/health")]
+pub async fn api_health(
+    active_connections: web::Data<ActiveConnections>,
+    start_time: web::Data<StartTime>,
+) -> impl Responder {
+    let draining = active_connections.is_draining();
+    let body = json!({
+        "status": if draining { "draining" } else { "ok" },
+        "active_proxy_connections": active_connections.count(),
+        "uptime_seconds": start_time.0.elapsed().as_secs(),
+    });
+
+    if draining {
+        HttpResponse::ServiceUnavailable().json(body)
+    } else {
+        HttpResponse::Ok().json(body)
+    }
+}
+
+// Whether a TCP connection to `addr` can be opened within the timeout
+async fn is_reachable(addr: &str) -> bool {
+    match tokio::time::timeout(DOWNSTREAM_CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => {
+            tracing::warn!("Websocket server at {} unreachable: {}", addr, e);
+            false
+        },
+        Err(_) => {
+            tracing::warn!("Timed out connecting to websocket server at {}", addr);
+            false
+        }
+    }
+}
+
+// Configure health check routes
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(health).service(health_deep).service(api_health);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App};
+    use tokio::net::TcpListener;
+
+    async fn deep_health(websocket_server_addr: String) -> (StatusCode, serde_json::Value) {
+        let config = Config {
+            websocket_server_addr,
+            ..Config::default()
+        };
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(config)).configure(configure)
+        ).await;
+
+        let req = test::TestRequest::get().uri("/health/deep").to_request();
+        let resp = test::call_service(&app, req).await;
+        let status = resp.status();
+        (status, test::read_body_json(resp).await)
+    }
+
+    #[actix_web::test]
+    async fn test_api_health_reports_proxy_connections() {
+        let active = web::Data::new(ActiveConnections::new());
+        // Registered ahead of the /api scope, as in main
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(StartTime::now()))
+                .configure(configure)
+                .configure(|cfg| crate::api::configure(cfg, None))
+                .configure(|cfg| crate::proxy::configure(cfg, active.clone(), None))
+        ).await;
+        let request = || test::TestRequest::get().uri("/api/health").to_request();
+
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request()).await;
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["active_proxy_connections"], 0);
+        assert!(body["uptime_seconds"].is_u64());
+
+        // A proxied client connection registers itself once started
+        let (_proxy, _frames) = common::test_support::start_ws_actor(crate::proxy::ProxyActor::new(
+            uuid::Uuid::new_v4(),
+            vec!["ws://127.0.0.1:1".to_string()],
+            Some("session".to_string()),
+            None,
+            Some(active.clone()),
+        ));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request()).await;
+        assert_eq!(body["active_proxy_connections"], 1);
+    }
+
+    #[actix_web::test]
+    async fn test_deep_health_with_backend_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (status, body) = deep_health(listener.local_addr().unwrap().to_string()).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "web": "ok", "websocket": "ok" }));
+    }
+
+    #[actix_web::test]
+    async fn test_deep_health_with_backend_down() {
+        // Bind then release a port so nothing is listening on it
+        let addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let (status, body) = deep_health(addr.to_string()).await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body, json!({ "web": "ok", "websocket": "unreachable" }));
+    }
+}
@@ -3,23 +3,40 @@ mod proxy;
 mod auth;
 mod static_files;
 mod api;
+mod health;
+mod frontend_config;
 mod client_registry;
+mod session_store;
+mod security;
 mod middleware;
 mod utils;
+#[cfg(test)]
+mod test_support;
 
 use actix::Actor;
 use actix_web::{web, App, HttpServer, middleware::{Compress, Logger}};
-use common::{setup_tracing, Config};
+use common::{setup_tracing, Config, ConnectionFilter};
 use client_registry::ClientRegistryActor;
-use middleware::RateLimiter;
+use middleware::{KeyStrategy, RateLimiter};
+use security::SecurityMonitor;
+use session_store::{InMemorySessionStore, SessionStore};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    // Setup tracing
-    setup_tracing();
+    // Setup tracing; the configured level applies once the config is loaded
+    let log_level = setup_tracing();
     
     // Load configuration
-    let config = Config::from_env();
+    let config = Config::from_env().inspect_err(|e| tracing::error!("Refusing to start: {}", e))?;
+    if let Err(e) = log_level.set(&config.log_level) {
+        tracing::warn!("{}, logging at info", e);
+    }
+    if config.has_default_jwt_secret() {
+        tracing::warn!(
+            "!!! JWT secret is the insecure development default; anyone can forge session tokens. \
+             Set APP__JWT_SECRET or jwt_secret_file before deploying !!!"
+        );
+    }
     
     // Save address before moving config into web::Data
     let server_addr = config.web_server_addr.clone();
@@ -27,13 +44,21 @@ async fn main() -> std::io::Result<()> {
     // Get static files configuration
     let static_config = static_files::get_static_config();
     
-    tracing::info!("Starting Web Server on {}", server_addr);
+    // Load the certificate up front so a bad one fails startup. The proxy
+    // trusts the same certificate on the websocket-server, which serves TLS
+    // from the same config
+    let tls = config.tls.as_ref().map(common::tls::server_config).transpose()?;
+    let upstream_tls = config.tls.as_ref().map(common::tls::client_config).transpose()?;
+    
+    tracing::info!("Starting Web Server on {}{}", server_addr, if tls.is_some() { " (TLS)" } else { "" });
     tracing::info!("Serving static files from: {:?}", static_config.root_path);
     
     // Initialize ClientRegistryActor with a 24-hour session TTL
     let client_registry = ClientRegistryActor::new()
         .with_ttl(86400) // 24 hours in seconds
         .with_cleanup_interval(3600) // Clean up expired sessions every hour
+        .with_max_sessions_per_client(config.max_sessions_per_client)
+        .with_session_store(session_store(&config))
         .start();
     tracing::info!("ClientRegistryActor started");
     
@@ -48,49 +73,144 @@ async fn main() -> std::io::Result<()> {
     tracing::info!("{}", cache_info);
     
     // Create rate limiter for client creation endpoint
-    let client_rate_limiter = RateLimiter::new(vec!["/api/client".to_string()]);
+    let rate_limit_key = config.client_rate_limit_key.parse::<KeyStrategy>()
+        .unwrap_or_else(|e| {
+            tracing::warn!("{}, falling back to ip", e);
+            KeyStrategy::Ip
+        });
+    let client_rate_limiter = RateLimiter::with_limits(
+        vec!["/api/client".to_string()], config.client_rate_limit_per_min as usize, std::time::Duration::from_secs(60),
+    ).with_burst(config.client_rate_limit_burst).with_key_strategy(rate_limit_key);
     tracing::info!("Rate limiter configured for /api/client endpoint");
     
+    // Client ID mismatches are audited, and optionally get the IP banned
+    let security = SecurityMonitor::new()
+        .with_mismatch_ban(config.client_id_mismatch_ban_threshold, config.client_id_mismatch_ban_secs);
+    
     // Create data references
+    let shutdown_grace = std::time::Duration::from_secs(config.shutdown_grace_secs);
     let config_data = web::Data::new(config);
+    let start_time_data = web::Data::new(health::StartTime::now());
     let client_registry_data = web::Data::new(client_registry);
+    let security_data = web::Data::new(security);
+    let connection_filter_data = web::Data::new(ConnectionFilter::from_config(&config_data));
+    let active_connections = web::Data::new(proxy::ActiveConnections::new());
+    let active_connections_clone = active_connections.clone();
     let static_config_clone = static_config.clone();
+    let cors_config = config_data.cors.clone();
+    let upstream_tls_data = upstream_tls.map(web::Data::new);
+    let development = Config::run_mode() == "development";
+    let log_level_data = web::Data::new(log_level);
     
-    // Start HTTP server with conditional configuration based on compression setting
-    if static_config.enable_compression {
+    // Start HTTP server with conditional configuration based on compression setting.
+    // Signals are handled below so proxied connections can be drained first
+    let server = if static_config.enable_compression {
         // With compression
-        HttpServer::new(move || {
+        let server = HttpServer::new(move || {
             App::new()
                 .app_data(config_data.clone())
                 .app_data(client_registry_data.clone())
+                .app_data(security_data.clone())
+                .app_data(connection_filter_data.clone())
+                .app_data(start_time_data.clone())
+                .app_data(log_level_data.clone())
                 .wrap(Logger::default())
                 .wrap(client_rate_limiter.clone())
                 .wrap(Compress::default())
-                .configure(api::configure)
-                .configure(proxy::configure)
+                // Before the /api scope, which would otherwise claim /api/health
+                .configure(health::configure)
+                .configure(frontend_config::configure)
+                .configure(|cfg| api::configure(cfg, middleware::cors(&cors_config, development)))
+                .configure(|cfg| proxy::configure(cfg, active_connections_clone.clone(), upstream_tls_data.clone()))
                 .configure(|cfg| {
                     static_files::configure(cfg, static_config_clone.clone());
                 })
-        })
-        .bind(&server_addr)?
+        });
+        match tls {
+            Some(tls) => server.bind_rustls(&server_addr, tls)?,
+            None => server.bind(&server_addr)?,
+        }
+        .disable_signals()
         .run()
-        .await
     } else {
         // Without compression
-        HttpServer::new(move || {
+        let server = HttpServer::new(move || {
             App::new()
                 .app_data(config_data.clone())
                 .app_data(client_registry_data.clone())
+                .app_data(security_data.clone())
+                .app_data(connection_filter_data.clone())
+                .app_data(start_time_data.clone())
+                .app_data(log_level_data.clone())
                 .wrap(Logger::default())
                 .wrap(client_rate_limiter.clone())
-                .configure(api::configure)
-                .configure(proxy::configure)
+                // Before the /api scope, which would otherwise claim /api/health
+                .configure(health::configure)
+                .configure(frontend_config::configure)
+                .configure(|cfg| api::configure(cfg, middleware::cors(&cors_config, development)))
+                .configure(|cfg| proxy::configure(cfg, active_connections_clone.clone(), upstream_tls_data.clone()))
                 .configure(|cfg| {
                     static_files::configure(cfg, static_config_clone.clone());
                 })
-        })
-        .bind(&server_addr)?
+        });
+        match tls {
+            Some(tls) => server.bind_rustls(&server_addr, tls)?,
+            None => server.bind(&server_addr)?,
+        }
+        .disable_signals()
         .run()
-        .await
+    };
+    
+    // On shutdown, close proxied connections before stopping the server
+    let server_handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        tracing::info!("Shutdown requested, draining connections for up to {:?}", shutdown_grace);
+        active_connections.drain(shutdown_grace).await;
+        server_handle.stop(true).await;
+    });
+    
+    server.await
+}
+
+// Resolves on Ctrl-C, or SIGTERM on Unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = terminate.recv() => {},
+                }
+                return;
+            },
+            Err(e) => tracing::error!("Failed to listen for SIGTERM: {}", e),
+        }
+    }
+    
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        tracing::error!("Failed to listen for Ctrl-C: {}", e);
     }
-}
\ No newline at end of file
+}
+
+// Redis when configured and compiled in, else in-memory sessions
+fn session_store(config: &Config) -> Box<dyn SessionStore> {
+    let Some(redis_url) = &config.redis_url else {
+        return Box::new(InMemorySessionStore::new());
+    };
+    
+    #[cfg(feature = "redis")]
+    match session_store::RedisSessionStore::open(redis_url) {
+        Ok(store) => {
+            tracing::info!("Client sessions persisted to Redis");
+            return Box::new(store);
+        },
+        Err(e) => tracing::error!("Failed to connect to Redis, keeping sessions in memory: {}", e),
+    }
+    #[cfg(not(feature = "redis"))]
+    tracing::warn!("redis_url {} ignored, built without the redis feature; keeping sessions in memory", redis_url);
+    
+    Box::new(InMemorySessionStore::new())
+}
@@ -0,0 +1,250 @@
+// web-server/src/middleware/compression.rs
+use std::io::Write;
+use std::str::FromStr;
+use actix_web::{
+    body::{self, BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
+    http::{header, StatusCode},
+    Error,
+};
+use futures_util::future::{LocalBoxFuture, Ready, ready};
+
+// Highest level each encoder supports
+const MAX_GZIP_LEVEL: u32 = 9;
+const MAX_BROTLI_QUALITY: u32 = 11;
+
+// Brotli encoder buffer size and window (log2), as used by the brotli CLI
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const BROTLI_WINDOW: u32 = 22;
+
+/// Preferred content encoding for compressed responses
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionAlgorithm {
+    Brotli,
+    Gzip,
+}
+
+impl CompressionAlgorithm {
+    /// Token used in `Accept-Encoding` and `Content-Encoding`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompressionAlgorithm::Brotli => "br",
+            CompressionAlgorithm::Gzip => "gzip",
+        }
+    }
+
+    fn other(&self) -> Self {
+        match self {
+            CompressionAlgorithm::Brotli => CompressionAlgorithm::Gzip,
+            CompressionAlgorithm::Gzip => CompressionAlgorithm::Brotli,
+        }
+    }
+
+    fn max_level(&self) -> u32 {
+        match self {
+            CompressionAlgorithm::Brotli => MAX_BROTLI_QUALITY,
+            CompressionAlgorithm::Gzip => MAX_GZIP_LEVEL,
+        }
+    }
+
+    fn compress(&self, data: &[u8], level: u32) -> std::io::Result<Vec<u8>> {
+        let level = level.min(self.max_level());
+        match self {
+            CompressionAlgorithm::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), BROTLI_BUFFER_SIZE, level, BROTLI_WINDOW);
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                Ok(encoder.into_inner())
+            },
+            CompressionAlgorithm::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+                encoder.write_all(data)?;
+                encoder.finish()
+            },
+        }
+    }
+}
+
+impl FromStr for CompressionAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "br" | "brotli" => Ok(CompressionAlgorithm::Brotli),
+            "gzip" => Ok(CompressionAlgorithm::Gzip),
+            other => Err(format!("Unknown compression algorithm: {}", other)),
+        }
+    }
+}
+
+// Whether an Accept-Encoding value allows `encoding` (ignoring explicit q=0)
+fn accepts_encoding(accept_encoding: &str, encoding: &str) -> bool {
+    accept_encoding.split(',').any(|entry| {
+        let mut parts = entry.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let refused = parts.any(|param| {
+            param.strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+        (name.eq_ignore_ascii_case(encoding) || name == "*") && !refused
+    })
+}
+
+/// Compresses successful responses with a configured algorithm and level.
+/// Unlike `actix_web::middleware::Compress`, the level is tunable; the body is
+/// buffered in full, so this is meant for static assets rather than streams.
+#[derive(Clone, Debug)]
+pub struct AssetCompression {
+    algorithm: CompressionAlgorithm,
+    level: u32,
+}
+
+impl AssetCompression {
+    pub fn new(algorithm: CompressionAlgorithm, level: u32) -> Self {
+        Self { algorithm, level }
+    }
+
+    // The preferred algorithm if the client takes it, else the other one
+    fn negotiate(&self, accept_encoding: &str) -> Option<CompressionAlgorithm> {
+        [self.algorithm, self.algorithm.other()]
+            .into_iter()
+            .find(|algorithm| accepts_encoding(accept_encoding, algorithm.as_str()))
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AssetCompression
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = AssetCompressionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AssetCompressionMiddleware {
+            service,
+            compression: self.clone(),
+        }))
+    }
+}
+
+pub struct AssetCompressionMiddleware<S> {
+    service: S,
+    compression: AssetCompression,
+}
+
+impl<S, B> Service<ServiceRequest> for AssetCompressionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<ServiceResponse<BoxBody>, Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let algorithm = req.headers()
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| self.compression.negotiate(value));
+        let level = self.compression.level;
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await?.map_into_boxed_body();
+
+            // Leave partial content, errors and pre-encoded bodies untouched
+            let Some(algorithm) = algorithm else { return Ok(res) };
+            if res.status() != StatusCode::OK || res.headers().contains_key(header::CONTENT_ENCODING) {
+                return Ok(res);
+            }
+
+            let (req, res) = res.into_parts();
+            let (mut res, body) = res.into_parts();
+            let bytes = body::to_bytes(body).await.map_err(actix_web::error::ErrorInternalServerError)?;
+            let compressed = algorithm.compress(&bytes, level)?;
+
+            let headers = res.headers_mut();
+            headers.insert(header::CONTENT_ENCODING, header::HeaderValue::from_static(algorithm.as_str()));
+            headers.append(header::VARY, header::HeaderValue::from_static("accept-encoding"));
+            headers.remove(header::CONTENT_LENGTH);
+
+            Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(compressed))))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use std::io::Read;
+
+    fn asset() -> String {
+        "let greeting = 'hello, static assets';\n".repeat(200)
+    }
+
+    async fn get_asset(compression: AssetCompression, accept_encoding: Option<&str>) -> (Option<String>, Vec<u8>) {
+        let app = init_service(
+            App::new()
+                .wrap(compression)
+                .route("/app.js", web::get().to(|| async { HttpResponse::Ok().body(asset()) }))
+        ).await;
+        let mut req = TestRequest::get().uri("/app.js");
+        if let Some(accept_encoding) = accept_encoding {
+            req = req.insert_header((header::ACCEPT_ENCODING, accept_encoding));
+        }
+        let res = call_service(&app, req.to_request()).await;
+        let encoding = res.headers()
+            .get(header::CONTENT_ENCODING)
+            .map(|value| value.to_str().unwrap().to_string());
+        (encoding, read_body(res).await.to_vec())
+    }
+
+    #[actix_web::test]
+    async fn test_preferred_algorithm_is_negotiated() {
+        let brotli = AssetCompression::new(CompressionAlgorithm::Brotli, 5);
+        let gzip = AssetCompression::new(CompressionAlgorithm::Gzip, 5);
+
+        assert_eq!(get_asset(brotli.clone(), Some("gzip, br")).await.0.as_deref(), Some("br"));
+        assert_eq!(get_asset(gzip.clone(), Some("gzip, br")).await.0.as_deref(), Some("gzip"));
+        // Falls back to whichever encoding the client takes
+        assert_eq!(get_asset(brotli.clone(), Some("gzip, br;q=0")).await.0.as_deref(), Some("gzip"));
+        assert_eq!(get_asset(gzip, Some("br")).await.0.as_deref(), Some("br"));
+
+        let (encoding, body) = get_asset(brotli, None).await;
+        assert_eq!(encoding, None);
+        assert_eq!(body, asset().into_bytes());
+    }
+
+    #[actix_web::test]
+    async fn test_configured_level_is_applied() {
+        let (_, stored) = get_asset(AssetCompression::new(CompressionAlgorithm::Gzip, 0), Some("gzip")).await;
+        let (_, best) = get_asset(AssetCompression::new(CompressionAlgorithm::Gzip, 9), Some("gzip")).await;
+
+        // Level 0 stores the data, so only the higher level actually shrinks it
+        assert!(stored.len() > asset().len());
+        assert!(best.len() < asset().len() / 10);
+
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(best.as_slice()).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, asset());
+    }
+
+    #[test]
+    fn test_algorithm_parses_from_config_values() {
+        assert_eq!("br".parse(), Ok(CompressionAlgorithm::Brotli));
+        assert_eq!("Brotli".parse(), Ok(CompressionAlgorithm::Brotli));
+        assert_eq!("gzip".parse(), Ok(CompressionAlgorithm::Gzip));
+        assert!("deflate".parse::<CompressionAlgorithm>().is_err());
+    }
+}
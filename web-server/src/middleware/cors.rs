@@ -0,0 +1,81 @@
+// web-server/src/middleware/cors.rs
+// Cross-origin access to the /api routes, for a SPA served from another
+// origin such as a dev server
+use actix_cors::Cors;
+use common::CorsConfig;
+
+/// CORS for the /api scope, or None when cross-origin requests get no CORS
+/// headers at all. With no origins configured, any origin is allowed in
+/// development and none otherwise; same-origin requests work either way.
+pub fn cors(config: &CorsConfig, development: bool) -> Option<Cors> {
+    let any_origin = config.allowed_origins.iter().any(|origin| origin == "*")
+        || (config.allowed_origins.is_empty() && development);
+    if config.allowed_origins.is_empty() && !any_origin {
+        return None;
+    }
+
+    let mut cors = Cors::default()
+        .allowed_methods(config.allowed_methods.iter().map(String::as_str))
+        .allow_any_header()
+        .max_age(3600);
+    if any_origin {
+        cors = cors.allow_any_origin();
+    } else {
+        for origin in &config.allowed_origins {
+            cors = cors.allowed_origin(origin);
+        }
+    }
+    if config.allow_credentials {
+        cors = cors.supports_credentials();
+    }
+    Some(cors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::header, test, App};
+
+    const DEV_SERVER: &str = "http://localhost:3000";
+
+    async fn allow_origin_for(config: &CorsConfig, development: bool, origin: &str) -> Option<String> {
+        let cors = cors(config, development);
+        let app = test::init_service(App::new().configure(|cfg| crate::api::configure(cfg, cors))).await;
+        let req = test::TestRequest::get()
+            .uri("/api/")
+            .insert_header((header::ORIGIN, origin))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        resp.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[actix_web::test]
+    async fn test_allowed_origin_gets_cors_headers() {
+        let config = CorsConfig {
+            allowed_origins: vec![DEV_SERVER.to_string()],
+            ..CorsConfig::default()
+        };
+        assert_eq!(allow_origin_for(&config, false, DEV_SERVER).await.as_deref(), Some(DEV_SERVER));
+        assert_eq!(allow_origin_for(&config, false, "https://evil.example").await, None);
+
+        // Credentials are allowed so the session cookie flows
+        let cors = cors(&config, false);
+        let app = test::init_service(App::new().configure(|cfg| crate::api::configure(cfg, cors))).await;
+        let req = test::TestRequest::default()
+            .method(actix_web::http::Method::OPTIONS)
+            .uri("/api/client")
+            .insert_header((header::ORIGIN, DEV_SERVER))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(), "true");
+    }
+
+    #[actix_web::test]
+    async fn test_empty_origins_depend_on_run_mode() {
+        let config = CorsConfig::default();
+        assert_eq!(allow_origin_for(&config, true, DEV_SERVER).await.as_deref(), Some(DEV_SERVER));
+        assert_eq!(allow_origin_for(&config, false, DEV_SERVER).await, None);
+    }
+}
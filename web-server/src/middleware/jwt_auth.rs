@@ -0,0 +1,246 @@
+// web-server/src/middleware/jwt_auth.rs
+use std::rc::Rc;
+use actix::Addr;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
+    error::{ErrorInternalServerError, ErrorUnauthorized},
+    web, Error, HttpMessage,
+};
+use common::{AuthorizationHeader, Config};
+use futures_util::future::{LocalBoxFuture, Ready, ready};
+use uuid::Uuid;
+use crate::client_registry::{ClientRegistryActor, IsTokenRevoked};
+
+/// Identity proven by a valid JWT, attached to the request extensions
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthenticatedClient {
+    pub client_id: Uuid,
+    pub wallet_address: String,
+}
+
+/// Validates `Authorization: Bearer` JWTs against the configured secret and
+/// the registry's revocation list. Requests without a bearer token pass
+/// through unauthenticated; requests with an invalid, expired or revoked
+/// token are rejected with 401.
+#[derive(Clone, Debug, Default)]
+pub struct JwtAuth;
+
+impl<S, B> Transform<S, ServiceRequest> for JwtAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = JwtAuthMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(JwtAuthMiddleware { service: Rc::new(service) }))
+    }
+}
+
+pub struct JwtAuthMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for JwtAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<ServiceResponse<B>, Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let token = req.headers()
+            .get("Authorization")
+            .and_then(|header| match AuthorizationHeader::from_header(header) {
+                Ok(AuthorizationHeader::Bearer(token)) => Some(token),
+                _ => None,
+            });
+
+        let Some(token) = token else {
+            return Box::pin(self.service.call(req));
+        };
+
+        let (Some(config), Some(registry)) = (
+            req.app_data::<web::Data<Config>>(),
+            req.app_data::<web::Data<Addr<ClientRegistryActor>>>(),
+        ) else {
+            tracing::error!("JwtAuth requires Config and ClientRegistryActor in app data");
+            return Box::pin(async { Err(ErrorInternalServerError("Server misconfigured")) });
+        };
+
+        let claims = config.validate_jwt(&token, config.jwt_leeway_secs)
+            .and_then(|claims| Ok((claims.client_id()?, claims)));
+        let (client_id, claims) = match claims {
+            Ok(claims) => claims,
+            Err(e) => {
+                tracing::warn!("Rejected JWT on {}: {}", req.path(), e);
+                return Box::pin(async { Err(ErrorUnauthorized("Invalid token")) });
+            }
+        };
+
+        let registry = registry.clone();
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            if registry.send(IsTokenRevoked { jti: claims.jti }).await.map_err(ErrorInternalServerError)? {
+                tracing::warn!("Rejected revoked JWT on {}", req.path());
+                return Err(ErrorUnauthorized("Token revoked"));
+            }
+
+            req.extensions_mut().insert(AuthenticatedClient { client_id, wallet_address: claims.wallet });
+            service.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::Actor;
+    use actix_web::test::{init_service, read_body_json, try_call_service, TestRequest};
+    use actix_web::{App, HttpRequest, HttpResponse};
+    use common::utils::JwtClaims;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    const WALLET: &str = "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf";
+
+    // Echo the identity the middleware attached, if any
+    async fn whoami(req: HttpRequest) -> HttpResponse {
+        let client = req.extensions().get::<AuthenticatedClient>().cloned();
+        HttpResponse::Ok().json(client.map(|c| json!({
+            "client_id": c.client_id,
+            "wallet_address": c.wallet_address,
+        })))
+    }
+
+    async fn call_with_token(token: Option<&str>) -> Result<ServiceResponse, Error> {
+        call_with_registry(ClientRegistryActor::new().start(), token).await
+    }
+
+    async fn call_with_registry(registry: Addr<ClientRegistryActor>, token: Option<&str>) -> Result<ServiceResponse, Error> {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Config::default()))
+                .app_data(web::Data::new(registry))
+                .wrap(JwtAuth)
+                .route("/whoami", web::get().to(whoami))
+        ).await;
+        let mut req = TestRequest::get().uri("/whoami");
+        if let Some(token) = token {
+            req = req.insert_header(("Authorization", format!("Bearer {}", token)));
+        }
+        try_call_service(&app, req.to_request()).await
+    }
+
+    #[actix_web::test]
+    async fn test_valid_token_attaches_client() {
+        let client_id = Uuid::new_v4();
+        let token = common::utils::generate_jwt_token(&client_id, WALLET, Config::default().jwt_secret.as_bytes()).unwrap();
+
+        let res = call_with_token(Some(&token)).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let body: serde_json::Value = read_body_json(res).await;
+        assert_eq!(body["client_id"], json!(client_id));
+        assert_eq!(body["wallet_address"], WALLET);
+
+        // No token is not an error, just no identity
+        let res = call_with_token(None).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let body: serde_json::Value = read_body_json(res).await;
+        assert!(body.is_null());
+    }
+
+    #[actix_web::test]
+    async fn test_expired_token_is_rejected() {
+        let issued = chrono::Utc::now() - chrono::Duration::days(2);
+        let claims = JwtClaims {
+            sub: Uuid::new_v4().to_string(),
+            wallet: WALLET.to_string(),
+            iat: issued.timestamp() as usize,
+            exp: (issued + chrono::Duration::days(1)).timestamp() as usize,
+            jti: Uuid::new_v4().to_string(),
+        };
+        let token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(Config::default().jwt_secret.as_bytes()),
+        ).unwrap();
+
+        for token in [token.as_str(), "not-a-jwt"] {
+            let err = call_with_token(Some(token)).await.unwrap_err();
+            assert_eq!(err.as_response_error().status_code(), 401);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_token_without_id_is_rejected() {
+        // Such a token could never be revoked
+        let now = chrono::Utc::now().timestamp() as usize;
+        let claims = json!({
+            "sub": Uuid::new_v4().to_string(),
+            "wallet": WALLET,
+            "iat": now,
+            "exp": now + 3600,
+        });
+        let token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(Config::default().jwt_secret.as_bytes()),
+        ).unwrap();
+
+        let err = call_with_token(Some(&token)).await.unwrap_err();
+        assert_eq!(err.as_response_error().status_code(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_revoked_token_is_rejected() {
+        use crate::client_registry::{InvalidateClientSession, RecordIssuedToken, RegisterAnonymousClient};
+
+        let registry = ClientRegistryActor::new().start();
+        let (client_id, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let token = common::utils::generate_jwt_token(&client_id, WALLET, Config::default().jwt_secret.as_bytes()).unwrap();
+        let claims = common::utils::validate_jwt_claims(&token, Config::default().jwt_secret.as_bytes(), 0).unwrap();
+        registry.send(RecordIssuedToken {
+            session_token: session_token.clone(),
+            jti: claims.jti,
+            expires_at: chrono::Utc::now() + chrono::Duration::days(1),
+        }).await.unwrap();
+
+        let res = call_with_registry(registry.clone(), Some(&token)).await.unwrap();
+        assert_eq!(res.status(), 200);
+
+        registry.send(InvalidateClientSession { session_token }).await.unwrap();
+        let err = call_with_registry(registry, Some(&token)).await.unwrap_err();
+        assert_eq!(err.as_response_error().status_code(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_evicted_session_token_is_rejected() {
+        use crate::client_registry::{CreateClientSession, RecordIssuedToken, RegisterAnonymousClient};
+
+        let registry = ClientRegistryActor::new().with_max_sessions_per_client(1).start();
+        let (client_id, session_token) = registry.send(RegisterAnonymousClient).await.unwrap();
+        let token = common::utils::generate_jwt_token(&client_id, WALLET, Config::default().jwt_secret.as_bytes()).unwrap();
+        let claims = common::utils::validate_jwt_claims(&token, Config::default().jwt_secret.as_bytes(), 0).unwrap();
+        registry.send(RecordIssuedToken {
+            session_token,
+            jti: claims.jti,
+            expires_at: chrono::Utc::now() + chrono::Duration::days(1),
+        }).await.unwrap();
+
+        // A newer session pushes the first out of the per-client cap
+        registry.send(CreateClientSession { client_id }).await.unwrap();
+        let err = call_with_registry(registry, Some(&token)).await.unwrap_err();
+        assert_eq!(err.as_response_error().status_code(), 401);
+    }
+}
@@ -1,4 +1,10 @@
 // web-server/src/middleware/mod.rs
+pub mod compression;
+pub mod cors;
+pub mod jwt_auth;
 pub mod rate_limiter;
 
-pub use rate_limiter::RateLimiter;
\ No newline at end of file
+pub use compression::{AssetCompression, CompressionAlgorithm};
+pub use cors::cors;
+pub use jwt_auth::{AuthenticatedClient, JwtAuth};
+pub use rate_limiter::{KeyStrategy, RateLimiter};
\ No newline at end of file
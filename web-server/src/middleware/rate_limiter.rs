@@ -1,24 +1,27 @@
 // web-server/src/middleware/rate_limiter.rs
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
-use std::time::{Instant, Duration};
-use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use actix_web::{
     dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
     http::header,
     Error, ResponseError,
     HttpResponse
 };
+use common::TokenBucket;
+use crate::api::sessions::SESSION_COOKIE_NAME;
 use futures_util::future::{LocalBoxFuture, Ready, ready};
 use std::fmt;
 
-// Client creation limits
+// Default client creation limits: a burst of 3, then one more every 20 seconds
 const MAX_REQUESTS_PER_MINUTE: usize = 3;
 const RATE_LIMIT_WINDOW_SECONDS: u64 = 60;
 
 // Custom error for rate limiting
 #[derive(Debug)]
-struct RateLimitExceeded;
+struct RateLimitExceeded {
+    retry_after_secs: u64,
+}
 
 impl fmt::Display for RateLimitExceeded {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -29,42 +32,119 @@ impl fmt::Display for RateLimitExceeded {
 impl ResponseError for RateLimitExceeded {
     fn error_response(&self) -> HttpResponse {
         HttpResponse::TooManyRequests()
-            .append_header((header::RETRY_AFTER, "60"))
+            .append_header((header::RETRY_AFTER, self.retry_after_secs.to_string()))
             .body("Rate limit exceeded. Please try again later.")
     }
 }
 
-// Store for rate limit data
-#[derive(Debug, Clone, Default)]
+// What a request's bucket is keyed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyStrategy {
+    // The client IP, shared by everyone behind the same NAT or proxy
+    #[default]
+    Ip,
+    // The session cookie when the request carries one, else the client IP
+    SessionThenIp,
+}
+
+impl std::str::FromStr for KeyStrategy {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ip" => Ok(KeyStrategy::Ip),
+            "session_then_ip" => Ok(KeyStrategy::SessionThenIp),
+            other => Err(format!("unknown rate limit key strategy: {}", other)),
+        }
+    }
+}
+
+// Token bucket per client key. Tokens refill continuously with elapsed time,
+// so unlike a fixed window there is no boundary to burst across
+#[derive(Debug, Clone)]
 pub struct RateLimiter {
     paths: Vec<String>,
-    store: Arc<Mutex<HashMap<String, (Vec<Instant>, Instant)>>>,
+    max_requests: usize, // Sustained requests allowed per key within each window
+    window: Duration,
+    burst: u32, // Requests an idle key may make at once
+    key_strategy: KeyStrategy,
+    store: Arc<Mutex<HashMap<String, TokenBucket>>>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
 }
 
 impl RateLimiter {
     pub fn new(paths: Vec<String>) -> Self {
+        Self::with_limits(paths, MAX_REQUESTS_PER_MINUTE, Duration::from_secs(RATE_LIMIT_WINDOW_SECONDS))
+    }
+    
+    // Allow `max_requests` per key within each `window` on `paths`, refilled
+    // evenly across the window, with bursts of up to `max_requests`
+    pub fn with_limits(paths: Vec<String>, max_requests: usize, window: Duration) -> Self {
         Self { 
             paths,
+            max_requests,
+            window,
+            burst: u32::try_from(max_requests).unwrap_or(u32::MAX),
+            key_strategy: KeyStrategy::default(),
             store: Arc::new(Mutex::new(HashMap::new())),
         }
     }
     
-    fn is_rate_limited(&self, ip: &str) -> bool {
-        let mut store = self.store.lock().unwrap();
-        let now = Instant::now();
-        
-        let entry = store.entry(ip.to_string()).or_insert_with(|| (Vec::new(), now));
-        
-        if now.duration_since(entry.1) > Duration::from_secs(60) {
-            entry.0.retain(|time| now.duration_since(*time) < Duration::from_secs(RATE_LIMIT_WINDOW_SECONDS));
-            entry.1 = now;
+    // Allow bursts of `burst` requests per key, whatever the sustained rate
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst;
+        self
+    }
+    
+    pub fn with_key_strategy(mut self, key_strategy: KeyStrategy) -> Self {
+        self.key_strategy = key_strategy;
+        self
+    }
+    
+    // Bucket key for a request. Prefixed so a cookie value can never collide
+    // with an IP's bucket
+    fn bucket_key(&self, req: &ServiceRequest) -> String {
+        if self.key_strategy == KeyStrategy::SessionThenIp {
+            let session = req.cookie(SESSION_COOKIE_NAME)
+                .map(|cookie| cookie.value().to_string())
+                .filter(|token| !token.is_empty());
+            if let Some(session) = session {
+                return format!("session:{}", session);
+            }
         }
         
-        if entry.0.len() >= MAX_REQUESTS_PER_MINUTE {
-            true
+        let ip = req.connection_info().realip_remote_addr()
+            .unwrap_or("unknown")
+            .to_string();
+        format!("ip:{}", ip)
+    }
+    
+    fn refill_per_sec(&self) -> f64 {
+        self.max_requests as f64 / self.window.as_secs_f64()
+    }
+    
+    fn is_rate_limited(&self, key: &str) -> bool {
+        self.is_rate_limited_at(key, Instant::now())
+    }
+    
+    fn is_rate_limited_at(&self, key: &str, now: Instant) -> bool {
+        let mut store = self.store.lock().unwrap();
+        let bucket = store.entry(key.to_string())
+            .or_insert_with(|| TokenBucket::with_refill_rate(self.refill_per_sec(), self.burst));
+        !bucket.try_acquire_at(now)
+    }
+    
+    // Seconds until an empty bucket has a token again
+    fn retry_after_secs(&self) -> u64 {
+        if self.max_requests > 0 {
+            (self.window.as_secs_f64() / self.max_requests as f64).ceil() as u64
         } else {
-            entry.0.push(now);
-            false
+            self.window.as_secs()
         }
     }
 }
@@ -112,18 +192,18 @@ where
         let should_rate_limit = self.limiter.paths.iter().any(|p| path.starts_with(p));
         
         if should_rate_limit {
-            // Get client IP
-            let ip = req.connection_info().realip_remote_addr()
-                .unwrap_or("unknown")
-                .to_string();
+            let key = self.limiter.bucket_key(&req);
             
             // Check if rate limited
-            if self.limiter.is_rate_limited(&ip) {
+            if self.limiter.is_rate_limited(&key) {
+                // Never log the key itself, it may be a session token
+                let ip = req.connection_info().realip_remote_addr().unwrap_or("unknown").to_string();
                 tracing::warn!("Rate limit exceeded for IP: {}", ip);
                 
                 // Create error future
-                return Box::pin(async { 
-                    Err(RateLimitExceeded.into()) 
+                let retry_after_secs = self.limiter.retry_after_secs();
+                return Box::pin(async move { 
+                    Err(RateLimitExceeded { retry_after_secs }.into()) 
                 });
             }
         }
@@ -134,4 +214,137 @@ where
             fut.await
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{init_service, try_call_service, TestRequest};
+    use actix_web::{web, App};
+    
+    #[test]
+    fn test_limits_apply_per_construction() {
+        let limiter = RateLimiter::with_limits(Vec::new(), 10, Duration::from_secs(60));
+        for _ in 0..10 {
+            assert!(!limiter.is_rate_limited("10.0.0.1"));
+        }
+        assert!(limiter.is_rate_limited("10.0.0.1"));
+        // Other IPs have their own count
+        assert!(!limiter.is_rate_limited("10.0.0.2"));
+        
+        // `new` keeps the original three per minute
+        let limiter = RateLimiter::new(Vec::new());
+        assert_eq!((limiter.max_requests, limiter.window), (3, Duration::from_secs(60)));
+    }
+    
+    #[test]
+    fn test_sustained_rate_never_trips_limiter() {
+        let limiter = RateLimiter::with_limits(Vec::new(), 2, Duration::from_secs(1));
+        let start = Instant::now();
+        
+        // One request every half second matches the refill rate exactly,
+        // including across what would have been fixed-window boundaries
+        for i in 0..240 {
+            assert!(!limiter.is_rate_limited_at("10.0.0.1", start + Duration::from_millis(500 * i)));
+        }
+    }
+    
+    #[test]
+    fn test_burst_beyond_capacity_is_limited_per_ip() {
+        let limiter = RateLimiter::with_limits(Vec::new(), 2, Duration::from_secs(1)).with_burst(3);
+        let start = Instant::now();
+        
+        for _ in 0..3 {
+            assert!(!limiter.is_rate_limited_at("10.0.0.1", start));
+        }
+        assert!(limiter.is_rate_limited_at("10.0.0.1", start));
+        // Other IPs have their own bucket
+        assert!(!limiter.is_rate_limited_at("10.0.0.2", start));
+        
+        // Half a second at 2/sec buys exactly one more request
+        let later = start + Duration::from_millis(500);
+        assert!(!limiter.is_rate_limited_at("10.0.0.1", later));
+        assert!(limiter.is_rate_limited_at("10.0.0.1", later));
+    }
+    
+    #[actix_web::test]
+    async fn test_only_configured_paths_are_limited() {
+        let app = init_service(
+            App::new()
+                .wrap(RateLimiter::with_limits(vec!["/api/client".to_string()], 1, Duration::from_secs(2)))
+                .route("/api/client", web::post().to(HttpResponse::Ok))
+                .route("/health", web::get().to(HttpResponse::Ok))
+        ).await;
+        
+        let res = try_call_service(&app, TestRequest::post().uri("/api/client").to_request()).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let err = try_call_service(&app, TestRequest::post().uri("/api/client").to_request()).await.unwrap_err();
+        let res = err.error_response();
+        assert_eq!(res.status(), 429);
+        assert_eq!(res.headers().get(header::RETRY_AFTER).unwrap(), "2");
+        
+        for _ in 0..3 {
+            let res = try_call_service(&app, TestRequest::get().uri("/health").to_request()).await.unwrap();
+            assert_eq!(res.status(), 200);
+        }
+    }
+    
+    fn client_request(ip: &str, session: Option<&str>) -> TestRequest {
+        let req = TestRequest::post()
+            .uri("/api/client")
+            .peer_addr(format!("{}:40000", ip).parse().unwrap());
+        match session {
+            Some(session) => req.cookie(actix_web::cookie::Cookie::new(SESSION_COOKIE_NAME, session)),
+            None => req,
+        }
+    }
+    
+    fn status(result: Result<ServiceResponse, Error>) -> u16 {
+        match result {
+            Ok(res) => res.status().as_u16(),
+            Err(err) => err.error_response().status().as_u16(),
+        }
+    }
+    
+    #[actix_web::test]
+    async fn test_session_key_shares_bucket_across_ips() {
+        let app = init_service(
+            App::new()
+                .wrap(RateLimiter::with_limits(vec!["/api/client".to_string()], 1, Duration::from_secs(100))
+                    .with_key_strategy(KeyStrategy::SessionThenIp))
+                .route("/api/client", web::post().to(HttpResponse::Ok))
+        ).await;
+        let post = |ip, session| try_call_service(&app, client_request(ip, session).to_request());
+        
+        // Rotating IPs does not escape the session's bucket
+        assert_eq!(status(post("10.0.0.1", Some("token-a")).await), 200);
+        assert_eq!(status(post("10.0.0.2", Some("token-a")).await), 429);
+        // Another session behind the same NAT is not throttled by the first
+        assert_eq!(status(post("10.0.0.1", Some("token-b")).await), 200);
+        
+        // Without a cookie each IP has its own bucket again
+        assert_eq!(status(post("10.0.0.3", None).await), 200);
+        assert_eq!(status(post("10.0.0.3", None).await), 429);
+        assert_eq!(status(post("10.0.0.4", None).await), 200);
+    }
+    
+    #[actix_web::test]
+    async fn test_ip_key_ignores_session_cookie() {
+        let app = init_service(
+            App::new()
+                .wrap(RateLimiter::with_limits(vec!["/api/client".to_string()], 1, Duration::from_secs(100)))
+                .route("/api/client", web::post().to(HttpResponse::Ok))
+        ).await;
+        let post = |ip, session| try_call_service(&app, client_request(ip, session).to_request());
+        
+        assert_eq!(status(post("10.0.0.1", Some("token-a")).await), 200);
+        assert_eq!(status(post("10.0.0.1", Some("token-b")).await), 429);
+    }
+    
+    #[test]
+    fn test_key_strategy_parses_from_config_values() {
+        assert_eq!("ip".parse(), Ok(KeyStrategy::Ip));
+        assert_eq!("Session_Then_IP".parse(), Ok(KeyStrategy::SessionThenIp));
+        assert!("cookie".parse::<KeyStrategy>().is_err());
+    }
+}
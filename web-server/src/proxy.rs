@@ -1,52 +1,114 @@
 // web-server/src/proxy.rs
 use actix::{Actor, StreamHandler, AsyncContext, Context, ActorContext, Addr, Message, Handler};
-use actix_web::{web, HttpRequest, HttpResponse, Error};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Error};
 use actix_web_actors::ws;
 use dashmap::DashMap;
 use serde::{Serialize, Deserialize};
 use tokio::sync::mpsc;
 use futures::{StreamExt, SinkExt};
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message as WsMessage};
+use tokio_tungstenite::{connect_async_tls_with_config, tungstenite::protocol::Message as WsMessage, Connector};
 use tokio_tungstenite::tungstenite::protocol::frame::CloseFrame;
-use common::Config;
+use common::{backoff_with_jitter, Config, ConnectionFilter, DuplicateConnectionPolicy, ErrorFrame, ProtocolVersion, SessionFrame, TENANT_ID_HEADER, WALLET_ADDRESS_HEADER};
 use common::models::session::SessionResult;
 use tokio_tungstenite::tungstenite::error::Error as WsError;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
 use uuid::Uuid;
 use std::time::{Duration, Instant};
 use std::borrow::Cow;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::convert::TryFrom;
 use tungstenite::protocol::frame::coding::CloseCode as TungsteniteCloseCode;
 // use common::utils::jwt;
 
+use crate::api::sessions::SESSION_COOKIE_NAME;
 use crate::client_registry::{ClientRegistryActor, GetClientSession, UpdateSessionActivity};
+use crate::middleware::{AuthenticatedClient, JwtAuth};
+use crate::security::{client_ip, SecurityMonitor};
 
 // Shared state for active WebSocket connections
 pub struct ActiveConnections {
-    // Maps session token to ProxyActor address
-    connections: DashMap<String, Addr<ProxyActor>>,
+    // Maps session token to its ProxyActor addresses; more than one only
+    // under the allow_both duplicate connection policy, or while a replaced
+    // connection is closing
+    connections: DashMap<String, Vec<Addr<ProxyActor>>>,
+    // Set once shutdown begins; new upgrades are refused from then on
+    draining: AtomicBool,
 }
 
 impl ActiveConnections {
     pub fn new() -> Self {
         Self {
             connections: DashMap::new(),
+            draining: AtomicBool::new(false),
         }
     }
     
     // Register a new connection
-    pub fn register(&self, session_token: String, addr: Addr<ProxyActor>) -> Option<Addr<ProxyActor>> {
-        self.connections.insert(session_token, addr)
+    pub fn register(&self, session_token: String, addr: Addr<ProxyActor>) {
+        self.connections.entry(session_token).or_default().push(addr);
     }
     
-    // Unregister a connection
-    pub fn unregister(&self, session_token: &str) -> bool {
-        self.connections.remove(session_token).is_some()
+    // Unregister a connection, forgetting the session once it has none left
+    pub fn unregister(&self, session_token: &str, addr: &Addr<ProxyActor>) -> bool {
+        let Some(mut addrs) = self.connections.get_mut(session_token) else {
+            return false;
+        };
+        let before = addrs.len();
+        addrs.retain(|current| current != addr);
+        let removed = addrs.len() < before;
+        drop(addrs);
+        self.connections.remove_if(session_token, |_, addrs| addrs.is_empty());
+        removed
+    }
+    
+    // Whether the session has an open connection
+    pub fn has_connection(&self, session_token: &str) -> bool {
+        self.connections.get(session_token).is_some_and(|addrs| !addrs.is_empty())
+    }
+    
+    // Tell the connections currently holding this session that they have been replaced.
+    // Returns whether any existed.
+    pub fn notify_replaced(&self, session_token: &str) -> bool {
+        let existing = self.connections.get(session_token)
+            .map(|entry| entry.value().clone())
+            .unwrap_or_default();
+        for addr in &existing {
+            addr.do_send(ProxyMessage::ConnectionReplaced);
+        }
+        !existing.is_empty()
     }
     
     // Get connection count
     pub fn count(&self) -> usize {
-        self.connections.len()
+        self.connections.iter().map(|entry| entry.value().len()).sum()
+    }
+    
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+    
+    // Refuse new connections, ask every open one to close for shutdown, and
+    // wait up to `grace` for them to go
+    pub async fn drain(&self, grace: Duration) {
+        self.draining.store(true, Ordering::SeqCst);
+        
+        tracing::info!("Draining {} proxy connections", self.count());
+        for entry in self.connections.iter() {
+            for addr in entry.value() {
+                addr.do_send(ProxyMessage::ServerShutdown);
+            }
+        }
+        
+        let deadline = Instant::now() + grace;
+        while self.count() > 0 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        
+        if self.count() > 0 {
+            tracing::warn!("{} proxy connections still open after {:?}", self.count(), grace);
+        }
     }
 }
 
@@ -65,17 +127,100 @@ pub enum ProxyMessage {
     WebSocketPing,
     WebSocketPong,
     WebSocketClose,
-    Disconnected,
+    ConnectionReplaced,
+    ServerShutdown,
+    // The upstream connection of this generation was lost
+    Disconnected(u64),
+}
+
+// Application close code sent to a connection displaced by a newer one
+pub const CLOSE_CODE_CONNECTION_REPLACED: u16 = 4000;
+
+// Close reason telling a displaced client why it was disconnected
+fn connection_replaced_reason() -> ws::CloseReason {
+    ws::CloseReason {
+        code: ws::CloseCode::Other(CLOSE_CODE_CONNECTION_REPLACED),
+        description: Some("Connection replaced by a newer session".to_string()),
+    }
+}
+
+// Close reason telling clients the server is going away
+fn server_shutdown_reason() -> ws::CloseReason {
+    ws::CloseReason {
+        code: ws::CloseCode::Away,
+        description: Some("Server shutting down".to_string()),
+    }
+}
+
+// Close reason for a client that sent a frame over the size limit
+fn frame_too_large_reason() -> ws::CloseReason {
+    ws::CloseReason {
+        code: ws::CloseCode::Policy,
+        description: Some("Frame too large".to_string()),
+    }
+}
+
+// Translate a client close reason into the frame forwarded upstream, keeping
+// the client's close code and description
+fn upstream_close_frame(reason: Option<&ws::CloseReason>) -> Option<CloseFrame<'static>> {
+    reason.map(|r| CloseFrame {
+        code: TungsteniteCloseCode::from(u16::from(r.code)),
+        reason: Cow::Owned(r.description.clone().unwrap_or_default()),
+    })
+}
+
+// A client frame's payload as tungstenite takes it. This reuses the frame's
+// buffer when nothing else holds it, but the codec's read buffer usually
+// still does, making this the one copy of the payload on its way upstream
+fn upstream_payload(payload: web::Bytes) -> Vec<u8> {
+    Vec::from(payload)
+}
+
+// Token a reconnecting client presents to resume its session upstream
+#[derive(Deserialize)]
+struct ResumeQuery {
+    resume_token: Option<String>,
+}
+
+// An upstream websocket-server and its failure history
+struct Backend {
+    url: String,
+    failures: u32,
+    last_failure: Option<Instant>,
+}
+
+impl Backend {
+    fn new(url: String) -> Self {
+        Self { url, failures: 0, last_failure: None }
+    }
 }
 
 // Enhanced ProxyActor with real proxying and session validation
 pub struct ProxyActor {
     client_id: Uuid,
+    connection_id: Uuid, // Unique per connection, assigned in started()
     session_token: Option<String>,
+    tenant_id: Option<String>, // Forwarded upstream so the client only reaches its tenant's agents
+    upstream_token: Option<String>, // Pre-shared key vouching for this proxy to the websocket-server
+    upstream_tls: Option<Arc<rustls::ClientConfig>>, // Certificates trusted for wss:// upstreams
+    max_frame_bytes: usize, // Larger client frames close the connection instead of being forwarded
+    protocol_version: Option<ProtocolVersion>, // Envelope version the client negotiated, requested upstream
+    resume_token: Option<String>, // Resumes the client's session upstream; the latest session frame's
+    authenticated: bool, // Whether the client presented a valid JWT
+    wallet_address: Option<String>, // Wallet proven by that JWT
     ws_sink: Option<mpsc::Sender<WsMessage>>,
     last_heartbeat: Instant,
+    heartbeat_interval: Duration,
+    heartbeat_timeout: Duration,
     reconnect_attempts: u32,
-    ws_server_url: String,
+    // A reconnect is scheduled; further failure reports are absorbed until it runs
+    reconnect_pending: bool,
+    // Bumped per upstream connection, so a late report from one already
+    // replaced is not mistaken for a new failure
+    upstream_generation: u64,
+    // Upstream websocket-servers, with the one currently in use
+    backends: Vec<Backend>,
+    current_backend: usize,
     // Flag to track if we're connected to WebSocket server
     is_connected_to_server: bool,
     // Registry for client session validation
@@ -85,53 +230,110 @@ pub struct ProxyActor {
 }
 
 impl ProxyActor {
+    // `ws_server_urls` are tried in order, failing over to the next on errors
     pub fn new(
         client_id: Uuid, 
-        ws_server_url: String, 
+        ws_server_urls: Vec<String>, 
         session_token: Option<String>,
         registry: Option<Addr<ClientRegistryActor>>,
         active_connections: Option<web::Data<ActiveConnections>>
     ) -> Self {
         Self { 
             client_id,
+            connection_id: Uuid::nil(),
             session_token,
+            tenant_id: None,
+            upstream_token: None,
+            upstream_tls: None,
+            max_frame_bytes: 64 * 1024,
+            protocol_version: None,
+            resume_token: None,
+            authenticated: false,
+            wallet_address: None,
             ws_sink: None,
             last_heartbeat: Instant::now(),
+            heartbeat_interval: Duration::from_secs(5),
+            heartbeat_timeout: Duration::from_secs(30),
             reconnect_attempts: 0,
-            ws_server_url,
+            reconnect_pending: false,
+            upstream_generation: 0,
+            backends: ws_server_urls.into_iter().map(Backend::new).collect(),
+            current_backend: 0,
             is_connected_to_server: false,
             registry,
             active_connections,
         }
     }
     
+    pub fn with_tenant_id(mut self, tenant_id: Option<String>) -> Self {
+        self.tenant_id = tenant_id;
+        self
+    }
+    
+    pub fn with_upstream_token(mut self, upstream_token: Option<String>) -> Self {
+        self.upstream_token = upstream_token;
+        self
+    }
+    
+    pub fn with_upstream_tls(mut self, upstream_tls: Option<Arc<rustls::ClientConfig>>) -> Self {
+        self.upstream_tls = upstream_tls;
+        self
+    }
+    
+    pub fn with_max_frame_bytes(mut self, max_frame_bytes: usize) -> Self {
+        self.max_frame_bytes = max_frame_bytes;
+        self
+    }
+    
+    pub fn with_protocol_version(mut self, protocol_version: Option<ProtocolVersion>) -> Self {
+        self.protocol_version = protocol_version;
+        self
+    }
+    
+    pub fn with_resume_token(mut self, resume_token: Option<String>) -> Self {
+        self.resume_token = resume_token;
+        self
+    }
+    
+    // Mark the client as authenticated by a JWT for `wallet_address`
+    pub fn with_authentication(mut self, wallet_address: Option<String>) -> Self {
+        self.authenticated = wallet_address.is_some();
+        self.wallet_address = wallet_address;
+        self
+    }
+    
+    // Upstream connection request, carrying the proxy's credentials and the
+    // client's tenant, authenticated wallet and protocol version when set
+    fn upstream_request(&self, ws_url: String) -> Result<tokio_tungstenite::tungstenite::handshake::client::Request, Box<WsError>> {
+        let mut request = ws_url.into_client_request()?;
+        if let Some(protocol_version) = self.protocol_version {
+            request.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static(protocol_version.subprotocol()));
+        }
+        if let Some(tenant_id) = &self.tenant_id {
+            let value = HeaderValue::from_str(tenant_id)
+                .map_err(|e| WsError::HttpFormat(e.into()))?;
+            request.headers_mut().insert(TENANT_ID_HEADER, value);
+        }
+        if let Some(wallet_address) = self.wallet_address.as_ref().filter(|_| self.authenticated) {
+            let value = HeaderValue::from_str(wallet_address)
+                .map_err(|e| WsError::HttpFormat(e.into()))?;
+            request.headers_mut().insert(WALLET_ADDRESS_HEADER, value);
+        }
+        if let Some(token) = &self.upstream_token {
+            let value = HeaderValue::from_str(&format!("Bearer {}", token))
+                .map_err(|e| WsError::HttpFormat(e.into()))?;
+            request.headers_mut().insert("Authorization", value);
+        }
+        Ok(request)
+    }
+    
     // Heartbeat to check client connection
     fn heartbeat(&self, ctx: &mut ws::WebsocketContext<Self>) {
-        ctx.run_interval(Duration::from_secs(5), |act, ctx| {
-            if Instant::now().duration_since(act.last_heartbeat) > Duration::from_secs(30) {
+        ctx.run_interval(self.heartbeat_interval, |act, ctx| {
+            if Instant::now().duration_since(act.last_heartbeat) > act.heartbeat_timeout {
                 // Heartbeat timeout - attempt reconnection to WebSocket server
-                tracing::warn!("Client heartbeat timeout: {}", act.client_id);
-                
-                // Close current connection if it exists
-                if act.ws_sink.is_some() {
-                    act.ws_sink = None;
-                    act.is_connected_to_server = false;
-                }
-                
-                // Calculate backoff for reconnection
-                let backoff = std::cmp::min(
-                    2u64.pow(act.reconnect_attempts),
-                    60 // Cap at 60 seconds
-                );
-                
-                ctx.run_later(Duration::from_secs(backoff), |act, ctx| {
-                    tracing::info!("Attempting reconnection for client: {}", act.client_id);
-                    act.connect_to_ws_server(ctx);
-                });
-                
-                // Increment reconnect counter
-                act.reconnect_attempts += 1;
-                
+                tracing::warn!(connection_id = %act.connection_id, "Client heartbeat timeout: {}", act.client_id);
+                act.schedule_reconnect(ctx);
                 return;
             }
             
@@ -140,13 +342,101 @@ impl ProxyActor {
         });
     }
     
+    // Close both sides of the connection rather than forward a frame over
+    // `max_frame_bytes` upstream
+    fn reject_oversized_frame(&mut self, len: usize, ctx: &mut ws::WebsocketContext<Self>) {
+        tracing::warn!(
+            connection_id = %self.connection_id,
+            "Client {} sent a {} byte frame, over the {} byte limit; closing", self.client_id, len, self.max_frame_bytes
+        );
+        ctx.text(ErrorFrame::new(ErrorFrame::FRAME_TOO_LARGE, "Frame too large")
+            .with_detail(serde_json::json!({ "max_frame_bytes": self.max_frame_bytes }))
+            .to_json());
+        self.close_frame_too_large(ctx);
+    }
+    
+    fn close_frame_too_large(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        let reason = frame_too_large_reason();
+        if let Some(tx) = &self.ws_sink {
+            let _ = tx.try_send(WsMessage::Close(upstream_close_frame(Some(&reason))));
+        }
+        ctx.close(Some(reason));
+        ctx.stop();
+    }
+    
+    // Record a failure of the backend in use and switch to the one that has
+    // gone longest without failing, preferring backends that never failed
+    // and otherwise rotating onward from the failed one. Returns whether the
+    // new backend has failed before.
+    fn fail_over(&mut self) -> bool {
+        if let Some(backend) = self.backends.get_mut(self.current_backend) {
+            backend.failures += 1;
+            backend.last_failure = Some(Instant::now());
+            tracing::warn!(
+                connection_id = %self.connection_id,
+                "Backend {} failed ({} failures so far)", backend.url, backend.failures
+            );
+        }
+        
+        let count = self.backends.len();
+        let next = (1..=count)
+            .map(|offset| (self.current_backend + offset) % count)
+            .min_by_key(|&index| self.backends[index].last_failure);
+        if let Some(next) = next {
+            if next != self.current_backend {
+                tracing::info!("Failing over client {} to backend {}", self.client_id, self.backends[next].url);
+            }
+            self.current_backend = next;
+        }
+        next.is_some_and(|index| self.backends[index].failures > 0)
+    }
+    
+    // Drop the upstream connection and reconnect after a backoff. A heartbeat
+    // timeout and the Disconnected it causes are one failure, so only the
+    // first report schedules a reconnect and counts an attempt
+    fn schedule_reconnect(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        self.ws_sink = None;
+        self.is_connected_to_server = false;
+        
+        if self.reconnect_pending {
+            tracing::debug!("Reconnect already scheduled for client: {}", self.client_id);
+            return;
+        }
+        self.reconnect_pending = true;
+        
+        // A backend that has not failed yet is tried straight away; once
+        // every backend has failed, back off with jitter, capped at 60 seconds
+        let backoff = if self.fail_over() {
+            backoff_with_jitter(self.reconnect_attempts, 60)
+        } else {
+            Duration::ZERO
+        };
+        self.reconnect_attempts = self.reconnect_attempts.saturating_add(1);
+        
+        ctx.run_later(backoff, |act, ctx| {
+            act.reconnect_pending = false;
+            tracing::info!("Attempting reconnection for client: {}", act.client_id);
+            act.connect_to_ws_server(ctx);
+        });
+    }
+    
     // Connect to WebSocket server
     fn connect_to_ws_server(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
         // Reset heartbeat
         self.last_heartbeat = Instant::now();
         
         // Create the WebSocket server URL with client ID
-        let ws_url = format!("{}/ws/client/{}", self.ws_server_url, self.client_id);
+        let Some(backend) = self.backends.get(self.current_backend) else {
+            tracing::error!("No websocket-server configured for client: {}", self.client_id);
+            return;
+        };
+        let mut ws_url = format!("{}/ws/client/{}", backend.url, self.client_id);
+        if let Some(resume_token) = &self.resume_token {
+            let resume_token: String = url::form_urlencoded::byte_serialize(resume_token.as_bytes()).collect();
+            ws_url = format!("{}?resume_token={}", ws_url, resume_token);
+        }
+        let request = self.upstream_request(ws_url);
+        let connector = self.upstream_tls.clone().map(Connector::Rustls);
         
         // Create channel for communication
         let (tx, mut rx) = mpsc::channel::<WsMessage>(100);
@@ -154,10 +444,16 @@ impl ProxyActor {
         
         // Get context address to communicate back
         let addr = ctx.address();
+        self.upstream_generation += 1;
+        let generation = self.upstream_generation;
         
         // Spawn connection task
         let fut = async move {
-            match connect_async(ws_url).await {
+            let connection = match request {
+                Ok(request) => connect_async_tls_with_config(request, None, connector).await,
+                Err(e) => Err(*e),
+            };
+            match connection {
                 Ok((ws_stream, _)) => {
                     let (mut ws_sink, mut ws_stream) = ws_stream.split();
                     
@@ -195,18 +491,18 @@ impl ProxyActor {
                             },
                             Err(e) => {
                                 tracing::error!("WebSocket error: {}", e);
-                                addr.do_send(ProxyMessage::Disconnected);
+                                addr.do_send(ProxyMessage::Disconnected(generation));
                                 break;
                             }
                         }
                     }
                     
                     // Connection closed
-                    addr.do_send(ProxyMessage::Disconnected);
+                    addr.do_send(ProxyMessage::Disconnected(generation));
                 },
                 Err(e) => {
                     tracing::error!("Failed to connect to WebSocket server: {}", e);
-                    addr.do_send(ProxyMessage::Disconnected);
+                    addr.do_send(ProxyMessage::Disconnected(generation));
                 }
             }
         };
@@ -233,7 +529,13 @@ impl Actor for ProxyActor {
     type Context = ws::WebsocketContext<Self>;
     
     fn started(&mut self, ctx: &mut Self::Context) {
-        tracing::info!("Proxy started for client: {}", self.client_id);
+        self.connection_id = Uuid::new_v4();
+        tracing::info!(
+            connection_id = %self.connection_id,
+            authenticated = self.authenticated,
+            wallet_address = self.wallet_address.as_deref().unwrap_or_default(),
+            "Proxy started for client: {}", self.client_id
+        );
         
         // Setup heartbeat
         self.heartbeat(ctx);
@@ -252,13 +554,13 @@ impl Actor for ProxyActor {
         }
     }
     
-    fn stopped(&mut self, _ctx: &mut Self::Context) {
-        tracing::info!("Proxy stopped for client: {}", self.client_id);
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        tracing::info!(connection_id = %self.connection_id, "Proxy stopped for client: {}", self.client_id);
         
         // Unregister from active connections if we have a session token
         if let Some(session_token) = &self.session_token {
             if let Some(active_conns) = &self.active_connections {
-                active_conns.unregister(session_token);
+                active_conns.unregister(session_token, &ctx.address());
                 tracing::debug!("Unregistered from active connections: {}", self.client_id);
             }
         }
@@ -277,12 +579,18 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ProxyActor {
                 
                 // Forward ping to WebSocket server
                 if let Some(tx) = &self.ws_sink {
-                    let _ = tx.try_send(WsMessage::Ping(msg.to_vec()));
+                    let _ = tx.try_send(WsMessage::Ping(upstream_payload(msg)));
                 }
             },
             Ok(ws::Message::Pong(_)) => {
                 // Just update the heartbeat timestamp
             },
+            Ok(ws::Message::Text(text)) if text.len() > self.max_frame_bytes => {
+                self.reject_oversized_frame(text.len(), ctx);
+            },
+            Ok(ws::Message::Binary(bin)) if bin.len() > self.max_frame_bytes => {
+                self.reject_oversized_frame(bin.len(), ctx);
+            },
             Ok(ws::Message::Text(text)) => {
                 // Forward text message to WebSocket server
                 tracing::debug!("Forwarding message from client {} to server: {}", self.client_id, text);
@@ -301,7 +609,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ProxyActor {
             Ok(ws::Message::Binary(bin)) => {
                 // Forward binary message to WebSocket server
                 if let Some(tx) = &self.ws_sink {
-                    let _ = tx.try_send(WsMessage::Binary(bin.to_vec()));
+                    let _ = tx.try_send(WsMessage::Binary(upstream_payload(bin)));
                 }
             }, 
             Ok(ws::Message::Close(reason)) => {
@@ -313,7 +621,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ProxyActor {
                             r.description
                         );
                     }
-                    let _ = tx.try_send(WsMessage::Close(None));
+                    let _ = tx.try_send(WsMessage::Close(upstream_close_frame(reason.as_ref())));
                 }
                 ctx.close(reason);
             },
@@ -325,6 +633,10 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ProxyActor {
             },
             Err(e) => {
                 tracing::error!("WebSocket protocol error: {}", e);
+                // The codec refuses frames over the limit before they reach us
+                if matches!(e, ws::ProtocolError::Overflow) {
+                    self.close_frame_too_large(ctx);
+                }
             }
         }
     }
@@ -336,11 +648,17 @@ impl Handler<ProxyMessage> for ProxyActor {
     fn handle(&mut self, msg: ProxyMessage, ctx: &mut Self::Context) -> Self::Result {
         match msg {
             ProxyMessage::WebSocketMessage(text) => {
+                // Each upstream connection issues a token for resuming it
+                if let Some(session) = SessionFrame::parse(&text) {
+                    self.resume_token = Some(session.resume_token);
+                }
                 // Forward text message to client
                 ctx.text(text);
             },
             ProxyMessage::WebSocketBinary(data) => {
-                // Forward binary message to client
+                // Forward binary message to client; the Vec becomes the
+                // frame's `Bytes` without a copy. The payload is still copied
+                // by tungstenite's reads and the encoder's socket write
                 ctx.binary(data);
             },
             ProxyMessage::WebSocketPing => {
@@ -354,42 +672,56 @@ impl Handler<ProxyMessage> for ProxyActor {
                 // Close client connection
                 ctx.close(None);
             },
-            ProxyMessage::Disconnected => {
-                tracing::warn!("WebSocket server connection lost for client: {}", self.client_id);
-                
-                // Clear sink
-                self.ws_sink = None;
-                self.is_connected_to_server = false;
-                
-                // Attempt reconnection
-                let backoff = std::cmp::min(
-                    2u64.pow(self.reconnect_attempts),
-                    60 // Cap at 60 seconds
+            ProxyMessage::ConnectionReplaced => {
+                tracing::info!(
+                    connection_id = %self.connection_id,
+                    "Connection for client {} replaced by a newer session, closing", self.client_id
+                );
+                ctx.close(Some(connection_replaced_reason()));
+                ctx.stop();
+            },
+            ProxyMessage::ServerShutdown => {
+                tracing::info!(connection_id = %self.connection_id, "Closing connection for client {} on shutdown", self.client_id);
+                let reason = server_shutdown_reason();
+                if let Some(tx) = &self.ws_sink {
+                    let _ = tx.try_send(WsMessage::Close(upstream_close_frame(Some(&reason))));
+                }
+                ctx.close(Some(reason));
+                ctx.stop();
+            },
+            ProxyMessage::Disconnected(generation) if generation != self.upstream_generation => {
+                tracing::debug!("Ignoring loss of a replaced upstream connection for client: {}", self.client_id);
+            },
+            ProxyMessage::Disconnected(_) => {
+                tracing::warn!(
+                    connection_id = %self.connection_id,
+                    "WebSocket server connection lost for client: {}", self.client_id
                 );
                 
-                ctx.run_later(Duration::from_secs(backoff), |act, ctx| {
-                    tracing::info!("Attempting reconnection for client: {}", act.client_id);
-                    act.connect_to_ws_server(ctx);
-                });
-                
-                // Increment reconnect counter
-                self.reconnect_attempts += 1;
+                self.schedule_reconnect(ctx);
             }
         }
     }
 }
 
 // Configure proxy routes - updated for session validation
-pub fn configure(cfg: &mut web::ServiceConfig) {
-    // Create shared state for active connections
-    let active_connections = web::Data::new(ActiveConnections::new());
-    
-    // Register the active connections data
-    cfg.app_data(active_connections.clone());
+// With `upstream_tls`, the proxy connects to the websocket-server over wss://
+// trusting those certificates
+pub fn configure(
+    cfg: &mut web::ServiceConfig,
+    active_connections: web::Data<ActiveConnections>,
+    upstream_tls: Option<web::Data<rustls::ClientConfig>>,
+) {
+    // Register the active connections data, shared by all workers so shutdown can drain them
+    cfg.app_data(active_connections);
+    if let Some(upstream_tls) = upstream_tls {
+        cfg.app_data(upstream_tls);
+    }
     
-    // Configure WebSocket route
+    // Configure WebSocket route; JwtAuth attaches the bearer token's identity
     cfg.service(
         web::resource("/ws/{client_id}")
+            .wrap(JwtAuth)
             .route(web::get().to(ws_route))
     );
 }
@@ -402,6 +734,7 @@ async fn ws_route(
     config: web::Data<Config>,
     active_connections: web::Data<ActiveConnections>,
     registry: web::Data<Addr<ClientRegistryActor>>,
+    security: web::Data<SecurityMonitor>,
 ) -> Result<HttpResponse, Error> {
     // Extract client_id from path
     let client_id_str = &path.0;
@@ -410,39 +743,56 @@ async fn ws_route(
         Err(_) => return Ok(HttpResponse::BadRequest().finish()),
     };
     
+    if active_connections.is_draining() {
+        tracing::info!("Refusing connection for client {} while shutting down", client_id);
+        return Ok(HttpResponse::ServiceUnavailable().finish());
+    }
+    
+    let ip = client_ip(&req);
+    if security.is_banned(&ip) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    
+    if let Some(connection_filter) = req.app_data::<web::Data<ConnectionFilter>>() {
+        if !connection_filter.allows(req.headers()) {
+            tracing::info!("Refusing connection for client {} from {}: not allowed by the connection filter", client_id, ip);
+            return Ok(HttpResponse::Forbidden().finish());
+        }
+    }
+    
+    let requested_protocols = req.headers().get(actix_web::http::header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok());
+    let protocol = match ProtocolVersion::negotiate(requested_protocols) {
+        Ok(protocol) => protocol,
+        Err(requested) => {
+            tracing::info!("Refusing connection for client {}: unsupported protocols {}", client_id, requested);
+            let supported: Vec<&str> = ProtocolVersion::SUPPORTED.iter().map(|version| version.subprotocol()).collect();
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Unsupported WebSocket protocol",
+                "supported": supported,
+            })));
+        },
+    };
+    
+    // Tenant of the validated session, if any
+    let mut tenant_id = None;
+    
     // Get session token from cookie
-    let session_token = req.cookie("sploots_session").map(|c| c.value().to_string());
-
-        // Check for JWT in Authorization header
-        let mut authenticated_with_jwt = false;
-        if let Some(auth_header) = req.headers().get("Authorization") {
-            if let Ok(auth_str) = auth_header.to_str() {
-                if auth_str.starts_with("Bearer ") {
-                    let token = &auth_str[7..]; // Skip "Bearer "
-                    
-                    // Validate JWT
-                    match common::utils::validate_jwt_token(token, b"your_jwt_secret_key_here") {
-                        Ok((token_client_id, _wallet_address)) => {
-                            // Verify client ID matches token
-                            if token_client_id == client_id {
-                                authenticated_with_jwt = true;
-                                tracing::info!("Client {} authenticated with JWT", client_id);
-                            } else {
-                                tracing::warn!(
-                                    "JWT client ID mismatch: token has {}, request for {}", 
-                                    token_client_id, client_id
-                                );
-                                return Ok(HttpResponse::Forbidden().finish());
-                            }
-                        },
-                        Err(e) => {
-                            tracing::warn!("Invalid JWT for client {}: {}", client_id, e);
-                            // We'll continue without authentication
-                        }
-                    }
-                }
-            }
+    let session_token = req.cookie(SESSION_COOKIE_NAME).map(|c| c.value().to_string());
+    
+    // A JWT, when presented, must have been issued for this client
+    let jwt_client = req.extensions().get::<AuthenticatedClient>().cloned();
+    if let Some(jwt_client) = &jwt_client {
+        if jwt_client.client_id != client_id {
+            tracing::warn!(
+                "JWT client ID mismatch: token has {}, request for {}", 
+                jwt_client.client_id, client_id
+            );
+            security.record_client_id_mismatch(&ip, client_id, jwt_client.client_id, "jwt");
+            return Ok(HttpResponse::Forbidden().finish());
         }
+        tracing::info!("Client {} authenticated with JWT", client_id);
+    }
     
     // Validate session if token is present
     if let Some(token) = &session_token {
@@ -454,27 +804,44 @@ async fn ws_route(
                         "Client ID mismatch: requested {}, session has {}", 
                         client_id, session.client_id
                     );
+                    security.record_client_id_mismatch(&ip, client_id, session.client_id, "session");
                     return Ok(HttpResponse::Forbidden().finish());
                 }
                 
-                // Check if another connection exists for this session
-                if let Some(existing_conn) = active_connections.connections.get(token) {
-                    tracing::info!(
-                        "Existing connection found for session {}, closing it", 
-                        session.client_id
-                    );
-                    
-                    // Send close message to existing connection
-                    // This is a policy choice: last connection wins
-                    let _ = existing_conn.send(ProxyMessage::WebSocketClose).await;
+                // Another connection for this session is closed with a "connection
+                // replaced" reason, refused, or left open, as configured
+                match config.duplicate_connection_policy {
+                    DuplicateConnectionPolicy::CloseExisting => {
+                        if active_connections.notify_replaced(token) {
+                            tracing::info!(
+                                "Existing connection found for session {}, closing it", 
+                                session.client_id
+                            );
+                        }
+                    },
+                    DuplicateConnectionPolicy::RejectNew => {
+                        if active_connections.has_connection(token) {
+                            tracing::info!(
+                                "Refusing second connection for session {}: one is already open",
+                                session.client_id
+                            );
+                            return Ok(HttpResponse::Conflict().finish());
+                        }
+                    },
+                    DuplicateConnectionPolicy::AllowBoth => {},
                 }
                 
+                tenant_id = session.tenant_id.clone();
                 tracing::info!("Session validated for client: {}", client_id);
             },
             Ok(SessionResult::Expired) => {
                 tracing::warn!("Expired session token for client: {}", client_id);
                 return Ok(HttpResponse::Unauthorized().finish());
             },
+            Ok(SessionResult::Invalid) => {
+                tracing::warn!("Malformed or tampered session token for client: {}", client_id);
+                return Ok(HttpResponse::Unauthorized().finish());
+            },
             Ok(_) => {
                 tracing::warn!("Invalid session token for client: {}", client_id);
                 return Ok(HttpResponse::Unauthorized().finish());
@@ -490,18 +857,686 @@ async fn ws_route(
         // This is a policy choice and can be changed
     }
     
-    // Get WebSocket server URL from config
-    let ws_server_url = format!("ws://{}", config.websocket_server_addr);
+    // Get WebSocket server URLs from config, primary first, over TLS when
+    // the websocket-server serves it
+    let upstream_tls = req.app_data::<web::Data<rustls::ClientConfig>>()
+        .map(|tls| tls.clone().into_inner());
+    let scheme = if upstream_tls.is_some() { "wss" } else { "ws" };
+    let ws_server_urls = config.websocket_server_addrs().iter()
+        .map(|addr| format!("{}://{}", scheme, addr))
+        .collect();
+    
+    // A reconnecting client resumes its upstream session with the token it was issued
+    let resume_token = web::Query::<ResumeQuery>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.into_inner().resume_token);
     
     // Create proxy actor with all dependencies injected 
     let proxy = ProxyActor::new(
         client_id, 
-        ws_server_url, 
+        ws_server_urls, 
         session_token,
         Some(registry.get_ref().clone()),
         Some(active_connections.clone())
-    );
+    )
+    .with_tenant_id(tenant_id)
+    .with_upstream_token(Some(config.proxy_token.clone()))
+    .with_upstream_tls(upstream_tls)
+    .with_authentication(jwt_client.map(|client| client.wallet_address))
+    .with_max_frame_bytes(config.max_frame_bytes)
+    .with_protocol_version(protocol)
+    .with_resume_token(resume_token);
     
-    // Start WebSocket connection
-    ws::start(proxy, &req, stream)
-}
\ No newline at end of file
+    // Start WebSocket connection, echoing the negotiated protocol when one
+    // was requested; the codec refuses frames over the same limit
+    let protocols: Vec<&str> = protocol.iter().map(|version| version.subprotocol()).collect();
+    ws::WsResponseBuilder::new(proxy, &req, stream)
+        .frame_size(config.max_frame_bytes)
+        .protocols(&protocols)
+        .start()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::test_support::{client_binary_frame, client_close_frame, client_text_frame, collect_raw_frames, decode_server_frames, start_ws_actor, start_ws_actor_with_input};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::handshake::server::{Callback, ErrorResponse, Request as ServerRequest, Response as ServerResponse};
+
+    const UNREACHABLE_WS_SERVER: &str = "ws://127.0.0.1:1";
+
+    fn start_proxy(
+        token: &str,
+        active: &web::Data<ActiveConnections>,
+    ) -> (Addr<ProxyActor>, mpsc::UnboundedReceiver<actix_web::web::Bytes>) {
+        start_ws_actor(ProxyActor::new(
+            Uuid::new_v4(),
+            vec![UNREACHABLE_WS_SERVER.to_string()],
+            Some(token.to_string()),
+            None,
+            Some(active.clone()),
+        ))
+    }
+
+    #[actix::test]
+    async fn test_displaced_connection_receives_replaced_reason() {
+        let active = web::Data::new(ActiveConnections::new());
+        let (first, mut first_frames) = start_proxy("session", &active);
+        // Let the first proxy start and register itself
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        // Second connection for the same session: last connection wins
+        assert!(active.notify_replaced("session"));
+        let (second, mut second_frames) = start_proxy("session", &active);
+
+        let frames = collect_raw_frames(&mut first_frames, Duration::from_millis(200)).await;
+        let close = frames.iter()
+            .find(|frame| frame.first() == Some(&0x88))
+            .expect("displaced connection should receive a close frame");
+        assert_eq!(&close[2..4], &CLOSE_CODE_CONNECTION_REPLACED.to_be_bytes());
+        assert!(String::from_utf8_lossy(close).contains("Connection replaced"));
+
+        // The replacement stays registered and open
+        let frames = collect_raw_frames(&mut second_frames, Duration::from_millis(50)).await;
+        assert!(frames.iter().all(|frame| frame.first() != Some(&0x88)));
+        assert!(!first.connected());
+        assert_eq!(active.count(), 1);
+        assert!(active.connections.get("session").map(|entry| *entry.value() == vec![second]).unwrap_or(false));
+    }
+
+    #[actix::test]
+    async fn test_drain_closes_connections_and_refuses_new_ones() {
+        let active = web::Data::new(ActiveConnections::new());
+        let (proxy, mut frames) = start_proxy("session", &active);
+        // Let the proxy start and register itself
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(active.count(), 1);
+
+        active.drain(Duration::from_secs(1)).await;
+
+        let frames = collect_raw_frames(&mut frames, Duration::from_millis(100)).await;
+        let close = frames.iter()
+            .find(|frame| frame.first() == Some(&0x88))
+            .expect("client should receive a close frame");
+        assert_eq!(&close[2..4], &u16::from(ws::CloseCode::Away).to_be_bytes());
+        assert!(String::from_utf8_lossy(close).contains("Server shutting down"));
+        assert!(!proxy.connected());
+        assert_eq!(active.count(), 0);
+
+        // Upgrades arriving during shutdown are turned away
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(Config::default()))
+                .app_data(web::Data::new(ClientRegistryActor::new().start()))
+                .app_data(web::Data::new(SecurityMonitor::new()))
+                .configure(|cfg| configure(cfg, active.clone(), None))
+        ).await;
+        let req = actix_web::test::TestRequest::get()
+            .uri(&format!("/ws/{}", Uuid::new_v4()))
+            .to_request();
+        assert_eq!(actix_web::test::call_service(&app, req).await.status(), 503);
+    }
+
+    // Serve the proxy on a local port under `policy`, returning requests for
+    // connections with one live session, and the shared connections
+    async fn serve_session_proxy(
+        policy: DuplicateConnectionPolicy,
+    ) -> (impl Fn() -> tokio_tungstenite::tungstenite::handshake::client::Request, web::Data<ActiveConnections>, actix_web::dev::ServerHandle) {
+        let config = web::Data::new(Config {
+            websocket_server_addr: "127.0.0.1:1".to_string(),
+            duplicate_connection_policy: policy,
+            ..Config::default()
+        });
+        let registry = ClientRegistryActor::new().start();
+        let (client_id, token) = registry.send(crate::client_registry::RegisterAnonymousClient).await.unwrap();
+        let active = web::Data::new(ActiveConnections::new());
+        let security = web::Data::new(SecurityMonitor::new());
+        let served = active.clone();
+        let server = actix_web::HttpServer::new(move || {
+            actix_web::App::new()
+                .app_data(config.clone())
+                .app_data(web::Data::new(registry.clone()))
+                .app_data(security.clone())
+                .configure(|cfg| configure(cfg, served.clone(), None))
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let port = server.addrs()[0].port();
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let request = move || {
+            let mut request = format!("ws://127.0.0.1:{}/ws/{}", port, client_id).into_client_request().unwrap();
+            request.headers_mut().insert("Cookie", HeaderValue::from_str(&format!("{}={}", SESSION_COOKIE_NAME, token)).unwrap());
+            request
+        };
+        (request, active, handle)
+    }
+
+    // Wait for the proxy to register `expected` connections
+    async fn await_connections(active: &ActiveConnections, expected: usize) {
+        for _ in 0..50 {
+            if active.count() == expected {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(active.count(), expected);
+    }
+
+    #[actix_web::test]
+    async fn test_duplicate_session_connection_closes_existing() {
+        let (request, active, handle) = serve_session_proxy(DuplicateConnectionPolicy::CloseExisting).await;
+        let (mut first, _) = tokio_tungstenite::connect_async(request()).await.unwrap();
+        await_connections(&active, 1).await;
+
+        let (_second, response) = tokio_tungstenite::connect_async(request()).await.unwrap();
+        assert_eq!(response.status(), 101);
+        let closed = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                match first.next().await {
+                    Some(Ok(WsMessage::Close(frame))) => break frame,
+                    Some(Ok(_)) => continue,
+                    other => panic!("closed without a close frame: {:?}", other),
+                }
+            }
+        }).await.expect("existing connection should be closed");
+        assert_eq!(u16::from(closed.unwrap().code), CLOSE_CODE_CONNECTION_REPLACED);
+        await_connections(&active, 1).await;
+
+        handle.stop(false).await;
+    }
+
+    #[actix_web::test]
+    async fn test_duplicate_session_connection_rejected() {
+        let (request, active, handle) = serve_session_proxy(DuplicateConnectionPolicy::RejectNew).await;
+        let (mut first, _) = tokio_tungstenite::connect_async(request()).await.unwrap();
+        await_connections(&active, 1).await;
+
+        match tokio_tungstenite::connect_async(request()).await {
+            Err(WsError::Http(response)) => assert_eq!(response.status(), 409),
+            other => panic!("second connection should be refused, got {:?}", other.map(|(_, response)| response)),
+        }
+
+        // The existing connection is left alone
+        let next = tokio::time::timeout(Duration::from_millis(200), first.next()).await;
+        assert!(next.is_err(), "existing connection should stay open: {:?}", next);
+        assert_eq!(active.count(), 1);
+
+        handle.stop(false).await;
+    }
+
+    #[actix_web::test]
+    async fn test_duplicate_session_connections_allowed_together() {
+        let (request, active, handle) = serve_session_proxy(DuplicateConnectionPolicy::AllowBoth).await;
+        let (mut first, _) = tokio_tungstenite::connect_async(request()).await.unwrap();
+        await_connections(&active, 1).await;
+
+        let (second, response) = tokio_tungstenite::connect_async(request()).await.unwrap();
+        assert_eq!(response.status(), 101);
+        await_connections(&active, 2).await;
+        let next = tokio::time::timeout(Duration::from_millis(200), first.next()).await;
+        assert!(next.is_err(), "existing connection should stay open: {:?}", next);
+
+        // Each is forgotten on its own when it closes
+        drop(second);
+        await_connections(&active, 1).await;
+
+        handle.stop(false).await;
+    }
+
+    // Proxy a client that immediately sends `close` through a local upstream
+    // server, returning the close message the upstream received
+    async fn upstream_close_for(close: actix_web::web::Bytes) -> WsMessage {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_url = format!("ws://{}", listener.local_addr().unwrap());
+
+        let (_proxy, _frames) = start_ws_actor_with_input(
+            ProxyActor::new(Uuid::new_v4(), vec![upstream_url], None, None, None),
+            vec![close],
+        );
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut upstream = tokio_tungstenite::accept_async(stream).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                match upstream.next().await {
+                    Some(Ok(msg @ WsMessage::Close(_))) => break msg,
+                    Some(Ok(_)) => continue,
+                    other => panic!("upstream closed without a close frame: {:?}", other),
+                }
+            }
+        }).await.expect("upstream should receive the client's close")
+    }
+
+    #[actix::test]
+    async fn test_large_binary_frames_proxied_intact() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_url = format!("ws://{}", listener.local_addr().unwrap());
+        let from_client: Vec<u8> = (0..60_000u32).map(|i| (i % 251) as u8).collect();
+        let from_upstream: Vec<u8> = (0..200_000u32).map(|i| (i % 241) as u8).collect();
+        
+        let (_proxy, mut frames) = start_ws_actor_with_input(
+            ProxyActor::new(Uuid::new_v4(), vec![upstream_url], None, None, None),
+            vec![client_binary_frame(&from_client)],
+        );
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut upstream = tokio_tungstenite::accept_async(stream).await.unwrap();
+        
+        let received = tokio::time::timeout(Duration::from_millis(500), upstream.next()).await
+            .ok().flatten().and_then(Result::ok);
+        assert!(matches!(received, Some(WsMessage::Binary(ref data)) if *data == from_client));
+        
+        upstream.send(WsMessage::Binary(from_upstream.clone())).await.unwrap();
+        let frames = decode_server_frames(&collect_raw_frames(&mut frames, Duration::from_millis(300)).await.concat());
+        assert!(frames.contains(&(0x2, from_upstream)));
+    }
+    
+    #[actix::test]
+    async fn test_binary_forwarding_allocates_no_extra_payload_copies() {
+        use crate::test_support::bytes_allocated;
+        use common::test_support::start_ws_actor_with_sender;
+        use tokio::sync::mpsc;
+        
+        // The upstream runs on its own thread, so only the proxy's side of the
+        // connection allocates on this one. It reports each binary frame it
+        // receives and echoes it back when told to.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream_url = format!("ws://{}", listener.local_addr().unwrap());
+        let (received_tx, mut received) = mpsc::unbounded_channel();
+        let (echo, mut echo_rx) = mpsc::unbounded_channel::<()>();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(async move {
+                listener.set_nonblocking(true).unwrap();
+                let (stream, _) = TcpListener::from_std(listener).unwrap().accept().await.unwrap();
+                let mut upstream = tokio_tungstenite::accept_async(stream).await.unwrap();
+                while let Some(Ok(message)) = upstream.next().await {
+                    if !message.is_binary() {
+                        continue;
+                    }
+                    received_tx.send(message.clone().into_data()).unwrap();
+                    if echo_rx.recv().await.is_none() || upstream.send(message).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        });
+        
+        let payload: Vec<u8> = (0..60_000u32).map(|i| (i % 251) as u8).collect();
+        let frame = client_binary_frame(&payload);
+        let (_proxy, input, mut output) = start_ws_actor_with_sender(
+            ProxyActor::new(Uuid::new_v4(), vec![upstream_url], None, None, None),
+        );
+        let timeout = Duration::from_secs(2);
+        
+        // The first round trip connects upstream and sizes the buffers
+        let rounds = 10;
+        let (mut upstream_bound, mut client_bound) = (0, 0);
+        for round in 0..=rounds {
+            let before = bytes_allocated();
+            input.send(frame.clone()).unwrap();
+            let forwarded = tokio::time::timeout(timeout, received.recv()).await.unwrap().unwrap();
+            let sent_upstream = bytes_allocated();
+            
+            echo.send(()).unwrap();
+            let mut echoed = Vec::with_capacity(payload.len() + 4);
+            while echoed.len() < payload.len() + 4 {
+                echoed.extend_from_slice(&tokio::time::timeout(timeout, output.recv()).await.unwrap().unwrap());
+            }
+            let sent_to_client = bytes_allocated() - echoed.capacity();
+            
+            assert_eq!(forwarded, payload);
+            assert_eq!(decode_server_frames(&echoed), vec![(0x2, payload.clone())]);
+            if round > 0 {
+                upstream_bound += sent_upstream - before;
+                client_bound += sent_to_client - sent_upstream;
+            }
+        }
+        let (upstream_bound, client_bound) = (upstream_bound / rounds, client_bound / rounds);
+        
+        // Upstream, the payload is copied once, out of the codec's read buffer
+        // in `upstream_payload`. Back to the client, tungstenite copies it out
+        // of its read buffer and again assembling the message, and the encoder
+        // copies it into the socket buffer; the proxy adds none. An extra copy
+        // anywhere on the path would exceed these bounds.
+        let slack = 4096;
+        assert!(upstream_bound <= payload.len() + slack, "{} bytes allocated forwarding upstream", upstream_bound);
+        assert!(client_bound <= 3 * payload.len() + slack, "{} bytes allocated forwarding to the client", client_bound);
+    }
+    
+    // Proxy one text frame of `len` bytes from a client limited to 1024 byte
+    // frames, returning what the upstream received and what the client was sent
+    async fn proxy_frame_of(len: usize) -> (Option<WsMessage>, Vec<actix_web::web::Bytes>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_url = format!("ws://{}", listener.local_addr().unwrap());
+        
+        let (_proxy, mut frames) = start_ws_actor_with_input(
+            ProxyActor::new(Uuid::new_v4(), vec![upstream_url], None, None, None).with_max_frame_bytes(1024),
+            vec![client_text_frame(&"x".repeat(len))],
+        );
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut upstream = tokio_tungstenite::accept_async(stream).await.unwrap();
+        
+        let received = tokio::time::timeout(Duration::from_millis(500), upstream.next()).await
+            .ok().flatten().and_then(Result::ok);
+        (received, collect_raw_frames(&mut frames, Duration::from_millis(100)).await)
+    }
+    
+    #[actix::test]
+    async fn test_frame_at_limit_forwarded_upstream() {
+        let (received, frames) = proxy_frame_of(1024).await;
+        assert_eq!(received, Some(WsMessage::Text("x".repeat(1024))));
+        assert!(frames.iter().all(|frame| frame.first() != Some(&0x88)));
+    }
+    
+    #[actix::test]
+    async fn test_frame_over_limit_closes_instead_of_forwarding() {
+        let (received, frames) = proxy_frame_of(1025).await;
+        assert!(matches!(received, Some(WsMessage::Close(Some(ref frame))) if u16::from(frame.code) == 1008), "{:?}", received);
+        let frames = decode_server_frames(&frames.concat());
+        let (_, close) = frames.iter()
+            .find(|(opcode, _)| *opcode == 0x8)
+            .expect("oversized frame should close the client connection");
+        assert_eq!(&close[..2], &u16::from(ws::CloseCode::Policy).to_be_bytes());
+        assert_eq!(&close[2..], b"Frame too large");
+        
+        // Preceded by an error frame the client can act on
+        let (_, error) = frames.iter()
+            .find(|(opcode, _)| *opcode == 0x1)
+            .expect("client should be told why before the close");
+        let error: ErrorFrame = serde_json::from_slice(error).unwrap();
+        assert_eq!(error.code, ErrorFrame::FRAME_TOO_LARGE);
+    }
+    
+    #[actix::test]
+    async fn test_close_with_reason_forwarded_upstream() {
+        let close = upstream_close_for(client_close_frame(Some(4001), "client going away")).await;
+        match close {
+            WsMessage::Close(Some(frame)) => {
+                assert_eq!(u16::from(frame.code), 4001);
+                assert_eq!(frame.reason, "client going away");
+            },
+            other => panic!("expected close with reason, got {:?}", other),
+        }
+    }
+
+    #[actix::test]
+    async fn test_close_without_reason_forwarded_upstream() {
+        let close = upstream_close_for(client_close_frame(None, "")).await;
+        assert_eq!(close, WsMessage::Close(None));
+    }
+
+    // Start `proxy` against a local upstream server, returning the value of
+    // `header` on the upstream handshake request
+    async fn upstream_header(proxy: impl FnOnce(String) -> ProxyActor, header: &str) -> Option<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_url = format!("ws://{}", listener.local_addr().unwrap());
+
+        let (_proxy, _frames) = start_ws_actor(proxy(upstream_url));
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut value = None;
+        let _upstream = tokio_tungstenite::accept_hdr_async(stream, CaptureHeader { header, value: &mut value }).await.unwrap();
+        value
+    }
+
+    // Handshake callback recording one request header
+    struct CaptureHeader<'a> {
+        header: &'a str,
+        value: &'a mut Option<String>,
+    }
+
+    impl Callback for CaptureHeader<'_> {
+        fn on_request(self, request: &ServerRequest, response: ServerResponse) -> Result<ServerResponse, ErrorResponse> {
+            *self.value = request.headers().get(self.header).map(|value| value.to_str().unwrap().to_string());
+            Ok(response)
+        }
+    }
+
+    #[derive(Message)]
+    #[rtype(result = "u32")]
+    struct GetReconnectAttempts;
+
+    impl Handler<GetReconnectAttempts> for ProxyActor {
+        type Result = u32;
+
+        fn handle(&mut self, _msg: GetReconnectAttempts, _ctx: &mut Self::Context) -> u32 {
+            self.reconnect_attempts
+        }
+    }
+
+    #[actix::test]
+    async fn test_heartbeat_timeout_and_disconnect_reconnect_once() {
+        use common::test_support::{client_pong_frame, start_ws_actor_with_sender};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_url = format!("ws://{}", listener.local_addr().unwrap());
+
+        // A silent client times out quickly, and the upstream going away
+        // reports Disconnected for the same outage
+        let mut proxy = ProxyActor::new(Uuid::new_v4(), vec![upstream_url], None, None, None);
+        proxy.heartbeat_interval = Duration::from_millis(20);
+        proxy.heartbeat_timeout = Duration::from_millis(100);
+        let (proxy, input, _frames) = start_ws_actor_with_sender(proxy);
+
+        let accept_upstream = || async {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio_tungstenite::accept_async(stream).await.unwrap()
+        };
+        let first = accept_upstream().await;
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        drop(first);
+
+        // The client answers from here on, so only that one outage counts
+        let pongs = actix::spawn(async move {
+            while input.send(client_pong_frame(&[])).is_ok() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+
+        // One reconnect within the (at most 1s) jittered backoff, and only one,
+        // even if the replaced upstream reports its loss after the reconnect
+        let second = tokio::time::timeout(Duration::from_millis(1500), accept_upstream()).await;
+        assert!(second.is_ok(), "proxy should reconnect once");
+        assert_eq!(proxy.send(GetReconnectAttempts).await.unwrap(), 1);
+        let third = tokio::time::timeout(Duration::from_millis(300), listener.accept()).await;
+        assert!(third.is_err(), "a single failure should not schedule two reconnects");
+        pongs.abort();
+    }
+
+    #[actix::test]
+    async fn test_fails_over_to_backup_upstream() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backup_url = format!("ws://{}", listener.local_addr().unwrap());
+
+        // The primary refuses connections, so the backup is tried without
+        // waiting out a backoff
+        let (proxy, _frames) = start_ws_actor(ProxyActor::new(
+            Uuid::new_v4(),
+            vec![UNREACHABLE_WS_SERVER.to_string(), backup_url],
+            None,
+            None,
+            None,
+        ));
+
+        let accepted = tokio::time::timeout(Duration::from_millis(500), listener.accept()).await;
+        assert!(accepted.is_ok(), "proxy should fail over to the backup upstream");
+        assert_eq!(proxy.send(GetReconnectAttempts).await.unwrap(), 1);
+    }
+
+    #[actix::test]
+    async fn test_tenant_forwarded_upstream() {
+        let tenant_id = upstream_header(
+            |url| ProxyActor::new(Uuid::new_v4(), vec![url], None, None, None).with_tenant_id(Some("acme".to_string())),
+            TENANT_ID_HEADER,
+        ).await;
+        assert_eq!(tenant_id.as_deref(), Some("acme"));
+    }
+
+    #[actix::test]
+    async fn test_upstream_token_sent_as_bearer() {
+        let authorization = upstream_header(
+            |url| ProxyActor::new(Uuid::new_v4(), vec![url], None, None, None).with_upstream_token(Some("dev_proxy_token".to_string())),
+            "Authorization",
+        ).await;
+        assert_eq!(authorization.as_deref(), Some("Bearer dev_proxy_token"));
+    }
+
+    #[actix::test]
+    async fn test_authenticated_wallet_forwarded_upstream() {
+        let wallet = upstream_header(
+            |url| ProxyActor::new(Uuid::new_v4(), vec![url], None, None, None).with_authentication(Some("0xabc".to_string())),
+            WALLET_ADDRESS_HEADER,
+        ).await;
+        assert_eq!(wallet.as_deref(), Some("0xabc"));
+
+        let wallet = upstream_header(
+            |url| ProxyActor::new(Uuid::new_v4(), vec![url], None, None, None),
+            WALLET_ADDRESS_HEADER,
+        ).await;
+        assert_eq!(wallet, None);
+    }
+
+    #[actix_web::test]
+    async fn test_protocol_negotiated_with_client_and_forwarded_upstream() {
+        let config = Config {
+            websocket_server_addr: "127.0.0.1:1".to_string(),
+            ..Config::default()
+        };
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(ClientRegistryActor::new().start()))
+                .app_data(web::Data::new(SecurityMonitor::new()))
+                .configure(|cfg| configure(cfg, web::Data::new(ActiveConnections::new()), None))
+        ).await;
+        let upgrade = |protocols: &str| actix_web::test::TestRequest::get()
+            .uri(&format!("/ws/{}", Uuid::new_v4()))
+            .insert_header(("Upgrade", "websocket"))
+            .insert_header(("Connection", "upgrade"))
+            .insert_header(("Sec-WebSocket-Version", "13"))
+            .insert_header(("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="))
+            .insert_header(("Sec-WebSocket-Protocol", protocols))
+            .to_request();
+
+        let res = actix_web::test::call_service(&app, upgrade("agent-bridge.v1")).await;
+        assert_eq!(res.status(), 101);
+        assert_eq!(res.headers().get("Sec-WebSocket-Protocol").unwrap(), "agent-bridge.v1");
+        drop(res);
+
+        let res = actix_web::test::call_service(&app, upgrade("agent-bridge.v9")).await;
+        assert_eq!(res.status(), 400);
+
+        let protocol = upstream_header(
+            |url| ProxyActor::new(Uuid::new_v4(), vec![url], None, None, None).with_protocol_version(Some(ProtocolVersion::V1)),
+            "Sec-WebSocket-Protocol",
+        ).await;
+        assert_eq!(protocol.as_deref(), Some("agent-bridge.v1"));
+    }
+
+    #[actix_web::test]
+    async fn test_jwt_for_another_client_is_forbidden() {
+        let config = Config::default();
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(config.clone()))
+                .app_data(web::Data::new(ClientRegistryActor::new().start()))
+                .app_data(web::Data::new(SecurityMonitor::new()))
+                .configure(|cfg| configure(cfg, web::Data::new(ActiveConnections::new()), None))
+        ).await;
+        let client_id = Uuid::new_v4();
+        let upgrade = |token_client: Uuid| {
+            let token = common::utils::generate_jwt_token(&token_client, "0xabc", config.jwt_secret.as_bytes()).unwrap();
+            actix_web::test::TestRequest::get()
+                .uri(&format!("/ws/{}", client_id))
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request()
+        };
+
+        let res = actix_web::test::call_service(&app, upgrade(Uuid::new_v4())).await;
+        assert_eq!(res.status(), 403);
+
+        // The matching subject gets past authentication to the (here, invalid) handshake
+        let res = actix_web::test::call_service(&app, upgrade(client_id)).await;
+        assert_ne!(res.status(), 403);
+        assert_ne!(res.status(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_connection_filter_refuses_unlisted_user_agents() {
+        let config = Config {
+            connection_filter_patterns: vec!["^AgentBridge/".to_string()],
+            ..Config::default()
+        };
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(ClientRegistryActor::new().start()))
+                .app_data(web::Data::new(SecurityMonitor::new()))
+                .app_data(web::Data::new(ConnectionFilter::from_config(&config)))
+                .app_data(web::Data::new(config))
+                .configure(|cfg| configure(cfg, web::Data::new(ActiveConnections::new()), None))
+        ).await;
+        let upgrade = |user_agent: &str| actix_web::test::TestRequest::get()
+            .uri(&format!("/ws/{}", Uuid::new_v4()))
+            .insert_header(("User-Agent", user_agent))
+            .to_request();
+
+        let res = actix_web::test::call_service(&app, upgrade("curl/8.5.0")).await;
+        assert_eq!(res.status(), 403);
+
+        // An allowed agent gets through to the (here, invalid) handshake
+        let res = actix_web::test::call_service(&app, upgrade("AgentBridge/1.0")).await;
+        assert_ne!(res.status(), 403);
+    }
+
+    // Greets each connection, standing in for a websocket-server
+    struct Greeter;
+
+    impl Actor for Greeter {
+        type Context = ws::WebsocketContext<Self>;
+
+        fn started(&mut self, ctx: &mut Self::Context) {
+            ctx.text("hello over tls");
+        }
+    }
+
+    impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Greeter {
+        fn handle(&mut self, _: Result<ws::Message, ws::ProtocolError>, _: &mut Self::Context) {}
+    }
+
+    #[actix::test]
+    async fn test_proxy_reaches_tls_upstream_over_wss() {
+        let tls = common::TlsConfig {
+            cert_path: concat!(env!("CARGO_MANIFEST_DIR"), "/../common/testdata/tls_localhost.crt").to_string(),
+            key_path: concat!(env!("CARGO_MANIFEST_DIR"), "/../common/testdata/tls_localhost.key").to_string(),
+        };
+        let server = actix_web::HttpServer::new(|| {
+            actix_web::App::new().route("/ws/client/{client_id}", web::get().to(
+                |req: HttpRequest, stream: web::Payload| async move { ws::start(Greeter, &req, stream) }
+            ))
+        })
+        .workers(1)
+        .bind_rustls("127.0.0.1:0", common::tls::server_config(&tls).unwrap())
+        .unwrap();
+        let port = server.addrs()[0].port();
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        // The certificate names localhost, and is trusted only because the
+        // proxy is given it
+        let (_proxy, mut frames) = start_ws_actor(
+            ProxyActor::new(Uuid::new_v4(), vec![format!("wss://localhost:{}", port)], None, None, None)
+                .with_upstream_tls(Some(Arc::new(common::tls::client_config(&tls).unwrap()))),
+        );
+        let raw = collect_raw_frames(&mut frames, Duration::from_millis(1000)).await.concat();
+        let greeted = decode_server_frames(&raw).into_iter()
+            .any(|(opcode, payload)| opcode == 0x1 && payload == b"hello over tls");
+        assert!(greeted, "proxy should relay the upstream's greeting over wss");
+
+        handle.stop(false).await;
+    }
+}
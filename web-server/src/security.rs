@@ -0,0 +1,186 @@
+// web-server/src/security.rs
+// Audit trail and counters for security events, such as a client asking for
+// another client's session, plus optional bans on IPs that keep doing it
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+// Audit events kept in memory for the admin endpoint; older ones are dropped
+const AUDIT_LOG_CAPACITY: usize = 1000;
+
+/// A security event as recorded in the audit log
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEvent {
+    pub at: DateTime<Utc>,
+    pub event: &'static str,
+    pub ip: String,
+    pub detail: String,
+}
+
+// Mismatches seen from one IP within the ban period, and any ban in force
+#[derive(Default)]
+struct IpRecord {
+    mismatches: VecDeque<Instant>,
+    banned_until: Option<Instant>,
+}
+
+/// Shared by the HTTP handlers that check a client ID against its session.
+/// Every mismatch is counted and audited; with a threshold set, an IP
+/// reaching it within the ban period is refused for the ban period.
+pub struct SecurityMonitor {
+    client_id_mismatches: AtomicU64,
+    audit_log: Mutex<VecDeque<AuditEvent>>,
+    ips: DashMap<String, IpRecord>,
+    ban_threshold: u32,
+    ban_duration: Duration,
+}
+
+impl Default for SecurityMonitor {
+    fn default() -> Self {
+        Self {
+            client_id_mismatches: AtomicU64::new(0),
+            audit_log: Mutex::new(VecDeque::new()),
+            ips: DashMap::new(),
+            ban_threshold: 0,
+            ban_duration: Duration::from_secs(900),
+        }
+    }
+}
+
+impl SecurityMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Ban an IP after `threshold` mismatches within `ban_secs`; 0 never bans
+    pub fn with_mismatch_ban(mut self, threshold: u32, ban_secs: u64) -> Self {
+        self.ban_threshold = threshold;
+        self.ban_duration = Duration::from_secs(ban_secs);
+        self
+    }
+
+    /// Record a request from `ip` for `requested`, whose credentials (a
+    /// session or JWT, named by `credential`) belong to `actual`
+    pub fn record_client_id_mismatch(&self, ip: &str, requested: Uuid, actual: Uuid, credential: &str) {
+        self.client_id_mismatches.fetch_add(1, Ordering::Relaxed);
+        self.audit(AuditEvent {
+            at: Utc::now(),
+            event: "client_id_mismatch",
+            ip: ip.to_string(),
+            detail: format!("requested {}, {} belongs to {}", requested, credential, actual),
+        });
+
+        if self.ban_threshold == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut record = self.ips.entry(ip.to_string()).or_default();
+        record.mismatches.retain(|at| now.duration_since(*at) < self.ban_duration);
+        record.mismatches.push_back(now);
+        if record.mismatches.len() >= self.ban_threshold as usize && record.banned_until.is_none() {
+            record.banned_until = Some(now + self.ban_duration);
+            drop(record);
+            tracing::warn!("Banning {} for {:?} after repeated client ID mismatches", ip, self.ban_duration);
+            self.audit(AuditEvent {
+                at: Utc::now(),
+                event: "ip_banned",
+                ip: ip.to_string(),
+                detail: format!("{} client ID mismatches", self.ban_threshold),
+            });
+        }
+    }
+
+    /// Whether requests from `ip` are currently refused
+    pub fn is_banned(&self, ip: &str) -> bool {
+        let Some(mut record) = self.ips.get_mut(ip) else {
+            return false;
+        };
+        match record.banned_until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                // Ban served; start counting afresh
+                *record = IpRecord::default();
+                false
+            },
+            None => false,
+        }
+    }
+
+    pub fn client_id_mismatches(&self) -> u64 {
+        self.client_id_mismatches.load(Ordering::Relaxed)
+    }
+
+    pub fn banned_ips(&self) -> Vec<String> {
+        let now = Instant::now();
+        self.ips.iter()
+            .filter(|entry| entry.value().banned_until.is_some_and(|until| now < until))
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    /// Audit events, oldest first
+    pub fn audit_events(&self) -> Vec<AuditEvent> {
+        self.audit_log.lock().map(|log| log.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    // Log under the `audit` target so the trail can be shipped separately,
+    // and keep the most recent events for the admin endpoint
+    fn audit(&self, event: AuditEvent) {
+        tracing::warn!(target: "audit", event = event.event, ip = %event.ip, "{}", event.detail);
+        if let Ok(mut log) = self.audit_log.lock() {
+            if log.len() == AUDIT_LOG_CAPACITY {
+                log.pop_front();
+            }
+            log.push_back(event);
+        }
+    }
+}
+
+/// The client address security events are attributed to, as the rate limiter sees it
+pub fn client_ip(req: &HttpRequest) -> String {
+    req.connection_info().realip_remote_addr().unwrap_or("unknown").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_mismatches_ban_the_ip() {
+        let monitor = SecurityMonitor::new().with_mismatch_ban(3, 60);
+        let (requested, actual) = (Uuid::new_v4(), Uuid::new_v4());
+
+        for _ in 0..2 {
+            monitor.record_client_id_mismatch("10.0.0.1", requested, actual, "session");
+        }
+        assert!(!monitor.is_banned("10.0.0.1"));
+        monitor.record_client_id_mismatch("10.0.0.1", requested, actual, "session");
+        assert!(monitor.is_banned("10.0.0.1"));
+        assert!(!monitor.is_banned("10.0.0.2"));
+        assert_eq!(monitor.banned_ips(), vec!["10.0.0.1".to_string()]);
+
+        // Once the ban has been served the IP starts afresh
+        monitor.ips.get_mut("10.0.0.1").unwrap().banned_until = Some(Instant::now());
+        assert!(!monitor.is_banned("10.0.0.1"));
+        monitor.record_client_id_mismatch("10.0.0.1", requested, actual, "session");
+        assert!(!monitor.is_banned("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_audit_log_is_bounded() {
+        let monitor = SecurityMonitor::new();
+        for _ in 0..AUDIT_LOG_CAPACITY + 5 {
+            monitor.record_client_id_mismatch("10.0.0.1", Uuid::new_v4(), Uuid::new_v4(), "jwt");
+        }
+        assert_eq!(monitor.client_id_mismatches(), AUDIT_LOG_CAPACITY as u64 + 5);
+        assert_eq!(monitor.audit_events().len(), AUDIT_LOG_CAPACITY);
+        // Without a threshold nobody is banned
+        assert!(!monitor.is_banned("10.0.0.1"));
+    }
+}
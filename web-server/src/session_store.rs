@@ -0,0 +1,251 @@
+// web-server/src/session_store.rs
+// Storage backends for the ClientRegistryActor's sessions
+use common::models::session::ClientSession;
+use dashmap::DashMap;
+
+/// Where client sessions live, keyed by session token. Methods take the
+/// session TTL in seconds so backends that expire entries themselves agree
+/// with the registry on when a session is stale.
+pub trait SessionStore {
+    fn get(&mut self, session_token: &str) -> Option<ClientSession>;
+    /// Insert or replace a session
+    fn insert(&mut self, session: ClientSession, ttl_seconds: i64);
+    fn remove(&mut self, session_token: &str) -> Option<ClientSession>;
+    /// Sessions idle for longer than the TTL
+    fn iter_expired(&mut self, ttl_seconds: i64) -> Box<dyn Iterator<Item = ClientSession> + '_>;
+    /// Every stored session, for metrics and rebuilding the client index
+    fn values(&mut self) -> Vec<ClientSession>;
+}
+
+/// Sessions held in process memory; lost on restart
+#[derive(Default)]
+pub struct InMemorySessionStore {
+    sessions: DashMap<String, ClientSession>,
+}
+
+impl InMemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SessionStore for InMemorySessionStore {
+    fn get(&mut self, session_token: &str) -> Option<ClientSession> {
+        self.sessions.get(session_token).map(|entry| entry.value().clone())
+    }
+
+    fn insert(&mut self, session: ClientSession, _ttl_seconds: i64) {
+        self.sessions.insert(session.session_token.clone(), session);
+    }
+
+    fn remove(&mut self, session_token: &str) -> Option<ClientSession> {
+        self.sessions.remove(session_token).map(|(_, session)| session)
+    }
+
+    fn iter_expired(&mut self, ttl_seconds: i64) -> Box<dyn Iterator<Item = ClientSession> + '_> {
+        // Collected up front so callers can remove while iterating
+        let expired: Vec<ClientSession> = self.sessions.iter()
+            .filter(|entry| entry.value().is_expired(ttl_seconds))
+            .map(|entry| entry.value().clone())
+            .collect();
+        Box::new(expired.into_iter())
+    }
+
+    fn values(&mut self) -> Vec<ClientSession> {
+        self.sessions.iter().map(|entry| entry.value().clone()).collect()
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_store::RedisSessionStore;
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use super::{InMemorySessionStore, SessionStore};
+    use actix_web::web;
+    use common::models::session::ClientSession;
+    use redis::{Commands, Connection, RedisResult};
+    use tokio::sync::mpsc;
+
+    const KEY_PREFIX: &str = "agent_bridge:session:";
+
+    /// Sessions stored in Redis as JSON under their token, expiring with the
+    /// session TTL, so they survive a web-server restart. Reads are served
+    /// from a copy loaded on open, and writes are queued to Redis in order on
+    /// the blocking thread pool, so the registry never waits on Redis. A
+    /// failed write is logged and the connection reopened for the next one.
+    pub struct RedisSessionStore {
+        cache: InMemorySessionStore,
+        writes: mpsc::UnboundedSender<Write>,
+    }
+
+    // A change waiting to be written to Redis
+    enum Write {
+        Set { key: String, json: String, ttl: u64 },
+        Delete { key: String },
+    }
+
+    impl Write {
+        fn apply(&self, conn: &mut Connection) -> RedisResult<()> {
+            match self {
+                Write::Set { key, json, ttl } => conn.set_ex(key, json, *ttl),
+                Write::Delete { key } => conn.del(key),
+            }
+        }
+    }
+
+    impl RedisSessionStore {
+        /// Connect and load the stored sessions. Loading blocks, so open the
+        /// store before the registry starts; the writes run on the actix
+        /// runtime it is opened from
+        pub fn open(url: &str) -> RedisResult<Self> {
+            let client = redis::Client::open(url)?;
+            let mut connection = client.get_connection()?;
+            let mut cache = InMemorySessionStore::new();
+            for session in load_sessions(&mut connection)? {
+                cache.insert(session, 0);
+            }
+
+            let (writes, queued) = mpsc::unbounded_channel();
+            actix_web::rt::spawn(write_queued(client, connection, queued));
+            Ok(Self { cache, writes })
+        }
+
+        fn key(session_token: &str) -> String {
+            format!("{}{}", KEY_PREFIX, session_token)
+        }
+
+        fn queue(&self, write: Write) {
+            if self.writes.send(write).is_err() {
+                tracing::error!("Redis session store writer has stopped, change not persisted");
+            }
+        }
+    }
+
+    // Every session in Redis
+    fn load_sessions(conn: &mut Connection) -> RedisResult<Vec<ClientSession>> {
+        let pattern = format!("{}*", KEY_PREFIX);
+        let keys: Vec<String> = conn.scan_match(&pattern)?.collect();
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let values: Vec<Option<String>> = conn.mget(&keys)?;
+        Ok(values.iter().flatten().filter_map(|json| decode(json)).collect())
+    }
+
+    fn decode(json: &str) -> Option<ClientSession> {
+        serde_json::from_str(json)
+            .map_err(|e| tracing::error!("Discarding undecodable stored session: {}", e))
+            .ok()
+    }
+
+    // Apply queued writes one at a time, keeping the connection between them
+    async fn write_queued(client: redis::Client, connection: Connection, mut queued: mpsc::UnboundedReceiver<Write>) {
+        let mut connection = Some(connection);
+        while let Some(write) = queued.recv().await {
+            let (client, conn) = (client.clone(), connection.take());
+            let result = web::block(move || {
+                let mut conn = match conn {
+                    Some(conn) => conn,
+                    None => client.get_connection()?,
+                };
+                write.apply(&mut conn).map(|()| conn)
+            }).await;
+
+            match result {
+                Ok(Ok(conn)) => connection = Some(conn),
+                Ok(Err(e)) => tracing::error!("Redis session store error: {}", e),
+                Err(e) => tracing::error!("Redis session store write did not run: {}", e),
+            }
+        }
+    }
+
+    impl SessionStore for RedisSessionStore {
+        fn get(&mut self, session_token: &str) -> Option<ClientSession> {
+            self.cache.get(session_token)
+        }
+
+        fn insert(&mut self, session: ClientSession, ttl_seconds: i64) {
+            let json = match serde_json::to_string(&session) {
+                Ok(json) => json,
+                Err(e) => return tracing::error!("Failed to encode session: {}", e),
+            };
+            let key = Self::key(&session.session_token);
+            self.queue(Write::Set { key, json, ttl: ttl_seconds.max(1) as u64 });
+            self.cache.insert(session, ttl_seconds);
+        }
+
+        fn remove(&mut self, session_token: &str) -> Option<ClientSession> {
+            self.queue(Write::Delete { key: Self::key(session_token) });
+            self.cache.remove(session_token)
+        }
+
+        fn iter_expired(&mut self, ttl_seconds: i64) -> Box<dyn Iterator<Item = ClientSession> + '_> {
+            self.cache.iter_expired(ttl_seconds)
+        }
+
+        fn values(&mut self) -> Vec<ClientSession> {
+            self.cache.values()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn session(token: &str) -> ClientSession {
+        ClientSession::new_anonymous(Uuid::new_v4(), token.to_string())
+    }
+
+    // Exercise a store only through the trait
+    fn round_trip(store: &mut dyn SessionStore) {
+        let mut idle = session("idle");
+        idle.last_active -= chrono::Duration::seconds(120);
+        store.insert(session("live"), 60);
+        store.insert(idle, 60);
+
+        assert_eq!(store.get("live").map(|s| s.session_token), Some("live".to_string()));
+        assert!(store.get("missing").is_none());
+        assert_eq!(store.values().len(), 2);
+
+        let expired: Vec<String> = store.iter_expired(60).map(|s| s.session_token).collect();
+        assert_eq!(expired, vec!["idle".to_string()]);
+
+        assert_eq!(store.remove("idle").map(|s| s.session_token), Some("idle".to_string()));
+        assert!(store.remove("idle").is_none());
+        assert!(store.remove("live").is_some());
+        assert!(store.values().is_empty());
+    }
+
+    #[test]
+    fn test_in_memory_store_round_trip() {
+        round_trip(&mut InMemorySessionStore::new());
+    }
+
+    // Needs a disposable Redis: REDIS_URL=redis://127.0.0.1/ cargo test --features redis -- --ignored
+    #[cfg(feature = "redis")]
+    #[actix_web::test]
+    #[ignore = "needs a Redis server at REDIS_URL"]
+    async fn test_redis_store_round_trip() {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL not set");
+        let mut store = RedisSessionStore::open(&url).unwrap();
+        for stale in store.values() {
+            store.remove(&stale.session_token);
+        }
+        round_trip(&mut store);
+
+        // Written through, so a store opened after a restart loads it
+        store.insert(session("kept"), 60);
+        let reloaded = async {
+            while RedisSessionStore::open(&url).unwrap().get("kept").is_none() {
+                actix_web::rt::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        };
+        actix_web::rt::time::timeout(std::time::Duration::from_secs(5), reloaded).await
+            .expect("session was not written to Redis");
+        store.remove("kept");
+    }
+}
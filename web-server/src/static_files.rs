@@ -1,9 +1,9 @@
 // web-server/src/static_files.rs
 use actix_web::{web, HttpRequest, Result, Error};
-use actix_web::middleware::Compress;
 use actix_web::http::header;
 use actix_files::{Files, NamedFile};
 use std::path::PathBuf;
+use crate::middleware::{AssetCompression, CompressionAlgorithm};
 
 // Configuration for static file serving
 #[derive(Clone)]
@@ -11,6 +11,8 @@ pub struct StaticFilesConfig {
     pub root_path: PathBuf, 
     pub index_file: String,
     pub enable_compression: bool,
+    pub compression_algorithm: CompressionAlgorithm,  // Preferred encoding when the client accepts both
+    pub compression_level: u32,                       // gzip 0-9, brotli 0-11; clamped per algorithm
     pub cache_control: CacheControl,
 }
 
@@ -38,6 +40,8 @@ impl Default for StaticFilesConfig {
             root_path: PathBuf::from("./static"),
             index_file: "index.html".to_string(),
             enable_compression: true,
+            compression_algorithm: CompressionAlgorithm::Brotli,
+            compression_level: 3,
             cache_control: CacheControl::default(),
         }
     }
@@ -52,6 +56,16 @@ impl StaticFilesConfig {
         let enable_compression = std::env::var("ENABLE_COMPRESSION")
             .map(|v| v.to_lowercase() == "true")
             .unwrap_or(true);
+            
+        let compression_algorithm = std::env::var("COMPRESSION_ALGORITHM")
+            .ok()
+            .and_then(|v| v.parse::<CompressionAlgorithm>().ok())
+            .unwrap_or(CompressionAlgorithm::Brotli);
+            
+        let compression_level = std::env::var("COMPRESSION_LEVEL")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(3);
         
         // Get cache settings from environment variables
         let cache_max_age = std::env::var("CACHE_MAX_AGE")
@@ -71,6 +85,8 @@ impl StaticFilesConfig {
             root_path: PathBuf::from(root_path),
             index_file: "index.html".to_string(),
             enable_compression,
+            compression_algorithm,
+            compression_level,
             cache_control: CacheControl {
                 max_age: cache_max_age,
                 immutable: cache_immutable,
@@ -116,7 +132,11 @@ pub fn configure(cfg: &mut web::ServiceConfig, config: StaticFilesConfig) {
     tracing::info!("Configuring static file serving from: {:?}", config.root_path);
     
     if config.enable_compression {
-        tracing::info!("File compression enabled");
+        tracing::info!(
+            "File compression enabled: {} at level {}",
+            config.compression_algorithm.as_str(),
+            config.compression_level
+        );
     } else {
         tracing::info!("File compression disabled");
     }
@@ -131,7 +151,7 @@ pub fn configure(cfg: &mut web::ServiceConfig, config: StaticFilesConfig) {
         // With compression
         cfg.service(
             web::scope("")
-                .wrap(Compress::default())
+                .wrap(AssetCompression::new(config.compression_algorithm, config.compression_level))
                 .wrap(
                     actix_web::middleware::DefaultHeaders::new()
                         .add((header::CACHE_CONTROL, build_cache_control_value(&config.cache_control)))
@@ -0,0 +1,68 @@
+// web-server/src/test_support.rs
+// Helpers for signing sign-in challenges like a wallet, and for counting
+// allocations. WebSocket actor helpers live in `common::test_support`.
+use k256::ecdsa::SigningKey;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use crate::auth::siwe::personal_message_hash;
+
+// Private key 1, whose address is a well-known test vector
+pub const KEY_ONE_ADDRESS: &str = "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf";
+
+pub fn key_one() -> SigningKey {
+    let mut secret = [0u8; 32];
+    secret[31] = 1;
+    SigningKey::from_slice(&secret).unwrap()
+}
+
+// Sign like a wallet's `personal_sign`, returning hex `r || s || v`
+pub fn personal_sign(key: &SigningKey, message: &str) -> String {
+    let (signature, recovery_id) = key.sign_prehash_recoverable(&personal_message_hash(message)).unwrap();
+    let mut bytes = signature.to_bytes().to_vec();
+    bytes.push(27 + recovery_id.to_byte());
+    format!("0x{}", hex::encode(bytes))
+}
+
+// The test binary's allocator, counting the bytes each thread allocates so
+// tests can check a path doesn't copy its payload
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATED_BYTES: Cell<usize> = const { Cell::new(0) };
+}
+
+fn count_allocation(bytes: usize) {
+    // Unavailable while the thread is torn down, when nothing is measuring
+    let _ = ALLOCATED_BYTES.try_with(|allocated| allocated.set(allocated.get() + bytes));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count_allocation(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation(new_size.saturating_sub(layout.size()));
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Bytes allocated on this thread so far; compare two readings to measure
+// what ran in between
+pub fn bytes_allocated() -> usize {
+    ALLOCATED_BYTES.with(Cell::get)
+}
@@ -46,6 +46,18 @@ pub fn create_session_token() -> String {
     hash_string(&input)
 }
 
+/// Longest session token accepted from a client
+pub const MAX_SESSION_TOKEN_LEN: usize = 128;
+
+/// Whether a token presented by a client could be a session token at all.
+/// Tokens are hex digests; anything outside the URL-safe alphabet, or
+/// longer than any token we issue, was not produced here.
+pub fn is_well_formed_session_token(token: &str) -> bool {
+    !token.is_empty()
+        && token.len() <= MAX_SESSION_TOKEN_LEN
+        && token.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -80,4 +92,13 @@ mod tests {
         let token2 = create_session_token();
         assert_ne!(token, token2);
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_is_well_formed_session_token() {
+        assert!(is_well_formed_session_token(&create_session_token()));
+        assert!(!is_well_formed_session_token(""));
+        assert!(!is_well_formed_session_token("abc def"));
+        assert!(!is_well_formed_session_token("agent_bridge:session:*"));
+        assert!(!is_well_formed_session_token(&"a".repeat(MAX_SESSION_TOKEN_LEN + 1)));
+    }
+}
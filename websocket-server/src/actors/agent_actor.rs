@@ -1,18 +1,53 @@
 // websocket-server/src/actors/agent_actor.rs
-use actix::{Actor, AsyncContext, ActorContext, StreamHandler, Context, Addr, Handler};
+use actix::{Actor, AsyncContext, ActorContext, StreamHandler, Addr, Handler};
+use actix::{ActorFutureExt, ContextFutureSpawner};
 use actix_web_actors::ws;
-use common::{AgentMessage, SystemMessage}; // Assuming SystemMessage might be used
-use std::time::{Duration, Instant, SystemTime}; // Added SystemTime
+use common::{backoff_with_jitter, content_exceeds, AgentHello, AgentMessage, AgentToAgent, PresenceRequest, SystemMessage, TokenBucket, TopicRequest}; // Assuming SystemMessage might be used
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
 use uuid::Uuid; // Added Uuid (might be needed if AgentMessage uses it)
 use super::state_manager::{
     StateManagerActor, UnregisterAgent, ConnectionState,
-    UpdateAgentState, AgentActivity
+    UpdateAgentState, AgentActivity, UpdateAgentConnection, AgentRateLimited, SetRouter, Shutdown,
+    AgentSessionState, SaveAgentSessionState, PersistAgentSession
 };
-use super::router_actor::{AgentActorMessage, RouterActor}; // Import RouterActor
+use super::router_actor::{
+    AgentActorMessage, RouterActor, SetAgentClientAuth, SetAgentTenant, SubscribePresence, SubscribeTopic, UnsubscribePresence,
+    UnsubscribeTopic
+}; // Import RouterActor
+
+// Characters of input shown either side of a parse error
+const PARSE_ERROR_SNIPPET_RADIUS: usize = 32;
+
+// Describe a malformed agent message, including the error position and the
+// input surrounding it
+fn parse_error_diagnostic(text: &str, error: &serde_json::Error) -> String {
+    let line: Vec<char> = text.lines()
+        .nth(error.line().saturating_sub(1))
+        .unwrap_or_default()
+        .chars()
+        .collect();
+    let start = error.column().saturating_sub(PARSE_ERROR_SNIPPET_RADIUS + 1).min(line.len());
+    let end = (start + 2 * PARSE_ERROR_SNIPPET_RADIUS).min(line.len());
+
+    let mut snippet: String = line[start..end].iter().collect();
+    if start > 0 {
+        snippet.insert_str(0, "...");
+    }
+    if end < line.len() {
+        snippet.push_str("...");
+    }
+
+    format!(
+        "Error: Received malformed message - {} (line {}, column {}) near: {}",
+        error, error.line(), error.column(), snippet
+    )
+}
 
 // Enhanced agent actor
 pub struct AgentActor {
     id: String,
+    connection_id: Uuid, // Unique per connection, assigned in started()
     token: String,
     state_manager: Option<Addr<StateManagerActor>>,
     router: Option<Addr<RouterActor>>, // <-- Add Router Actor Address
@@ -20,13 +55,22 @@ pub struct AgentActor {
     heartbeat_interval: Duration,
     heartbeat_timeout: Duration,
     reconnect_attempts: u32,
-    message_buffer: Vec<AgentMessage>, // Changed buffer to AgentMessage if needed
+    message_buffer: Vec<String>, // Serialized router deliveries held while the agent is unresponsive
+    unresponsive: bool, // Heartbeat timed out and the agent has not been heard from since
+    rate_limiter: Option<TokenBucket>, // Inbound message limit, None when disabled
+    throttled: bool, // Whether the previous inbound message was dropped by the limit
+    max_content_len: usize, // Characters allowed in a message's content; 0 means no limit
+    // What the agent has told the router, kept to be saved with its session
+    hello: Option<AgentHello>,
+    presence_subscriptions: HashSet<Uuid>,
+    topics: HashSet<String>,
 }
 
 impl AgentActor {
     pub fn new(id: String, token: String) -> Self {
         Self {
             id,
+            connection_id: Uuid::nil(),
             token,
             state_manager: None,
             router: None, // <-- Initialize router as None
@@ -35,6 +79,13 @@ impl AgentActor {
             heartbeat_timeout: Duration::from_secs(30),
             reconnect_attempts: 0,
             message_buffer: Vec::new(),
+            unresponsive: false,
+            rate_limiter: None,
+            throttled: false,
+            max_content_len: 0,
+            hello: None,
+            presence_subscriptions: HashSet::new(),
+            topics: HashSet::new(),
         }
     }
 
@@ -48,11 +99,78 @@ impl AgentActor {
         self.router = Some(addr);
     }
 
+    // Limit inbound messages to `messages_per_second`, allowing bursts of `burst`.
+    // A rate of 0 disables the limit.
+    pub fn set_rate_limit(&mut self, messages_per_second: u32, burst: u32) {
+        self.rate_limiter = (messages_per_second > 0)
+            .then(|| TokenBucket::new(messages_per_second, burst));
+    }
+
+    // Reject messages whose content is over `max_content_len` characters; 0 allows any length
+    pub fn set_max_content_len(&mut self, max_content_len: usize) {
+        self.max_content_len = max_content_len;
+    }
+
+    // Send the agent an error message describing why its input was rejected
+    fn send_error(&self, ctx: &mut ws::WebsocketContext<Self>, content: String) {
+        let error_response = AgentMessage {
+            message_type: Some("error".to_string()),
+            ..AgentMessage::broadcast(content)
+        };
+        if let Ok(json) = serde_json::to_string(&error_response) {
+            ctx.text(json);
+        }
+    }
+
+    // Whether `content` is within the content limit, telling the agent when it is not
+    fn check_content_len(&self, content: &str, ctx: &mut ws::WebsocketContext<Self>) -> bool {
+        if !content_exceeds(content, self.max_content_len) {
+            return true;
+        }
+        tracing::warn!("Agent {} sent a message over {} characters", self.id, self.max_content_len);
+        self.send_error(ctx, format!(
+            "Error: Message content exceeds {} characters, message dropped", self.max_content_len
+        ));
+        false
+    }
+
+    // Check an inbound message against the rate limit, reporting drops
+    fn allow_inbound(&mut self, ctx: &mut ws::WebsocketContext<Self>) -> bool {
+        let allowed = self.rate_limiter.as_mut().is_none_or(|limiter| limiter.try_acquire());
+        if allowed {
+            if self.throttled {
+                tracing::info!("Agent {} is back under its message rate limit", self.id);
+                self.throttled = false;
+            }
+            return true;
+        }
+
+        // Warn once per burst of dropped messages rather than once per message
+        if !self.throttled {
+            tracing::warn!(connection_id = %self.connection_id, "Agent {} exceeded its message rate limit, dropping messages", self.id);
+            self.throttled = true;
+        }
+        if let Some(state_manager) = &self.state_manager {
+            state_manager.do_send(AgentRateLimited {
+                agent_id: self.id.clone(),
+            });
+        }
+
+        self.send_error(ctx, "Error: Rate limit exceeded, message dropped".to_string());
+        false
+    }
+
+    // Jittered backoff before the next reconnection attempt, capped at 60 seconds
+    fn reconnect_backoff(&self) -> Duration {
+        backoff_with_jitter(self.reconnect_attempts, 60)
+    }
+
     // Enhanced heartbeat (no changes needed here for routing)
      fn heartbeat(&self, ctx: &mut ws::WebsocketContext<Self>) {
         ctx.run_interval(self.heartbeat_interval, |act, ctx| {
             if Instant::now().duration_since(act.last_heartbeat) > act.heartbeat_timeout {
-                tracing::warn!("Agent heartbeat timeout: {}", act.id);
+                tracing::warn!(connection_id = %act.connection_id, "Agent heartbeat timeout: {}", act.id);
+                act.unresponsive = true;
 
                 if let Some(state_manager) = &act.state_manager {
                     state_manager.do_send(UpdateAgentState {
@@ -63,21 +181,18 @@ impl AgentActor {
                 }
 
                 // Calculate backoff duration
-                let backoff_seconds = std::cmp::min(
-                    2u64.pow(act.reconnect_attempts),
-                    60
-                );
-                let backoff = Duration::from_secs(backoff_seconds);
+                let backoff = act.reconnect_backoff();
+                let backoff_seconds = backoff.as_secs_f64();
 
                 tracing::info!(
-                    "Agent {} reconnection attempt {} scheduled in {} seconds",
-                    act.id, act.reconnect_attempts + 1, backoff_seconds
+                    "Agent {} reconnection attempt {} scheduled in {:.1} seconds",
+                    act.id, act.reconnect_attempts.saturating_add(1), backoff_seconds
                 );
 
                 // Attempt reconnection with backoff
                  ctx.run_later(backoff, |act, ctx| {
                      act.last_heartbeat = Instant::now(); // Reset for attempt
-                     act.reconnect_attempts += 1;
+                     act.reconnect_attempts = act.reconnect_attempts.saturating_add(1);
                      // Just send a ping to check connectivity
                      ctx.ping(b"reconnect_attempt");
                  });
@@ -91,10 +206,10 @@ impl AgentActor {
     }
 
 
-    // Buffer a message (if needed)
-    pub fn buffer_message(&mut self, message: AgentMessage) {
+    // Hold a message the router delivered, already serialized, until the agent responds
+    pub fn buffer_message(&mut self, content: String) {
         if self.message_buffer.len() < 100 {
-            self.message_buffer.push(message);
+            self.message_buffer.push(content);
         } else {
             tracing::warn!("Message buffer full for agent: {}, dropping message", self.id);
         }
@@ -105,16 +220,55 @@ impl AgentActor {
         if !self.message_buffer.is_empty() {
             tracing::info!("Sending {} buffered messages for agent: {}",
                           self.message_buffer.len(), self.id);
-            for msg in self.message_buffer.drain(..) {
-                if let Ok(serialized) = serde_json::to_string(&msg) {
-                    ctx.text(serialized);
-                } else {
-                    tracing::error!("Failed to serialize buffered AgentMessage for agent {}", self.id);
-                }
+            for content in self.message_buffer.drain(..) {
+                ctx.text(content);
             }
         }
     }
 
+    // Any frame from the agent shows it is reachable again after a heartbeat
+    // timeout; deliver what was held meanwhile
+    fn mark_responsive(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.unresponsive {
+            self.unresponsive = false;
+            self.send_buffered_messages(ctx);
+        }
+    }
+
+    // Tell the router the tenant and client auth the agent's hello declared
+    fn announce_hello(&self, hello: &AgentHello) {
+        if let Some(router) = &self.router {
+            router.do_send(SetAgentTenant {
+                agent_id: self.id.clone(),
+                tenant_id: hello.tenant_id.clone(),
+            });
+            router.do_send(SetAgentClientAuth {
+                agent_id: self.id.clone(),
+                require_authenticated_clients: hello.require_authenticated_clients,
+            });
+        }
+    }
+
+    // Current session state, as saved for the agent's next connection
+    fn session_snapshot(&self) -> AgentSessionState {
+        AgentSessionState {
+            agent_id: self.id.clone(),
+            message_buffer: self.message_buffer.clone(),
+            presence_subscriptions: self.presence_subscriptions.clone(),
+            topics: self.topics.clone(),
+            hello: self.hello.clone(),
+            last_seen: self.last_heartbeat,
+        }
+    }
+
+    // Save current session state; the state manager keeps it only when
+    // agent sessions are enabled
+    fn save_session_state(&self) {
+        if let Some(state_manager) = &self.state_manager {
+            state_manager.do_send(SaveAgentSessionState { state: self.session_snapshot() });
+        }
+    }
+
     // Update activity with state manager (no changes needed here)
     fn update_activity(&self, is_message: bool) {
         if let Some(state_manager) = &self.state_manager {
@@ -130,13 +284,18 @@ impl Actor for AgentActor {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        tracing::info!("Agent connected: {}", self.id);
+        self.connection_id = Uuid::new_v4();
+        tracing::info!(connection_id = %self.connection_id, "Agent connected: {}", self.id);
         self.last_heartbeat = Instant::now();
         self.reconnect_attempts = 0; // Reset on successful connection
         self.heartbeat(ctx);
         self.send_buffered_messages(ctx);
         // Notify state manager
         if let Some(state_manager) = &self.state_manager {
+            state_manager.do_send(UpdateAgentConnection {
+                agent_id: self.id.clone(),
+                connection_id: self.connection_id,
+            });
             state_manager.do_send(UpdateAgentState {
                 agent_id: self.id.clone(),
                 state: ConnectionState::Connected,
@@ -146,7 +305,9 @@ impl Actor for AgentActor {
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
-        tracing::info!("Agent disconnected: {}", self.id);
+        tracing::info!(connection_id = %self.connection_id, "Agent disconnected: {}", self.id);
+        // Save session state before unregistering
+        self.save_session_state();
         if let Some(state_manager) = &self.state_manager {
              state_manager.do_send(UpdateAgentState {
                  agent_id: self.id.clone(),
@@ -171,6 +332,11 @@ impl Handler<AgentActorMessage> for AgentActor {
     type Result = ();
 
     fn handle(&mut self, msg: AgentActorMessage, ctx: &mut Self::Context) -> Self::Result {
+        if self.unresponsive {
+            tracing::debug!("Agent {} is unresponsive, buffering message from router", self.id);
+            self.buffer_message(msg.content);
+            return;
+        }
         tracing::info!("Received message via router for agent {}, sending to WebSocket", self.id);
         // Update last heartbeat? Maybe not on outgoing messages unless needed.
         // self.last_heartbeat = Instant::now();
@@ -186,6 +352,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for AgentActor {
                 self.last_heartbeat = Instant::now();
                 self.update_activity(false);
                 ctx.pong(&msg);
+                self.mark_responsive(ctx);
                  // Reset reconnect attempts on successful ping
                  if self.reconnect_attempts > 0 {
                      tracing::info!("Agent {} reconnected successfully via ping", self.id);
@@ -202,6 +369,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for AgentActor {
             Ok(ws::Message::Pong(_)) => {
                 self.last_heartbeat = Instant::now();
                 self.update_activity(false);
+                self.mark_responsive(ctx);
                  // Reset reconnect attempts on successful pong (could be response to reconnection ping)
                  if self.reconnect_attempts > 0 {
                      tracing::info!("Agent {} reconnected successfully via pong", self.id);
@@ -217,16 +385,98 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for AgentActor {
             },
             Ok(ws::Message::Text(text)) => {
                 self.last_heartbeat = Instant::now();
+                self.mark_responsive(ctx);
+                if !self.allow_inbound(ctx) {
+                    return;
+                }
                 self.update_activity(true);
                 tracing::debug!("Received raw message from agent {}: {}", self.id, text);
 
+                // The hello handshake declares the agent's tenant, and whether
+                // it serves anonymous clients, and is not routed
+                if let Some(hello) = AgentHello::parse(&text) {
+                    self.announce_hello(&hello);
+                    self.hello = Some(hello);
+                    return;
+                }
+
+                // Messages for another agent go through the router, never to clients
+                if let Some(mut frame) = AgentToAgent::parse(&text) {
+                    if !self.check_content_len(&frame.content, ctx) {
+                        return;
+                    }
+                    // The sender is whoever owns this connection, whatever the frame claims
+                    frame.source_agent_id = Some(self.id.clone());
+                    match &self.router {
+                        Some(router) => router.do_send(frame),
+                        None => tracing::error!("Router address not available for agent {}", self.id),
+                    }
+                    return;
+                }
+
+                // Presence (un)subscriptions are registered with the router, not routed
+                if let Some(request) = PresenceRequest::parse(&text) {
+                    let Some(router) = &self.router else {
+                        tracing::error!("Router address not available for agent {}", self.id);
+                        return;
+                    };
+                    let agent_id = self.id.clone();
+                    match request {
+                        PresenceRequest::SubscribePresence { client_id } => {
+                            router.do_send(SubscribePresence { agent_id, client_id });
+                            self.presence_subscriptions.insert(client_id);
+                        }
+                        PresenceRequest::UnsubscribePresence { client_id } => {
+                            router.do_send(UnsubscribePresence { agent_id, client_id });
+                            self.presence_subscriptions.remove(&client_id);
+                        }
+                    }
+                    return;
+                }
+
+                // As are topic (un)subscriptions
+                if let Some(request) = TopicRequest::parse(&text) {
+                    let Some(router) = &self.router else {
+                        tracing::error!("Router address not available for agent {}", self.id);
+                        return;
+                    };
+                    let agent_id = self.id.clone();
+                    match request {
+                        TopicRequest::SubscribeTopic { topic } => {
+                            router.do_send(SubscribeTopic { agent_id, topic: topic.clone() });
+                            self.topics.insert(topic);
+                        }
+                        TopicRequest::UnsubscribeTopic { topic } => {
+                            self.topics.remove(&topic);
+                            router.do_send(UnsubscribeTopic { agent_id, topic });
+                        }
+                    }
+                    return;
+                }
+
                 // ---- START ROUTING LOGIC ----
                 match serde_json::from_str::<AgentMessage>(&text) {
-                    Ok(agent_msg) => {
+                    Ok(mut agent_msg) => {
+                        if !self.check_content_len(&agent_msg.content, ctx) {
+                            return;
+                        }
+                        // Acks for this message go back to whoever owns this connection
+                        agent_msg.source_agent_id = Some(self.id.clone());
                         if let Some(router) = &self.router {
                              tracing::info!("Forwarding message from agent {} to router", self.id);
-                             // Send the parsed AgentMessage to the RouterActor
-                             if let Err(e) = router.try_send(agent_msg) {
+                             if agent_msg.target_client_id.is_none() {
+                                 // Broadcasts may report their reach back to the agent
+                                 actix::fut::wrap_future::<_, Self>(router.send(agent_msg))
+                                     .map(|result, act, ctx| match result {
+                                         Ok(Some(reach)) => match serde_json::to_string(&reach) {
+                                             Ok(json) => ctx.text(json),
+                                             Err(e) => tracing::error!("Failed to serialize broadcast result: {}", e),
+                                         },
+                                         Ok(None) => {},
+                                         Err(e) => tracing::error!("Failed to send broadcast from agent {} to router: {}", act.id, e),
+                                     })
+                                     .spawn(ctx);
+                             } else if let Err(e) = router.try_send(agent_msg) {
                                  tracing::error!("Failed to send agent message to router: {}", e);
                                  // Optionally inform the agent about the internal error
                                  // Note: Need a way to structure error messages back to agent
@@ -237,21 +487,8 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for AgentActor {
                     },
                     Err(e) => {
                          tracing::warn!("Failed to parse message from agent {}: {}", self.id, e);
-                         // Optionally inform agent about the error
-                         // Note: Need a structured error message format
-                         let error_response = AgentMessage {
-                            target_client_id: None, // Or maybe specific client if context known?
-                            content: format!("Error: Received malformed message - {}", e),
-                            timestamp: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs(),
-                            message_id: None, // Optional message ID for tracking
-                            requires_ack: false, // This message doesn't require acknowledgment
-                            message_type: Some("error".to_string()), // Message type classification
-                        };                        
-                         if let Ok(json) = serde_json::to_string(&error_response) {
-                            // This echoes back, maybe not ideal, consider logging only or specific error channel
-                            // ctx.text(json);
-                            tracing::error!("Sent parsing error back to agent {}", self.id); // Log instead of echoing?
-                         }
+                         // Tell the agent where parsing failed so integrations can be debugged
+                         self.send_error(ctx, parse_error_diagnostic(&text, &e));
                     }
                 }
                  // ---- END ROUTING LOGIC ----
@@ -262,7 +499,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for AgentActor {
                 tracing::warn!("Binary messages not supported for agent: {}", self.id);
             },
             Ok(ws::Message::Close(reason)) => {
-                tracing::info!("Agent closing connection: {:?}", reason);
+                tracing::info!(connection_id = %self.connection_id, "Agent closing connection: {:?}", reason);
                 if let Some(state_manager) = &self.state_manager {
                      state_manager.do_send(UpdateAgentState {
                          agent_id: self.id.clone(),
@@ -281,7 +518,10 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for AgentActor {
                 tracing::trace!("Received nop frame from agent {}", self.id);
             },
             Err(e) => {
-                tracing::error!("WebSocket protocol error for agent {}: {}", self.id, e);
+                tracing::error!(
+                    connection_id = %self.connection_id,
+                    "WebSocket protocol error for agent {}: {}", self.id, e
+                );
                 if let Some(state_manager) = &self.state_manager {
                     state_manager.do_send(UpdateAgentState {
                         agent_id: self.id.clone(),
@@ -294,4 +534,234 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for AgentActor {
         }
     } 
 
-}
\ No newline at end of file
+}
+
+// Handle an operator kicking this agent
+impl Handler<Shutdown> for AgentActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: Shutdown, ctx: &mut Self::Context) -> Self::Result {
+        tracing::info!(connection_id = %self.connection_id, "Shutting down agent {}: {}", self.id, msg.reason);
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some(msg.reason),
+        }));
+        ctx.stop();
+    }
+}
+
+// The state manager timed this agent out
+impl Handler<PersistAgentSession> for AgentActor {
+    type Result = ();
+
+    fn handle(&mut self, _msg: PersistAgentSession, _ctx: &mut Self::Context) -> Self::Result {
+        self.save_session_state();
+    }
+}
+
+// Restore what the agent had on its previous connection: its declarations
+// and subscriptions are replayed to the router, and messages it never
+// received go out ahead of anything buffered since
+impl Handler<AgentSessionState> for AgentActor {
+    type Result = ();
+
+    fn handle(&mut self, session: AgentSessionState, ctx: &mut Self::Context) -> Self::Result {
+        tracing::info!(
+            "Restoring session for agent {}: {} buffered messages, {} presence subscriptions, {} topics",
+            self.id, session.message_buffer.len(), session.presence_subscriptions.len(), session.topics.len()
+        );
+        if self.hello.is_none() {
+            if let Some(hello) = session.hello {
+                self.announce_hello(&hello);
+                self.hello = Some(hello);
+            }
+        }
+        if let Some(router) = &self.router {
+            for &client_id in session.presence_subscriptions.difference(&self.presence_subscriptions) {
+                router.do_send(SubscribePresence { agent_id: self.id.clone(), client_id });
+            }
+            for topic in session.topics.difference(&self.topics) {
+                router.do_send(SubscribeTopic { agent_id: self.id.clone(), topic: topic.clone() });
+            }
+        }
+        self.presence_subscriptions.extend(session.presence_subscriptions);
+        self.topics.extend(session.topics);
+
+        let mut message_buffer = session.message_buffer;
+        message_buffer.append(&mut self.message_buffer);
+        self.message_buffer = message_buffer;
+        if !self.unresponsive {
+            self.send_buffered_messages(ctx);
+        }
+    }
+}
+
+// The state manager replaced a router that stopped
+impl Handler<SetRouter> for AgentActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetRouter, _ctx: &mut Self::Context) -> Self::Result {
+        self.router = Some(msg.router);
+        // A replacement router starts without the agent's declarations and
+        // subscriptions, so send them again
+        if let Some(hello) = &self.hello {
+            self.announce_hello(hello);
+        }
+        if let Some(router) = &self.router {
+            for &client_id in &self.presence_subscriptions {
+                router.do_send(SubscribePresence { agent_id: self.id.clone(), client_id });
+            }
+            for topic in &self.topics {
+                router.do_send(SubscribeTopic { agent_id: self.id.clone(), topic: topic.clone() });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actors::state_manager::{GetAgentStatus, RegisterAgent};
+    use crate::actors::router_actor::RegisterAgent as RouterRegisterAgent;
+    use common::test_support::{client_text_frame, collect_frames, start_ws_actor_with_input};
+    use common::ClientMessage;
+
+    #[actix::test]
+    async fn test_parse_error_pinpoints_missing_field() {
+        let malformed = r#"{"target_client_id":null,"content":"hello"}"#;
+        let agent = AgentActor::new("agent1".to_string(), "token".to_string());
+        let (_addr, mut output) = start_ws_actor_with_input(agent, vec![client_text_frame(malformed)]);
+
+        let output = collect_frames(&mut output, Duration::from_millis(100)).await.concat();
+        assert!(output.contains(r#""message_type":"error""#));
+        assert!(output.contains("missing field `timestamp`"));
+        assert!(output.contains("(line 1, column 43)"));
+        assert!(output.contains(r#"near: ...lient_id\":null,\"content\":\"hello\"}"#));
+    }
+
+    #[test]
+    fn test_parse_error_snippet_is_truncated() {
+        let long_input = format!(r#"{{"content":"{}","timestamp":"soon"}}"#, "x".repeat(200));
+        let error = serde_json::from_str::<AgentMessage>(&long_input).unwrap_err();
+        let diagnostic = parse_error_diagnostic(&long_input, &error);
+
+        assert!(diagnostic.contains("invalid type: string \"soon\""));
+        let snippet = diagnostic.split("near: ").nth(1).unwrap();
+        assert!(snippet.starts_with("...") && snippet.contains("\"timestamp\":\"soon"));
+        assert!(snippet.chars().count() <= 2 * PARSE_ERROR_SNIPPET_RADIUS + 6);
+    }
+
+    #[actix::test]
+    async fn test_flooding_agent_is_throttled() {
+        let state_manager = StateManagerActor::new().start();
+        let message = serde_json::to_string(&AgentMessage::broadcast("flood")).unwrap();
+        let frames = (0..10).map(|_| client_text_frame(&message)).collect();
+
+        // A burst of 3 with a negligible refill rate over the test window
+        let mut agent = AgentActor::new("agent1".to_string(), "token".to_string());
+        agent.set_rate_limit(1, 3);
+        agent.set_state_manager(state_manager.clone());
+        let (addr, mut output) = start_ws_actor_with_input(agent, frames);
+        state_manager.send(RegisterAgent { agent_id: "agent1".to_string(), addr }).await.unwrap();
+
+        let output = collect_frames(&mut output, Duration::from_millis(200)).await.concat();
+        assert_eq!(output.matches("Rate limit exceeded").count(), 7);
+
+        let status = state_manager.send(GetAgentStatus { agent_id: "agent1".to_string() }).await.unwrap().unwrap();
+        assert_eq!(status.messages_rate_limited, 7);
+    }
+
+    #[actix::test]
+    async fn test_content_over_limit_is_rejected() {
+        use crate::actors::client_session_actor::ClientSessionActor;
+        use crate::actors::router_actor::RegisterClient;
+        use common::test_support::start_ws_actor;
+
+        let router = RouterActor::new(None).start();
+        let client_id = Uuid::new_v4();
+        let (client, mut client_output) = start_ws_actor(ClientSessionActor::new(client_id));
+        router.send(RegisterClient { client_id, addr: client, authenticated: false, wallet_address: None }).await.unwrap();
+
+        // Ten characters is allowed, eleven is not, however many bytes they take
+        let broadcast = |content: &str| client_text_frame(&format!(
+            r#"{{"target_client_id":null,"content":"{}","timestamp":0}}"#, content
+        ));
+        let mut agent = AgentActor::new("agent1".to_string(), "token".to_string());
+        agent.set_router(router.clone());
+        agent.set_max_content_len(10);
+        let (_addr, mut output) = start_ws_actor_with_input(agent, vec![
+            broadcast("ééééé12345"),
+            broadcast("ééééé123456"),
+        ]);
+
+        let window = Duration::from_millis(100);
+        let output = collect_frames(&mut output, window).await.concat();
+        assert_eq!(output.matches("exceeds 10 characters").count(), 1);
+        let delivered = collect_frames(&mut client_output, window).await.concat();
+        assert!(delivered.contains("ééééé12345"));
+        assert!(!delivered.contains("ééééé123456"));
+    }
+
+    #[actix::test]
+    async fn test_agent_hello_declares_tenant() {
+        let router = RouterActor::new(None).start();
+        let mut agent = AgentActor::new("acme-agent".to_string(), "token".to_string());
+        agent.set_router(router.clone());
+        let hello = client_text_frame(r#"{"type":"agent_hello","tenant_id":"acme"}"#);
+        let (addr, mut output) = start_ws_actor_with_input(agent, vec![hello]);
+        router.send(RouterRegisterAgent { agent_id: "acme-agent".to_string(), addr }).await.unwrap();
+        // Let the hello reach the router
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let message = |content: &str, tenant_id: Option<&str>| {
+            ClientMessage::new(Uuid::new_v4(), content).with_tenant_id(tenant_id.map(str::to_string))
+        };
+        router.send(message("for-acme", Some("acme"))).await.unwrap();
+        router.send(message("for-globex", Some("globex"))).await.unwrap();
+
+        let output = collect_frames(&mut output, Duration::from_millis(100)).await.concat();
+        assert!(output.contains("for-acme"));
+        assert!(!output.contains("for-globex"));
+        // The handshake is consumed, not answered with a parse error
+        assert!(!output.contains("malformed"));
+    }
+
+    #[actix::test]
+    async fn test_router_messages_buffered_until_unresponsive_agent_returns() {
+        use common::test_support::{client_pong_frame, start_ws_actor_with_sender};
+
+        let router = RouterActor::new(Some("agent1".to_string())).start();
+        let mut agent = AgentActor::new("agent1".to_string(), "token".to_string());
+        agent.heartbeat_interval = Duration::from_millis(20);
+        agent.heartbeat_timeout = Duration::from_millis(50);
+        let (addr, input, mut output) = start_ws_actor_with_sender(agent);
+        router.send(RouterRegisterAgent { agent_id: "agent1".to_string(), addr }).await.unwrap();
+
+        // The agent goes quiet past the heartbeat timeout, then a client
+        // message is routed to it
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        router.send(ClientMessage::new(Uuid::new_v4(), "held for agent".to_string())).await.unwrap();
+        let window = Duration::from_millis(100);
+        assert!(!collect_frames(&mut output, window).await.concat().contains("held for agent"));
+
+        // Its next pong shows it is back, and the held message goes out as routed
+        input.send(client_pong_frame(b"")).unwrap();
+        let delivered = collect_frames(&mut output, window).await.concat();
+        assert_eq!(delivered.matches("held for agent").count(), 1);
+        assert!(delivered.contains(r#""content":"held for agent""#));
+    }
+
+    #[test]
+    fn test_reconnect_backoff_does_not_overflow() {
+        let mut agent = AgentActor::new("agent1".to_string(), "token".to_string());
+
+        agent.reconnect_attempts = 3;
+        assert!(agent.reconnect_backoff() <= Duration::from_secs(8));
+
+        // 2^64 overflows u64 - must saturate at the cap instead of panicking
+        for attempts in [63, 64, 65, 1000, u32::MAX] {
+            agent.reconnect_attempts = attempts;
+            assert!(agent.reconnect_backoff() <= Duration::from_secs(60));
+        }
+    }
+}
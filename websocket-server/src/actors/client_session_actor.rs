@@ -1,17 +1,68 @@
 // websocket-server/src/actors/client_session_actor.rs
 use actix::{Actor, ActorContext, AsyncContext, StreamHandler, Addr, Handler};
-use actix::ContextFutureSpawner; // Added missing trait import
+use actix::{ActorFutureExt, ContextFutureSpawner};
+use actix_web::web::Bytes;
 use actix_web_actors::ws;
-use common::{ClientMessage, SystemMessage, MessageAcknowledgement, AckStatus};
+use common::{content_exceeds, time_message, ClientMessage, ErrorFrame, SystemMessage, MessageAcknowledgement, AckStatus, StateManagerConfig, ProtocolVersion, SessionFrame};
 use uuid::Uuid;
 use std::time::{Duration, Instant, SystemTime};
 use std::collections::{VecDeque, HashMap};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
 use super::state_manager::{
     StateManagerActor, UnregisterClient, ConnectionState,
     UpdateClientState, ClientActivity, SessionState, SaveSessionState, GetSessionState,
-    UpdateClientMessageMetrics
+    UpdateClientMessageMetrics, UpdateClientConnection, ClientMessageDropped, SnapshotSession,
+    BufferedMessagesExpired, SetRouter, Shutdown, UpdateClientLatency
 };
-use super::router_actor::{ClientActorMessage, RouterActor};
+use super::router_actor::{
+    ClientActorBinaryMessage, ClientActorMessage, PauseClientDelivery, ResumeClientDelivery, RouterActor
+};
+use serde::Deserialize;
+use crate::compression;
+
+// A message queued for a client, buffered and replayed the same way whether
+// it is text or binary
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutboundFrame {
+    Text(String),
+    Binary(Bytes),
+}
+
+impl From<String> for OutboundFrame {
+    fn from(text: String) -> Self {
+        OutboundFrame::Text(text)
+    }
+}
+
+// Control fields a client may include in a JSON text frame. Everything else
+// in the frame is passed through to the agent untouched.
+#[derive(Debug, Default, Deserialize)]
+struct IncomingClientFrame {
+    #[serde(default, rename = "type")]
+    frame_type: Option<String>,
+    #[serde(default)]
+    message_id: Option<u64>,
+    #[serde(default)]
+    ack: Option<bool>,
+    #[serde(default)]
+    target_agent_id: Option<String>,
+    #[serde(default)]
+    topic: Option<String>,
+}
+
+impl IncomingClientFrame {
+    // Parse a text frame, treating non-JSON or unexpected shapes as plain content
+    fn parse(text: &str) -> Self {
+        serde_json::from_str(text).unwrap_or_default()
+    }
+    
+    // The acknowledged message id, if this frame is an acknowledgement
+    fn ack_id(&self) -> Option<u64> {
+        let is_ack = self.frame_type.as_deref() == Some("ack") || self.ack == Some(true);
+        self.message_id.filter(|_| is_ack)
+    }
+}
 
 // Message tracking structure for delivery confirmation
 struct MessageTracker {
@@ -44,6 +95,13 @@ impl MessageTracker {
         self.pending_acks.remove(&msg_id).is_some()
     }
     
+    // Stop tracking messages sent longer than `ttl` ago, returning how many
+    fn discard_older_than(&mut self, ttl: Duration) -> usize {
+        let before = self.pending_acks.len();
+        self.pending_acks.retain(|_, (_, sent_time)| sent_time.elapsed() <= ttl);
+        before - self.pending_acks.len()
+    }
+    
     // Check for expired acknowledgements and return list of expired message IDs
     fn check_expired(&self) -> Vec<u64> {
         let now = Instant::now();
@@ -54,11 +112,36 @@ impl MessageTracker {
     }
 }
 
+// Pause between replayed messages for a flush rate in messages per second
+fn flush_interval(messages_per_sec: u32) -> Duration {
+    Duration::from_secs(1) / messages_per_sec.max(1)
+}
+
+// Close reason for a client that sent a frame over the size limit
+fn frame_too_large_reason() -> ws::CloseReason {
+    ws::CloseReason {
+        code: ws::CloseCode::Policy,
+        description: Some("Frame too large".to_string()),
+    }
+}
+
+// Append `"field":value` to the text of a JSON object, or None if it isn't one
+fn append_json_field(content: &str, field: &str, value: u64) -> Option<String> {
+    let body = content.trim().strip_prefix('{')?.strip_suffix('}')?.trim_end();
+    let separator = if body.trim_start().is_empty() || body.ends_with(',') { "" } else { "," };
+    Some(format!("{{{}{}\"{}\":{}}}", body, separator, field, value))
+}
+
+// Weight of the newest round-trip time in the rolling average
+const RTT_SMOOTHING: f64 = 0.2;
+
 // Enhanced client session actor with session persistence
 pub struct ClientSessionActor {
     client_id: Uuid,
+    connection_id: Uuid, // Unique per connection, assigned in started()
     authenticated: bool,
     wallet_address: Option<String>,
+    tenant_id: Option<String>, // Messages from this client only reach agents of this tenant
     state_manager: Option<Addr<StateManagerActor>>,
     router: Option<Addr<RouterActor>>,
     last_heartbeat: Instant,
@@ -67,23 +150,56 @@ pub struct ClientSessionActor {
     reconnect_interval: Duration,
     reconnect_attempts: u32,
     max_reconnect_attempts: u32,
+    // Pings awaiting their pong, by payload, with when each was sent
+    pending_pings: HashMap<Vec<u8>, Instant>,
+    ping_seq: u64,
+    last_rtt_ms: Option<f64>, // Rolling average round-trip time of answered pings
     // Enhanced session state
-    message_buffer: VecDeque<String>,
+    message_buffer: VecDeque<(OutboundFrame, Instant)>, // Frames with the time they were buffered
     max_buffer_size: usize,
+    message_ttl: Duration, // Buffered or unacked messages older than this are discarded
+    flush_interval: Duration, // Pause between buffered messages replayed on reconnect
+    flushing: bool, // Whether a paced replay of the buffer is under way
     session_id: Option<String>, // Unique session identifier
     session_data: HashMap<String, String>, // Arbitrary session data
+    next_sequence: u64, // Stamped on the next JSON message out, across buffered and live sends
+    // Issued to the client on connect; a later connection presenting it may
+    // resume this session
+    resume_token: String,
+    claimed_resume_token: Option<String>, // Presented by this connection to resume a saved session
     // Message tracking for delivery confirmation
     message_tracker: MessageTracker,
     delivery_confirmation: bool, // Whether to use delivery confirmation
+    require_acks: bool, // Track every message regardless of delivery_confirmation
+    max_unacked: usize, // Unacked messages before the router pauses delivery; 0 disables
+    compression_threshold: usize, // Text frames larger than this may be deflated; 0 disables
+    compress_outbound: bool, // Whether the client asked for compressed frames
+    max_content_len: usize, // Characters allowed in a text message; 0 means no limit
+    max_frame_bytes: usize, // Larger text or binary frames close the connection
+    protocol_version: ProtocolVersion, // Envelope version negotiated at the handshake
+    slow_message_threshold: Duration, // Handling slower than this is logged; zero disables
+    delivery_paused: bool, // Whether the router was asked to hold our messages
     is_connected: bool, // Added to track connection status
+    // Outbound messages held back until restored messages have been sent
+    restoring: bool,
+    pending_outbound: VecDeque<OutboundFrame>,
+    // Shared across connections to cap session restores in flight at once
+    restore_permits: Option<Arc<Semaphore>>,
 }
 
 impl ClientSessionActor {
     pub fn new(client_id: Uuid) -> Self {
+        Self::with_buffer_size(client_id, StateManagerConfig::default().client_buffer_size)
+    }
+
+    // Create an actor buffering up to `buffer_size` messages while disconnected
+    pub fn with_buffer_size(client_id: Uuid, buffer_size: usize) -> Self {
         Self {
             client_id,
+            connection_id: Uuid::nil(),
             authenticated: false,
             wallet_address: None,
+            tenant_id: None,
             state_manager: None,
             router: None,
             last_heartbeat: Instant::now(),
@@ -92,25 +208,46 @@ impl ClientSessionActor {
             reconnect_interval: Duration::from_secs(5),
             reconnect_attempts: 0,
             max_reconnect_attempts: 5,
-            message_buffer: VecDeque::with_capacity(100),
-            max_buffer_size: 100,
+            pending_pings: HashMap::new(),
+            ping_seq: 0,
+            last_rtt_ms: None,
+            message_buffer: VecDeque::with_capacity(buffer_size),
+            max_buffer_size: buffer_size,
+            message_ttl: Duration::from_secs(StateManagerConfig::default().buffered_message_ttl_secs),
+            flush_interval: flush_interval(StateManagerConfig::default().buffer_flush_rate),
+            flushing: false,
             session_id: Some(format!("session-{}-{}", client_id, 
                                    SystemTime::now()
                                       .duration_since(SystemTime::UNIX_EPOCH)
                                       .unwrap_or_default()
                                       .as_secs())),
             session_data: HashMap::new(),
+            next_sequence: 0,
+            resume_token: Uuid::new_v4().simple().to_string(),
+            claimed_resume_token: None,
             message_tracker: MessageTracker::new(),
             delivery_confirmation: true, // Enable by default
+            require_acks: false,
+            max_unacked: 0,
+            compression_threshold: 0,
+            compress_outbound: false,
+            max_content_len: 0,
+            max_frame_bytes: 64 * 1024,
+            protocol_version: ProtocolVersion::default(),
+            slow_message_threshold: Duration::ZERO,
+            delivery_paused: false,
             is_connected: false, // Initialize as not connected
+            restoring: false,
+            pending_outbound: VecDeque::new(),
+            restore_permits: None,
         }
     }
 
-    pub fn with_auth(client_id: Uuid, wallet_address: String) -> Self {
-        let mut actor = Self::new(client_id);
-        actor.authenticated = true;
-        actor.wallet_address = Some(wallet_address);
-        actor
+    // Mark the session authenticated for `wallet_address`, when there is one
+    pub fn with_auth(mut self, wallet_address: Option<String>) -> Self {
+        self.authenticated = wallet_address.is_some();
+        self.wallet_address = wallet_address;
+        self
     }
 
     pub fn set_state_manager(&mut self, addr: Addr<StateManagerActor>) {
@@ -121,11 +258,77 @@ impl ClientSessionActor {
         self.router = Some(addr);
     }
 
+    pub fn set_require_acks(&mut self, require_acks: bool) {
+        self.require_acks = require_acks;
+    }
+
+    // Ask the router to hold messages once `max_unacked` are awaiting an ack
+    pub fn set_max_unacked_messages(&mut self, max_unacked: usize) {
+        self.max_unacked = max_unacked;
+    }
+
+    // Offer deflate compression of text frames over `threshold` bytes to
+    // clients that ask for it; 0 leaves compression off
+    pub fn set_compression_threshold(&mut self, threshold: usize) {
+        self.compression_threshold = threshold;
+    }
+
+    // Reject text messages longer than `max_content_len` characters; 0 allows any length
+    pub fn set_max_content_len(&mut self, max_content_len: usize) {
+        self.max_content_len = max_content_len;
+    }
+
+    // Close the connection on text or binary frames larger than this
+    pub fn set_max_frame_bytes(&mut self, max_frame_bytes: usize) {
+        self.max_frame_bytes = max_frame_bytes;
+    }
+
+    // Message envelope version the client negotiated
+    pub fn set_protocol_version(&mut self, protocol_version: ProtocolVersion) {
+        self.protocol_version = protocol_version;
+    }
+
+    // Warn when handling one client message takes longer than this
+    pub fn set_slow_message_threshold(&mut self, threshold: Duration) {
+        self.slow_message_threshold = threshold;
+    }
+
+    pub fn set_tenant_id(&mut self, tenant_id: Option<String>) {
+        self.tenant_id = tenant_id;
+    }
+
+    // Discard buffered and unacknowledged messages once they are `ttl` old
+    pub fn set_message_ttl(&mut self, ttl: Duration) {
+        self.message_ttl = ttl;
+    }
+
+    // Replay buffered messages at no more than `messages_per_sec`
+    pub fn set_flush_rate(&mut self, messages_per_sec: u32) {
+        self.flush_interval = flush_interval(messages_per_sec);
+    }
+
+    // Wait for one of `permits` before asking the state manager for a session,
+    // so a mass reconnection doesn't flood it with restores
+    pub fn set_restore_permits(&mut self, permits: Arc<Semaphore>) {
+        self.restore_permits = Some(permits);
+    }
+
+    // Resume the saved session only if it was issued this token; without a
+    // matching one the connection starts a fresh session
+    pub fn set_claimed_resume_token(&mut self, token: Option<String>) {
+        self.claimed_resume_token = token;
+    }
+
+    // Whether outbound messages are tracked until acknowledged
+    fn tracks_delivery(&self) -> bool {
+        self.delivery_confirmation || self.require_acks
+    }
+
     // Enhanced heartbeat with reconnection attempts
     fn heartbeat(&self, ctx: &mut ws::WebsocketContext<Self>) {
         ctx.run_interval(self.heartbeat_interval, |act, ctx| {
             if Instant::now().duration_since(act.last_heartbeat) > act.heartbeat_timeout {
-                tracing::warn!("Client heartbeat timeout: {}", act.client_id);
+                tracing::warn!(connection_id = %act.connection_id, "Client heartbeat timeout: {}", act.client_id);
 
                 // Save session state before attempting reconnection
                 act.save_session_state();
@@ -147,6 +350,7 @@ impl ClientSessionActor {
                 
                 if act.reconnect_attempts > act.max_reconnect_attempts {
                     tracing::error!(
+                        connection_id = %act.connection_id,
                         "Client {} exceeded maximum reconnection attempts ({}), stopping", 
                         act.client_id, act.max_reconnect_attempts
                     );
@@ -173,52 +377,83 @@ impl ClientSessionActor {
                 );
                 
                 // Try to ping again for reconnection
-                ctx.ping(b"reconnect");
+                act.send_ping(ctx);
             } else {
                 // Check for expired message acknowledgements during normal operation
                 act.check_and_resend_pending_messages(ctx);
                 
                 // Send regular ping
-                ctx.ping(b"");
+                act.send_ping(ctx);
             }
         });
     }
 
+    // Ping with a payload of our own, so the pong answering it can be timed.
+    // Pings unanswered within the heartbeat timeout are forgotten
+    fn send_ping(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        let now = Instant::now();
+        let timeout = self.heartbeat_timeout;
+        self.pending_pings.retain(|_, sent| now.duration_since(*sent) <= timeout);
+        
+        self.ping_seq += 1;
+        let payload = self.ping_seq.to_be_bytes().to_vec();
+        ctx.ping(&payload);
+        self.pending_pings.insert(payload, now);
+    }
+
+    // Fold the round-trip time of the ping `payload` answers into the rolling
+    // average and report it
+    fn record_pong(&mut self, payload: &[u8]) {
+        let Some(sent) = self.pending_pings.remove(payload) else {
+            return;
+        };
+        let rtt_ms = sent.elapsed().as_secs_f64() * 1000.0;
+        let average = match self.last_rtt_ms {
+            Some(average) => average * (1.0 - RTT_SMOOTHING) + rtt_ms * RTT_SMOOTHING,
+            None => rtt_ms,
+        };
+        self.last_rtt_ms = Some(average);
+        if let Some(state_manager) = &self.state_manager {
+            state_manager.do_send(UpdateClientLatency { client_id: self.client_id, rtt_ms: average });
+        }
+    }
+
     // Buffer a message for later delivery
-    pub fn buffer_message(&mut self, content: String) -> Option<u64> {
+    pub fn buffer_message(&mut self, frame: OutboundFrame) -> Option<u64> {
         let buffer_full = self.message_buffer.len() >= self.max_buffer_size;
         
         if buffer_full {
             tracing::warn!("Message buffer full for client: {}, dropping message", self.client_id);
+            self.record_dropped_message();
             None
         } else {
-            // If delivery confirmation is enabled, track the message
-            let message_id = if self.delivery_confirmation {
-                let id = self.message_tracker.next_id();
-                self.message_tracker.add_pending(id, content.clone());
-                Some(id)
-            } else {
-                None
+            // If delivery confirmation is enabled, track the message.
+            // Acks reference text messages only, so binary frames are untracked.
+            let message_id = match &frame {
+                OutboundFrame::Text(content) if self.tracks_delivery() => {
+                    let id = self.message_tracker.next_id();
+                    self.message_tracker.add_pending(id, content.clone());
+                    Some(id)
+                },
+                _ => None,
             };
             
-            self.message_buffer.push_back(content);
-            
-            // Update metrics on message buffering
-            if let Some(state_manager) = &self.state_manager {
-                state_manager.do_send(UpdateClientMessageMetrics {
-                    client_id: self.client_id,
-                    sent: false, // Not sent yet, just buffered
-                    bytes: None, // No byte count for just buffering
-                });
-            }
+            // Counted as sent once a flush delivers it
+            self.message_buffer.push_back((frame, Instant::now()));
             
             message_id
         }
     }
 
-    // Send buffered messages with optional batching to avoid flooding
-    fn send_buffered_messages(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
-        if self.message_buffer.is_empty() {
+    // A client that has stopped answering pings is treated as backed up, so
+    // buffered messages are not piled onto a socket it is not draining
+    fn is_backed_up(&self) -> bool {
+        Instant::now().duration_since(self.last_heartbeat) > self.heartbeat_interval * 2
+    }
+
+    // Begin replaying the buffer, unless a replay is already pacing it
+    fn start_flush(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.flushing || self.message_buffer.is_empty() {
             return;
         }
         
@@ -226,33 +461,108 @@ impl ClientSessionActor {
             "Sending {} buffered messages for client: {}", 
             self.message_buffer.len(), self.client_id
         );
+        self.flushing = true;
+        self.send_buffered_messages(ctx);
+    }
+
+    // Replay buffered messages one at a time, paced by the flush rate
+    fn send_buffered_messages(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.message_buffer.is_empty() {
+            self.flushing = false;
+            return;
+        }
         
-        // Take at most 10 messages at a time to avoid flooding
-        let batch_size = std::cmp::min(10, self.message_buffer.len());
-        for _ in 0..batch_size {
-            if let Some(msg) = self.message_buffer.pop_front() {
-                ctx.text(msg.clone()); // Fixed: Clone the message
-                
-                // Update metrics
-                if let Some(state_manager) = &self.state_manager {
-                    state_manager.do_send(UpdateClientMessageMetrics {
-                        client_id: self.client_id,
-                        sent: true,
-                        bytes: Some(msg.len()),
-                    });
-                }
-            }
+        if self.is_backed_up() {
+            tracing::debug!(
+                "Client {} is not responding, holding {} buffered messages",
+                self.client_id, self.message_buffer.len()
+            );
+        } else if let Some(msg) = self.pop_fresh_buffered() {
+            let msg = match msg {
+                OutboundFrame::Text(text) => OutboundFrame::Text(self.sequenced(text)),
+                binary => binary,
+            };
+            let bytes = self.write_frame(&msg, ctx);
+            self.record_sent(bytes);
         }
         
-        // If more messages remain, schedule another send after a short delay
+        // If more messages remain, send the next one once the interval has passed
         if !self.message_buffer.is_empty() {
-            let remaining = self.message_buffer.len();
-            tracing::debug!("Scheduled sending of remaining {} messages", remaining);
-            
-            // Schedule next batch after a short delay
-            ctx.run_later(Duration::from_millis(100), |act, ctx| {
+            ctx.run_later(self.flush_interval, |act, ctx| {
                 act.send_buffered_messages(ctx);
             });
+        } else {
+            self.flushing = false;
+            
+            // Buffer drained - release messages that arrived in the meantime
+            self.flush_pending_outbound(ctx);
+        }
+    }
+    
+    // Take the next buffered message still within its TTL, discarding and
+    // counting any expired ones ahead of it
+    fn pop_fresh_buffered(&mut self) -> Option<OutboundFrame> {
+        let mut expired = 0;
+        let fresh = loop {
+            match self.message_buffer.pop_front() {
+                Some((_, buffered_at)) if buffered_at.elapsed() > self.message_ttl => expired += 1,
+                next => break next.map(|(frame, _)| frame),
+            }
+        };
+        
+        if expired > 0 {
+            tracing::debug!("Discarded {} expired buffered messages for client {}", expired, self.client_id);
+            if let Some(state_manager) = &self.state_manager {
+                state_manager.do_send(BufferedMessagesExpired { client_id: self.client_id, count: expired });
+            }
+        }
+        fresh
+    }
+    
+    // Whether new outbound messages must wait behind restored/buffered ones
+    fn holds_outbound(&self) -> bool {
+        self.is_connected && (self.restoring || !self.message_buffer.is_empty())
+    }
+    
+    // Count a message lost to a full buffer against this client
+    fn record_dropped_message(&self) {
+        if let Some(state_manager) = &self.state_manager {
+            state_manager.do_send(ClientMessageDropped { client_id: self.client_id });
+        }
+    }
+    
+    // Queue an outbound message that arrived during restore
+    fn hold_outbound(&mut self, frame: OutboundFrame) {
+        if self.pending_outbound.len() >= self.max_buffer_size {
+            tracing::warn!("Pending outbound queue full for client: {}, dropping message", self.client_id);
+            self.record_dropped_message();
+        } else {
+            self.pending_outbound.push_back(frame);
+        }
+    }
+    
+    // Deliver held messages once restored messages have been sent
+    fn flush_pending_outbound(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.holds_outbound() || self.pending_outbound.is_empty() {
+            return;
+        }
+        
+        tracing::debug!(
+            "Delivering {} messages held during session restore for client {}",
+            self.pending_outbound.len(), self.client_id
+        );
+        while let Some(frame) = self.pending_outbound.pop_front() {
+            self.deliver_frame(frame, ctx);
+        }
+    }
+    
+    // Mark the session restore as complete and release held messages
+    fn finish_restore(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        self.restoring = false;
+        if self.message_buffer.is_empty() {
+            self.flush_pending_outbound(ctx);
+        } else {
+            self.start_flush(ctx);
         }
     }
 
@@ -264,28 +574,57 @@ impl ClientSessionActor {
             
             tracing::info!("Requesting session state for client {}", client_id);
             
-            // Create future to get session state
-            let future = state_manager.send(GetSessionState { client_id });
+            // Create future to get session state, holding a permit (if
+            // restores are capped) until the state manager replies
+            let state_manager = state_manager.clone();
+            let permits = self.restore_permits.clone();
+            let future = async move {
+                let _permit = match permits {
+                    Some(permits) => permits.acquire_owned().await.ok(),
+                    None => None,
+                };
+                state_manager.send(GetSessionState { client_id }).await
+            };
+            
+            // Outbound messages are held until the restore completes
+            self.restoring = true;
             
-            // Handle the future response properly
-            actix::fut::wrap_future::<_, Self>(async move {
-                match future.await {
-                    Ok(Some(session)) => {
-                        tracing::info!("Retrieved session state for client {}", client_id);
-                        addr.do_send(session);
-                    },
-                    Ok(None) => {
-                        tracing::debug!("No saved session state for client {}", client_id);
-                    },
-                    Err(e) => {
-                        tracing::error!("Error retrieving session state: {}", e);
+            // Fetch in the background so the actor keeps processing messages
+            // while the session loads; the result is delivered via do_send.
+            // Never `.wait` on this: it suspends the mailbox until the state
+            // manager replies, so a burst of reconnects would leave every new
+            // connection stalled behind the state manager's backlog.
+            actix::fut::wrap_future::<_, Self>(future)
+                .map(move |result, act, ctx| {
+                    match result {
+                        Ok(Some(session)) if act.may_resume(&session) => {
+                            tracing::info!("Retrieved session state for client {}", client_id);
+                            // The SessionState handler completes the restore
+                            addr.do_send(session);
+                        },
+                        Ok(Some(_)) => {
+                            tracing::warn!("Client {} presented no valid resume token, starting a fresh session", client_id);
+                            act.finish_restore(ctx);
+                        },
+                        Ok(None) => {
+                            tracing::debug!("No saved session state for client {}", client_id);
+                            act.finish_restore(ctx);
+                        },
+                        Err(e) => {
+                            tracing::error!("Error retrieving session state: {}", e);
+                            act.finish_restore(ctx);
+                        }
                     }
-                }
-            })
-            .wait(ctx); // Fixed: wait method now available from imported trait
+                })
+                .spawn(ctx);
         }
     }
 
+    // Whether this connection presented the token `session` was issued
+    fn may_resume(&self, session: &SessionState) -> bool {
+        !session.resume_token.is_empty() && self.claimed_resume_token.as_ref() == Some(&session.resume_token)
+    }
+
     // Update activity with state manager
     fn update_activity(&self, is_message: bool) {
         if let Some(state_manager) = &self.state_manager {
@@ -296,33 +635,43 @@ impl ClientSessionActor {
         }
     }
     
+    // Current session state, as saved for a later connection to restore
+    fn session_snapshot(&self) -> SessionState {
+        SessionState {
+            client_id: self.client_id,
+            authenticated: self.authenticated,
+            wallet_address: self.wallet_address.clone(),
+            // Convert VecDeque to Vec for serialization
+            message_buffer: self.message_buffer.iter().cloned().collect(),
+            last_seen: self.last_heartbeat,
+            session_data: self.session_data.clone(),
+            next_sequence: self.next_sequence,
+            resume_token: self.resume_token.clone(),
+        }
+    }
+    
     // Save current session state
     fn save_session_state(&self) {
         if let Some(state_manager) = &self.state_manager {
-            // Convert VecDeque to Vec for serialization
-            let buffer_vec: Vec<String> = self.message_buffer.iter().cloned().collect();
-            
-            let session_state = SessionState {
-                client_id: self.client_id,
-                authenticated: self.authenticated,
-                wallet_address: self.wallet_address.clone(),
-                message_buffer: buffer_vec,
-                last_seen: self.last_heartbeat,
-                session_data: self.session_data.clone(),
-            };
-            
-            state_manager.do_send(SaveSessionState { state: session_state });
+            state_manager.do_send(SaveSessionState { state: self.session_snapshot() });
             tracing::debug!("Saved session state for client {}", self.client_id);
         }
     }
     
     // Check for expired message acknowledgements and resend
-    fn check_and_resend_pending_messages(&self, ctx: &mut ws::WebsocketContext<Self>) {
+    fn check_and_resend_pending_messages(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
         // Only proceed if delivery confirmation is enabled
-        if !self.delivery_confirmation {
+        if !self.tracks_delivery() {
             return;
         }
         
+        // Messages past their TTL are stale, so stop resending them
+        let stale = self.message_tracker.discard_older_than(self.message_ttl);
+        if stale > 0 {
+            tracing::debug!("Gave up on {} stale unacknowledged messages for client {}", stale, self.client_id);
+            self.update_flow_control();
+        }
+        
         // Check for expired messages
         let expired_ids = self.message_tracker.check_expired();
         if !expired_ids.is_empty() {
@@ -335,16 +684,8 @@ impl ClientSessionActor {
             for msg_id in expired_ids {
                 if let Some((content, _)) = self.message_tracker.pending_acks.get(&msg_id) {
                     tracing::debug!("Resending message {} to client {}", msg_id, self.client_id);
-                    ctx.text(content.clone());
-                    
-                    // Update metrics
-                    if let Some(state_manager) = &self.state_manager {
-                        state_manager.do_send(UpdateClientMessageMetrics {
-                            client_id: self.client_id,
-                            sent: true,
-                            bytes: Some(content.len()),
-                        });
-                    }
+                    let bytes = self.write_text(content.clone(), ctx);
+                    self.record_sent(bytes);
                 }
             }
         }
@@ -358,6 +699,26 @@ impl ClientSessionActor {
             tracing::warn!("Received ack for unknown message ID {} from client {}", 
                         msg_id, self.client_id);
         }
+        self.update_flow_control();
+    }
+    
+    // Pause router delivery while too many messages await an ack, and
+    // resume once acks bring the count back under the limit
+    fn update_flow_control(&mut self) {
+        if self.max_unacked == 0 {
+            return;
+        }
+        let Some(router) = &self.router else { return };
+        
+        let outstanding = self.message_tracker.pending_acks.len();
+        if !self.delivery_paused && outstanding >= self.max_unacked {
+            tracing::info!("Client {} has {} unacknowledged messages, pausing delivery", self.client_id, outstanding);
+            self.delivery_paused = true;
+            router.do_send(PauseClientDelivery { client_id: self.client_id });
+        } else if self.delivery_paused && outstanding < self.max_unacked {
+            self.delivery_paused = false;
+            router.do_send(ResumeClientDelivery { client_id: self.client_id });
+        }
     }
     
     // Create acknowledgement message
@@ -373,6 +734,24 @@ impl ClientSessionActor {
         }
     }
     
+    // Close the connection over a frame larger than `max_frame_bytes`,
+    // before it is buffered or forwarded. Returns whether it was rejected
+    fn reject_oversized_frame(&self, len: usize, ctx: &mut ws::WebsocketContext<Self>) -> bool {
+        if len <= self.max_frame_bytes {
+            return false;
+        }
+        tracing::warn!(
+            connection_id = %self.connection_id,
+            "Client {} sent a {} byte frame, over the {} byte limit; closing", self.client_id, len, self.max_frame_bytes
+        );
+        ctx.text(ErrorFrame::new(ErrorFrame::FRAME_TOO_LARGE, "Frame too large")
+            .with_detail(serde_json::json!({ "max_frame_bytes": self.max_frame_bytes }))
+            .to_json());
+        ctx.close(Some(frame_too_large_reason()));
+        ctx.stop();
+        true
+    }
+    
     // Handle client text messages
     fn handle_client_message(&mut self, text: String, ctx: &mut ws::WebsocketContext<Self>) {
         // Update metrics
@@ -384,42 +763,58 @@ impl ClientSessionActor {
             });
         }
         
-        // Check if this is an acknowledgement message
-        if text.contains("\"ack\":") || text.contains("\"message_id\":") {
-            // Simple check - in production use proper JSON parsing
-            if let Some(msg_id_start) = text.find("\"message_id\":") {
-                let after_id = &text[msg_id_start + 13..]; // Skip "message_id":
-                if let Some(end) = after_id.find(',').or_else(|| after_id.find('}')) {
-                    if let Ok(msg_id) = after_id[..end].trim().parse::<u64>() {
-                        self.process_ack(msg_id);
-                        
-                        // If this is just an ack message, don't forward to router
-                        if text.contains("\"type\":\"ack\"") {
-                            return;
-                        }
-                    }
-                }
-            }
+        // Acknowledgements are handled here and not forwarded to the router
+        let frame = IncomingClientFrame::parse(&text);
+        if let Some(msg_id) = frame.ack_id() {
+            self.process_ack(msg_id);
+            return;
+        }
+        if frame.frame_type.as_deref() == Some("enable_compression") {
+            self.enable_compression(ctx);
+            return;
         }
         
-        // Create client message for router
-        let timestamp = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-            
-        let client_msg = ClientMessage {
-            client_id: self.client_id,
-            content: text.clone(), // Fixed: Clone here to avoid move
-            authenticated: self.authenticated,
-            wallet_address: self.wallet_address.clone(),
-            timestamp,
-            message_id: None,
-            requires_ack: false,
-            session_id: self.session_id.clone(),
-        };
+        if content_exceeds(&text, self.max_content_len) {
+            tracing::warn!("Client {} sent a message over {} characters", self.client_id, self.max_content_len);
+            let error = ErrorFrame::new(ErrorFrame::CONTENT_TOO_LONG, "Message content too long")
+                .with_detail(serde_json::json!({ "max_content_len": self.max_content_len }));
+            ctx.text(error.to_json());
+            return;
+        }
+        
+        // Create client message for router; JSON payloads may name the
+        // agent that should handle them, or a topic its subscribers handle
+        let client_msg = self.client_message(ClientMessage::new(self.client_id, text))
+            .with_target_agent_id(frame.target_agent_id)
+            .with_topic(frame.topic);
+        
+        self.forward_to_router(client_msg, ctx);
+    }
+    
+    // Handle client binary messages, forwarded to agents base64-encoded
+    fn handle_client_binary(&mut self, data: Bytes, ctx: &mut ws::WebsocketContext<Self>) {
+        if let Some(state_manager) = &self.state_manager {
+            state_manager.do_send(UpdateClientMessageMetrics {
+                client_id: self.client_id,
+                sent: false, // We're receiving this
+                bytes: Some(data.len()),
+            });
+        }
         
-        // Forward to router
+        let client_msg = self.client_message(ClientMessage::binary(self.client_id, data.to_vec()));
+        self.forward_to_router(client_msg, ctx);
+    }
+    
+    // Stamp a message with this session's identity
+    fn client_message(&self, message: ClientMessage) -> ClientMessage {
+        message
+            .with_authentication(self.authenticated, self.wallet_address.clone())
+            .with_session_id(self.session_id.clone())
+            .with_tenant_id(self.tenant_id.clone())
+    }
+    
+    // Forward a client message to the router, reporting failures to the client
+    fn forward_to_router(&self, client_msg: ClientMessage, ctx: &mut ws::WebsocketContext<Self>) {
         if let Some(router) = &self.router {
             match router.try_send(client_msg) {
                 Ok(_) => {
@@ -427,12 +822,153 @@ impl ClientSessionActor {
                 },
                 Err(e) => {
                     tracing::error!("Failed to send message to router: {}", e);
-                    ctx.text(r#"{"error":"Internal routing error"}"#);
+                    ctx.text(ErrorFrame::new(ErrorFrame::ROUTING_FAILED, "Internal routing error").to_json());
                 }
             }
         } else {
             tracing::error!("Router not available for client {}", self.client_id);
-            ctx.text(r#"{"error":"Router not configured"}"#);
+            ctx.text(ErrorFrame::new(ErrorFrame::ROUTER_UNAVAILABLE, "Router not configured").to_json());
+        }
+    }
+    
+    // Send a routed text or binary frame to the client
+    fn deliver_frame(&mut self, frame: OutboundFrame, ctx: &mut ws::WebsocketContext<Self>) {
+        match frame {
+            OutboundFrame::Text(content) => self.deliver_message(content, ctx),
+            OutboundFrame::Binary(data) => self.deliver_binary(data, ctx),
+        }
+    }
+    
+    // Switch this connection to compressed frames, confirming to the client
+    // so it knows to expect header bytes on binary frames from now on
+    fn enable_compression(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.compression_threshold == 0 {
+            ctx.text(ErrorFrame::new(ErrorFrame::COMPRESSION_UNAVAILABLE, "Compression not available").to_json());
+            return;
+        }
+        
+        tracing::debug!("Client {} enabled frame compression", self.client_id);
+        self.compress_outbound = true;
+        ctx.text(format!(
+            r#"{{"type":"compression_enabled","encoding":"deflate","threshold":{}}}"#,
+            self.compression_threshold
+        ));
+    }
+    
+    // Write a frame to the socket, returning the bytes written for metrics
+    fn write_frame(&self, frame: &OutboundFrame, ctx: &mut ws::WebsocketContext<Self>) -> usize {
+        match frame {
+            OutboundFrame::Text(text) => self.write_text(text.clone(), ctx),
+            OutboundFrame::Binary(data) => {
+                let data = if self.compress_outbound { compression::mark_raw(data) } else { data.clone() };
+                let bytes = data.len();
+                ctx.binary(data);
+                bytes
+            }
+        }
+    }
+    
+    // Write a text frame, deflated into a binary frame when the client
+    // enabled compression and the text is over the threshold
+    fn write_text(&self, text: String, ctx: &mut ws::WebsocketContext<Self>) -> usize {
+        if self.compress_outbound && text.len() > self.compression_threshold {
+            match compression::deflate_text(&text) {
+                Ok(data) => {
+                    let bytes = data.len();
+                    ctx.binary(data);
+                    return bytes;
+                }
+                Err(e) => tracing::warn!("Failed to compress frame for client {}, sending as text: {}", self.client_id, e),
+            }
+        }
+        let bytes = text.len();
+        ctx.text(text);
+        bytes
+    }
+    
+    // Count bytes written to the client's socket
+    fn record_sent(&self, bytes: usize) {
+        if let Some(state_manager) = &self.state_manager {
+            state_manager.do_send(UpdateClientMessageMetrics {
+                client_id: self.client_id,
+                sent: true,
+                bytes: Some(bytes),
+            });
+        }
+    }
+    
+    // Send a binary frame to the client, buffering it while disconnected
+    fn deliver_binary(&mut self, data: Bytes, ctx: &mut ws::WebsocketContext<Self>) {
+        if !self.is_connected {
+            tracing::warn!("Client {} WebSocket not connected, buffering binary message", self.client_id);
+            self.buffer_message(OutboundFrame::Binary(data));
+            return;
+        }
+        
+        let bytes = self.write_frame(&OutboundFrame::Binary(data), ctx);
+        self.record_sent(bytes);
+    }
+    
+    // Stamp a JSON message with the next sequence number, so the client can
+    // spot reordering or gaps. Other text goes out as it is
+    fn sequenced(&mut self, content: String) -> String {
+        match append_json_field(&content, "sequence", self.next_sequence) {
+            Some(stamped) => {
+                self.next_sequence += 1;
+                stamped
+            },
+            None => content,
+        }
+    }
+    
+    // Send a routed message to the client, tracking it for delivery confirmation
+    fn deliver_message(&mut self, content: String, ctx: &mut ws::WebsocketContext<Self>) {
+        // Check if WebSocket is connected
+        if !self.is_connected { // Fixed: Use is_connected field instead of ctx.connected()
+            tracing::warn!("Client {} WebSocket not connected, buffering message", self.client_id);
+            self.buffer_message(OutboundFrame::Text(content));
+            return;
+        }
+        
+        // Stamped before tracking, so a resend keeps its sequence
+        let content = self.sequenced(content);
+        
+        // Check if we should add message ID for delivery confirmation
+        if self.tracks_delivery() {
+            // Try to parse as JSON to add message ID
+            // For real implementation, use proper JSON parsing libraries
+            if content.trim_start().starts_with('{') && content.trim_end().ends_with('}') {
+                let msg_id = self.message_tracker.next_id();
+                
+                // Add message ID to content
+                let content_with_id = if content.contains("\"message_id\":") {
+                    // Already has message ID
+                    content
+                } else {
+                    append_json_field(&content, "message_id", msg_id).unwrap_or(content)
+                };
+                
+                // Track message for delivery confirmation
+                self.message_tracker.add_pending(msg_id, content_with_id.clone());
+                self.update_flow_control();
+                
+                // Send to client
+                let bytes = self.write_text(content_with_id, ctx);
+                self.record_sent(bytes);
+                tracing::debug!(
+                    "Sent message to client {} with tracking ID {}", 
+                    self.client_id, msg_id
+                );
+            } else {
+                // Not valid JSON, send as-is without tracking
+                let bytes = self.write_text(content, ctx);
+                self.record_sent(bytes);
+                tracing::debug!("Sent untracked message to client {}", self.client_id);
+            }
+        } else {
+            // No delivery confirmation, send as-is
+            let bytes = self.write_text(content, ctx);
+            self.record_sent(bytes);
         }
     }
 }
@@ -441,7 +977,11 @@ impl Actor for ClientSessionActor {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        tracing::info!("Client connected: {}", self.client_id);
+        self.connection_id = Uuid::new_v4();
+        tracing::info!(
+            connection_id = %self.connection_id, protocol = %self.protocol_version,
+            "Client connected: {}", self.client_id
+        );
         self.last_heartbeat = Instant::now();
         self.reconnect_attempts = 0; // Reset on successful connection
         self.is_connected = true; // Set connection status to true
@@ -449,11 +989,18 @@ impl Actor for ClientSessionActor {
         // Start heartbeat
         self.heartbeat(ctx);
         
+        // Tell the client how to resume this session should it reconnect
+        ctx.text(SessionFrame { resume_token: self.resume_token.clone() }.to_json());
+        
         // Restore session state
         self.restore_session(ctx);
         
         // Notify state manager about connection
         if let Some(state_manager) = &self.state_manager {
+            state_manager.do_send(UpdateClientConnection {
+                client_id: self.client_id,
+                connection_id: self.connection_id,
+            });
             state_manager.do_send(UpdateClientState {
                 client_id: self.client_id,
                 state: ConnectionState::Connected,
@@ -479,13 +1026,13 @@ impl Actor for ClientSessionActor {
         // Send any existing buffered messages (if any)
         if !self.message_buffer.is_empty() {
             ctx.run_later(Duration::from_millis(100), |act, ctx| {
-                act.send_buffered_messages(ctx);
+                act.start_flush(ctx);
             });
         }
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
-        tracing::info!("Client disconnected: {}", self.client_id);
+        tracing::info!(connection_id = %self.connection_id, "Client disconnected: {}", self.client_id);
         self.is_connected = false; // Set connection status to false
         
         // Save session state before stopping
@@ -534,9 +1081,10 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ClientSessionActo
                     self.reconnect_attempts = 0;
                 }
             },
-            Ok(ws::Message::Pong(_)) => {
+            Ok(ws::Message::Pong(payload)) => {
                 self.last_heartbeat = Instant::now();
                 self.update_activity(false);
+                self.record_pong(&payload);
                 
                 // Reset reconnection attempts on successful pong
                 if self.reconnect_attempts > 0 {
@@ -545,31 +1093,32 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ClientSessionActo
                 }
             },
             Ok(ws::Message::Text(text)) => {
+                if self.reject_oversized_frame(text.len(), ctx) {
+                    return;
+                }
                 self.last_heartbeat = Instant::now();
                 self.update_activity(true);
                 tracing::debug!("Received text message from client {}: {} bytes", 
                               self.client_id, text.len());
                 
                 // Use our enhanced message handler
-                self.handle_client_message(text.to_string(), ctx);
+                let (size, threshold) = (text.len(), self.slow_message_threshold);
+                time_message("client_session", "text", size, threshold, || self.handle_client_message(text.to_string(), ctx));
             },
             Ok(ws::Message::Binary(bin)) => {
+                if self.reject_oversized_frame(bin.len(), ctx) {
+                    return;
+                }
                 self.last_heartbeat = Instant::now();
                 self.update_activity(true);
-                tracing::warn!("Binary messages not supported for client: {}", self.client_id);
-                ctx.text(r#"{"error":"Binary messages not supported"}"#);
+                tracing::debug!("Received binary message from client {}: {} bytes", 
+                              self.client_id, bin.len());
                 
-                // Update metrics for binary messages too
-                if let Some(state_manager) = &self.state_manager {
-                    state_manager.do_send(UpdateClientMessageMetrics {
-                        client_id: self.client_id,
-                        sent: false, // Received, not sent
-                        bytes: Some(bin.len()),
-                    });
-                }
+                let (size, threshold) = (bin.len(), self.slow_message_threshold);
+                time_message("client_session", "binary", size, threshold, || self.handle_client_binary(bin, ctx));
             },
             Ok(ws::Message::Close(reason)) => {
-                tracing::info!("Client closing connection: {:?}", reason);
+                tracing::info!(connection_id = %self.connection_id, "Client closing connection: {:?}", reason);
                 
                 // Save session state before closing
                 self.save_session_state();
@@ -593,7 +1142,15 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ClientSessionActo
                 tracing::trace!("Received nop frame from client {}", self.client_id);
             },
             Err(e) => {
-                tracing::error!("WebSocket protocol error from client {}: {}", self.client_id, e);
+                tracing::error!(
+                    connection_id = %self.connection_id,
+                    "WebSocket protocol error from client {}: {}", self.client_id, e
+                );
+                
+                // The codec refuses frames over the limit before they reach us
+                if matches!(e, ws::ProtocolError::Overflow) {
+                    ctx.close(Some(frame_too_large_reason()));
+                }
                 
                 // Save session state on error
                 self.save_session_state();
@@ -617,65 +1174,35 @@ impl Handler<ClientActorMessage> for ClientSessionActor {
     type Result = ();
 
     fn handle(&mut self, msg: ClientActorMessage, ctx: &mut Self::Context) -> Self::Result {
-        let content = msg.content.clone();
         tracing::info!("Received message via router for client {}, {} bytes", 
-                      self.client_id, content.len());
+                      self.client_id, msg.content.len());
         
-        // Update metrics for sending to client
-        if let Some(state_manager) = &self.state_manager {
-            state_manager.do_send(UpdateClientMessageMetrics {
-                client_id: self.client_id,
-                sent: true,
-                bytes: Some(content.len()),
-            });
+        // Keep ordering: restored messages go out before anything new
+        if self.holds_outbound() {
+            tracing::debug!("Session restore in progress for client {}, holding message", self.client_id);
+            self.hold_outbound(OutboundFrame::Text(msg.content));
+            return;
         }
         
-        // Check if WebSocket is connected
-        if !self.is_connected { // Fixed: Use is_connected field instead of ctx.connected()
-            tracing::warn!("Client {} WebSocket not connected, buffering message", self.client_id);
-            self.buffer_message(content);
+        self.deliver_message(msg.content, ctx);
+    }
+}
+
+// Handle binary messages FROM the router TO this client
+impl Handler<ClientActorBinaryMessage> for ClientSessionActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClientActorBinaryMessage, ctx: &mut Self::Context) -> Self::Result {
+        tracing::info!("Received binary message via router for client {}, {} bytes", 
+                      self.client_id, msg.content.len());
+        
+        if self.holds_outbound() {
+            tracing::debug!("Session restore in progress for client {}, holding binary message", self.client_id);
+            self.hold_outbound(OutboundFrame::Binary(msg.content));
             return;
         }
         
-        // Check if we should add message ID for delivery confirmation
-        if self.delivery_confirmation {
-            // Try to parse as JSON to add message ID
-            // For real implementation, use proper JSON parsing libraries
-            if content.trim_start().starts_with('{') && content.trim_end().ends_with('}') {
-                let msg_id = self.message_tracker.next_id();
-                
-                // Add message ID to content
-                let content_with_id = if content.contains("\"message_id\":") {
-                    // Already has message ID
-                    content
-                } else {
-                    // Add message ID
-                    let content_without_brace = content.trim_end_matches('}');
-                    if content_without_brace.ends_with(',') {
-                        format!("{}\"message_id\":{}}}", content_without_brace, msg_id)
-                    } else {
-                        format!("{},\"message_id\":{}}}", content_without_brace, msg_id)
-                    }
-                };
-                
-                // Track message for delivery confirmation
-                self.message_tracker.add_pending(msg_id, content_with_id.clone());
-                
-                // Send to client
-                ctx.text(content_with_id);
-                tracing::debug!(
-                    "Sent message to client {} with tracking ID {}", 
-                    self.client_id, msg_id
-                );
-            } else {
-                // Not valid JSON, send as-is without tracking
-                ctx.text(content);
-                tracing::debug!("Sent untracked message to client {}", self.client_id);
-            }
-        } else {
-            // No delivery confirmation, send as-is
-            ctx.text(content);
-        }
+        self.deliver_binary(msg.content, ctx);
     }
 }
 
@@ -687,9 +1214,11 @@ impl Handler<SessionState> for ClientSessionActor {
         if msg.client_id == self.client_id {
             tracing::info!("Restoring session state for client {}", self.client_id);
             
-            // Restore authentication state
-            self.authenticated = msg.authenticated;
-            self.wallet_address = msg.wallet_address;
+            // Restore authentication state, unless this connection brought its own
+            if !self.authenticated {
+                self.authenticated = msg.authenticated;
+                self.wallet_address = msg.wallet_address;
+            }
             
             // Queue messages from saved session
             for message in msg.message_buffer {
@@ -698,11 +1227,8 @@ impl Handler<SessionState> for ClientSessionActor {
             
             // Restore session data
             self.session_data = msg.session_data;
-            
-            // Send buffered messages
-            if !self.message_buffer.is_empty() {
-                self.send_buffered_messages(ctx);
-            }
+            // Carry on the sequence, so the client sees no step back
+            self.next_sequence = self.next_sequence.max(msg.next_sequence);
             
             // Notify about session restoration
             if let Some(session_id) = &self.session_id {
@@ -716,6 +1242,17 @@ impl Handler<SessionState> for ClientSessionActor {
             
             tracing::info!("Session restored for client {}", self.client_id);
         }
+        
+        self.finish_restore(ctx);
+    }
+}
+
+// Handler for session snapshots taken on shutdown
+impl Handler<SnapshotSession> for ClientSessionActor {
+    type Result = actix::MessageResult<SnapshotSession>;
+    
+    fn handle(&mut self, _msg: SnapshotSession, _ctx: &mut Self::Context) -> Self::Result {
+        actix::MessageResult(self.session_snapshot())
     }
 }
 
@@ -729,4 +1266,692 @@ impl Handler<MessageAcknowledgement> for ClientSessionActor {
         // Process acknowledgement
         self.process_ack(msg.message_id);
     }
-}
\ No newline at end of file
+}
+
+// Handler for an operator kicking this client
+impl Handler<Shutdown> for ClientSessionActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: Shutdown, ctx: &mut Self::Context) -> Self::Result {
+        tracing::info!(connection_id = %self.connection_id, "Shutting down client {}: {}", self.client_id, msg.reason);
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some(msg.reason),
+        }));
+        ctx.stop();
+    }
+}
+
+// The state manager replaced a router that stopped
+impl Handler<SetRouter> for ClientSessionActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: SetRouter, _ctx: &mut Self::Context) -> Self::Result {
+        self.router = Some(msg.router);
+        // The new router holds nothing back for us yet
+        self.delivery_paused = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::AgentMessage;
+    use crate::actors::agent_actor::AgentActor;
+    use crate::actors::router_actor::RegisterAgent;
+    use common::test_support::{
+        client_binary_frame, client_pong_frame, client_text_frame, collect_frames, collect_raw_frames,
+        decode_server_frames, start_ws_actor, start_ws_actor_with_input, start_ws_actor_with_sender,
+    };
+    use crate::actors::state_manager::{GetClientStatus, RegisterClient as StateRegisterClient};
+    use tokio::sync::mpsc;
+
+    // Issued to the sessions tests save for a client to resume
+    const RESUME_TOKEN: &str = "resume-token";
+
+    #[actix::test]
+    async fn test_messages_processed_while_session_restore_pending() {
+        // Run the state manager on its own arbiter and stall that thread so the
+        // session fetch issued from started() stays pending
+        let arbiter = actix::Arbiter::new();
+        let state_manager = StateManagerActor::start_in_arbiter(&arbiter.handle(), |_| StateManagerActor::new());
+        arbiter.spawn(async {
+            std::thread::sleep(Duration::from_millis(500));
+        });
+
+        let mut client = ClientSessionActor::new(Uuid::new_v4());
+        client.set_state_manager(state_manager);
+        let (addr, _frames) = start_ws_actor(client);
+
+        // The handler must run while the fetch is still pending
+        let handled = tokio::time::timeout(
+            Duration::from_millis(150),
+            addr.send(ClientActorMessage { content: "while-restoring".to_string() }),
+        ).await;
+        assert!(handled.is_ok(), "actor blocked while restoring session");
+
+        arbiter.stop();
+    }
+
+    #[actix::test]
+    async fn test_concurrent_reconnects_do_not_wait_on_each_other() {
+        const CLIENTS: usize = 100;
+        let arbiter = actix::Arbiter::new();
+        let state_manager = StateManagerActor::start_in_arbiter(&arbiter.handle(), |_| StateManagerActor::new());
+        let client_ids: Vec<Uuid> = (0..CLIENTS).map(|_| Uuid::new_v4()).collect();
+        for client_id in &client_ids {
+            state_manager.send(SaveSessionState {
+                state: SessionState {
+                    client_id: *client_id,
+                    authenticated: false,
+                    wallet_address: None,
+                    message_buffer: vec![(OutboundFrame::from(format!("restored-{}", client_id)), Instant::now())],
+                    last_seen: Instant::now(),
+                    session_data: HashMap::new(),
+                    next_sequence: 0,
+                    resume_token: RESUME_TOKEN.to_string(),
+                },
+            }).await.unwrap();
+        }
+        // Stall the state manager so every restore is in flight at once
+        arbiter.spawn(async {
+            std::thread::sleep(Duration::from_millis(300));
+        });
+        
+        let clients: Vec<_> = client_ids.iter().map(|client_id| {
+            let mut client = ClientSessionActor::new(*client_id);
+            client.set_state_manager(state_manager.clone());
+            client.set_claimed_resume_token(Some(RESUME_TOKEN.to_string()));
+            start_ws_actor(client)
+        }).collect();
+        
+        // Every connection stays responsive while its restore is pending
+        let pings = clients.iter().map(|(addr, _)| addr.send(ClientActorMessage { content: "ping".to_string() }));
+        let handled = tokio::time::timeout(Duration::from_millis(150), futures::future::join_all(pings)).await;
+        assert!(handled.is_ok(), "connections blocked while restoring sessions");
+        
+        // Once the state manager frees up, all restores land within one short window
+        let outputs = futures::future::join_all(clients.into_iter().map(|(_, mut frames)| async move {
+            collect_frames(&mut frames, Duration::from_millis(600)).await.concat()
+        })).await;
+        for (client_id, output) in client_ids.iter().zip(outputs) {
+            assert!(output.contains(&format!("restored-{}", client_id)), "client {} was not restored", client_id);
+        }
+        
+        arbiter.stop();
+    }
+
+    #[actix::test]
+    async fn test_restore_permits_cap_requests_to_the_state_manager() {
+        const CLIENTS: usize = 20;
+        const LIMIT: usize = 3;
+        let arbiter = actix::Arbiter::new();
+        let state_manager = StateManagerActor::start_in_arbiter(&arbiter.handle(), |_| StateManagerActor::new());
+        let session = |client_id: Uuid, content: String| SaveSessionState {
+            state: SessionState {
+                client_id,
+                authenticated: false,
+                wallet_address: None,
+                message_buffer: vec![(OutboundFrame::from(content), Instant::now())],
+                last_seen: Instant::now(),
+                session_data: HashMap::new(),
+                next_sequence: 0,
+                resume_token: RESUME_TOKEN.to_string(),
+            },
+        };
+        let client_ids: Vec<Uuid> = (0..CLIENTS).map(|_| Uuid::new_v4()).collect();
+        for client_id in &client_ids {
+            state_manager.send(session(*client_id, format!("restored-{}", client_id))).await.unwrap();
+        }
+        // Stall the state manager, and only connect once it is stalled
+        let (stalled_tx, stalled_rx) = tokio::sync::oneshot::channel();
+        arbiter.spawn(async move {
+            let _ = stalled_tx.send(());
+            std::thread::sleep(Duration::from_millis(400));
+        });
+        stalled_rx.await.unwrap();
+        
+        let permits = Arc::new(Semaphore::new(LIMIT));
+        let clients: Vec<_> = client_ids.iter().map(|client_id| {
+            let mut client = ClientSessionActor::new(*client_id);
+            client.set_state_manager(state_manager.clone());
+            client.set_claimed_resume_token(Some(RESUME_TOKEN.to_string()));
+            client.set_restore_permits(permits.clone());
+            start_ws_actor(client)
+        }).collect();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        
+        // Queue an overwrite of every session behind whichever restores
+        // reached the stalled state manager; only those see the original
+        for client_id in &client_ids {
+            state_manager.do_send(session(*client_id, format!("late-{}", client_id)));
+        }
+        
+        let outputs = futures::future::join_all(clients.into_iter().map(|(_, mut frames)| async move {
+            collect_frames(&mut frames, Duration::from_millis(800)).await.concat()
+        })).await;
+        let mut restored_early = 0;
+        for (client_id, output) in client_ids.iter().zip(outputs) {
+            if output.contains(&format!("restored-{}", client_id)) {
+                restored_early += 1;
+            } else {
+                assert!(output.contains(&format!("late-{}", client_id)), "client {} was not restored", client_id);
+            }
+        }
+        assert_eq!(restored_early, LIMIT);
+        assert_eq!(permits.available_permits(), LIMIT);
+        
+        arbiter.stop();
+    }
+
+    #[actix::test]
+    async fn test_restored_messages_sent_before_new_ones() {
+        let client_id = Uuid::new_v4();
+        let arbiter = actix::Arbiter::new();
+        let state_manager = StateManagerActor::start_in_arbiter(&arbiter.handle(), |_| StateManagerActor::new());
+        state_manager.send(SaveSessionState {
+            state: SessionState {
+                client_id,
+                authenticated: false,
+                wallet_address: None,
+                message_buffer: vec![
+                    (OutboundFrame::from("restored-1".to_string()), Instant::now()),
+                    (OutboundFrame::from("restored-2".to_string()), Instant::now()),
+                ],
+                last_seen: Instant::now(),
+                session_data: HashMap::new(),
+                next_sequence: 0,
+                resume_token: RESUME_TOKEN.to_string(),
+            },
+        }).await.unwrap();
+        // Keep the restore pending while a new message arrives
+        arbiter.spawn(async {
+            std::thread::sleep(Duration::from_millis(200));
+        });
+
+        let mut client = ClientSessionActor::new(client_id);
+        client.set_state_manager(state_manager);
+        client.set_claimed_resume_token(Some(RESUME_TOKEN.to_string()));
+        let (addr, mut frames) = start_ws_actor(client);
+        addr.do_send(ClientActorMessage { content: "new-message".to_string() });
+
+        // Frames may be coalesced, so compare offsets in the combined output
+        let output = collect_frames(&mut frames, Duration::from_millis(500)).await.concat();
+        let position = |needle: &str| output.find(needle)
+            .unwrap_or_else(|| panic!("{} was never sent", needle));
+        assert!(position("restored-1") < position("restored-2"));
+        assert!(position("restored-2") < position("new-message"));
+
+        arbiter.stop();
+    }
+
+    #[actix::test]
+    async fn test_sequence_increases_across_buffered_and_live_messages() {
+        let client_id = Uuid::new_v4();
+        let arbiter = actix::Arbiter::new();
+        let state_manager = StateManagerActor::start_in_arbiter(&arbiter.handle(), |_| StateManagerActor::new());
+        state_manager.send(SaveSessionState {
+            state: SessionState {
+                client_id,
+                authenticated: false,
+                wallet_address: None,
+                message_buffer: (0..5)
+                    .map(|i| (OutboundFrame::from(format!(r#"{{"content":"buffered-{}"}}"#, i)), Instant::now()))
+                    .collect(),
+                last_seen: Instant::now(),
+                session_data: HashMap::new(),
+                // Where the previous connection left off
+                next_sequence: 7,
+                resume_token: RESUME_TOKEN.to_string(),
+            },
+        }).await.unwrap();
+        arbiter.spawn(async {
+            std::thread::sleep(Duration::from_millis(100));
+        });
+
+        let mut client = ClientSessionActor::new(client_id);
+        client.set_state_manager(state_manager);
+        client.set_claimed_resume_token(Some(RESUME_TOKEN.to_string()));
+        let (addr, mut frames) = start_ws_actor(client);
+        // Live messages arrive during the restore, mid-flush and after it
+        addr.do_send(ClientActorMessage { content: r#"{"content":"live-0"}"#.to_string() });
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        addr.do_send(ClientActorMessage { content: r#"{"content":"live-1"}"#.to_string() });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        addr.do_send(ClientActorMessage { content: r#"{"content":"live-2"}"#.to_string() });
+
+        let raw = collect_raw_frames(&mut frames, Duration::from_millis(300)).await;
+        let sequences: Vec<u64> = decode_server_frames(&raw.concat()).iter()
+            .filter(|(opcode, _)| *opcode == 0x1)
+            .filter_map(|(_, payload)| serde_json::from_slice::<serde_json::Value>(payload).ok())
+            .filter(|message| message.get("content").is_some())
+            .map(|message| message["sequence"].as_u64().expect("every message should carry a sequence"))
+            .collect();
+        assert_eq!(sequences, (7..15).collect::<Vec<_>>());
+
+        arbiter.stop();
+    }
+
+    #[actix::test]
+    async fn test_binary_frames_buffered_across_reconnect() {
+        let client_id = Uuid::new_v4();
+        let state_manager = StateManagerActor::new().start();
+        
+        // First connection: the socket is down, so text and binary are buffered
+        let mut first = ClientSessionActor::new(client_id);
+        first.set_state_manager(state_manager.clone());
+        first.is_connected = false;
+        first.buffer_message(OutboundFrame::from("buffered-text".to_string()));
+        first.buffer_message(OutboundFrame::Binary(Bytes::from_static(b"\x00buffered-binary")));
+        first.save_session_state();
+        
+        // Second connection restores both, in order, with binary kept binary
+        let mut second = ClientSessionActor::new(client_id);
+        second.set_state_manager(state_manager);
+        second.set_claimed_resume_token(Some(first.resume_token.clone()));
+        let (addr, mut frames) = start_ws_actor(second);
+        addr.do_send(ClientActorBinaryMessage { content: Bytes::from_static(b"\x00live-binary") });
+        
+        let output = collect_raw_frames(&mut frames, Duration::from_millis(300)).await.concat();
+        let position = |needle: &[u8]| output.windows(needle.len()).position(|window| window == needle)
+            .unwrap_or_else(|| panic!("{} was never sent", String::from_utf8_lossy(needle)));
+        
+        // Binary frames keep the binary opcode (0x82), followed by the payload length
+        let buffered_binary = position(b"\x82\x10\x00buffered-binary");
+        let live_binary = position(b"\x82\x0c\x00live-binary");
+        assert!(position(b"\x81\x0dbuffered-text") < buffered_binary);
+        assert!(buffered_binary < live_binary);
+    }
+
+    // Reconnect a client whose previous connection buffered `count` messages,
+    // returning the frames written to the new socket
+    async fn reconnect_with_buffered(
+        count: usize,
+        configure: impl FnOnce(&mut ClientSessionActor),
+    ) -> mpsc::UnboundedReceiver<Bytes> {
+        let client_id = Uuid::new_v4();
+        let state_manager = StateManagerActor::new().start();
+        
+        let mut first = ClientSessionActor::with_buffer_size(client_id, count);
+        first.set_state_manager(state_manager.clone());
+        for i in 0..count {
+            first.buffer_message(OutboundFrame::from(format!("buffered-{}", i)));
+        }
+        first.save_session_state();
+        
+        let mut second = ClientSessionActor::with_buffer_size(client_id, count);
+        second.set_state_manager(state_manager);
+        second.set_claimed_resume_token(Some(first.resume_token.clone()));
+        configure(&mut second);
+        let (_addr, frames) = start_ws_actor(second);
+        frames
+    }
+    
+    fn count_buffered(frames: &[String]) -> usize {
+        frames.iter().filter(|frame| frame.contains("buffered-")).count()
+    }
+    
+    #[actix::test]
+    async fn test_buffer_flush_stays_within_configured_rate() {
+        let mut frames = reconnect_with_buffered(100, |client| client.set_flush_rate(50)).await;
+        
+        // The first message goes out right away, then one every 20ms
+        let window = Duration::from_millis(300);
+        let sent = count_buffered(&collect_frames(&mut frames, window).await);
+        let bound = 1 + (50.0 * window.as_secs_f64()) as usize;
+        assert!(sent >= 2, "flush should make progress, sent {}", sent);
+        assert!(sent <= bound, "sent {} messages, bound is {}", sent, bound);
+    }
+    
+    #[actix::test]
+    async fn test_buffer_flush_pauses_for_unresponsive_client() {
+        // Pings go unanswered, so the client is soon considered backed up
+        let mut frames = reconnect_with_buffered(100, |client| {
+            client.set_flush_rate(1000);
+            client.heartbeat_interval = Duration::from_millis(10);
+        }).await;
+        
+        let sent = count_buffered(&collect_frames(&mut frames, Duration::from_millis(300)).await);
+        assert!(sent < 100, "flush should stall, but all {} messages were sent", sent);
+    }
+    
+    #[actix::test]
+    async fn test_pong_round_trip_time_is_reported() {
+        let client_id = Uuid::new_v4();
+        let state_manager = StateManagerActor::new().start();
+        let mut client = ClientSessionActor::new(client_id);
+        client.set_state_manager(state_manager.clone());
+        client.heartbeat_interval = Duration::from_millis(20);
+        let (addr, input, mut frames) = start_ws_actor_with_sender(client);
+        state_manager.send(StateRegisterClient { client_id, addr, authenticated: false, wallet_address: None })
+            .await.unwrap();
+        
+        let raw = collect_raw_frames(&mut frames, Duration::from_millis(50)).await;
+        let (_, payload) = decode_server_frames(&raw.concat()).into_iter()
+            .find(|(opcode, _)| *opcode == 0x9)
+            .expect("client should be pinged");
+        
+        // A pong for a ping we never sent is not timed
+        input.send(client_pong_frame(b"unknown")).unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        input.send(client_pong_frame(&payload)).unwrap();
+        
+        let mut rtt = None;
+        for _ in 0..50 {
+            rtt = state_manager.send(GetClientStatus { client_id }).await.unwrap().unwrap().last_rtt_ms;
+            if rtt.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let rtt = rtt.expect("round-trip time should be reported");
+        assert!(rtt >= 5.0, "round trip took at least the 5ms before the pong, got {}ms", rtt);
+    }
+    
+    #[test]
+    fn test_buffer_holds_configured_number_of_messages() {
+        let mut client = ClientSessionActor::with_buffer_size(Uuid::new_v4(), 3);
+        for i in 0..3 {
+            client.buffer_message(OutboundFrame::from(format!("message-{}", i)));
+        }
+        assert_eq!(client.message_buffer.len(), 3);
+        
+        // Past the cap, new messages are dropped and the buffer keeps the oldest
+        assert_eq!(client.buffer_message(OutboundFrame::from("overflow".to_string())), None);
+        assert_eq!(client.message_buffer.len(), 3);
+        assert_eq!(client.message_buffer.back().map(|(frame, _)| frame), Some(&OutboundFrame::from("message-2".to_string())));
+    }
+
+    #[actix::test]
+    async fn test_buffer_overflow_counts_dropped_messages() {
+        let client_id = Uuid::new_v4();
+        let state_manager = StateManagerActor::new().start();
+        let (addr, _frames) = start_ws_actor(ClientSessionActor::new(client_id));
+        state_manager.send(StateRegisterClient { client_id, addr, authenticated: false, wallet_address: None })
+            .await.unwrap();
+        
+        let mut client = ClientSessionActor::with_buffer_size(client_id, 2);
+        client.set_state_manager(state_manager.clone());
+        for i in 0..5 {
+            client.buffer_message(OutboundFrame::from(format!("message-{}", i)));
+        }
+        
+        let status = state_manager.send(GetClientStatus { client_id }).await.unwrap().unwrap();
+        assert_eq!(status.dropped_messages, 3);
+    }
+
+    #[actix::test]
+    async fn test_expired_buffered_messages_are_not_replayed() {
+        let client_id = Uuid::new_v4();
+        let state_manager = StateManagerActor::new().start();
+        
+        let mut first = ClientSessionActor::new(client_id);
+        first.set_state_manager(state_manager.clone());
+        first.set_message_ttl(Duration::from_secs(60));
+        for content in ["stale-1", "fresh-1", "stale-2", "fresh-2"] {
+            first.buffer_message(OutboundFrame::from(content.to_string()));
+        }
+        // Age the stale messages past the TTL rather than waiting it out
+        for (frame, buffered_at) in first.message_buffer.iter_mut() {
+            if matches!(frame, OutboundFrame::Text(text) if text.starts_with("stale")) {
+                *buffered_at -= Duration::from_secs(61);
+            }
+        }
+        for (content, sent_time) in first.message_tracker.pending_acks.values_mut() {
+            if content.starts_with("stale") {
+                *sent_time -= Duration::from_secs(61);
+            }
+        }
+        // Their acks are no longer awaited either
+        assert_eq!(first.message_tracker.discard_older_than(first.message_ttl), 2);
+        assert_eq!(first.message_tracker.pending_acks.len(), 2);
+        first.save_session_state();
+        
+        let mut second = ClientSessionActor::new(client_id);
+        second.set_state_manager(state_manager.clone());
+        second.set_claimed_resume_token(Some(first.resume_token.clone()));
+        second.set_message_ttl(Duration::from_secs(60));
+        let (addr, mut frames) = start_ws_actor(second);
+        state_manager.send(StateRegisterClient { client_id, addr, authenticated: false, wallet_address: None })
+            .await.unwrap();
+        
+        let output = collect_frames(&mut frames, Duration::from_millis(400)).await.concat();
+        assert!(output.contains("fresh-1") && output.contains("fresh-2"));
+        assert!(!output.contains("stale"));
+        let status = state_manager.send(GetClientStatus { client_id }).await.unwrap().unwrap();
+        assert_eq!(status.expired_buffered, 2);
+    }
+
+    #[actix::test]
+    async fn test_large_frames_are_compressed_once_client_enables_it() {
+        let client_id = Uuid::new_v4();
+        let state_manager = StateManagerActor::new().start();
+        let mut client = ClientSessionActor::new(client_id);
+        client.set_state_manager(state_manager.clone());
+        client.set_compression_threshold(256);
+        let (addr, mut frames) = start_ws_actor_with_input(
+            client,
+            vec![client_text_frame(r#"{"type":"enable_compression"}"#)],
+        );
+        state_manager.send(StateRegisterClient { client_id, addr: addr.clone(), authenticated: false, wallet_address: None })
+            .await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        
+        let large = format!(r#"{{"content":"{}"}}"#, "repeated broadcast text ".repeat(100));
+        addr.do_send(ClientActorMessage { content: large.clone() });
+        addr.do_send(ClientActorMessage { content: r#"{"content":"small"}"#.to_string() });
+        
+        let raw = collect_raw_frames(&mut frames, Duration::from_millis(200)).await.concat();
+        let written = decode_server_frames(&raw);
+        let payloads: Vec<_> = written.iter()
+            .filter(|(opcode, payload)| (*opcode == 0x1 || *opcode == 0x2) && !payload.starts_with(br#"{"type":"session""#))
+            .collect();
+        assert_eq!(payloads.len(), 3, "expected confirmation, large and small frames");
+        assert!(String::from_utf8_lossy(&payloads[0].1).contains("compression_enabled"));
+        
+        // The large message arrives deflated behind its header byte
+        let (opcode, compressed) = payloads[1];
+        assert_eq!(*opcode, 0x2);
+        assert_eq!(compressed[0], compression::DEFLATE_TEXT_FRAME);
+        let inflated = String::from_utf8(compression::decode_frame(compressed).unwrap()).unwrap();
+        assert!(inflated.starts_with(large.trim_end_matches('}')));
+        assert!(compressed.len() < large.len() / 4);
+        
+        // Small frames stay plain text
+        let (opcode, small) = payloads[2];
+        assert_eq!(*opcode, 0x1);
+        assert!(String::from_utf8_lossy(small).contains("small"));
+        
+        // bytes_sent counts what went over the wire
+        let status = state_manager.send(GetClientStatus { client_id }).await.unwrap().unwrap();
+        assert_eq!(status.bytes_sent, (compressed.len() + small.len()) as u64);
+    }
+
+    #[actix::test]
+    async fn test_content_over_limit_is_rejected() {
+        let router = RouterActor::new(Some("agent1".to_string())).start();
+        let (agent, mut agent_output) = start_ws_actor(AgentActor::new("agent1".to_string(), "token".to_string()));
+        router.send(RegisterAgent { agent_id: "agent1".to_string(), addr: agent }).await.unwrap();
+        
+        let mut client = ClientSessionActor::new(Uuid::new_v4());
+        client.set_router(router);
+        client.set_max_content_len(10);
+        let (_addr, mut output) = start_ws_actor_with_input(client, vec![
+            client_text_frame("ééééé12345"),
+            client_text_frame("ééééé123456"),
+        ]);
+        
+        let window = Duration::from_millis(100);
+        let written = collect_raw_frames(&mut output, window).await.concat();
+        let errors: Vec<ErrorFrame> = decode_server_frames(&written).iter()
+            .filter_map(|(_, payload)| serde_json::from_slice(payload).ok())
+            .collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, ErrorFrame::CONTENT_TOO_LONG);
+        assert_eq!(errors[0].detail, Some(serde_json::json!({ "max_content_len": 10 })));
+        let forwarded = collect_frames(&mut agent_output, window).await.concat();
+        assert!(forwarded.contains("ééééé12345"));
+        assert!(!forwarded.contains("ééééé123456"));
+    }
+
+    // Send one frame of `len` bytes to a client limited to 1024 byte frames,
+    // returning the frames the client wrote and whether the agent got it
+    async fn send_frame_of(len: usize) -> (Vec<(u8, Vec<u8>)>, bool) {
+        let router = RouterActor::new(Some("agent1".to_string())).start();
+        let (agent, mut agent_output) = start_ws_actor(AgentActor::new("agent1".to_string(), "token".to_string()));
+        router.send(RegisterAgent { agent_id: "agent1".to_string(), addr: agent }).await.unwrap();
+        
+        let mut client = ClientSessionActor::new(Uuid::new_v4());
+        client.set_router(router);
+        client.set_max_frame_bytes(1024);
+        let (_addr, mut output) = start_ws_actor_with_input(client, vec![client_text_frame(&"x".repeat(len))]);
+        
+        let window = Duration::from_millis(100);
+        let written = collect_raw_frames(&mut output, window).await.concat();
+        let forwarded = collect_frames(&mut agent_output, window).await.concat();
+        (decode_server_frames(&written), forwarded.contains(&"x".repeat(len)))
+    }
+    
+    #[actix::test]
+    async fn test_frame_at_limit_is_accepted() {
+        let (frames, forwarded) = send_frame_of(1024).await;
+        assert!(forwarded);
+        assert!(frames.iter().all(|(opcode, _)| *opcode != 0x8));
+    }
+    
+    #[actix::test]
+    async fn test_frame_over_limit_closes_with_policy_violation() {
+        let (frames, forwarded) = send_frame_of(1025).await;
+        assert!(!forwarded);
+        let (_, close) = frames.iter()
+            .find(|(opcode, _)| *opcode == 0x8)
+            .expect("oversized frame should close the connection");
+        assert_eq!(&close[..2], &u16::from(ws::CloseCode::Policy).to_be_bytes());
+        assert_eq!(&close[2..], b"Frame too large");
+        
+        // Preceded by an error frame the client can act on
+        let error = frames.iter()
+            .filter(|(opcode, _)| *opcode == 0x1)
+            .find_map(|(_, payload)| serde_json::from_slice::<ErrorFrame>(payload).ok())
+            .unwrap();
+        assert_eq!(error.code, ErrorFrame::FRAME_TOO_LARGE);
+    }
+
+    #[actix::test]
+    async fn test_required_acks_resend_unacked_message() {
+        let mut client = ClientSessionActor::new(Uuid::new_v4());
+        client.set_require_acks(true);
+        // Required acks must apply even when per-session confirmation is off
+        client.delivery_confirmation = false;
+        client.heartbeat_interval = Duration::from_millis(20);
+        client.message_tracker.ack_timeout = Duration::from_millis(50);
+        let (addr, mut frames) = start_ws_actor(client);
+
+        let message = AgentMessage::broadcast("needs-ack");
+        addr.do_send(ClientActorMessage { content: serde_json::to_string(&message).unwrap() });
+
+        let output = collect_frames(&mut frames, Duration::from_millis(300)).await.concat();
+        let deliveries = output.matches("needs-ack").count();
+        assert!(deliveries >= 2, "expected a resend, got {} deliveries", deliveries);
+        assert!(output.contains("\"message_id\":1"));
+    }
+
+    #[actix::test]
+    async fn test_delivery_pauses_until_acks_catch_up() {
+        use crate::actors::router_actor::RegisterClient as RouterRegisterClient;
+
+        let router = RouterActor::new(None).start();
+        let client_id = Uuid::new_v4();
+        let mut client = ClientSessionActor::new(client_id);
+        client.set_router(router.clone());
+        client.set_max_unacked_messages(2);
+        let (addr, mut frames) = start_ws_actor(client);
+        router.send(RouterRegisterClient { client_id, addr: addr.clone(), authenticated: false, wallet_address: None }).await.unwrap();
+
+        let send = |content: &str| router.send(AgentMessage::to_client(client_id, content));
+
+        // The client stops acking, so delivery pauses after two messages
+        for content in ["first", "second"] {
+            send(content).await.unwrap();
+        }
+        assert!(collect_frames(&mut frames, Duration::from_millis(100)).await.concat().contains("second"));
+        for content in ["third", "fourth"] {
+            send(content).await.unwrap();
+        }
+        let output = collect_frames(&mut frames, Duration::from_millis(100)).await.concat();
+        assert!(!output.contains("third"), "delivery should be paused: {}", output);
+
+        // One ack brings it back under the limit and releases the held messages
+        addr.do_send(MessageAcknowledgement {
+            source_id: client_id.to_string(),
+            message_id: 1,
+            timestamp: 0,
+            status: AckStatus::Received,
+        });
+        let output = collect_frames(&mut frames, Duration::from_millis(100)).await.concat();
+        assert!(output.find("third").unwrap() < output.find("fourth").unwrap());
+    }
+
+    #[test]
+    fn test_incoming_frame_detects_ack() {
+        assert_eq!(IncomingClientFrame::parse(r#"{"type":"ack","message_id":7}"#).ack_id(), Some(7));
+        assert_eq!(IncomingClientFrame::parse(r#"{ "ack" : true, "message_id" : 8 }"#).ack_id(), Some(8));
+    }
+
+    #[test]
+    fn test_incoming_frame_content_mentioning_message_id_is_not_ack() {
+        let frame = IncomingClientFrame::parse(r#"{"content":"what is \"message_id\": 5 for?"}"#);
+        assert_eq!(frame.ack_id(), None);
+        // A message id alone, without an ack marker, is not an acknowledgement
+        assert_eq!(IncomingClientFrame::parse(r#"{"type":"chat","message_id":5}"#).ack_id(), None);
+    }
+
+    #[test]
+    fn test_incoming_frame_malformed_json_is_not_ack() {
+        assert_eq!(IncomingClientFrame::parse(r#"{"type":"ack","message_id":"#).ack_id(), None);
+        assert_eq!(IncomingClientFrame::parse("plain text with \"ack\": 1").ack_id(), None);
+    }
+
+    #[actix::test]
+    async fn test_ack_frames_are_not_forwarded_to_agents() {
+        let router = RouterActor::new(Some("agent1".to_string())).start();
+        let (agent, mut agent_frames) = start_ws_actor(AgentActor::new("agent1".to_string(), "token".to_string()));
+        router.send(RegisterAgent { agent_id: "agent1".to_string(), addr: agent }).await.unwrap();
+
+        let mut client = ClientSessionActor::new(Uuid::new_v4());
+        client.set_router(router);
+        let frames = vec![
+            client_text_frame(r#"{"type":"ack","message_id":1}"#),
+            client_text_frame(r#"{"content":"chat about \"message_id\":2"}"#),
+        ];
+        let (_addr, _client_frames) = start_ws_actor_with_input(client, frames);
+
+        let output = collect_frames(&mut agent_frames, Duration::from_millis(100)).await.concat();
+        assert!(output.contains("chat about"));
+        assert!(!output.contains(r#"\"type\":\"ack\""#));
+    }
+
+    #[actix::test]
+    async fn test_binary_frames_are_forwarded_to_agents() {
+        let router = RouterActor::new(Some("agent1".to_string())).start();
+        let (agent, mut agent_frames) = start_ws_actor(AgentActor::new("agent1".to_string(), "token".to_string()));
+        router.send(RegisterAgent { agent_id: "agent1".to_string(), addr: agent }).await.unwrap();
+        let state_manager = StateManagerActor::new().start();
+
+        let client_id = Uuid::new_v4();
+        let mut client = ClientSessionActor::new(client_id);
+        client.set_router(router);
+        client.set_state_manager(state_manager.clone());
+        let payload = b"\x00\x01binary payload\xff";
+        let (addr, _client_frames) = start_ws_actor_with_input(client, vec![client_binary_frame(payload)]);
+        // Queued before the actor first runs, so registration precedes the metrics update
+        state_manager.do_send(StateRegisterClient { client_id, addr, authenticated: false, wallet_address: None });
+
+        let output = collect_frames(&mut agent_frames, Duration::from_millis(100)).await.concat();
+        // The payload travels base64-encoded in the JSON envelope
+        assert!(output.contains(r#""content_binary":"AAFiaW5hcnkgcGF5bG9hZP8=""#), "agent output: {}", output);
+
+        let status = state_manager.send(GetClientStatus { client_id }).await.unwrap().unwrap();
+        assert_eq!(status.bytes_received, payload.len() as u64);
+    }
+}
@@ -4,4 +4,4 @@
 pub mod agent_actor;
 pub mod client_session_actor;
 pub mod router_actor;
-pub mod state_manager;
\ No newline at end of file
+pub mod state_manager;
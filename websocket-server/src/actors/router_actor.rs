@@ -1,10 +1,15 @@
 // websocket-server/src/actors/router_actor.rs
-use actix::{Actor, Context, Handler, Message, Addr};
+use actix::{Actor, Arbiter, Context, Handler, Message, Addr};
+use actix_web::web::Bytes;
 use uuid::Uuid;
 use dashmap::DashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use super::client_session_actor::ClientSessionActor;
 use super::agent_actor::AgentActor;
-use common::{ClientMessage, AgentMessage, SystemMessage};
+use common::{time_message, AckStatus, Audience, ClientMessage, AgentMessage, AgentToAgent, BroadcastResult, MessageAcknowledgement, MessageSize, StateManagerConfig, SystemMessage};
+use serde_json::json;
 
 // Message to send to a ClientSessionActor - actor-specific, so kept here
 #[derive(Message)]
@@ -13,6 +18,13 @@ pub struct ClientActorMessage {
     pub content: String,
 }
 
+// Binary message to send to a ClientSessionActor
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ClientActorBinaryMessage {
+    pub content: Bytes,
+}
+
 // Message to send to an AgentActor - actor-specific, so kept here
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -26,6 +38,8 @@ pub struct AgentActorMessage {
 pub struct RegisterClient {
     pub client_id: Uuid,
     pub addr: Addr<ClientSessionActor>,
+    pub authenticated: bool,
+    pub wallet_address: Option<String>,
 }
 
 #[derive(Message)]
@@ -47,31 +61,267 @@ pub struct UnregisterAgent {
     pub agent_id: String,
 }
 
-// Router actor for message routing
+// Authoritative live set from the state manager; anything else the router
+// still holds missed its unregister and is evicted
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ReconcileRegistrations {
+    pub clients: HashSet<Uuid>,
+    pub agents: HashSet<String>,
+}
+
+// Flow control from a client with too many unacknowledged messages: the
+// router queues its messages until it resumes
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PauseClientDelivery {
+    pub client_id: Uuid,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ResumeClientDelivery {
+    pub client_id: Uuid,
+}
+
+// An agent's interest in a client's connection lifecycle
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SubscribePresence {
+    pub agent_id: String,
+    pub client_id: Uuid,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct UnsubscribePresence {
+    pub agent_id: String,
+    pub client_id: Uuid,
+}
+
+// An agent's interest in client messages on topics matching `topic`
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SubscribeTopic {
+    pub agent_id: String,
+    pub topic: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct UnsubscribeTopic {
+    pub agent_id: String,
+    pub topic: String,
+}
+
+// Sent when an agent declares its tenant in an AgentHello
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetAgentTenant {
+    pub agent_id: String,
+    pub tenant_id: Option<String>,
+}
+
+// Sent when an agent's AgentHello says whether it serves anonymous clients
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetAgentClientAuth {
+    pub agent_id: String,
+    pub require_authenticated_clients: bool,
+}
+
+// Default agent management - lets operators switch agents at runtime
+#[derive(Message)]
+#[rtype(result = "Option<String>")]
+pub struct GetDefaultAgent;
+
+#[derive(Message)]
+#[rtype(result = "Result<(), RouterError>")]
+pub struct SetDefaultAgent {
+    pub agent_id: String,
+}
+
+// Errors returned by router management messages
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum RouterError {
+    #[error("agent {0} is not registered")]
+    AgentNotRegistered(String),
+}
+
+// How client messages are distributed across registered agents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoutingStrategy {
+    // Send to the default agent, or to every agent when none is available
+    #[default]
+    DefaultAgent,
+    // Rotate through registered agents, one message each
+    RoundRobin,
+    // Send every message to every agent
+    Broadcast,
+}
+
+impl std::str::FromStr for RoutingStrategy {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "default_agent" => Ok(RoutingStrategy::DefaultAgent),
+            "round_robin" => Ok(RoutingStrategy::RoundRobin),
+            "broadcast" => Ok(RoutingStrategy::Broadcast),
+            other => Err(format!("unknown routing strategy: {}", other)),
+        }
+    }
+}
+
+// Whether a subscription `pattern` covers `topic`; each `*` matches any run
+// of characters, so "orders.*" covers "orders.created"
+fn topic_matches(pattern: &str, topic: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = topic.strip_prefix(first) else {
+        return false;
+    };
+    let Some(last) = parts.next_back() else {
+        return rest.is_empty(); // No wildcard: an exact match
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+// The shard out of `shard_count` that a client's messages are routed on.
+// Client ids are random, so this spreads clients evenly
+fn shard_index(client_id: &Uuid, shard_count: usize) -> usize {
+    (client_id.as_u128() % shard_count as u128) as usize
+}
+
+// Router actor for message routing. With shards, client messages are routed
+// by shard actors on their own threads; they share the registrations below,
+// while everything else is still handled here
 pub struct RouterActor {
-    clients: DashMap<Uuid, Addr<ClientSessionActor>>,
-    agents: DashMap<String, Addr<AgentActor>>,
-    default_agent_id: Option<String>, // Default agent for Phase 2
+    clients: Arc<DashMap<Uuid, Addr<ClientSessionActor>>>,
+    client_auth: Arc<DashMap<Uuid, (bool, Option<String>)>>, // Authenticated flag and wallet, for broadcast audiences
+    agents: Arc<DashMap<String, Addr<AgentActor>>>,
+    agent_tenants: Arc<DashMap<String, String>>, // Agents that declared a tenant
+    auth_only_agents: Arc<DashMap<String, ()>>, // Agents that refuse anonymous clients
+    presence_subscriptions: Arc<DashMap<Uuid, HashSet<String>>>, // Agents following each client's presence
+    presence_by_agent: Arc<DashMap<String, HashSet<Uuid>>>, // Reverse index: clients each agent follows
+    topic_subscriptions: Arc<DashMap<String, HashSet<String>>>, // Agents subscribed to each topic pattern
+    topics_by_agent: Arc<DashMap<String, HashSet<String>>>, // Reverse index: topic patterns each agent holds
+    default_agent_id: Option<String>, // Agent receiving client messages by default
+    require_acks: bool, // Force requires_ack on every agent -> client message
+    server_timestamps: bool, // Stamp agent -> client messages with the routing time
+    strategy: RoutingStrategy,
+    last_round_robin_agent: Option<String>, // Round-robin cursor, resumes after this agent; one per shard
+    paused_clients: HashMap<Uuid, VecDeque<String>>, // Messages held for clients behind on acks
+    max_paused_messages: usize, // Held per paused client; extras are dropped
+    slow_message_threshold: Duration, // Routing slower than this is logged; zero disables
+    shard_count: usize, // Shards started with the router; 1 routes everything here
+    shards: Vec<(Arbiter, Addr<RouterActor>)>,
 }
 
 impl RouterActor {
-    pub fn new() -> Self {
+    pub fn new(default_agent_id: Option<String>) -> Self {
+        Self {
+            clients: Arc::default(),
+            client_auth: Arc::default(),
+            agents: Arc::default(),
+            agent_tenants: Arc::default(),
+            auth_only_agents: Arc::default(),
+            presence_subscriptions: Arc::default(),
+            presence_by_agent: Arc::default(),
+            topic_subscriptions: Arc::default(),
+            topics_by_agent: Arc::default(),
+            default_agent_id,
+            require_acks: false,
+            server_timestamps: false,
+            strategy: RoutingStrategy::default(),
+            last_round_robin_agent: None,
+            paused_clients: HashMap::new(),
+            max_paused_messages: StateManagerConfig::default().client_buffer_size,
+            slow_message_threshold: Duration::ZERO,
+            shard_count: 1,
+            shards: Vec::new(),
+        }
+    }
+    
+    pub fn with_required_acks(mut self, require_acks: bool) -> Self {
+        self.require_acks = require_acks;
+        self
+    }
+    
+    pub fn with_server_timestamps(mut self, server_timestamps: bool) -> Self {
+        self.server_timestamps = server_timestamps;
+        self
+    }
+    
+    pub fn with_strategy(mut self, strategy: RoutingStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+    
+    pub fn with_max_paused_messages(mut self, max_paused_messages: usize) -> Self {
+        self.max_paused_messages = max_paused_messages;
+        self
+    }
+    
+    pub fn with_slow_message_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_message_threshold = threshold;
+        self
+    }
+    
+    // Route client messages on `shard_count` threads; 0 and 1 route them here
+    pub fn with_shards(mut self, shard_count: usize) -> Self {
+        self.shard_count = shard_count.max(1);
+        self
+    }
+    
+    // A router for one shard, sharing this router's registrations
+    fn shard(&self) -> Self {
         Self {
-            clients: DashMap::new(),
-            agents: DashMap::new(),
-            default_agent_id: Some("agent1".to_string()), // Hardcoded for Phase 2
+            clients: self.clients.clone(),
+            client_auth: self.client_auth.clone(),
+            agents: self.agents.clone(),
+            agent_tenants: self.agent_tenants.clone(),
+            auth_only_agents: self.auth_only_agents.clone(),
+            presence_subscriptions: self.presence_subscriptions.clone(),
+            presence_by_agent: self.presence_by_agent.clone(),
+            topic_subscriptions: self.topic_subscriptions.clone(),
+            topics_by_agent: self.topics_by_agent.clone(),
+            require_acks: self.require_acks,
+            server_timestamps: self.server_timestamps,
+            strategy: self.strategy,
+            max_paused_messages: self.max_paused_messages,
+            slow_message_threshold: self.slow_message_threshold,
+            ..Self::new(self.default_agent_id.clone())
         }
     }
     
+    // The shard routing `client_id`'s messages, so they keep their order
+    fn shard_for(&self, client_id: &Uuid) -> Option<&Addr<RouterActor>> {
+        if self.shards.is_empty() {
+            return None;
+        }
+        let index = shard_index(client_id, self.shards.len());
+        self.shards.get(index).map(|(_, addr)| addr)
+    }
+    
     // Register client address
-    pub fn register_client(&self, client_id: Uuid, addr: Addr<ClientSessionActor>) {
+    pub fn register_client(&self, client_id: Uuid, addr: Addr<ClientSessionActor>, authenticated: bool, wallet_address: Option<String>) {
         self.clients.insert(client_id, addr);
+        self.client_auth.insert(client_id, (authenticated, wallet_address));
         tracing::info!("Client registered with router: {}", client_id);
     }
     
     // Unregister client
-    pub fn unregister_client(&self, client_id: &Uuid) {
+    pub fn unregister_client(&mut self, client_id: &Uuid) {
         self.clients.remove(client_id);
+        self.client_auth.remove(client_id);
+        self.paused_clients.remove(client_id);
         tracing::info!("Client unregistered from router: {}", client_id);
     }
     
@@ -84,9 +334,202 @@ impl RouterActor {
     // Unregister agent
     pub fn unregister_agent(&self, agent_id: &str) {
         self.agents.remove(agent_id);
+        self.agent_tenants.remove(agent_id);
+        self.auth_only_agents.remove(agent_id);
+        self.drop_presence_subscriber(agent_id);
+        self.drop_topic_subscriber(agent_id);
         tracing::info!("Agent unregistered from router: {}", agent_id);
     }
     
+    // Remove an agent from every presence subscription it holds. The reverse
+    // index keeps this proportional to the agent's own subscriptions rather
+    // than to every followed client.
+    fn drop_presence_subscriber(&self, agent_id: &str) {
+        let Some((_, client_ids)) = self.presence_by_agent.remove(agent_id) else {
+            return;
+        };
+        for client_id in client_ids {
+            if let Some(mut agents) = self.presence_subscriptions.get_mut(&client_id) {
+                agents.remove(agent_id);
+            }
+            self.presence_subscriptions.remove_if(&client_id, |_, agents| agents.is_empty());
+        }
+    }
+    
+    pub fn subscribe_presence(&self, agent_id: String, client_id: Uuid) {
+        if !self.agents.contains_key(&agent_id) {
+            tracing::warn!("Ignoring presence subscription from unregistered agent {}", agent_id);
+            return;
+        }
+        tracing::debug!("Agent {} subscribed to presence of client {}", agent_id, client_id);
+        self.presence_by_agent.entry(agent_id.clone()).or_default().insert(client_id);
+        self.presence_subscriptions.entry(client_id).or_default().insert(agent_id);
+    }
+    
+    pub fn unsubscribe_presence(&self, agent_id: &str, client_id: &Uuid) {
+        if let Some(mut agents) = self.presence_subscriptions.get_mut(client_id) {
+            agents.remove(agent_id);
+        }
+        self.presence_subscriptions.remove_if(client_id, |_, agents| agents.is_empty());
+        if let Some(mut client_ids) = self.presence_by_agent.get_mut(agent_id) {
+            client_ids.remove(client_id);
+        }
+        self.presence_by_agent.remove_if(agent_id, |_, client_ids| client_ids.is_empty());
+    }
+    
+    pub fn subscribe_topic(&self, agent_id: String, topic: String) {
+        if !self.agents.contains_key(&agent_id) {
+            tracing::warn!("Ignoring topic subscription from unregistered agent {}", agent_id);
+            return;
+        }
+        tracing::debug!("Agent {} subscribed to topic {}", agent_id, topic);
+        self.topics_by_agent.entry(agent_id.clone()).or_default().insert(topic.clone());
+        self.topic_subscriptions.entry(topic).or_default().insert(agent_id);
+    }
+    
+    pub fn unsubscribe_topic(&self, agent_id: &str, topic: &str) {
+        if let Some(mut agents) = self.topic_subscriptions.get_mut(topic) {
+            agents.remove(agent_id);
+        }
+        self.topic_subscriptions.remove_if(topic, |_, agents| agents.is_empty());
+        if let Some(mut topics) = self.topics_by_agent.get_mut(agent_id) {
+            topics.remove(topic);
+        }
+        self.topics_by_agent.remove_if(agent_id, |_, topics| topics.is_empty());
+    }
+    
+    // Remove an agent from every topic pattern it subscribed to, through the
+    // reverse index like presence subscriptions
+    fn drop_topic_subscriber(&self, agent_id: &str) {
+        let Some((_, topics)) = self.topics_by_agent.remove(agent_id) else {
+            return;
+        };
+        for topic in topics {
+            if let Some(mut agents) = self.topic_subscriptions.get_mut(&topic) {
+                agents.remove(agent_id);
+            }
+            self.topic_subscriptions.remove_if(&topic, |_, agents| agents.is_empty());
+        }
+    }
+    
+    // Agents subscribed to a pattern matching `topic`, each listed once
+    fn topic_subscribers(&self, topic: &str) -> HashSet<String> {
+        self.topic_subscriptions.iter()
+            .filter(|entry| topic_matches(entry.key(), topic))
+            .flat_map(|entry| entry.value().clone())
+            .collect()
+    }
+    
+    // Drop clients and agents missing from the live set, returning how many went
+    pub fn reconcile(&mut self, clients: &HashSet<Uuid>, agents: &HashSet<String>) -> usize {
+        let before = self.clients.len() + self.agents.len();
+        self.clients.retain(|client_id, _| clients.contains(client_id));
+        self.client_auth.retain(|client_id, _| clients.contains(client_id));
+        self.paused_clients.retain(|client_id, _| clients.contains(client_id));
+        self.agents.retain(|agent_id, _| agents.contains(agent_id));
+        self.agent_tenants.retain(|agent_id, _| agents.contains(agent_id));
+        self.auth_only_agents.retain(|agent_id, _| agents.contains(agent_id));
+        let stale_subscribers: Vec<String> = self.presence_by_agent.iter()
+            .map(|entry| entry.key().clone())
+            .chain(self.topics_by_agent.iter().map(|entry| entry.key().clone()))
+            .filter(|agent_id| !agents.contains(agent_id))
+            .collect();
+        for agent_id in stale_subscribers {
+            self.drop_presence_subscriber(&agent_id);
+            self.drop_topic_subscriber(&agent_id);
+        }
+        before - (self.clients.len() + self.agents.len())
+    }
+    
+    // Send a routed message to a client, or queue it while the client's
+    // delivery is paused. Returns whether the message was accepted.
+    fn deliver_to_client(&mut self, client_id: Uuid, addr: &Addr<ClientSessionActor>, content: String) -> bool {
+        if let Some(queue) = self.paused_clients.get_mut(&client_id) {
+            if queue.len() >= self.max_paused_messages {
+                tracing::warn!("Paused queue full for client {}, dropping message", client_id);
+                return false;
+            }
+            queue.push_back(content);
+            return true;
+        }
+        
+        addr.try_send(ClientActorMessage { content }).map_err(|e| {
+            tracing::error!("Failed to deliver message to client {}: {}", client_id, e);
+        }).is_ok()
+    }
+    
+    // Confirm a direct delivery back to the agent that sent it. Only messages
+    // that asked for an ack and carry an id and a sender are confirmed.
+    fn ack_agent(&self, msg: &AgentMessage, client_id: Uuid, status: AckStatus) {
+        let (true, Some(message_id), Some(agent_id)) = (msg.requires_ack, msg.message_id, &msg.source_agent_id) else {
+            return;
+        };
+        let Some(agent) = self.agents.get(agent_id).map(|entry| entry.value().clone()) else {
+            tracing::debug!("Agent {} left before its message {} was confirmed", agent_id, message_id);
+            return;
+        };
+        
+        let ack = MessageAcknowledgement {
+            source_id: client_id.to_string(),
+            message_id,
+            timestamp: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs(),
+            status,
+        };
+        match serde_json::to_string(&ack) {
+            Ok(content) => {
+                let _ = agent.try_send(AgentActorMessage { content });
+            }
+            Err(e) => tracing::error!("Failed to serialize delivery ack for agent {}: {}", agent_id, e),
+        }
+    }
+    
+    // Record the tenant an agent serves; None makes it serve untenanted clients
+    pub fn set_agent_tenant(&self, agent_id: String, tenant_id: Option<String>) {
+        tracing::info!("Agent {} serves tenant {:?}", agent_id, tenant_id);
+        match tenant_id {
+            Some(tenant_id) => self.agent_tenants.insert(agent_id, tenant_id),
+            None => self.agent_tenants.remove(&agent_id).map(|(_, tenant_id)| tenant_id),
+        };
+    }
+    
+    // Whether an agent may receive messages from clients of `tenant_id`.
+    // Tenanted clients reach only their tenant's agents, untenanted clients
+    // only agents without a tenant.
+    fn serves_tenant(&self, agent_id: &str, tenant_id: Option<&str>) -> bool {
+        self.agent_tenants.get(agent_id).as_deref().map(String::as_str) == tenant_id
+    }
+    
+    // Record whether an agent refuses messages from anonymous clients
+    pub fn set_agent_client_auth(&self, agent_id: String, require_authenticated_clients: bool) {
+        if require_authenticated_clients {
+            tracing::info!("Agent {} serves only authenticated clients", agent_id);
+            self.auth_only_agents.insert(agent_id, ());
+        } else {
+            self.auth_only_agents.remove(&agent_id);
+        }
+    }
+    
+    // Whether an agent may receive messages from a client of `tenant_id`,
+    // which is anonymous unless `authenticated`
+    fn serves_client(&self, agent_id: &str, tenant_id: Option<&str>, authenticated: bool) -> bool {
+        self.serves_tenant(agent_id, tenant_id) && (authenticated || !self.auth_only_agents.contains_key(agent_id))
+    }
+    
+    // Switch the default agent, only if it is currently registered
+    pub fn set_default_agent(&mut self, agent_id: String) -> Result<(), RouterError> {
+        if !self.agents.contains_key(&agent_id) {
+            tracing::warn!("Rejected default agent change to unregistered agent: {}", agent_id);
+            return Err(RouterError::AgentNotRegistered(agent_id));
+        }
+        
+        tracing::info!(
+            "Default agent changed: {:?} -> {}",
+            self.default_agent_id, agent_id
+        );
+        self.default_agent_id = Some(agent_id);
+        Ok(())
+    }
+    
     // Get the default agent for Phase 2
     fn get_default_agent(&self) -> Option<Addr<AgentActor>> {
         if let Some(id) = &self.default_agent_id {
@@ -96,6 +539,51 @@ impl RouterActor {
         }
         None
     }
+    
+    // Send to the next agent after the cursor that accepts the message.
+    // The cursor is an agent id rather than an index, so agents registering
+    // or unregistering between messages neither skip nor repeat anyone.
+    fn send_round_robin(&mut self, content: String, tenant_id: Option<&str>, authenticated: bool) -> bool {
+        let mut agent_ids: Vec<String> = self.agents.iter()
+            .map(|entry| entry.key().clone())
+            .filter(|agent_id| self.serves_client(agent_id, tenant_id, authenticated))
+            .collect();
+        agent_ids.sort();
+        
+        let start = self.last_round_robin_agent.as_ref()
+            .map_or(0, |last| agent_ids.partition_point(|id| id <= last));
+        
+        for offset in 0..agent_ids.len() {
+            let agent_id = &agent_ids[(start + offset) % agent_ids.len()];
+            let Some(agent) = self.agents.get(agent_id).map(|entry| entry.value().clone()) else {
+                continue;
+            };
+            
+            match agent.try_send(AgentActorMessage { content: content.clone() }) {
+                Ok(()) => {
+                    self.last_round_robin_agent = Some(agent_id.clone());
+                    return true;
+                },
+                Err(e) => tracing::warn!("Skipping agent {} in round-robin: {}", agent_id, e),
+            }
+        }
+        false
+    }
+    
+    // Send to every registered agent serving the client, returning whether any accepted it
+    fn send_broadcast(&self, content: &str, tenant_id: Option<&str>, authenticated: bool) -> bool {
+        let mut sent = false;
+        for agent_entry in self.agents.iter() {
+            if !self.serves_client(agent_entry.key(), tenant_id, authenticated) {
+                continue;
+            }
+            let agent_message = AgentActorMessage { content: content.to_string() };
+            if agent_entry.value().try_send(agent_message).is_ok() {
+                sent = true;
+            }
+        }
+        sent
+    }
 }
 
 impl Actor for RouterActor {
@@ -103,9 +591,21 @@ impl Actor for RouterActor {
     
     fn started(&mut self, _ctx: &mut Self::Context) {
         tracing::info!("RouterActor started");
+        if self.shard_count > 1 {
+            self.shards = (0..self.shard_count).map(|_| {
+                let arbiter = Arbiter::new();
+                let shard = self.shard();
+                let addr = RouterActor::start_in_arbiter(&arbiter.handle(), |_| shard);
+                (arbiter, addr)
+            }).collect();
+            tracing::info!("Routing client messages on {} shards", self.shard_count);
+        }
     }
     
     fn stopped(&mut self, _ctx: &mut Self::Context) {
+        for (arbiter, _) in self.shards.drain(..) {
+            arbiter.stop();
+        }
         tracing::info!("RouterActor stopped");
     }
 }
@@ -115,79 +615,165 @@ impl Handler<ClientMessage> for RouterActor {
     type Result = ();
     
     fn handle(&mut self, msg: ClientMessage, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(shard) = self.shard_for(&msg.client_id) {
+            shard.do_send(msg);
+            return;
+        }
+        let (size, threshold) = (msg.size_bytes(), self.slow_message_threshold);
+        time_message("router", "client_message", size, threshold, || self.route_client_message(msg))
+    }
+}
+
+impl RouterActor {
+    fn route_client_message(&mut self, msg: ClientMessage) {
         tracing::info!("Routing client message from {}", msg.client_id);
         
-        // In Phase 2, we route to the default agent if available
-        if let Some(default_agent) = self.get_default_agent() {
-            match serde_json::to_string(&msg) {
-                Ok(content) => {
-                    let agent_message = AgentActorMessage { content };
-                    
-                    if let Err(e) = default_agent.try_send(agent_message) {
-                        tracing::error!("Failed to send message to default agent: {}", e);
-                    }
-                },
-                Err(e) => tracing::error!("Failed to serialize client message: {}", e)
+        let content = match serde_json::to_string(&msg) {
+            Ok(content) => content,
+            Err(e) => {
+                tracing::error!("Failed to serialize client message: {}", e);
+                return;
             }
-        } else {
-            // Try each agent if no default is set
-            let mut sent = false;
-            
-            for agent_entry in self.agents.iter() {
-                if let Ok(content) = serde_json::to_string(&msg) {
-                    let agent_message = AgentActorMessage { content };
-                    
-                    if agent_entry.value().try_send(agent_message).is_ok() {
-                        sent = true;
-                    }
+        };
+        
+        let tenant_id = msg.tenant_id.as_deref();
+        let authenticated = self.client_auth.get(&msg.client_id).is_some_and(|entry| entry.0);
+        
+        // A message naming its agent bypasses the routing strategy, but not
+        // tenant isolation or the agent's refusal of anonymous clients
+        if let Some(target_agent_id) = &msg.target_agent_id {
+            let target = self.agents.get(target_agent_id)
+                .filter(|_| self.serves_client(target_agent_id, tenant_id, authenticated))
+                .map(|entry| entry.value().clone());
+            if let Some(agent) = target {
+                if let Err(e) = agent.try_send(AgentActorMessage { content }) {
+                    tracing::error!("Failed to send message to agent {}: {}", target_agent_id, e);
                 }
+                return;
             }
             
-            if !sent {
-                tracing::warn!("No agents available to receive message from client {}", msg.client_id);
+            let error = if self.agents.contains_key(target_agent_id) && self.serves_tenant(target_agent_id, tenant_id) {
+                "Target agent requires an authenticated client"
+            } else {
+                "Target agent not found"
+            };
+            tracing::warn!(
+                "Client {} cannot reach agent {} ({}), falling back to default routing",
+                msg.client_id, target_agent_id, error
+            );
+            if let Some(client) = self.clients.get(&msg.client_id) {
+                let error = json!({
+                    "error": error,
+                    "target_agent_id": target_agent_id,
+                });
+                client.value().do_send(ClientActorMessage { content: error.to_string() });
             }
         }
+        
+        // A topic goes to all its subscribers; one nobody follows is routed as usual
+        if let Some(topic) = &msg.topic {
+            let mut sent = false;
+            for agent_id in self.topic_subscribers(topic) {
+                if !self.serves_client(&agent_id, tenant_id, authenticated) {
+                    continue;
+                }
+                let Some(agent) = self.agents.get(&agent_id).map(|entry| entry.value().clone()) else {
+                    continue;
+                };
+                match agent.try_send(AgentActorMessage { content: content.clone() }) {
+                    Ok(()) => sent = true,
+                    Err(e) => tracing::error!("Failed to send topic {} message to agent {}: {}", topic, agent_id, e),
+                }
+            }
+            if sent {
+                return;
+            }
+            tracing::debug!("No agents subscribed to topic {}, falling back to default routing", topic);
+        }
+        
+        let sent = match self.strategy {
+            RoutingStrategy::DefaultAgent => {
+                let default_agent = self.get_default_agent().filter(|_| {
+                    self.default_agent_id.as_deref().is_some_and(|id| self.serves_client(id, tenant_id, authenticated))
+                });
+                if let Some(default_agent) = default_agent {
+                    if let Err(e) = default_agent.try_send(AgentActorMessage { content }) {
+                        tracing::error!("Failed to send message to default agent: {}", e);
+                    }
+                    true
+                } else {
+                    // Try each agent if no default is set
+                    self.send_broadcast(&content, tenant_id, authenticated)
+                }
+            },
+            RoutingStrategy::RoundRobin => self.send_round_robin(content, tenant_id, authenticated),
+            RoutingStrategy::Broadcast => self.send_broadcast(&content, tenant_id, authenticated),
+        };
+        
+        if !sent {
+            tracing::warn!("No agents available to receive message from client {}", msg.client_id);
+        }
     }
 }
 
 // Handle AgentMessage directly
 impl Handler<AgentMessage> for RouterActor {
-    type Result = ();
+    type Result = Option<BroadcastResult>;
     
     fn handle(&mut self, msg: AgentMessage, _ctx: &mut Self::Context) -> Self::Result {
+        let (size, threshold) = (msg.size_bytes(), self.slow_message_threshold);
+        time_message("router", "agent_message", size, threshold, || self.route_agent_message(msg))
+    }
+}
+
+impl RouterActor {
+    fn route_agent_message(&mut self, mut msg: AgentMessage) -> Option<BroadcastResult> {
+        if self.require_acks {
+            msg.requires_ack = true;
+        }
+        if self.server_timestamps {
+            let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+            msg.server_timestamp = Some(now.as_millis() as u64);
+        }
+        
         match msg.target_client_id {
             Some(client_id) => {
                 // Direct message to specific client
                 tracing::info!("Routing agent message to client {}", client_id);
                 
-                if let Some(client_entry) = self.clients.get(&client_id) {
+                let client = self.clients.get(&client_id).map(|entry| entry.value().clone());
+                if let Some(client) = client {
                     if let Ok(content) = serde_json::to_string(&msg) {
-                        let client_message = ClientActorMessage { content };
-                        
-                        if let Err(e) = client_entry.value().try_send(client_message) {
-                            tracing::error!("Failed to deliver message to client {}: {}", client_id, e);
-                        } else {
+                        if self.deliver_to_client(client_id, &client, content) {
                             tracing::debug!("Message delivered to client {}", client_id);
+                            self.ack_agent(&msg, client_id, AckStatus::Received);
+                        } else {
+                            self.ack_agent(&msg, client_id, AckStatus::Error("Delivery to client failed".to_string()));
                         }
                     } else {
                         tracing::error!("Failed to serialize agent message for client {}", client_id);
                     }
                 } else {
                     tracing::warn!("Client {} not found for message delivery", client_id);
+                    self.ack_agent(&msg, client_id, AckStatus::Error("Client not found".to_string()));
                 }
+                None
             },
             None => {
-                // Broadcast to all clients
-                tracing::info!("Broadcasting agent message to all clients");
+                // Broadcast to every client in the audience, which clients don't see
+                let audience = msg.audience.take().unwrap_or(Audience::All);
+                tracing::info!("Broadcasting agent message to {:?}", audience);
+                
+                let clients: Vec<_> = self.clients.iter()
+                    .filter(|entry| self.in_audience(entry.key(), &audience))
+                    .map(|entry| (*entry.key(), entry.value().clone()))
+                    .collect();
+                let mut sent_count = 0;
+                let total_count = clients.len();
                 
                 if let Ok(content) = serde_json::to_string(&msg) {
-                    let mut sent_count = 0;
-                    let total_count = self.clients.len();
-                    
-                    for client_entry in self.clients.iter() {
-                        let client_message = ClientActorMessage { content: content.clone() };
-                        
-                        if client_entry.value().try_send(client_message).is_ok() {
+                    for (client_id, client) in clients {
+                        if self.deliver_to_client(client_id, &client, content.clone()) {
                             sent_count += 1;
                         }
                     }
@@ -196,9 +782,68 @@ impl Handler<AgentMessage> for RouterActor {
                 } else {
                     tracing::error!("Failed to serialize agent broadcast message");
                 }
+                
+                // Only reported back when the sender asked for confirmation
+                msg.requires_ack.then_some(BroadcastResult {
+                    message_id: msg.message_id,
+                    attempted: total_count,
+                    delivered: sent_count,
+                })
             }
         }
     }
+    
+    fn in_audience(&self, client_id: &Uuid, audience: &Audience) -> bool {
+        match self.client_auth.get(client_id) {
+            Some(auth) => audience.includes(auth.0, auth.1.as_deref()),
+            None => audience.includes(false, None),
+        }
+    }
+}
+
+// Deliver a message from one agent to another. Agents only reach agents of
+// their own tenant, as with clients.
+impl Handler<AgentToAgent> for RouterActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: AgentToAgent, _ctx: &mut Self::Context) -> Self::Result {
+        let (size, threshold) = (msg.content.len(), self.slow_message_threshold);
+        time_message("router", "agent_to_agent", size, threshold, || self.route_agent_to_agent(msg))
+    }
+}
+
+impl RouterActor {
+    fn route_agent_to_agent(&mut self, msg: AgentToAgent) {
+        let source_agent_id = msg.source_agent_id.clone().unwrap_or_default();
+        let source_tenant = self.agent_tenants.get(&source_agent_id).map(|entry| entry.value().clone());
+        let target = self.agents.get(&msg.target_agent_id)
+            .filter(|_| self.serves_tenant(&msg.target_agent_id, source_tenant.as_deref()))
+            .map(|entry| entry.value().clone());
+        
+        let Some(target) = target else {
+            tracing::warn!(
+                "Agent {} messaged unknown agent {}", source_agent_id, msg.target_agent_id
+            );
+            if let Some(source) = self.agents.get(&source_agent_id) {
+                let error = json!({
+                    "error": "Target agent not found",
+                    "target_agent_id": msg.target_agent_id,
+                });
+                source.value().do_send(AgentActorMessage { content: error.to_string() });
+            }
+            return;
+        };
+        
+        match serde_json::to_string(&msg) {
+            Ok(content) => {
+                tracing::info!("Routing message from agent {} to agent {}", source_agent_id, msg.target_agent_id);
+                if let Err(e) = target.try_send(AgentActorMessage { content }) {
+                    tracing::error!("Failed to deliver message to agent {}: {}", msg.target_agent_id, e);
+                }
+            },
+            Err(e) => tracing::error!("Failed to serialize agent-to-agent message: {}", e),
+        }
+    }
 }
 
 // Handle SystemMessage
@@ -206,35 +851,111 @@ impl Handler<SystemMessage> for RouterActor {
     type Result = ();
     
     fn handle(&mut self, msg: SystemMessage, _ctx: &mut Self::Context) -> Self::Result {
-        match &msg {
-            SystemMessage::ClientConnected { client_id, authenticated, wallet_address } => {
+        // The client this message is about, and whether the default agent hears of it
+        let (client_id, notify_default) = match &msg {
+            SystemMessage::ClientConnected { client_id, authenticated, .. } => {
                 tracing::info!(
                     "System message: Client connected - ID: {}, Authenticated: {}", 
                     client_id, authenticated
                 );
-                
-                // Notify agents about client connection
-                if let Some(default_agent) = self.get_default_agent() {
-                    if let Ok(content) = serde_json::to_string(&msg) {
-                        let agent_message = AgentActorMessage { content };
-                        let _ = default_agent.try_send(agent_message);
-                    }
-                }
+                (*client_id, true)
             },
             SystemMessage::ClientDisconnected { client_id } => {
                 tracing::info!("System message: Client disconnected - ID: {}", client_id);
-                
-                // Notify agents about client disconnection
-                if let Some(default_agent) = self.get_default_agent() {
-                    if let Ok(content) = serde_json::to_string(&msg) {
-                        let agent_message = AgentActorMessage { content };
-                        let _ = default_agent.try_send(agent_message);
-                    }
-                }
+                (*client_id, true)
+            },
+            SystemMessage::SessionCreated { client_id, .. }
+            | SystemMessage::SessionRestored { client_id, .. }
+            | SystemMessage::SessionExpired { client_id, .. } => {
+                tracing::debug!("System message: {:?}", msg);
+                (*client_id, false)
             },
             _ => {
                 // Handle other system messages
                 tracing::debug!("System message: {:?}", msg);
+                return;
+            }
+        };
+        
+        let mut recipients = self.presence_subscriptions.get(&client_id)
+            .map(|agents| agents.clone())
+            .unwrap_or_default();
+        if notify_default && self.get_default_agent().is_some() {
+            recipients.extend(self.default_agent_id.clone());
+        }
+        if recipients.is_empty() {
+            return;
+        }
+        
+        let Ok(content) = serde_json::to_string(&msg) else {
+            tracing::error!("Failed to serialize system message for client {}", client_id);
+            return;
+        };
+        for agent_id in recipients {
+            if let Some(agent) = self.agents.get(&agent_id) {
+                let _ = agent.value().try_send(AgentActorMessage { content: content.clone() });
+            }
+        }
+    }
+}
+
+// Presence subscription handlers
+impl Handler<SubscribePresence> for RouterActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: SubscribePresence, _ctx: &mut Self::Context) -> Self::Result {
+        self.subscribe_presence(msg.agent_id, msg.client_id);
+    }
+}
+
+impl Handler<UnsubscribePresence> for RouterActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: UnsubscribePresence, _ctx: &mut Self::Context) -> Self::Result {
+        self.unsubscribe_presence(&msg.agent_id, &msg.client_id);
+    }
+}
+
+// Topic subscription handlers
+impl Handler<SubscribeTopic> for RouterActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: SubscribeTopic, _ctx: &mut Self::Context) -> Self::Result {
+        self.subscribe_topic(msg.agent_id, msg.topic);
+    }
+}
+
+impl Handler<UnsubscribeTopic> for RouterActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: UnsubscribeTopic, _ctx: &mut Self::Context) -> Self::Result {
+        self.unsubscribe_topic(&msg.agent_id, &msg.topic);
+    }
+}
+
+// Flow control handlers
+impl Handler<PauseClientDelivery> for RouterActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: PauseClientDelivery, _ctx: &mut Self::Context) -> Self::Result {
+        if self.clients.contains_key(&msg.client_id) {
+            tracing::info!("Pausing delivery to client {} until it acknowledges", msg.client_id);
+            self.paused_clients.entry(msg.client_id).or_default();
+        }
+    }
+}
+
+impl Handler<ResumeClientDelivery> for RouterActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: ResumeClientDelivery, _ctx: &mut Self::Context) -> Self::Result {
+        let Some(queue) = self.paused_clients.remove(&msg.client_id) else { return };
+        tracing::info!("Resuming delivery to client {}, releasing {} held messages", msg.client_id, queue.len());
+        
+        let client = self.clients.get(&msg.client_id).map(|entry| entry.value().clone());
+        if let Some(client) = client {
+            for content in queue {
+                self.deliver_to_client(msg.client_id, &client, content);
             }
         }
     }
@@ -245,7 +966,7 @@ impl Handler<RegisterClient> for RouterActor {
     type Result = ();
     
     fn handle(&mut self, msg: RegisterClient, _ctx: &mut Self::Context) -> Self::Result {
-        self.register_client(msg.client_id, msg.addr);
+        self.register_client(msg.client_id, msg.addr, msg.authenticated, msg.wallet_address);
     }
 }
 
@@ -271,4 +992,750 @@ impl Handler<UnregisterAgent> for RouterActor {
     fn handle(&mut self, msg: UnregisterAgent, _ctx: &mut Self::Context) -> Self::Result {
         self.unregister_agent(&msg.agent_id);
     }
-}
\ No newline at end of file
+}
+
+impl Handler<ReconcileRegistrations> for RouterActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: ReconcileRegistrations, _ctx: &mut Self::Context) -> Self::Result {
+        let evicted = self.reconcile(&msg.clients, &msg.agents);
+        if evicted > 0 {
+            tracing::warn!("Evicted {} stale router registrations", evicted);
+        }
+    }
+}
+
+impl Handler<SetAgentClientAuth> for RouterActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: SetAgentClientAuth, _ctx: &mut Self::Context) -> Self::Result {
+        self.set_agent_client_auth(msg.agent_id, msg.require_authenticated_clients);
+    }
+}
+
+impl Handler<SetAgentTenant> for RouterActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: SetAgentTenant, _ctx: &mut Self::Context) -> Self::Result {
+        self.set_agent_tenant(msg.agent_id, msg.tenant_id);
+    }
+}
+
+impl Handler<GetDefaultAgent> for RouterActor {
+    type Result = Option<String>;
+    
+    fn handle(&mut self, _msg: GetDefaultAgent, _ctx: &mut Self::Context) -> Self::Result {
+        self.default_agent_id.clone()
+    }
+}
+
+impl Handler<SetDefaultAgent> for RouterActor {
+    type Result = Result<(), RouterError>;
+    
+    fn handle(&mut self, msg: SetDefaultAgent, _ctx: &mut Self::Context) -> Self::Result {
+        self.set_default_agent(msg.agent_id.clone())?;
+        for (_, shard) in &self.shards {
+            shard.do_send(SetDefaultAgent { agent_id: msg.agent_id.clone() });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::Actor;
+    use common::test_support::{
+        client_text_frame, collect_frames, collect_raw_frames, decode_server_frames, start_ws_actor, start_ws_actor_with_input,
+    };
+    use std::time::Duration;
+    
+    fn client_message(content: &str) -> ClientMessage {
+        ClientMessage::new(Uuid::new_v4(), content)
+    }
+    
+    fn targeted_message(content: &str, client_id: Uuid, target_agent_id: &str) -> ClientMessage {
+        ClientMessage::new(client_id, content).with_target_agent_id(Some(target_agent_id.to_string()))
+    }
+    
+    fn tenant_message(content: &str, tenant_id: &str) -> ClientMessage {
+        client_message(content).with_tenant_id(Some(tenant_id.to_string()))
+    }
+    
+    #[actix::test]
+    async fn test_tenants_only_reach_their_own_agents() {
+        // No default agent, so each message is offered to every eligible agent
+        let router = RouterActor::new(None).start();
+        let (acme_agent, mut acme_frames) = start_ws_actor(AgentActor::new("acme-agent".to_string(), "token".to_string()));
+        let (globex_agent, mut globex_frames) = start_ws_actor(AgentActor::new("globex-agent".to_string(), "token".to_string()));
+        router.send(RegisterAgent { agent_id: "acme-agent".to_string(), addr: acme_agent }).await.unwrap();
+        router.send(RegisterAgent { agent_id: "globex-agent".to_string(), addr: globex_agent }).await.unwrap();
+        router.send(SetAgentTenant { agent_id: "acme-agent".to_string(), tenant_id: Some("acme".to_string()) }).await.unwrap();
+        router.send(SetAgentTenant { agent_id: "globex-agent".to_string(), tenant_id: Some("globex".to_string()) }).await.unwrap();
+        
+        router.send(tenant_message("from-acme", "acme")).await.unwrap();
+        router.send(tenant_message("from-globex", "globex")).await.unwrap();
+        // Naming another tenant's agent does not get around isolation
+        router.send(
+            tenant_message("acme-targets-globex", "acme").with_target_agent_id(Some("globex-agent".to_string()))
+        ).await.unwrap();
+        router.send(client_message("untenanted")).await.unwrap();
+        
+        let window = Duration::from_millis(100);
+        let acme_output = collect_frames(&mut acme_frames, window).await.concat();
+        let globex_output = collect_frames(&mut globex_frames, window).await.concat();
+        assert!(acme_output.contains("from-acme"));
+        assert!(acme_output.contains("acme-targets-globex"));
+        assert!(!acme_output.contains("from-globex"));
+        assert!(globex_output.contains("from-globex"));
+        assert!(!globex_output.contains("from-acme"));
+        assert!(!globex_output.contains("acme-targets-globex"));
+        assert!(!acme_output.contains("untenanted") && !globex_output.contains("untenanted"));
+    }
+    
+    #[actix::test]
+    async fn test_anonymous_clients_never_reach_auth_only_agents() {
+        // The auth-only agent is the default, so anonymous messages must be
+        // routed elsewhere rather than to it
+        let router = RouterActor::new(Some("private-agent".to_string())).start();
+        let (private_agent, mut private_frames) = start_ws_actor(AgentActor::new("private-agent".to_string(), "token".to_string()));
+        let (public_agent, mut public_frames) = start_ws_actor(AgentActor::new("public-agent".to_string(), "token".to_string()));
+        router.send(RegisterAgent { agent_id: "private-agent".to_string(), addr: private_agent }).await.unwrap();
+        router.send(RegisterAgent { agent_id: "public-agent".to_string(), addr: public_agent }).await.unwrap();
+        router.send(SetAgentClientAuth { agent_id: "private-agent".to_string(), require_authenticated_clients: true }).await.unwrap();
+        
+        let anonymous_id = Uuid::new_v4();
+        let (anonymous, mut anonymous_frames) = start_ws_actor(ClientSessionActor::new(anonymous_id));
+        router.send(RegisterClient { client_id: anonymous_id, addr: anonymous, authenticated: false, wallet_address: None }).await.unwrap();
+        let member_id = Uuid::new_v4();
+        let (member, _member_frames) = start_ws_actor(ClientSessionActor::new(member_id));
+        router.send(RegisterClient { client_id: member_id, addr: member, authenticated: true, wallet_address: Some("0xabc".to_string()) }).await.unwrap();
+        
+        router.send(ClientMessage::new(anonymous_id, "anonymous-default")).await.unwrap();
+        router.send(targeted_message("anonymous-targeted", anonymous_id, "private-agent")).await.unwrap();
+        router.send(ClientMessage::new(member_id, "member-default")).await.unwrap();
+        
+        let window = Duration::from_millis(100);
+        let private_output = collect_frames(&mut private_frames, window).await.concat();
+        let public_output = collect_frames(&mut public_frames, window).await.concat();
+        assert!(!private_output.contains("anonymous-"));
+        assert!(private_output.contains("member-default"));
+        assert!(public_output.contains("anonymous-default"));
+        assert!(public_output.contains("anonymous-targeted"));
+        let anonymous_output = collect_frames(&mut anonymous_frames, window).await.concat();
+        assert!(anonymous_output.contains("Target agent requires an authenticated client"));
+    }
+    
+    #[actix::test]
+    async fn test_default_agent_of_another_tenant_is_skipped() {
+        let router = RouterActor::new(Some("acme-agent".to_string())).start();
+        let (acme_agent, mut acme_frames) = start_ws_actor(AgentActor::new("acme-agent".to_string(), "token".to_string()));
+        let (globex_agent, mut globex_frames) = start_ws_actor(AgentActor::new("globex-agent".to_string(), "token".to_string()));
+        router.send(RegisterAgent { agent_id: "acme-agent".to_string(), addr: acme_agent }).await.unwrap();
+        router.send(RegisterAgent { agent_id: "globex-agent".to_string(), addr: globex_agent }).await.unwrap();
+        router.send(SetAgentTenant { agent_id: "acme-agent".to_string(), tenant_id: Some("acme".to_string()) }).await.unwrap();
+        router.send(SetAgentTenant { agent_id: "globex-agent".to_string(), tenant_id: Some("globex".to_string()) }).await.unwrap();
+        
+        router.send(tenant_message("from-globex", "globex")).await.unwrap();
+        
+        let window = Duration::from_millis(100);
+        assert!(collect_frames(&mut globex_frames, window).await.concat().contains("from-globex"));
+        assert!(!collect_frames(&mut acme_frames, window).await.concat().contains("from-globex"));
+    }
+    
+    fn agent_to_agent_frame(target_agent_id: &str, content: &str) -> Bytes {
+        client_text_frame(&serde_json::to_string(&AgentToAgent {
+            // Claims to be someone else; the receiving agent actor overrides it
+            source_agent_id: Some("spoofed".to_string()),
+            target_agent_id: target_agent_id.to_string(),
+            content: content.to_string(),
+        }).unwrap())
+    }
+    
+    // Start an agent wired to `router` that sends `frames` once running
+    fn start_agent(router: &Addr<RouterActor>, agent_id: &str, frames: Vec<Bytes>) -> tokio::sync::mpsc::UnboundedReceiver<Bytes> {
+        let mut agent = AgentActor::new(agent_id.to_string(), "token".to_string());
+        agent.set_router(router.clone());
+        let (addr, output) = start_ws_actor_with_input(agent, frames);
+        // Queued ahead of any frame the agent forwards once it starts
+        router.do_send(RegisterAgent { agent_id: agent_id.to_string(), addr });
+        output
+    }
+    
+    #[actix::test]
+    async fn test_agents_exchange_messages_through_router() {
+        let router = RouterActor::new(None).start();
+        let mut alpha_frames = start_agent(&router, "alpha", vec![agent_to_agent_frame("beta", "ping-from-alpha")]);
+        let mut beta_frames = start_agent(&router, "beta", vec![agent_to_agent_frame("alpha", "pong-from-beta")]);
+        
+        let window = Duration::from_millis(100);
+        let alpha_output = collect_frames(&mut alpha_frames, window).await.concat();
+        let beta_output = collect_frames(&mut beta_frames, window).await.concat();
+        assert!(beta_output.contains("ping-from-alpha"));
+        assert!(beta_output.contains(r#""source_agent_id":"alpha""#));
+        assert!(alpha_output.contains("pong-from-beta"));
+        assert!(alpha_output.contains(r#""source_agent_id":"beta""#));
+        assert!(!alpha_output.contains("spoofed") && !beta_output.contains("spoofed"));
+    }
+    
+    fn broadcast(content: &str, requires_ack: bool) -> AgentMessage {
+        AgentMessage {
+            message_id: Some(7),
+            requires_ack,
+            ..AgentMessage::broadcast(content)
+        }
+    }
+    
+    fn direct_message_frame(content: &str, client_id: Uuid) -> Bytes {
+        let message = AgentMessage {
+            target_client_id: Some(client_id),
+            ..broadcast(content, true)
+        };
+        client_text_frame(&serde_json::to_string(&message).unwrap())
+    }
+    
+    #[actix::test]
+    async fn test_direct_delivery_is_acked_to_sending_agent() {
+        let router = RouterActor::new(None).start();
+        let client_id = Uuid::new_v4();
+        let (client, mut client_frames) = start_ws_actor(ClientSessionActor::new(client_id));
+        router.send(RegisterClient { client_id, addr: client, authenticated: false, wallet_address: None }).await.unwrap();
+        
+        let mut agent_frames = start_agent(&router, "alpha", vec![direct_message_frame("hello-client", client_id)]);
+        
+        let window = Duration::from_millis(100);
+        assert!(collect_frames(&mut client_frames, window).await.concat().contains("hello-client"));
+        let output = collect_frames(&mut agent_frames, window).await.concat();
+        assert!(output.contains(&format!(r#""source_id":"{}","message_id":7"#, client_id)));
+        assert!(output.contains(r#""status":"Received""#));
+    }
+    
+    #[actix::test]
+    async fn test_direct_message_to_absent_client_acks_error() {
+        let router = RouterActor::new(None).start();
+        let missing = Uuid::new_v4();
+        
+        let mut agent_frames = start_agent(&router, "alpha", vec![direct_message_frame("into-the-void", missing)]);
+        
+        let output = collect_frames(&mut agent_frames, Duration::from_millis(100)).await.concat();
+        assert!(output.contains(&format!(r#""source_id":"{}","message_id":7"#, missing)));
+        assert!(output.contains(r#""status":{"Error":"Client not found"}"#));
+    }
+    
+    #[actix::test]
+    async fn test_server_timestamp_is_added_only_when_enabled() {
+        for enabled in [true, false] {
+            let router = RouterActor::new(None).with_server_timestamps(enabled).start();
+            let client_id = Uuid::new_v4();
+            let (client, mut client_frames) = start_ws_actor(ClientSessionActor::new(client_id));
+            router.send(RegisterClient { client_id, addr: client, authenticated: false, wallet_address: None }).await.unwrap();
+            
+            let before = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as u64;
+            router.send(AgentMessage { target_client_id: Some(client_id), ..broadcast("stamped", false) }).await.unwrap();
+            
+            let raw = collect_raw_frames(&mut client_frames, Duration::from_millis(100)).await.concat();
+            let (_, json) = decode_server_frames(&raw).into_iter()
+                .find(|(_, payload)| String::from_utf8_lossy(payload).contains("stamped"))
+                .expect("the message should be delivered");
+            assert_eq!(String::from_utf8_lossy(&json).contains(r#""server_timestamp":"#), enabled);
+            if enabled {
+                let delivered: AgentMessage = serde_json::from_slice(&json).unwrap();
+                assert!(delivered.server_timestamp.unwrap() >= before);
+            }
+        }
+    }
+    
+    #[actix::test]
+    async fn test_broadcast_reports_reach_when_ack_required() {
+        let router = RouterActor::new(None).start();
+        let mut live_frames = Vec::new();
+        for _ in 0..2 {
+            let (client, frames) = start_ws_actor(ClientSessionActor::new(Uuid::new_v4()));
+            router.send(RegisterClient { client_id: Uuid::new_v4(), addr: client, authenticated: false, wallet_address: None }).await.unwrap();
+            live_frames.push(frames);
+        }
+        // A client whose connection has gone away counts as attempted only
+        let (dead, _dead_frames) = start_ws_actor_with_input(
+            ClientSessionActor::new(Uuid::new_v4()),
+            vec![Bytes::from_static(&[0x81, 0x00])],
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!dead.connected());
+        router.send(RegisterClient { client_id: Uuid::new_v4(), addr: dead, authenticated: false, wallet_address: None }).await.unwrap();
+        
+        let result = router.send(broadcast("to-everyone", true)).await.unwrap();
+        assert_eq!(result, Some(BroadcastResult { message_id: Some(7), attempted: 3, delivered: 2 }));
+        for frames in live_frames.iter_mut() {
+            assert!(collect_frames(frames, Duration::from_millis(100)).await.concat().contains("to-everyone"));
+        }
+        
+        // Without an ack the broadcast is fire-and-forget
+        assert_eq!(router.send(broadcast("no-ack", false)).await.unwrap(), None);
+        
+        // Agents get the result back on their own connection
+        let frame = client_text_frame(&serde_json::to_string(&broadcast("from-agent", true)).unwrap());
+        let mut agent_frames = start_agent(&router, "alpha", vec![frame]);
+        let output = collect_frames(&mut agent_frames, Duration::from_millis(100)).await.concat();
+        assert!(output.contains(r#""type":"broadcast_result""#));
+        assert!(output.contains(r#""attempted":3,"delivered":2"#));
+    }
+    
+    #[actix::test]
+    async fn test_broadcast_reaches_only_its_audience() {
+        let router = RouterActor::new(None).start();
+        let mut frames = Vec::new();
+        for (authenticated, wallet_address) in [(false, None), (true, Some("0xAbC")), (true, Some("0xdef"))] {
+            let (client, client_frames) = start_ws_actor(ClientSessionActor::new(Uuid::new_v4()));
+            let wallet_address = wallet_address.map(str::to_string);
+            router.send(RegisterClient { client_id: Uuid::new_v4(), addr: client, authenticated, wallet_address }).await.unwrap();
+            frames.push(client_frames);
+        }
+        
+        let audiences = [
+            (Audience::All, [true, true, true]),
+            (Audience::AuthenticatedOnly, [false, true, true]),
+            (Audience::WalletList(vec!["0xabc".to_string(), "0x123".to_string()]), [false, true, false]),
+        ];
+        for (audience, reached) in audiences {
+            let message = AgentMessage { audience: Some(audience.clone()), ..broadcast("for-some", true) };
+            let result = router.send(message).await.unwrap().unwrap();
+            let expected = reached.iter().filter(|reached| **reached).count();
+            assert_eq!((result.attempted, result.delivered), (expected, expected), "{:?}", audience);
+            
+            for (client_frames, reached) in frames.iter_mut().zip(reached) {
+                let output = collect_frames(client_frames, Duration::from_millis(100)).await.concat();
+                assert_eq!(output.contains("for-some"), reached, "{:?}", audience);
+                // Recipients aren't told who else the broadcast was for
+                assert!(!output.contains("audience"));
+            }
+        }
+    }
+    
+    #[actix::test]
+    async fn test_agent_to_missing_agent_notifies_sender() {
+        let router = RouterActor::new(None).start();
+        let (client, mut client_frames) = start_ws_actor(ClientSessionActor::new(Uuid::new_v4()));
+        router.do_send(RegisterClient { client_id: Uuid::new_v4(), addr: client, authenticated: false, wallet_address: None });
+        let mut alpha_frames = start_agent(&router, "alpha", vec![agent_to_agent_frame("missing", "into-the-void")]);
+        
+        let window = Duration::from_millis(100);
+        let alpha_output = collect_frames(&mut alpha_frames, window).await.concat();
+        assert!(alpha_output.contains("Target agent not found"));
+        assert!(alpha_output.contains("missing"));
+        // Agent-to-agent traffic never falls back to clients
+        assert!(!collect_frames(&mut client_frames, window).await.concat().contains("into-the-void"));
+    }
+    
+    #[actix::test]
+    async fn test_targeted_message_reaches_named_agent() {
+        let router = RouterActor::new(Some("agent1".to_string())).start();
+        let (default_agent, mut default_frames) = start_ws_actor(AgentActor::new("agent1".to_string(), "token".to_string()));
+        let (target_agent, mut target_frames) = start_ws_actor(AgentActor::new("agent2".to_string(), "token".to_string()));
+        router.send(RegisterAgent { agent_id: "agent1".to_string(), addr: default_agent }).await.unwrap();
+        router.send(RegisterAgent { agent_id: "agent2".to_string(), addr: target_agent }).await.unwrap();
+        
+        router.send(targeted_message("for-agent2", Uuid::new_v4(), "agent2")).await.unwrap();
+        
+        let window = Duration::from_millis(100);
+        assert!(collect_frames(&mut target_frames, window).await.concat().contains("for-agent2"));
+        assert!(!collect_frames(&mut default_frames, window).await.concat().contains("for-agent2"));
+    }
+    
+    #[actix::test]
+    async fn test_targeted_message_to_missing_agent_notifies_client() {
+        let router = RouterActor::new(Some("agent1".to_string())).start();
+        let (default_agent, mut default_frames) = start_ws_actor(AgentActor::new("agent1".to_string(), "token".to_string()));
+        router.send(RegisterAgent { agent_id: "agent1".to_string(), addr: default_agent }).await.unwrap();
+        
+        let client_id = Uuid::new_v4();
+        let (client, mut client_frames) = start_ws_actor(ClientSessionActor::new(client_id));
+        router.send(RegisterClient { client_id, addr: client, authenticated: false, wallet_address: None }).await.unwrap();
+        
+        router.send(targeted_message("for-missing", client_id, "missing-agent")).await.unwrap();
+        
+        let window = Duration::from_millis(100);
+        let client_output = collect_frames(&mut client_frames, window).await.concat();
+        assert!(client_output.contains("Target agent not found"));
+        assert!(client_output.contains("missing-agent"));
+        // The message itself still falls back to the default agent
+        assert!(collect_frames(&mut default_frames, window).await.concat().contains("for-missing"));
+    }
+    
+    #[actix::test]
+    async fn test_untargeted_message_uses_default_agent() {
+        let router = RouterActor::new(Some("agent1".to_string())).start();
+        let (default_agent, mut default_frames) = start_ws_actor(AgentActor::new("agent1".to_string(), "token".to_string()));
+        let (other_agent, mut other_frames) = start_ws_actor(AgentActor::new("agent2".to_string(), "token".to_string()));
+        router.send(RegisterAgent { agent_id: "agent1".to_string(), addr: default_agent }).await.unwrap();
+        router.send(RegisterAgent { agent_id: "agent2".to_string(), addr: other_agent }).await.unwrap();
+        
+        router.send(client_message("untargeted")).await.unwrap();
+        
+        let window = Duration::from_millis(100);
+        assert!(collect_frames(&mut default_frames, window).await.concat().contains("untargeted"));
+        assert!(!collect_frames(&mut other_frames, window).await.concat().contains("untargeted"));
+    }
+    
+    #[actix::test]
+    async fn test_routes_to_configured_default_agent() {
+        let router = RouterActor::new(Some("custom-agent".to_string())).start();
+        let (default_agent, mut default_frames) = start_ws_actor(AgentActor::new("custom-agent".to_string(), "token".to_string()));
+        let (other_agent, mut other_frames) = start_ws_actor(AgentActor::new("agent1".to_string(), "token".to_string()));
+        router.send(RegisterAgent { agent_id: "custom-agent".to_string(), addr: default_agent }).await.unwrap();
+        router.send(RegisterAgent { agent_id: "agent1".to_string(), addr: other_agent }).await.unwrap();
+        
+        router.send(client_message("hello-default")).await.unwrap();
+        
+        let window = Duration::from_millis(100);
+        assert!(collect_frames(&mut default_frames, window).await.concat().contains("hello-default"));
+        assert!(!collect_frames(&mut other_frames, window).await.concat().contains("hello-default"));
+    }
+    
+    #[test]
+    fn test_clients_spread_evenly_across_shards() {
+        let client_id = Uuid::new_v4();
+        assert_eq!(shard_index(&client_id, 4), shard_index(&client_id, 4));
+        
+        let mut per_shard = [0; 4];
+        for _ in 0..4000 {
+            per_shard[shard_index(&Uuid::new_v4(), 4)] += 1;
+        }
+        assert!(per_shard.iter().all(|count| (800..1200).contains(count)), "{:?}", per_shard);
+    }
+    
+    #[actix::test]
+    async fn test_sharded_router_keeps_each_clients_messages_in_order() {
+        let router = RouterActor::new(Some("agent1".to_string())).with_shards(4).start();
+        let (agent1, mut agent1_frames) = start_ws_actor(AgentActor::new("agent1".to_string(), "token".to_string()));
+        let (agent2, mut agent2_frames) = start_ws_actor(AgentActor::new("agent2".to_string(), "token".to_string()));
+        router.send(RegisterAgent { agent_id: "agent1".to_string(), addr: agent1 }).await.unwrap();
+        router.send(RegisterAgent { agent_id: "agent2".to_string(), addr: agent2 }).await.unwrap();
+        
+        // Consecutive ids land on every shard in turn
+        let clients: Vec<Uuid> = (0..8u128).map(Uuid::from_u128).collect();
+        for seq in 0..5 {
+            for (i, client_id) in clients.iter().enumerate() {
+                router.do_send(ClientMessage::new(*client_id, format!("client{}-seq{}", i, seq)));
+            }
+        }
+        
+        let output = collect_frames(&mut agent1_frames, Duration::from_millis(200)).await.concat();
+        for i in 0..clients.len() {
+            let positions: Vec<usize> = (0..5)
+                .map(|seq| output.find(&format!("client{}-seq{}", i, seq)).expect("every message should be routed"))
+                .collect();
+            assert!(positions.windows(2).all(|pair| pair[0] < pair[1]), "client{} out of order", i);
+        }
+        
+        // A new default agent takes effect on every shard
+        router.send(SetDefaultAgent { agent_id: "agent2".to_string() }).await.unwrap().unwrap();
+        for client_id in &clients {
+            router.do_send(ClientMessage::new(*client_id, "after-switch"));
+        }
+        let output = collect_frames(&mut agent2_frames, Duration::from_millis(200)).await.concat();
+        assert_eq!(output.matches("after-switch").count(), clients.len());
+    }
+    
+    #[test]
+    fn test_shards_inherit_router_settings() {
+        let router = RouterActor::new(Some("agent1".to_string()))
+            .with_required_acks(true)
+            .with_server_timestamps(true)
+            .with_strategy(RoutingStrategy::RoundRobin)
+            .with_max_paused_messages(7)
+            .with_slow_message_threshold(Duration::from_millis(25))
+            .with_shards(4);
+        let shard = router.shard();
+        assert!(shard.require_acks);
+        assert!(shard.server_timestamps);
+        assert_eq!(shard.strategy, RoutingStrategy::RoundRobin);
+        assert_eq!(shard.max_paused_messages, 7);
+        assert_eq!(shard.slow_message_threshold, Duration::from_millis(25));
+        assert_eq!(shard.default_agent_id.as_deref(), Some("agent1"));
+        assert_eq!(shard.shard_count, 1);
+    }
+    
+    // Keep the thread routing shard 0's clients busy, as a slow message would;
+    // without shards that is the router's own thread
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    struct BusyFirstShard(Duration);
+    
+    impl Handler<BusyFirstShard> for RouterActor {
+        type Result = ();
+        
+        fn handle(&mut self, msg: BusyFirstShard, _ctx: &mut Self::Context) {
+            match self.shards.first() {
+                Some((_, shard)) => shard.do_send(msg),
+                None => std::thread::sleep(msg.0),
+            }
+        }
+    }
+    
+    #[actix::test]
+    async fn test_throughput_scales_with_shard_count() {
+        // Messages from 8 clients routed while one routing thread is busy
+        let routed_while_busy = |shard_count: usize| async move {
+            let arbiter = Arbiter::new();
+            let router = RouterActor::start_in_arbiter(&arbiter.handle(), move |_| {
+                RouterActor::new(Some("agent1".to_string())).with_shards(shard_count)
+            });
+            let (agent, mut frames) = start_ws_actor(AgentActor::new("agent1".to_string(), "token".to_string()));
+            router.send(RegisterAgent { agent_id: "agent1".to_string(), addr: agent }).await.unwrap();
+            
+            router.do_send(BusyFirstShard(Duration::from_millis(400)));
+            for client_id in (0..8u128).map(Uuid::from_u128) {
+                router.do_send(ClientMessage::new(client_id, "while-busy"));
+            }
+            let routed = collect_frames(&mut frames, Duration::from_millis(200)).await.concat().matches("while-busy").count();
+            
+            // The busy thread catches up on the rest afterwards
+            let caught_up = collect_frames(&mut frames, Duration::from_millis(400)).await.concat().matches("while-busy").count();
+            assert_eq!(routed + caught_up, 8);
+            arbiter.stop();
+            routed
+        };
+        
+        // Only the busy shard's share of clients waits
+        assert_eq!(routed_while_busy(1).await, 0);
+        assert_eq!(routed_while_busy(2).await, 4);
+        assert_eq!(routed_while_busy(4).await, 6);
+    }
+    
+    #[test]
+    fn test_topic_patterns() {
+        assert!(topic_matches("orders.created", "orders.created"));
+        assert!(!topic_matches("orders.created", "orders.created.eu"));
+        assert!(topic_matches("orders.*", "orders.created"));
+        assert!(topic_matches("*.created", "orders.created"));
+        assert!(topic_matches("orders.*.eu", "orders.created.eu"));
+        assert!(topic_matches("*", "anything"));
+        assert!(!topic_matches("orders.*", "billing.created"));
+        assert!(!topic_matches("orders.*.eu", "orders.eu"));
+    }
+    
+    #[actix::test]
+    async fn test_topic_messages_reach_matching_subscribers() {
+        let router = RouterActor::new(Some("agent1".to_string())).start();
+        let mut frames = HashMap::new();
+        for (agent_id, topic) in [("agent1", None), ("created", Some("orders.created")), ("orders", Some("orders.*")), ("billing", Some("billing.*"))] {
+            let (addr, output) = start_ws_actor(AgentActor::new(agent_id.to_string(), "token".to_string()));
+            router.send(RegisterAgent { agent_id: agent_id.to_string(), addr }).await.unwrap();
+            if let Some(topic) = topic {
+                router.send(SubscribeTopic { agent_id: agent_id.to_string(), topic: topic.to_string() }).await.unwrap();
+            }
+            frames.insert(agent_id, output);
+        }
+        async fn received_by(frames: &mut HashMap<&'static str, tokio::sync::mpsc::UnboundedReceiver<Bytes>>, content: &str) -> Vec<&'static str> {
+            let mut received = Vec::new();
+            for (agent_id, output) in frames.iter_mut() {
+                if collect_frames(output, Duration::from_millis(50)).await.concat().contains(content) {
+                    received.push(*agent_id);
+                }
+            }
+            received.sort();
+            received
+        }
+        let topic_message = |content: &str, topic: &str| client_message(content).with_topic(Some(topic.to_string()));
+        
+        // Exact and wildcard subscriptions both match
+        router.send(topic_message("order-one", "orders.created")).await.unwrap();
+        assert_eq!(received_by(&mut frames, "order-one").await, vec!["created", "orders"]);
+        
+        router.send(topic_message("order-two", "orders.cancelled")).await.unwrap();
+        assert_eq!(received_by(&mut frames, "order-two").await, vec!["orders"]);
+        
+        // A topic nobody follows goes to the default agent
+        router.send(topic_message("shipped", "shipping.sent")).await.unwrap();
+        assert_eq!(received_by(&mut frames, "shipped").await, vec!["agent1"]);
+        
+        router.send(UnsubscribeTopic { agent_id: "orders".to_string(), topic: "orders.*".to_string() }).await.unwrap();
+        router.send(topic_message("order-three", "orders.cancelled")).await.unwrap();
+        assert_eq!(received_by(&mut frames, "order-three").await, vec!["agent1"]);
+    }
+    
+    #[actix::test]
+    async fn test_round_robin_distributes_evenly() {
+        let router = RouterActor::new(None).with_strategy(RoutingStrategy::RoundRobin).start();
+        let mut outputs = Vec::new();
+        for agent_id in ["agent-a", "agent-b", "agent-c"] {
+            let (addr, frames) = start_ws_actor(AgentActor::new(agent_id.to_string(), "token".to_string()));
+            router.send(RegisterAgent { agent_id: agent_id.to_string(), addr }).await.unwrap();
+            outputs.push(frames);
+        }
+        
+        // An agent whose connection has gone away must be skipped, not counted.
+        // An unmasked client frame is a protocol error, which stops the actor.
+        let (dead, _dead_frames) = start_ws_actor_with_input(
+            AgentActor::new("agent-0".to_string(), "token".to_string()),
+            vec![Bytes::from_static(&[0x81, 0x00])],
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!dead.connected());
+        router.send(RegisterAgent { agent_id: "agent-0".to_string(), addr: dead }).await.unwrap();
+        
+        for i in 0..9 {
+            router.send(client_message(&format!("rr-msg-{}", i))).await.unwrap();
+        }
+        
+        for frames in outputs.iter_mut() {
+            let output = collect_frames(frames, Duration::from_millis(100)).await.concat();
+            assert_eq!(output.matches("rr-msg-").count(), 3);
+        }
+    }
+    
+    #[actix::test]
+    async fn test_round_robin_wraps_when_agents_change() {
+        let mut router = RouterActor::new(None).with_strategy(RoutingStrategy::RoundRobin);
+        let (agent_a, mut frames_a) = start_ws_actor(AgentActor::new("agent-a".to_string(), "token".to_string()));
+        let (agent_b, mut frames_b) = start_ws_actor(AgentActor::new("agent-b".to_string(), "token".to_string()));
+        router.register_agent("agent-a".to_string(), agent_a);
+        router.register_agent("agent-b".to_string(), agent_b);
+        
+        assert!(router.send_round_robin("first".to_string(), None, false));
+        assert_eq!(router.last_round_robin_agent.as_deref(), Some("agent-a"));
+        
+        // The cursor agent leaving must not reset or skip the rotation
+        router.unregister_agent("agent-a");
+        assert!(router.send_round_robin("second".to_string(), None, false));
+        assert_eq!(router.last_round_robin_agent.as_deref(), Some("agent-b"));
+        
+        // Past the last agent the cursor wraps to the start
+        assert!(router.send_round_robin("third".to_string(), None, false));
+        assert_eq!(router.last_round_robin_agent.as_deref(), Some("agent-b"));
+        
+        let window = Duration::from_millis(100);
+        assert_eq!(collect_frames(&mut frames_a, window).await.concat().matches("first").count(), 1);
+        let output_b = collect_frames(&mut frames_b, window).await.concat();
+        assert!(output_b.contains("second") && output_b.contains("third"));
+        
+        router.unregister_agent("agent-b");
+        assert!(!router.send_round_robin("nobody".to_string(), None, false));
+    }
+    
+    #[actix::test]
+    async fn test_set_default_agent_to_registered_agent() {
+        let router = RouterActor::new(Some("agent1".to_string())).start();
+        let (agent, _frames) = start_ws_actor(AgentActor::new("agent2".to_string(), "token".to_string()));
+        
+        router.send(RegisterAgent { agent_id: "agent2".to_string(), addr: agent }).await.unwrap();
+        let result = router.send(SetDefaultAgent { agent_id: "agent2".to_string() }).await.unwrap();
+        
+        assert_eq!(result, Ok(()));
+        assert_eq!(router.send(GetDefaultAgent).await.unwrap(), Some("agent2".to_string()));
+    }
+    
+    #[actix::test]
+    async fn test_set_default_agent_to_unregistered_agent_is_rejected() {
+        let router = RouterActor::new(Some("agent1".to_string())).start();
+        let previous = router.send(GetDefaultAgent).await.unwrap();
+        
+        let result = router.send(SetDefaultAgent { agent_id: "missing".to_string() }).await.unwrap();
+        
+        assert_eq!(result, Err(RouterError::AgentNotRegistered("missing".to_string())));
+        assert_eq!(router.send(GetDefaultAgent).await.unwrap(), previous);
+    }
+    
+    #[actix::test]
+    async fn test_presence_events_reach_subscribed_agents_only() {
+        let router = RouterActor::new(None).start();
+        let (watcher, mut watcher_frames) = start_ws_actor(AgentActor::new("watcher".to_string(), "token".to_string()));
+        let (bystander, mut bystander_frames) = start_ws_actor(AgentActor::new("bystander".to_string(), "token".to_string()));
+        router.send(RegisterAgent { agent_id: "watcher".to_string(), addr: watcher }).await.unwrap();
+        router.send(RegisterAgent { agent_id: "bystander".to_string(), addr: bystander }).await.unwrap();
+        
+        let client_id = Uuid::new_v4();
+        router.send(SubscribePresence { agent_id: "watcher".to_string(), client_id }).await.unwrap();
+        router.send(SystemMessage::ClientDisconnected { client_id }).await.unwrap();
+        
+        let window = Duration::from_millis(100);
+        let watcher_output = collect_frames(&mut watcher_frames, window).await.concat();
+        let bystander_output = collect_frames(&mut bystander_frames, window).await.concat();
+        assert!(watcher_output.contains("ClientDisconnected") && watcher_output.contains(&client_id.to_string()));
+        assert!(bystander_output.is_empty());
+        
+        // Nothing more once the agent unsubscribes
+        router.send(UnsubscribePresence { agent_id: "watcher".to_string(), client_id }).await.unwrap();
+        router.send(SystemMessage::ClientDisconnected { client_id }).await.unwrap();
+        assert!(collect_frames(&mut watcher_frames, window).await.is_empty());
+    }
+    
+    #[actix::test]
+    async fn test_unregistering_agent_drops_its_presence_subscriptions() {
+        let router = RouterActor::new(None);
+        let (agent, _frames) = start_ws_actor(AgentActor::new("watcher".to_string(), "token".to_string()));
+        router.register_agent("watcher".to_string(), agent);
+        let client_id = Uuid::new_v4();
+        router.subscribe_presence("watcher".to_string(), client_id);
+        assert!(router.presence_subscriptions.contains_key(&client_id));
+        
+        router.unregister_agent("watcher");
+        assert!(router.presence_subscriptions.is_empty());
+        // Subscribing on behalf of an unknown agent is ignored
+        router.subscribe_presence("watcher".to_string(), client_id);
+        assert!(router.presence_subscriptions.is_empty());
+    }
+    
+    #[actix::test]
+    async fn test_agent_with_many_presence_subscriptions_is_fully_cleaned_up() {
+        let router = RouterActor::new(None);
+        let (watcher, _watcher_frames) = start_ws_actor(AgentActor::new("watcher".to_string(), "token".to_string()));
+        let (other, _other_frames) = start_ws_actor(AgentActor::new("other".to_string(), "token".to_string()));
+        router.register_agent("watcher".to_string(), watcher);
+        router.register_agent("other".to_string(), other);
+        
+        let client_ids: Vec<Uuid> = (0..1000).map(|_| Uuid::new_v4()).collect();
+        for client_id in &client_ids {
+            router.subscribe_presence("watcher".to_string(), *client_id);
+        }
+        // One client is shared, so its entry must outlive the watcher
+        let shared = client_ids[0];
+        router.subscribe_presence("other".to_string(), shared);
+        assert_eq!(router.presence_subscriptions.len(), 1000);
+        
+        router.unregister_agent("watcher");
+        assert!(!router.presence_by_agent.contains_key("watcher"));
+        assert_eq!(router.presence_subscriptions.len(), 1);
+        assert_eq!(
+            router.presence_subscriptions.get(&shared).map(|agents| agents.clone()),
+            Some(HashSet::from(["other".to_string()]))
+        );
+        
+        router.unsubscribe_presence("other", &shared);
+        assert!(router.presence_subscriptions.is_empty());
+        assert!(router.presence_by_agent.is_empty());
+    }
+    
+    #[actix::test]
+    async fn test_agent_with_many_topic_subscriptions_is_fully_cleaned_up() {
+        let mut router = RouterActor::new(None);
+        let (watcher, _watcher_frames) = start_ws_actor(AgentActor::new("watcher".to_string(), "token".to_string()));
+        let (other, _other_frames) = start_ws_actor(AgentActor::new("other".to_string(), "token".to_string()));
+        router.register_agent("watcher".to_string(), watcher);
+        router.register_agent("other".to_string(), other);
+        
+        let topics: Vec<String> = (0..1000).map(|i| format!("prices.{}", i)).collect();
+        for topic in &topics {
+            router.subscribe_topic("watcher".to_string(), topic.clone());
+        }
+        // One pattern is shared, so its entry must outlive the watcher
+        let shared = topics[0].clone();
+        router.subscribe_topic("other".to_string(), shared.clone());
+        assert_eq!(router.topic_subscriptions.len(), 1000);
+        
+        router.unregister_agent("watcher");
+        assert!(!router.topics_by_agent.contains_key("watcher"));
+        assert_eq!(router.topic_subscriptions.len(), 1);
+        assert_eq!(router.topic_subscribers(&shared), HashSet::from(["other".to_string()]));
+        
+        router.unsubscribe_topic("other", &shared);
+        assert!(router.topic_subscriptions.is_empty());
+        assert!(router.topics_by_agent.is_empty());
+        
+        // Reconciling drops the subscriptions of agents no longer live
+        router.subscribe_topic("other".to_string(), shared);
+        router.reconcile(&HashSet::new(), &HashSet::new());
+        assert!(router.topic_subscriptions.is_empty());
+        assert!(router.topics_by_agent.is_empty());
+    }
+}
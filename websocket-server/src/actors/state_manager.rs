@@ -1,15 +1,19 @@
 // websocket-server/src/actors/state_manager.rs
 
-use actix::{Actor, Context, Handler, Message, Addr, AsyncContext};
+use actix::{Actor, ActorFutureExt, Context, Handler, Message, Addr, AsyncContext, ResponseActFuture};
+use actix::prelude::SendError;
 use dashmap::DashMap;
 use uuid::Uuid;
-use std::time::{Duration, Instant};
-use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use chrono::{DateTime, Utc};
-use super::client_session_actor::ClientSessionActor;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use super::client_session_actor::{ClientSessionActor, OutboundFrame};
 use super::agent_actor::AgentActor;
 use super::router_actor::RouterActor;
-use common::SystemMessage;
+use common::{AgentHello, StateManagerConfig, SystemMessage};
 
 // Enhanced connection states
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -22,15 +26,17 @@ pub enum ConnectionState {
 }
 
 // New: Session state structure for persistence
-#[derive(Debug, Clone, Message)]
+#[derive(Debug, Clone, PartialEq, Message)]
 #[rtype(result = "()")]
 pub struct SessionState {
     pub client_id: Uuid,
     pub authenticated: bool,
     pub wallet_address: Option<String>,
-    pub message_buffer: Vec<String>,
+    pub message_buffer: Vec<(OutboundFrame, Instant)>, // Frames with the time they were buffered
     pub last_seen: Instant,
     pub session_data: HashMap<String, String>,
+    pub next_sequence: u64, // Sequence number the client's next message gets
+    pub resume_token: String, // Proves a reconnecting client is the one this session belongs to
 }
 
 // New: Message to save session state
@@ -47,9 +53,69 @@ pub struct GetSessionState {
     pub client_id: Uuid,
 }
 
+// What an agent had set up on its connection, kept while it is away so a
+// reconnect picks up where it left off
+#[derive(Debug, Clone, PartialEq, Message)]
+#[rtype(result = "()")]
+pub struct AgentSessionState {
+    pub agent_id: String,
+    pub message_buffer: Vec<String>, // Router deliveries the agent never received
+    pub presence_subscriptions: HashSet<Uuid>, // Clients whose presence it follows
+    pub topics: HashSet<String>,
+    pub hello: Option<AgentHello>, // Its tenant and client auth declaration
+    pub last_seen: Instant,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SaveAgentSessionState {
+    pub state: AgentSessionState,
+}
+
+// Tells a connected AgentActor to save its session state, as it does when it stops
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PersistAgentSession;
+
+// Asks a connected ClientSessionActor for its current session state
+#[derive(Message)]
+#[rtype(result = "SessionState")]
+pub struct SnapshotSession;
+
+// Sent on shutdown: snapshot every connected client's session, then write
+// all sessions to the sessions file. Returns how many were written
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct DrainAndPersist;
+
+// Tells a connection actor to close its WebSocket with a policy violation
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Shutdown {
+    pub reason: String,
+}
+
+// Admin request to kick a client: its connection is closed and it is dropped
+// from tracking rather than kept for reconnection. Returns whether it was known
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct ForceDisconnectClient {
+    pub client_id: Uuid,
+    pub reason: String,
+}
+
+// Like `ForceDisconnectClient`, for an agent
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct ForceDisconnectAgent {
+    pub agent_id: String,
+    pub reason: String,
+}
+
 // Enhanced client data structure with metrics
 pub struct ClientData {
     pub addr: Addr<ClientSessionActor>,
+    pub connection_id: Option<Uuid>, // Set once the connection actor has started
     pub state: ConnectionState,
     pub last_seen: Instant,
     pub connected_at: Instant,
@@ -63,11 +129,15 @@ pub struct ClientData {
     pub bytes_sent: u64,
     pub bytes_received: u64,
     pub disconnection_count: u32,
+    pub dropped_messages: u64, // Outbound messages dropped because the client's buffer was full
+    pub expired_buffered: u64, // Buffered messages discarded for outliving their TTL
+    pub last_rtt_ms: Option<f64>, // Rolling average ping round-trip time, once a pong has come back
 }
 
 // Enhanced agent data structure with metrics
 pub struct AgentData {
     pub addr: Addr<AgentActor>,
+    pub connection_id: Option<Uuid>, // Set once the connection actor has started
     pub state: ConnectionState,
     pub last_seen: Instant,
     pub connected_at: Instant,
@@ -79,6 +149,7 @@ pub struct AgentData {
     pub bytes_sent: u64,
     pub bytes_received: u64,
     pub disconnection_count: u32,
+    pub messages_rate_limited: u64, // Inbound messages dropped by the agent rate limit
 }
 
 // Existing message types (unchanged)
@@ -127,6 +198,21 @@ pub struct UpdateAgentState {
     pub last_seen_update: bool,
 }
 
+// Sent by a connection actor once started, so reconnects can be told apart
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct UpdateClientConnection {
+    pub client_id: Uuid,
+    pub connection_id: Uuid,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct UpdateAgentConnection {
+    pub agent_id: String,
+    pub connection_id: Uuid,
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct ClientActivity {
@@ -151,6 +237,7 @@ pub struct GetClientStatus {
 #[derive(Debug)]
 pub struct ClientStatusResponse {
     pub client_id: Uuid,
+    pub connection_id: Option<Uuid>,
     pub state: ConnectionState,
     pub connected_duration: Duration,
     pub last_seen_ago: Duration,
@@ -162,6 +249,16 @@ pub struct ClientStatusResponse {
     pub bytes_sent: u64,
     pub bytes_received: u64,
     pub disconnection_count: u32,
+    pub dropped_messages: u64,
+    pub expired_buffered: u64,
+    pub last_rtt_ms: Option<f64>,
+}
+
+// List every known client, optionally only those in `state_filter`
+#[derive(Message)]
+#[rtype(result = "Vec<ClientStatusResponse>")]
+pub struct GetAllClients {
+    pub state_filter: Option<ConnectionState>,
 }
 
 // Enhanced response with agent status including metrics
@@ -174,6 +271,7 @@ pub struct GetAgentStatus {
 #[derive(Debug)]
 pub struct AgentStatusResponse {
     pub agent_id: String,
+    pub connection_id: Option<Uuid>,
     pub state: ConnectionState,
     pub connected_duration: Duration,
     pub last_seen_ago: Duration,
@@ -184,6 +282,14 @@ pub struct AgentStatusResponse {
     pub bytes_sent: u64,
     pub bytes_received: u64,
     pub disconnection_count: u32,
+    pub messages_rate_limited: u64,
+}
+
+// List every known agent, optionally only those in `state_filter`
+#[derive(Message)]
+#[rtype(result = "Vec<AgentStatusResponse>")]
+pub struct GetAllAgents {
+    pub state_filter: Option<ConnectionState>,
 }
 
 // New: Message to fetch system metrics
@@ -201,7 +307,48 @@ pub struct SystemMetrics {
     pub total_messages_processed: u64,
     pub messages_per_second: f64,
     pub bytes_transferred: u64,
-    pub timestamp: std::time::SystemTime, // Changed from DateTime<Utc>
+    // Agent pre-shared key checks since start, so repeated failures can be alerted on
+    pub agent_auth_successes: u64,
+    pub agent_auth_failures: u64,
+    pub timestamp: SystemTime,
+}
+
+/// Serializable view of `SystemMetrics` for the metrics endpoints.
+/// `SystemTime` has no stable wire format, so the snapshot time is sent as
+/// milliseconds since the Unix epoch.
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemMetricsResponse {
+    pub total_clients: usize,
+    pub active_clients: usize,
+    pub total_agents: usize,
+    pub active_agents: usize,
+    pub total_messages_processed: u64,
+    pub messages_per_second: f64,
+    pub bytes_transferred: u64,
+    pub agent_auth_successes: u64,
+    pub agent_auth_failures: u64,
+    pub timestamp_ms: u64,
+}
+
+impl From<SystemMetrics> for SystemMetricsResponse {
+    fn from(metrics: SystemMetrics) -> Self {
+        Self {
+            total_clients: metrics.total_clients,
+            active_clients: metrics.active_clients,
+            total_agents: metrics.total_agents,
+            active_agents: metrics.active_agents,
+            total_messages_processed: metrics.total_messages_processed,
+            messages_per_second: metrics.messages_per_second,
+            bytes_transferred: metrics.bytes_transferred,
+            agent_auth_successes: metrics.agent_auth_successes,
+            agent_auth_failures: metrics.agent_auth_failures,
+            // A clock set before the epoch reports 0 rather than failing the request
+            timestamp_ms: metrics.timestamp
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as u64)
+                .unwrap_or_default(),
+        }
+    }
 }
 
 // New: Message to update message metrics
@@ -222,6 +369,46 @@ pub struct UpdateAgentMessageMetrics {
     pub bytes: Option<usize>,
 }
 
+// Sent by an agent actor when it drops an inbound message over its rate limit
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct AgentRateLimited {
+    pub agent_id: String,
+}
+
+// Sent by a client actor when it drops an outbound message over its buffer size
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ClientMessageDropped {
+    pub client_id: Uuid,
+}
+
+// Sent by a client actor whenever a pong updates its average ping round-trip time
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct UpdateClientLatency {
+    pub client_id: Uuid,
+    pub rtt_ms: f64,
+}
+
+// Sent by a client actor when buffered messages outlive their TTL unsent
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct BufferedMessagesExpired {
+    pub client_id: Uuid,
+    pub count: u64,
+}
+
+// Sent by the agent route for every pre-shared key check, to be counted and
+// audited with the address it came from
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct AgentAuthAttempt {
+    pub ip: String,
+    // Why the agent was turned away; None when its key was accepted
+    pub failure: Option<&'static str>,
+}
+
 // Unchanged
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -229,46 +416,206 @@ pub struct SetRouter {
     pub router: Addr<RouterActor>,
 }
 
+// Sent to itself by the state manager when it finds the router stopped
+#[derive(Message)]
+#[rtype(result = "()")]
+struct RouterLost;
+
+// Lifetime counters saved to the metrics file, so totals keep growing across restarts
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct PersistedCounters {
+    total_messages: u64,
+    bytes_transferred: u64,
+}
+
+// Read a JSON file, treating a missing file as nothing saved yet
+fn load_json<T: DeserializeOwned>(path: &Path) -> std::io::Result<Option<T>> {
+    match std::fs::read(path) {
+        Ok(contents) => serde_json::from_slice(&contents)
+            .map(Some)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// Write to a sibling temp file and rename it over the old one, so a crash
+// mid-write never leaves a truncated file
+fn save_json<T: Serialize>(path: &Path, value: &T) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, serde_json::to_vec(value)?)?;
+    std::fs::rename(tmp_path, path)
+}
+
+// A buffered frame as written to the sessions file
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+enum PersistedFrame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+// A buffered frame and when it was buffered. Files written before buffer
+// times were kept load as if buffered on reload
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct PersistedBufferedFrame {
+    #[serde(flatten)]
+    frame: PersistedFrame,
+    #[serde(default = "Utc::now")]
+    buffered_at: DateTime<Utc>,
+}
+
+// A SessionState as written to the sessions file. Instants mean nothing to
+// the next process, so they are kept as wall-clock time
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct PersistedSession {
+    client_id: Uuid,
+    authenticated: bool,
+    wallet_address: Option<String>,
+    message_buffer: Vec<PersistedBufferedFrame>,
+    last_seen: DateTime<Utc>,
+    session_data: HashMap<String, String>,
+    #[serde(default)]
+    next_sequence: u64,
+    #[serde(default)]
+    resume_token: String,
+}
+
+// Wall-clock time of an instant in this process
+fn wall_clock(instant: Instant) -> DateTime<Utc> {
+    Utc::now() - chrono::Duration::from_std(instant.elapsed()).unwrap_or_default()
+}
+
+// Instant in this process for a wall-clock time, clamped to now
+fn instant_at(time: DateTime<Utc>) -> Instant {
+    let now = Instant::now();
+    (Utc::now() - time).to_std().ok()
+        .and_then(|ago| now.checked_sub(ago))
+        .unwrap_or(now)
+}
+
+impl From<&SessionState> for PersistedSession {
+    fn from(session: &SessionState) -> Self {
+        Self {
+            client_id: session.client_id,
+            authenticated: session.authenticated,
+            wallet_address: session.wallet_address.clone(),
+            message_buffer: session.message_buffer.iter().map(|(frame, buffered_at)| PersistedBufferedFrame {
+                frame: match frame {
+                    OutboundFrame::Text(text) => PersistedFrame::Text(text.clone()),
+                    OutboundFrame::Binary(data) => PersistedFrame::Binary(data.to_vec()),
+                },
+                buffered_at: wall_clock(*buffered_at),
+            }).collect(),
+            last_seen: wall_clock(session.last_seen),
+            session_data: session.session_data.clone(),
+            next_sequence: session.next_sequence,
+            resume_token: session.resume_token.clone(),
+        }
+    }
+}
+
+impl From<PersistedSession> for SessionState {
+    fn from(session: PersistedSession) -> Self {
+        Self {
+            client_id: session.client_id,
+            authenticated: session.authenticated,
+            wallet_address: session.wallet_address,
+            message_buffer: session.message_buffer.into_iter().map(|buffered| {
+                let frame = match buffered.frame {
+                    PersistedFrame::Text(text) => OutboundFrame::Text(text),
+                    PersistedFrame::Binary(data) => OutboundFrame::Binary(data.into()),
+                };
+                (frame, instant_at(buffered.buffered_at))
+            }).collect(),
+            last_seen: instant_at(session.last_seen),
+            session_data: session.session_data,
+            next_sequence: session.next_sequence,
+            resume_token: session.resume_token,
+        }
+    }
+}
+
 // Enhanced state manager actor
 pub struct StateManagerActor {
     clients: DashMap<Uuid, ClientData>,
     agents: DashMap<String, AgentData>,
     router: Option<Addr<RouterActor>>,
+    // Builds a replacement when the router turns out to have stopped; without
+    // one, a new router has to be sent with SetRouter
+    router_factory: Option<Box<dyn Fn() -> Addr<RouterActor>>>,
+    // Set when a send finds the router stopped, until it is replaced
+    router_lost: AtomicBool,
+    // Once the router has been replaced, connections registering afterwards
+    // may hold the original from the HTTP routes, so are told the current one
+    router_replaced: bool,
+    self_addr: Option<Addr<Self>>,
     // New fields for session persistence and metrics
     sessions: DashMap<Uuid, SessionState>,
+    // Saved state of agents that went away, when agent sessions are kept
+    agent_sessions: DashMap<String, AgentSessionState>,
+    persist_agent_sessions: bool,
     total_messages: u64,
     last_metrics_update: Instant,
     message_rate_window: Vec<(Instant, u64)>,
     bytes_transferred: u64,
+    agent_auth_successes: u64,
+    agent_auth_failures: u64,
+    // Where the lifetime counters are persisted, if anywhere
+    metrics_file: Option<PathBuf>,
+    // Where sessions are persisted across restarts, if anywhere
+    sessions_file: Option<PathBuf>,
     // Configuration
     client_timeout: Duration,
+    idle_threshold: Duration,
     agent_timeout: Duration,
     cleanup_interval: Duration,
     metrics_interval: Duration,
+    metrics_log_every: u32,
+    // Metrics intervals run so far, for sampling the summary log
+    metrics_updates: u64,
     max_reconnect_attempts: u32,
     session_ttl: Duration,
+    router_reconcile_interval: Duration,
 }
 
 impl StateManagerActor {
     pub fn new() -> Self {
+        Self::with_config(StateManagerConfig::default())
+    }
+    
+    pub fn with_config(config: StateManagerConfig) -> Self {
         Self {
             clients: DashMap::new(),
             agents: DashMap::new(),
             router: None,
+            router_factory: None,
+            router_lost: AtomicBool::new(false),
+            router_replaced: false,
+            self_addr: None,
             // Initialize new fields
             sessions: DashMap::new(),
+            agent_sessions: DashMap::new(),
+            persist_agent_sessions: config.persist_agent_sessions,
             total_messages: 0,
             last_metrics_update: Instant::now(),
             message_rate_window: Vec::new(),
             bytes_transferred: 0,
-            // Default configuration - unchanged
-            client_timeout: Duration::from_secs(60),   // 1 minute timeout
-            agent_timeout: Duration::from_secs(120),   // 2 minutes timeout
-            cleanup_interval: Duration::from_secs(30), // Check every 30 seconds
-            // New configuration values
-            metrics_interval: Duration::from_secs(5),   // Update metrics every 5 seconds
-            max_reconnect_attempts: 10,                // Max reconnection attempts
-            session_ttl: Duration::from_secs(3600),    // 1 hour session TTL
+            agent_auth_successes: 0,
+            agent_auth_failures: 0,
+            metrics_file: config.metrics_file.map(PathBuf::from),
+            sessions_file: config.sessions_file.map(PathBuf::from),
+            // Configuration
+            client_timeout: Duration::from_secs(config.client_timeout_secs),
+            idle_threshold: Duration::from_secs(config.idle_threshold_secs),
+            agent_timeout: Duration::from_secs(config.agent_timeout_secs),
+            cleanup_interval: Duration::from_secs(config.cleanup_interval_secs),
+            metrics_interval: Duration::from_secs(config.metrics_interval_secs),
+            metrics_log_every: config.metrics_log_every,
+            metrics_updates: 0,
+            max_reconnect_attempts: config.max_reconnect_attempts,
+            session_ttl: Duration::from_secs(config.session_ttl_secs),
+            router_reconcile_interval: Duration::from_secs(config.router_reconcile_interval_secs),
         }
     }
     
@@ -277,6 +624,70 @@ impl StateManagerActor {
         self.router = Some(router_addr);
     }
     
+    // Start a fresh router with `factory` whenever the current one is found stopped
+    pub fn with_router_factory(mut self, factory: impl Fn() -> Addr<RouterActor> + 'static) -> Self {
+        self.router_factory = Some(Box::new(factory));
+        self
+    }
+    
+    // Send to the router without waiting on a full mailbox. A stopped router
+    // drops the message; the replacement is re-announced every live
+    // connection, and agents re-send their hello and subscriptions to it
+    fn notify_router<M>(&self, msg: M)
+    where
+        M: Message + Send + 'static,
+        M::Result: Send,
+        RouterActor: Handler<M>,
+    {
+        let Some(router) = &self.router else { return };
+        match router.try_send(msg) {
+            Ok(()) => {},
+            Err(SendError::Full(msg)) => router.do_send(msg),
+            Err(SendError::Closed(_)) => {
+                if !self.router_lost.swap(true, Ordering::Relaxed) {
+                    tracing::error!("Router has stopped, requesting a replacement");
+                    if let Some(addr) = &self.self_addr {
+                        addr.do_send(RouterLost);
+                    }
+                }
+            },
+        }
+    }
+    
+    // Switch to `router`, registering every live client and agent with it.
+    // When replacing a router, the connections are told to use the new one too
+    fn link_router(&mut self, router: Addr<RouterActor>) {
+        // A replacement router starts empty, so hand it every live connection;
+        // disconnected entries were already unregistered from the old one
+        for entry in self.clients.iter().filter(|entry| entry.state != ConnectionState::Disconnected) {
+            router.do_send(super::router_actor::RegisterClient {
+                client_id: *entry.key(),
+                addr: entry.addr.clone(),
+                authenticated: entry.authenticated,
+                wallet_address: entry.wallet_address.clone(),
+            });
+        }
+        for entry in self.agents.iter().filter(|entry| entry.state != ConnectionState::Disconnected) {
+            router.do_send(super::router_actor::RegisterAgent {
+                agent_id: entry.key().clone(),
+                addr: entry.addr.clone(),
+            });
+        }
+
+        if self.router.is_some() {
+            for entry in self.clients.iter() {
+                entry.addr.do_send(SetRouter { router: router.clone() });
+            }
+            for entry in self.agents.iter() {
+                entry.addr.do_send(SetRouter { router: router.clone() });
+            }
+            self.router_replaced = true;
+            tracing::info!("Router replaced, re-announced live clients and agents");
+        }
+        self.router = Some(router);
+        self.router_lost.store(false, Ordering::Relaxed);
+    }
+    
     // Enhanced to also start metrics and session cleanup tasks
     fn start_monitoring_tasks(&self, ctx: &mut Context<Self>) {
         // Existing connection monitoring task
@@ -287,6 +698,12 @@ impl StateManagerActor {
         // New metrics collection task
         ctx.run_interval(self.metrics_interval, |act, _ctx| {
             act.update_metrics();
+            act.save_counters();
+        });
+        
+        // Evict router entries whose unregister was lost
+        ctx.run_interval(self.router_reconcile_interval, |act, _ctx| {
+            act.reconcile_router();
         });
         
         // New session cleanup task
@@ -299,6 +716,21 @@ impl StateManagerActor {
     fn monitor_connections(&self) {
         let now = Instant::now();
         
+        // Connected clients that have stopped sending messages, though their
+        // heartbeats still arrive, are idle until their next message
+        if !self.idle_threshold.is_zero() {
+            for mut entry in self.clients.iter_mut() {
+                let quiet_since = entry.last_message_at.unwrap_or(entry.connected_at);
+                if entry.state == ConnectionState::Connected
+                    && now.duration_since(entry.last_seen) <= self.client_timeout
+                    && now.duration_since(quiet_since) > self.idle_threshold
+                {
+                    tracing::debug!("Client {} is idle", entry.key());
+                    entry.state = ConnectionState::Idle;
+                }
+            }
+        }
+        
         // Monitor client connections (similar logic but added session saving)
         for entry in self.clients.iter() {
             let client_id = *entry.key();
@@ -306,19 +738,14 @@ impl StateManagerActor {
             
             // Check for timeout based on state
             match client_data.state {
-                ConnectionState::Connected => {
+                ConnectionState::Connected | ConnectionState::Idle => {
                     if now.duration_since(client_data.last_seen) > self.client_timeout {
                         tracing::warn!("Client timeout detected: {}", client_id);
                         
                         // Save minimal session state before marking as disconnected
-                        let session_state = SessionState {
-                            client_id,
-                            authenticated: client_data.authenticated,
-                            wallet_address: client_data.wallet_address.clone(),
-                            message_buffer: Vec::new(), // Can't access client message buffer from here
-                            last_seen: client_data.last_seen,
-                            session_data: HashMap::new(), // Initialize empty
-                        };
+                        let session_state = self.minimal_session(
+                            client_id, client_data.authenticated, client_data.wallet_address.clone(), client_data.last_seen,
+                        );
                         self.sessions.insert(client_id, session_state);
                         
                         if let Some(mut client) = self.clients.get_mut(&client_id) {
@@ -327,11 +754,9 @@ impl StateManagerActor {
                             client.disconnection_count += 1; // Update metrics
                             
                             // Notify router about disconnection
-                            if let Some(router) = &self.router {
-                                router.do_send(SystemMessage::ClientDisconnected { 
-                                    client_id 
-                                });
-                            }
+                            self.notify_router(SystemMessage::ClientDisconnected { 
+                                client_id 
+                            });
                         }
                     }
                 },
@@ -354,7 +779,6 @@ impl StateManagerActor {
                         self.clients.remove(&client_id);
                     }
                 },
-                _ => {}
             }
         }
         
@@ -369,15 +793,18 @@ impl StateManagerActor {
                     if now.duration_since(agent_data.last_seen) > self.agent_timeout {
                         tracing::warn!("Agent timeout detected: {}", agent_id);
                         
+                        // Its actor may outlive the connection, so ask it for its state
+                        if self.persist_agent_sessions {
+                            agent_data.addr.do_send(PersistAgentSession);
+                        }
+                        
                         if let Some(mut agent) = self.agents.get_mut(&agent_id) {
                             // Update state to disconnected
                             agent.state = ConnectionState::Disconnected;
                             agent.disconnection_count += 1; // Update metrics
                             
                             // Notify router about disconnection
-                            if let Some(router) = &self.router {
-                                router.do_send(SystemMessage::AgentDisconnected);
-                            }
+                            self.notify_router(SystemMessage::AgentDisconnected);
                         }
                     }
                 },
@@ -455,8 +882,9 @@ impl StateManagerActor {
             0.0
         };
         
-        // Log metrics summary
-        tracing::info!(
+        // Log metrics summary, at info only every `metrics_log_every` intervals
+        self.metrics_updates += 1;
+        let summary = format!(
             "System metrics - Clients: {}, Agents: {}, Messages: {}, Rate: {:.2} msg/s, Bandwidth: {} bytes",
             self.clients.len(),
             self.agents.len(),
@@ -464,6 +892,106 @@ impl StateManagerActor {
             messages_per_second,
             self.bytes_transferred
         );
+        if self.metrics_log_every > 0 && self.metrics_updates.is_multiple_of(u64::from(self.metrics_log_every)) {
+            tracing::info!("{}", summary);
+        } else {
+            tracing::debug!("{}", summary);
+        }
+    }
+    
+    // Resume the lifetime counters from the metrics file, if configured
+    fn load_counters(&mut self) {
+        let Some(path) = &self.metrics_file else { return };
+        match load_json::<PersistedCounters>(path) {
+            Ok(Some(counters)) => {
+                tracing::info!(
+                    "Restored metrics counters from {}: {} messages, {} bytes",
+                    path.display(), counters.total_messages, counters.bytes_transferred
+                );
+                self.total_messages = counters.total_messages;
+                self.bytes_transferred = counters.bytes_transferred;
+            },
+            Ok(None) => tracing::info!("No metrics file at {} yet, counters start at zero", path.display()),
+            Err(e) => tracing::warn!("Ignoring unreadable metrics file {}: {}", path.display(), e),
+        }
+    }
+    
+    // Save the lifetime counters to the metrics file, if configured
+    fn save_counters(&self) {
+        let Some(path) = &self.metrics_file else { return };
+        let counters = PersistedCounters {
+            total_messages: self.total_messages,
+            bytes_transferred: self.bytes_transferred,
+        };
+        if let Err(e) = save_json(path, &counters) {
+            tracing::warn!("Failed to save metrics counters to {}: {}", path.display(), e);
+        }
+    }
+    
+    // Restore sessions saved by the previous process, if configured
+    fn load_sessions(&mut self) {
+        let Some(path) = &self.sessions_file else { return };
+        match load_json::<Vec<PersistedSession>>(path) {
+            Ok(Some(sessions)) => {
+                tracing::info!("Restored {} sessions from {}", sessions.len(), path.display());
+                for session in sessions {
+                    self.sessions.insert(session.client_id, session.into());
+                }
+            },
+            Ok(None) => tracing::info!("No sessions file at {} yet", path.display()),
+            Err(e) => tracing::warn!("Ignoring unreadable sessions file {}: {}", path.display(), e),
+        }
+    }
+    
+    // Write every session to the sessions file, returning how many were written
+    fn save_sessions(&self) -> usize {
+        let Some(path) = &self.sessions_file else { return 0 };
+        let sessions: Vec<PersistedSession> = self.sessions.iter()
+            .map(|entry| PersistedSession::from(entry.value()))
+            .collect();
+        match save_json(path, &sessions) {
+            Ok(()) => {
+                tracing::info!("Saved {} sessions to {}", sessions.len(), path.display());
+                sessions.len()
+            },
+            Err(e) => {
+                tracing::error!("Failed to save sessions to {}: {}", path.display(), e);
+                0
+            }
+        }
+    }
+    
+    // Session state for a client whose actor can't be asked for its buffer or
+    // data, keeping the sequence and resume token of any session it saved
+    fn minimal_session(&self, client_id: Uuid, authenticated: bool, wallet_address: Option<String>, last_seen: Instant) -> SessionState {
+        let saved = self.sessions.get(&client_id);
+        SessionState {
+            client_id,
+            authenticated,
+            wallet_address,
+            message_buffer: Vec::new(),
+            last_seen,
+            session_data: HashMap::new(),
+            next_sequence: saved.as_ref().map_or(0, |session| session.next_sequence),
+            resume_token: saved.map(|session| session.resume_token.clone()).unwrap_or_default(),
+        }
+    }
+    
+    // Push the live clients and agents to the router so it can drop entries
+    // whose unregister never arrived (e.g. dropped on a full mailbox)
+    fn reconcile_router(&self) {
+        if self.router.is_none() {
+            return;
+        }
+        let clients: HashSet<Uuid> = self.clients.iter()
+            .filter(|entry| entry.state != ConnectionState::Disconnected)
+            .map(|entry| *entry.key())
+            .collect();
+        let agents: HashSet<String> = self.agents.iter()
+            .filter(|entry| entry.state != ConnectionState::Disconnected)
+            .map(|entry| entry.key().clone())
+            .collect();
+        self.notify_router(super::router_actor::ReconcileRegistrations { clients, agents });
     }
     
     // New: Clean up expired sessions
@@ -488,6 +1016,14 @@ impl StateManagerActor {
             tracing::info!("Cleaned up {} expired sessions, remaining: {}", 
                          expired_count, self.sessions.len());
         }
+        
+        self.agent_sessions.retain(|agent_id, session| {
+            let live = now.duration_since(session.last_seen) <= self.session_ttl;
+            if !live {
+                tracing::info!("Removing expired session for agent: {}", agent_id);
+            }
+            live
+        });
     }
 }
 
@@ -496,6 +1032,10 @@ impl Actor for StateManagerActor {
     
     fn started(&mut self, ctx: &mut Self::Context) {
         tracing::info!("StateManagerActor started with session persistence and metrics");
+        self.self_addr = Some(ctx.address());
+        
+        self.load_counters();
+        self.load_sessions();
         
         // Start monitoring tasks (including new ones)
         self.start_monitoring_tasks(ctx);
@@ -510,6 +1050,7 @@ impl Actor for StateManagerActor {
     }
     
     fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.save_counters();
         tracing::info!(
             "StateManagerActor stopped - Final metrics: Clients: {}, Agents: {}, Messages: {}, Bandwidth: {} bytes",
             self.clients.len(),
@@ -533,6 +1074,7 @@ impl Handler<RegisterClient> for StateManagerActor {
         if let Some(mut entry) = self.clients.get_mut(&msg.client_id) {
             // Update existing client entry
             entry.addr = msg.addr.clone();
+            entry.connection_id = None; // Reported by the new connection once started
             entry.state = ConnectionState::Connected;
             entry.last_seen = now;
             entry.authenticated = msg.authenticated;
@@ -544,6 +1086,7 @@ impl Handler<RegisterClient> for StateManagerActor {
             // Create new client entry with metrics initialized to zero
             let client_data = ClientData {
                 addr: msg.addr.clone(),
+                connection_id: None,
                 state: ConnectionState::Connected,
                 last_seen: now,
                 connected_at: now,
@@ -557,6 +1100,9 @@ impl Handler<RegisterClient> for StateManagerActor {
                 bytes_sent: 0,
                 bytes_received: 0,
                 disconnection_count: 0,
+                dropped_messages: 0,
+                expired_buffered: 0,
+                last_rtt_ms: None,
             };
             
             self.clients.insert(msg.client_id, client_data);
@@ -576,20 +1122,26 @@ impl Handler<RegisterClient> for StateManagerActor {
             // msg.addr.do_send(session.clone());
         }
         
-        // Notify router about client connection
-        if let Some(router) = &self.router {
-            router.do_send(SystemMessage::ClientConnected {
-                client_id: msg.client_id,
-                authenticated: msg.authenticated,
-                wallet_address: msg.wallet_address.clone(),
-            });
-            
-            // Register with router
-            router.do_send(super::router_actor::RegisterClient {
-                client_id: msg.client_id,
-                addr: msg.addr,
-            });
+        if self.router_replaced {
+            if let Some(router) = &self.router {
+                msg.addr.do_send(SetRouter { router: router.clone() });
+            }
         }
+        
+        // Notify router about client connection
+        self.notify_router(SystemMessage::ClientConnected {
+            client_id: msg.client_id,
+            authenticated: msg.authenticated,
+            wallet_address: msg.wallet_address.clone(),
+        });
+        
+        // Register with router
+        self.notify_router(super::router_actor::RegisterClient {
+            client_id: msg.client_id,
+            addr: msg.addr,
+            authenticated: msg.authenticated,
+            wallet_address: msg.wallet_address,
+        });
     }
 }
 
@@ -603,6 +1155,7 @@ impl Handler<RegisterAgent> for StateManagerActor {
         if let Some(mut entry) = self.agents.get_mut(&msg.agent_id) {
             // Update existing agent entry
             entry.addr = msg.addr.clone();
+            entry.connection_id = None; // Reported by the new connection once started
             entry.state = ConnectionState::Connected;
             entry.last_seen = now;
             entry.reconnect_attempts = 0; // Reset reconnect attempts on successful reconnection
@@ -612,6 +1165,7 @@ impl Handler<RegisterAgent> for StateManagerActor {
             // Create new agent entry with metrics initialized to zero
             let agent_data = AgentData {
                 addr: msg.addr.clone(),
+                connection_id: None,
                 state: ConnectionState::Connected,
                 last_seen: now,
                 connected_at: now,
@@ -623,21 +1177,33 @@ impl Handler<RegisterAgent> for StateManagerActor {
                 bytes_sent: 0,
                 bytes_received: 0,
                 disconnection_count: 0,
+                messages_rate_limited: 0,
             };
             
             self.agents.insert(msg.agent_id.clone(), agent_data);
             tracing::info!("Agent registered: {}", msg.agent_id);
         }
         
+        if self.router_replaced {
+            if let Some(router) = &self.router {
+                msg.addr.do_send(SetRouter { router: router.clone() });
+            }
+        }
+        
         // Notify router about agent connection
-        if let Some(router) = &self.router {
-            router.do_send(SystemMessage::AgentConnected);
-            
-            // Register with router
-            router.do_send(super::router_actor::RegisterAgent {
-                agent_id: msg.agent_id,
-                addr: msg.addr,
-            });
+        self.notify_router(SystemMessage::AgentConnected);
+        
+        // Register with router
+        self.notify_router(super::router_actor::RegisterAgent {
+            agent_id: msg.agent_id.clone(),
+            addr: msg.addr.clone(),
+        });
+        
+        // Hand back what the agent had before it went away. Sent after the
+        // router registration, so its resubscriptions find it registered
+        if let Some((_, session)) = self.agent_sessions.remove(&msg.agent_id) {
+            tracing::info!("Restoring session state for agent: {}", msg.agent_id);
+            msg.addr.do_send(session);
         }
     }
 }
@@ -649,14 +1215,9 @@ impl Handler<UnregisterClient> for StateManagerActor {
         // Try to save session state if client exists
         if let Some(client) = self.clients.get(&msg.client_id) {
             // Create minimal session state
-            let session_state = SessionState {
-                client_id: msg.client_id,
-                authenticated: client.authenticated,
-                wallet_address: client.wallet_address.clone(),
-                message_buffer: Vec::new(), // Can't access client's buffer from here
-                last_seen: client.last_seen,
-                session_data: HashMap::new(), // Initialize empty
-            };
+            let session_state = self.minimal_session(
+                msg.client_id, client.authenticated, client.wallet_address.clone(), client.last_seen,
+            );
             
             // Save session state
             self.sessions.insert(msg.client_id, session_state);
@@ -673,16 +1234,14 @@ impl Handler<UnregisterClient> for StateManagerActor {
         }
         
         // Notify router about client disconnection
-        if let Some(router) = &self.router {
-            router.do_send(SystemMessage::ClientDisconnected {
-                client_id: msg.client_id,
-            });
-            
-            // Unregister from router
-            router.do_send(super::router_actor::UnregisterClient {
-                client_id: msg.client_id,
-            });
-        }
+        self.notify_router(SystemMessage::ClientDisconnected {
+            client_id: msg.client_id,
+        });
+        
+        // Unregister from router
+        self.notify_router(super::router_actor::UnregisterClient {
+            client_id: msg.client_id,
+        });
     }
 }
 
@@ -700,14 +1259,50 @@ impl Handler<UnregisterAgent> for StateManagerActor {
         }
         
         // Notify router about agent disconnection
-        if let Some(router) = &self.router {
-            router.do_send(SystemMessage::AgentDisconnected);
-            
-            // Unregister from router
-            router.do_send(super::router_actor::UnregisterAgent {
-                agent_id: msg.agent_id,
-            });
-        }
+        self.notify_router(SystemMessage::AgentDisconnected);
+        
+        // Unregister from router
+        self.notify_router(super::router_actor::UnregisterAgent {
+            agent_id: msg.agent_id,
+        });
+    }
+}
+
+impl Handler<ForceDisconnectClient> for StateManagerActor {
+    type Result = bool;
+    
+    fn handle(&mut self, msg: ForceDisconnectClient, _ctx: &mut Self::Context) -> Self::Result {
+        let Some((_, client)) = self.clients.remove(&msg.client_id) else {
+            return false;
+        };
+        tracing::warn!("Force-disconnecting client {}: {}", msg.client_id, msg.reason);
+        client.addr.do_send(Shutdown { reason: msg.reason });
+        
+        self.notify_router(SystemMessage::ClientDisconnected {
+            client_id: msg.client_id,
+        });
+        self.notify_router(super::router_actor::UnregisterClient {
+            client_id: msg.client_id,
+        });
+        true
+    }
+}
+
+impl Handler<ForceDisconnectAgent> for StateManagerActor {
+    type Result = bool;
+    
+    fn handle(&mut self, msg: ForceDisconnectAgent, _ctx: &mut Self::Context) -> Self::Result {
+        let Some((_, agent)) = self.agents.remove(&msg.agent_id) else {
+            return false;
+        };
+        tracing::warn!("Force-disconnecting agent {}: {}", msg.agent_id, msg.reason);
+        agent.addr.do_send(Shutdown { reason: msg.reason });
+        
+        self.notify_router(SystemMessage::AgentDisconnected);
+        self.notify_router(super::router_actor::UnregisterAgent {
+            agent_id: msg.agent_id,
+        });
+        true
     }
 }
 
@@ -789,6 +1384,94 @@ impl Handler<UpdateAgentState> for StateManagerActor {
     }
 }
 
+impl Handler<UpdateClientConnection> for StateManagerActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: UpdateClientConnection, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(mut entry) = self.clients.get_mut(&msg.client_id) {
+            tracing::debug!(
+                connection_id = %msg.connection_id,
+                "Client {} connection id updated (previous: {:?})",
+                msg.client_id, entry.connection_id
+            );
+            entry.connection_id = Some(msg.connection_id);
+        }
+    }
+}
+
+impl Handler<UpdateAgentConnection> for StateManagerActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: UpdateAgentConnection, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(mut entry) = self.agents.get_mut(&msg.agent_id) {
+            tracing::debug!(
+                connection_id = %msg.connection_id,
+                "Agent {} connection id updated (previous: {:?})",
+                msg.agent_id, entry.connection_id
+            );
+            entry.connection_id = Some(msg.connection_id);
+        }
+    }
+}
+
+impl Handler<AgentRateLimited> for StateManagerActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: AgentRateLimited, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(mut entry) = self.agents.get_mut(&msg.agent_id) {
+            entry.messages_rate_limited += 1;
+        }
+    }
+}
+
+impl Handler<ClientMessageDropped> for StateManagerActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: ClientMessageDropped, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(mut entry) = self.clients.get_mut(&msg.client_id) {
+            entry.dropped_messages += 1;
+        }
+    }
+}
+
+impl Handler<UpdateClientLatency> for StateManagerActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: UpdateClientLatency, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(mut entry) = self.clients.get_mut(&msg.client_id) {
+            entry.last_rtt_ms = Some(msg.rtt_ms);
+        }
+    }
+}
+
+impl Handler<AgentAuthAttempt> for StateManagerActor {
+    type Result = ();
+    
+    // Audited under the `audit` target, as the web-server does its security events
+    fn handle(&mut self, msg: AgentAuthAttempt, _ctx: &mut Self::Context) -> Self::Result {
+        match msg.failure {
+            None => {
+                self.agent_auth_successes += 1;
+                tracing::info!(target: "audit", event = "agent_auth_success", ip = %msg.ip, "Agent authenticated");
+            },
+            Some(reason) => {
+                self.agent_auth_failures += 1;
+                tracing::warn!(target: "audit", event = "agent_auth_failure", ip = %msg.ip, "Agent authentication failed: {}", reason);
+            },
+        }
+    }
+}
+
+impl Handler<BufferedMessagesExpired> for StateManagerActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: BufferedMessagesExpired, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(mut entry) = self.clients.get_mut(&msg.client_id) {
+            entry.expired_buffered += msg.count;
+        }
+    }
+}
+
 impl Handler<ClientActivity> for StateManagerActor {
     type Result = ();
     
@@ -798,13 +1481,14 @@ impl Handler<ClientActivity> for StateManagerActor {
             entry.last_seen = Instant::now();
             
             // Update last message timestamp if this is a message activity
+            // (counted by UpdateClientMessageMetrics, which also has its size)
             if msg.is_message {
                 entry.last_message_at = Some(Instant::now());
                 
-                // Update message count for metrics
-                if msg.is_message {
-                    entry.message_count_received += 1;
-                    self.total_messages += 1;
+                // A message ends an idle spell; heartbeats alone don't
+                if entry.state == ConnectionState::Idle {
+                    entry.state = ConnectionState::Connected;
+                    tracing::debug!("Client {} is active again", msg.client_id);
                 }
             }
             
@@ -817,13 +1501,11 @@ impl Handler<ClientActivity> for StateManagerActor {
                 tracing::info!("Client {} reconnected through activity", msg.client_id);
                 
                 // Notify router about reconnection
-                if let Some(router) = &self.router {
-                    router.do_send(SystemMessage::ClientConnected {
-                        client_id: msg.client_id,
-                        authenticated: entry.authenticated,
-                        wallet_address: entry.wallet_address.clone(),
-                    });
-                }
+                self.notify_router(SystemMessage::ClientConnected {
+                    client_id: msg.client_id,
+                    authenticated: entry.authenticated,
+                    wallet_address: entry.wallet_address.clone(),
+                });
             }
         }
     }
@@ -838,14 +1520,11 @@ impl Handler<AgentActivity> for StateManagerActor {
             entry.last_seen = Instant::now();
             
             // Update last message timestamp if this is a message activity
+            // and count it; agents send no separate message metrics
             if msg.is_message {
                 entry.last_message_at = Some(Instant::now());
-                
-                // Update message count for metrics
-                if msg.is_message {
-                    entry.message_count_received += 1;
-                    self.total_messages += 1;
-                }
+                entry.message_count_received += 1;
+                self.total_messages += 1;
             }
             
             // If disconnected or reconnecting, update state to connected
@@ -857,38 +1536,66 @@ impl Handler<AgentActivity> for StateManagerActor {
                 tracing::info!("Agent {} reconnected through activity", msg.agent_id);
                 
                 // Notify router about reconnection
-                if let Some(router) = &self.router {
-                    router.do_send(SystemMessage::AgentConnected);
-                }
+                self.notify_router(SystemMessage::AgentConnected);
             }
         }
     }
 }
 
+impl ClientData {
+    // Snapshot of this client's status and metrics
+    fn status(&self, client_id: Uuid) -> ClientStatusResponse {
+        let now = Instant::now();
+        
+        ClientStatusResponse {
+            client_id,
+            connection_id: self.connection_id,
+            state: self.state,
+            connected_duration: now.duration_since(self.connected_at),
+            last_seen_ago: now.duration_since(self.last_seen),
+            authenticated: self.authenticated,
+            reconnect_attempts: self.reconnect_attempts,
+            // Include metrics in response
+            message_count_sent: self.message_count_sent,
+            message_count_received: self.message_count_received,
+            bytes_sent: self.bytes_sent,
+            bytes_received: self.bytes_received,
+            disconnection_count: self.disconnection_count,
+            dropped_messages: self.dropped_messages,
+            expired_buffered: self.expired_buffered,
+            last_rtt_ms: self.last_rtt_ms,
+        }
+    }
+}
+
+impl AgentData {
+    // Snapshot of this agent's status and metrics
+    fn status(&self, agent_id: String) -> AgentStatusResponse {
+        let now = Instant::now();
+        
+        AgentStatusResponse {
+            agent_id,
+            connection_id: self.connection_id,
+            state: self.state,
+            connected_duration: now.duration_since(self.connected_at),
+            last_seen_ago: now.duration_since(self.last_seen),
+            reconnect_attempts: self.reconnect_attempts,
+            // Include metrics in response
+            message_count_sent: self.message_count_sent,
+            message_count_received: self.message_count_received,
+            bytes_sent: self.bytes_sent,
+            bytes_received: self.bytes_received,
+            disconnection_count: self.disconnection_count,
+            messages_rate_limited: self.messages_rate_limited,
+        }
+    }
+}
+
 impl Handler<GetClientStatus> for StateManagerActor {
     type Result = Option<ClientStatusResponse>;
     
     fn handle(&mut self, msg: GetClientStatus, _ctx: &mut Self::Context) -> Self::Result {
-        if let Some(entry) = self.clients.get(&msg.client_id) {
-            let now = Instant::now();
-            
-            Some(ClientStatusResponse {
-                client_id: msg.client_id,
-                state: entry.state,
-                connected_duration: now.duration_since(entry.connected_at),
-                last_seen_ago: now.duration_since(entry.last_seen),
-                authenticated: entry.authenticated,
-                reconnect_attempts: entry.reconnect_attempts,
-                // Include metrics in response
-                message_count_sent: entry.message_count_sent,
-                message_count_received: entry.message_count_received,
-                bytes_sent: entry.bytes_sent,
-                bytes_received: entry.bytes_received,
-                disconnection_count: entry.disconnection_count,
-            })
-        } else {
-            None
-        }
+        self.clients.get(&msg.client_id).map(|entry| entry.status(msg.client_id))
     }
 }
 
@@ -896,25 +1603,31 @@ impl Handler<GetAgentStatus> for StateManagerActor {
     type Result = Option<AgentStatusResponse>;
     
     fn handle(&mut self, msg: GetAgentStatus, _ctx: &mut Self::Context) -> Self::Result {
-        if let Some(entry) = self.agents.get(&msg.agent_id) {
-            let now = Instant::now();
-            
-            Some(AgentStatusResponse {
-                agent_id: msg.agent_id.clone(),
-                state: entry.state,
-                connected_duration: now.duration_since(entry.connected_at),
-                last_seen_ago: now.duration_since(entry.last_seen),
-                reconnect_attempts: entry.reconnect_attempts,
-                // Include metrics in response
-                message_count_sent: entry.message_count_sent,
-                message_count_received: entry.message_count_received,
-                bytes_sent: entry.bytes_sent,
-                bytes_received: entry.bytes_received,
-                disconnection_count: entry.disconnection_count,
-            })
-        } else {
-            None
-        }
+        self.agents.get(&msg.agent_id).map(|entry| entry.status(msg.agent_id.clone()))
+    }
+}
+
+impl Handler<GetAllClients> for StateManagerActor {
+    type Result = actix::MessageResult<GetAllClients>;
+    
+    fn handle(&mut self, msg: GetAllClients, _ctx: &mut Self::Context) -> Self::Result {
+        let clients = self.clients.iter()
+            .filter(|entry| msg.state_filter.is_none_or(|state| entry.value().state == state))
+            .map(|entry| entry.value().status(*entry.key()))
+            .collect();
+        actix::MessageResult(clients)
+    }
+}
+
+impl Handler<GetAllAgents> for StateManagerActor {
+    type Result = actix::MessageResult<GetAllAgents>;
+    
+    fn handle(&mut self, msg: GetAllAgents, _ctx: &mut Self::Context) -> Self::Result {
+        let agents = self.agents.iter()
+            .filter(|entry| msg.state_filter.is_none_or(|state| entry.value().state == state))
+            .map(|entry| entry.value().status(entry.key().clone()))
+            .collect();
+        actix::MessageResult(agents)
     }
 }
 
@@ -922,7 +1635,28 @@ impl Handler<SetRouter> for StateManagerActor {
     type Result = ();
     
     fn handle(&mut self, msg: SetRouter, _ctx: &mut Self::Context) -> Self::Result {
-        self.router = Some(msg.router);
+        if self.router.as_ref() == Some(&msg.router) {
+            tracing::debug!("Ignoring SetRouter for the current router");
+            return;
+        }
+        self.link_router(msg.router);
+    }
+}
+
+impl Handler<RouterLost> for StateManagerActor {
+    type Result = ();
+    
+    fn handle(&mut self, _msg: RouterLost, _ctx: &mut Self::Context) -> Self::Result {
+        if !self.router_lost.load(Ordering::Relaxed) {
+            return;
+        }
+        match &self.router_factory {
+            Some(factory) => {
+                let router = factory();
+                self.link_router(router);
+            },
+            None => tracing::error!("Router has stopped and no replacement is configured; waiting for SetRouter"),
+        }
     }
 }
 
@@ -936,6 +1670,40 @@ impl Handler<SaveSessionState> for StateManagerActor {
     }
 }
 
+impl Handler<SaveAgentSessionState> for StateManagerActor {
+    type Result = ();
+    
+    fn handle(&mut self, msg: SaveAgentSessionState, _ctx: &mut Self::Context) -> Self::Result {
+        if !self.persist_agent_sessions {
+            return;
+        }
+        tracing::info!("Saving session state for agent: {}", msg.state.agent_id);
+        self.agent_sessions.insert(msg.state.agent_id.clone(), msg.state);
+    }
+}
+
+// Collect connected clients' sessions and persist everything ahead of shutdown
+impl Handler<DrainAndPersist> for StateManagerActor {
+    type Result = ResponseActFuture<Self, usize>;
+    
+    fn handle(&mut self, _msg: DrainAndPersist, _ctx: &mut Self::Context) -> Self::Result {
+        let snapshots: Vec<_> = self.clients.iter()
+            .filter(|entry| entry.state != ConnectionState::Disconnected)
+            .map(|entry| entry.addr.send(SnapshotSession))
+            .collect();
+        tracing::info!("Draining {} connected client sessions", snapshots.len());
+        
+        Box::pin(actix::fut::wrap_future(futures::future::join_all(snapshots))
+            .map(|snapshots, act: &mut Self, _ctx| {
+                // Clients that already went away saved their state on the way out
+                for session in snapshots.into_iter().flatten() {
+                    act.sessions.insert(session.client_id, session);
+                }
+                act.save_sessions()
+            }))
+    }
+}
+
 // New: Handle getting session state
 impl Handler<GetSessionState> for StateManagerActor {
     type Result = Option<SessionState>;
@@ -989,7 +1757,9 @@ impl Handler<GetSystemMetrics> for StateManagerActor {
             total_messages_processed: self.total_messages,
             messages_per_second,
             bytes_transferred: self.bytes_transferred,
-            timestamp: std::time::SystemTime::now(),
+            agent_auth_successes: self.agent_auth_successes,
+            agent_auth_failures: self.agent_auth_failures,
+            timestamp: SystemTime::now(),
         };
         actix::MessageResult(result)
     }
@@ -1053,4 +1823,552 @@ impl Handler<UpdateAgentMessageMetrics> for StateManagerActor {
             self.total_messages += 1;
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::test_support::start_ws_actor;
+    
+    // Start a client connection and wait until the state manager knows its connection id
+    async fn connect_client(state_manager: &Addr<StateManagerActor>, client_id: Uuid) -> Uuid {
+        let mut client = ClientSessionActor::new(client_id);
+        client.set_state_manager(state_manager.clone());
+        let (addr, _frames) = start_ws_actor(client);
+        
+        state_manager.do_send(RegisterClient {
+            client_id,
+            addr,
+            authenticated: false,
+            wallet_address: None,
+        });
+        
+        for _ in 0..50 {
+            let status = state_manager.send(GetClientStatus { client_id }).await.unwrap();
+            if let Some(connection_id) = status.and_then(|s| s.connection_id) {
+                return connection_id;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("connection id was never reported for client {}", client_id);
+    }
+    
+    #[test]
+    fn test_with_config_applies_custom_durations() {
+        let actor = StateManagerActor::with_config(StateManagerConfig {
+            client_timeout_secs: 10,
+            idle_threshold_secs: 120,
+            agent_timeout_secs: 20,
+            cleanup_interval_secs: 3,
+            metrics_interval_secs: 1,
+            metrics_log_every: 3,
+            max_reconnect_attempts: 4,
+            session_ttl_secs: 600,
+            client_buffer_size: 100,
+            buffer_flush_rate: 100,
+            buffered_message_ttl_secs: 300,
+            metrics_file: None,
+            router_reconcile_interval_secs: 15,
+            sessions_file: None,
+            max_concurrent_restores: 8,
+            persist_agent_sessions: true,
+        });
+        
+        assert_eq!(actor.client_timeout, Duration::from_secs(10));
+        assert_eq!(actor.idle_threshold, Duration::from_secs(120));
+        assert_eq!(actor.agent_timeout, Duration::from_secs(20));
+        assert_eq!(actor.cleanup_interval, Duration::from_secs(3));
+        assert_eq!(actor.metrics_interval, Duration::from_secs(1));
+        assert_eq!(actor.metrics_log_every, 3);
+        assert_eq!(actor.max_reconnect_attempts, 4);
+        assert_eq!(actor.session_ttl, Duration::from_secs(600));
+        assert_eq!(actor.router_reconcile_interval, Duration::from_secs(15));
+        
+        // The defaults are unchanged
+        let actor = StateManagerActor::new();
+        assert_eq!(actor.client_timeout, Duration::from_secs(60));
+        assert_eq!(actor.agent_timeout, Duration::from_secs(120));
+        assert_eq!(actor.cleanup_interval, Duration::from_secs(30));
+        assert_eq!(actor.metrics_interval, Duration::from_secs(5));
+        assert_eq!(actor.max_reconnect_attempts, 10);
+        assert_eq!(actor.session_ttl, Duration::from_secs(3600));
+        assert_eq!(actor.router_reconcile_interval, Duration::from_secs(60));
+    }
+    
+    #[test]
+    fn test_metrics_summary_logged_every_nth_interval() {
+        let summaries = |metrics_log_every: u32| {
+            let log = common::test_support::CapturedLog::default();
+            let writer = log.clone();
+            let (subscriber, _) = common::reloadable_subscriber(
+                tracing::level_filters::LevelFilter::INFO,
+                move || writer.clone(),
+            );
+            let mut actor = StateManagerActor::with_config(StateManagerConfig {
+                metrics_log_every,
+                ..StateManagerConfig::default()
+            });
+            tracing::subscriber::with_default(subscriber, || {
+                for _ in 0..7 {
+                    actor.update_metrics();
+                }
+            });
+            log.contents().matches("System metrics").count()
+        };
+        
+        // The 3rd and 6th of seven intervals
+        assert_eq!(summaries(3), 2);
+        assert_eq!(summaries(1), 7);
+        assert_eq!(summaries(0), 0);
+    }
+    
+    #[actix::test]
+    async fn test_counters_continue_after_restart_from_metrics_file() {
+        let path = std::env::temp_dir().join(format!("state-manager-metrics-{}.json", Uuid::new_v4()));
+        let config = StateManagerConfig {
+            metrics_file: Some(path.display().to_string()),
+            ..StateManagerConfig::default()
+        };
+        let start = || {
+            let mut actor = StateManagerActor::with_config(config.clone());
+            actor.metrics_interval = Duration::from_millis(20);
+            actor.start()
+        };
+        let record = |state_manager: Addr<StateManagerActor>, client_id: Uuid| async move {
+            for _ in 0..3 {
+                state_manager.send(UpdateClientMessageMetrics { client_id, sent: true, bytes: Some(10) }).await.unwrap();
+            }
+        };
+        
+        let first = start();
+        let client_id = Uuid::new_v4();
+        connect_client(&first, client_id).await;
+        record(first.clone(), client_id).await;
+        let before = first.send(GetSystemMetrics).await.unwrap();
+        assert!(before.total_messages_processed >= 3);
+        // Give the metrics interval a chance to flush
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        
+        let restarted = start();
+        let resumed = restarted.send(GetSystemMetrics).await.unwrap();
+        assert_eq!(resumed.total_messages_processed, before.total_messages_processed);
+        assert_eq!(resumed.bytes_transferred, before.bytes_transferred);
+        
+        let client_id = Uuid::new_v4();
+        connect_client(&restarted, client_id).await;
+        record(restarted.clone(), client_id).await;
+        let after = restarted.send(GetSystemMetrics).await.unwrap();
+        assert!(after.total_messages_processed >= before.total_messages_processed + 3);
+        assert_eq!(after.bytes_transferred, before.bytes_transferred + 30);
+        
+        let _ = std::fs::remove_file(path);
+    }
+    
+    #[actix::test]
+    async fn test_sessions_reload_from_sessions_file_after_restart() {
+        let path = std::env::temp_dir().join(format!("state-manager-sessions-{}.json", Uuid::new_v4()));
+        let config = StateManagerConfig {
+            sessions_file: Some(path.display().to_string()),
+            ..StateManagerConfig::default()
+        };
+        let saved = vec![
+            SessionState {
+                client_id: Uuid::new_v4(),
+                authenticated: false,
+                wallet_address: None,
+                message_buffer: vec![
+                    (OutboundFrame::from("buffered-text".to_string()), Instant::now() - Duration::from_secs(10)),
+                    (OutboundFrame::Binary(actix_web::web::Bytes::from_static(b"\x00\xffbinary")), Instant::now()),
+                ],
+                last_seen: Instant::now() - Duration::from_secs(30),
+                session_data: HashMap::from([("theme".to_string(), "dark".to_string())]),
+                next_sequence: 7,
+                resume_token: "resume-token".to_string(),
+            },
+            SessionState {
+                client_id: Uuid::new_v4(),
+                authenticated: true,
+                wallet_address: Some("0xabc".to_string()),
+                message_buffer: Vec::new(),
+                last_seen: Instant::now(),
+                session_data: HashMap::new(),
+                next_sequence: 0,
+                resume_token: String::new(),
+            },
+        ];
+        
+        let first = StateManagerActor::with_config(config.clone()).start();
+        for state in &saved {
+            first.send(SaveSessionState { state: state.clone() }).await.unwrap();
+        }
+        // A connected client is asked for its session as part of the drain
+        let connected_id = Uuid::new_v4();
+        connect_client(&first, connected_id).await;
+        assert_eq!(first.send(DrainAndPersist).await.unwrap(), 3);
+        
+        // Instants are rebuilt from wall-clock time, so allow for drift
+        fn assert_close(restored: &mut Instant, saved: Instant) {
+            let drift = (*restored).max(saved) - (*restored).min(saved);
+            assert!(drift < Duration::from_secs(1), "instant drifted by {:?}", drift);
+            *restored = saved;
+        }
+        
+        let restarted = StateManagerActor::with_config(config).start();
+        for state in saved {
+            let mut restored = restarted.send(GetSessionState { client_id: state.client_id }).await.unwrap().unwrap();
+            assert_close(&mut restored.last_seen, state.last_seen);
+            assert_eq!(restored.message_buffer.len(), state.message_buffer.len());
+            for ((_, restored_at), (_, saved_at)) in restored.message_buffer.iter_mut().zip(&state.message_buffer) {
+                assert_close(restored_at, *saved_at);
+            }
+            assert_eq!(restored, state);
+        }
+        assert!(restarted.send(GetSessionState { client_id: connected_id }).await.unwrap().is_some());
+        
+        let _ = std::fs::remove_file(path);
+    }
+    
+    #[actix::test]
+    async fn test_reconnects_get_distinct_connection_ids() {
+        let state_manager = StateManagerActor::new().start();
+        let client_id = Uuid::new_v4();
+        
+        let first = connect_client(&state_manager, client_id).await;
+        let second = connect_client(&state_manager, client_id).await;
+        
+        assert_ne!(first, Uuid::nil());
+        assert_ne!(first, second);
+    }
+    
+    #[actix::test]
+    async fn test_get_all_clients_lists_registered_clients() {
+        let state_manager = StateManagerActor::new().start();
+        let client_ids = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        for client_id in client_ids {
+            connect_client(&state_manager, client_id).await;
+        }
+        
+        // One client drops to reconnecting and is excluded by the filter
+        state_manager.send(UpdateClientState {
+            client_id: client_ids[2],
+            state: ConnectionState::Reconnecting,
+            last_seen_update: true,
+        }).await.unwrap();
+        
+        let all = state_manager.send(GetAllClients { state_filter: None }).await.unwrap();
+        assert_eq!(all.len(), 3);
+        assert!(client_ids.iter().all(|id| all.iter().any(|status| status.client_id == *id)));
+        
+        let connected = state_manager.send(GetAllClients {
+            state_filter: Some(ConnectionState::Connected),
+        }).await.unwrap();
+        assert_eq!(connected.len(), 2);
+        assert!(connected.iter().all(|status| status.state == ConnectionState::Connected));
+        assert!(connected.iter().all(|status| status.client_id != client_ids[2]));
+    }
+    
+    #[actix::test]
+    async fn test_quiet_client_goes_idle_until_its_next_message() {
+        let mut actor = StateManagerActor::new();
+        actor.idle_threshold = Duration::from_millis(50);
+        actor.cleanup_interval = Duration::from_millis(20);
+        let state_manager = actor.start();
+        let client_id = Uuid::new_v4();
+        connect_client(&state_manager, client_id).await;
+        
+        let state = || async {
+            state_manager.send(GetClientStatus { client_id }).await.unwrap().unwrap().state
+        };
+        let active = || async { state_manager.send(GetSystemMetrics).await.unwrap().active_clients };
+        assert_eq!(state().await, ConnectionState::Connected);
+        assert_eq!(active().await, 1);
+        
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(state().await, ConnectionState::Idle);
+        assert_eq!(active().await, 0);
+        
+        // A heartbeat keeps the connection alive but doesn't end the idle spell
+        state_manager.send(ClientActivity { client_id, is_message: false }).await.unwrap();
+        assert_eq!(state().await, ConnectionState::Idle);
+        
+        state_manager.send(ClientActivity { client_id, is_message: true }).await.unwrap();
+        assert_eq!(state().await, ConnectionState::Connected);
+        assert_eq!(active().await, 1);
+    }
+    
+    #[actix::test]
+    async fn test_received_message_is_counted_once() {
+        use common::test_support::{client_text_frame, start_ws_actor_with_sender};
+        
+        let state_manager = StateManagerActor::new().start();
+        let client_id = Uuid::new_v4();
+        let mut client = ClientSessionActor::new(client_id);
+        client.set_state_manager(state_manager.clone());
+        let (addr, input, _frames) = start_ws_actor_with_sender(client);
+        state_manager.send(RegisterClient { client_id, addr, authenticated: false, wallet_address: None }).await.unwrap();
+        
+        // The client reports both activity and message metrics for the frame
+        input.send(client_text_frame("hello")).unwrap();
+        let received = || async {
+            state_manager.send(GetClientStatus { client_id }).await.unwrap().unwrap().message_count_received
+        };
+        for _ in 0..50 {
+            if received().await > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(received().await, 1);
+        
+        // Agents are counted through their activity alone
+        let (addr, _agent_frames) = start_ws_actor(AgentActor::new("agent1".to_string(), "token".to_string()));
+        state_manager.send(RegisterAgent { agent_id: "agent1".to_string(), addr }).await.unwrap();
+        let before = state_manager.send(GetSystemMetrics).await.unwrap().total_messages_processed;
+        state_manager.send(AgentActivity { agent_id: "agent1".to_string(), is_message: true }).await.unwrap();
+        let agent = state_manager.send(GetAgentStatus { agent_id: "agent1".to_string() }).await.unwrap().unwrap();
+        assert_eq!(agent.message_count_received, 1);
+        assert_eq!(state_manager.send(GetSystemMetrics).await.unwrap().total_messages_processed, before + 1);
+    }
+    
+    #[actix::test]
+    async fn test_force_disconnect_closes_and_forgets_the_connection() {
+        use common::test_support::{collect_raw_frames, decode_server_frames};
+        
+        let state_manager = StateManagerActor::new().start();
+        let client_id = Uuid::new_v4();
+        let mut client = ClientSessionActor::new(client_id);
+        client.set_state_manager(state_manager.clone());
+        let (addr, mut client_frames) = start_ws_actor(client);
+        state_manager.send(RegisterClient { client_id, addr, authenticated: false, wallet_address: None }).await.unwrap();
+        let (addr, mut agent_frames) = start_ws_actor(AgentActor::new("agent1".to_string(), "token".to_string()));
+        state_manager.send(RegisterAgent { agent_id: "agent1".to_string(), addr }).await.unwrap();
+        
+        let kicked = state_manager.send(ForceDisconnectClient {
+            client_id,
+            reason: "Abusive traffic".to_string(),
+        }).await.unwrap();
+        assert!(kicked);
+        let kicked = state_manager.send(ForceDisconnectAgent {
+            agent_id: "agent1".to_string(),
+            reason: "Maintenance".to_string(),
+        }).await.unwrap();
+        assert!(kicked);
+        
+        for (frames, reason) in [(&mut client_frames, "Abusive traffic"), (&mut agent_frames, "Maintenance")] {
+            let raw = collect_raw_frames(frames, Duration::from_millis(100)).await.concat();
+            let (_, close) = decode_server_frames(&raw).into_iter()
+                .find(|(opcode, _)| *opcode == 0x8)
+                .expect("a force-disconnect should close the connection");
+            assert_eq!(&close[..2], &u16::from(actix_web_actors::ws::CloseCode::Policy).to_be_bytes());
+            assert_eq!(&close[2..], reason.as_bytes());
+        }
+        
+        assert!(state_manager.send(GetAllClients { state_filter: None }).await.unwrap().is_empty());
+        assert!(state_manager.send(GetAllAgents { state_filter: None }).await.unwrap().is_empty());
+        
+        // Nothing left to kick
+        let kicked = state_manager.send(ForceDisconnectClient {
+            client_id,
+            reason: "Again".to_string(),
+        }).await.unwrap();
+        assert!(!kicked);
+    }
+    
+    #[actix::test]
+    async fn test_metrics_timestamp_serializes_as_monotonic_epoch_millis() {
+        let state_manager = StateManagerActor::new().start();
+        let snapshot = || async {
+            let metrics = SystemMetricsResponse::from(state_manager.send(GetSystemMetrics).await.unwrap());
+            serde_json::to_value(metrics).unwrap()
+        };
+        
+        let first = snapshot().await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let second = snapshot().await;
+        
+        let first_ms = first["timestamp_ms"].as_u64().unwrap();
+        let second_ms = second["timestamp_ms"].as_u64().unwrap();
+        let now_ms = Utc::now().timestamp_millis() as u64;
+        assert!(now_ms - first_ms < 60_000, "timestamp should be epoch millis");
+        assert!(second_ms > first_ms);
+    }
+    
+    #[actix::test]
+    async fn test_replacement_router_learns_existing_registrations() {
+        use common::test_support::collect_frames;
+        use common::{AgentMessage, ClientMessage};
+        
+        let state_manager = StateManagerActor::new().start();
+        state_manager.send(SetRouter { router: RouterActor::new(None).start() }).await.unwrap();
+        
+        let client_id = Uuid::new_v4();
+        let (client, mut client_frames) = start_ws_actor(ClientSessionActor::new(client_id));
+        let (agent, mut agent_frames) = start_ws_actor(AgentActor::new("agent1".to_string(), "token".to_string()));
+        state_manager.send(RegisterClient { client_id, addr: client, authenticated: false, wallet_address: None }).await.unwrap();
+        state_manager.send(RegisterAgent { agent_id: "agent1".to_string(), addr: agent }).await.unwrap();
+        
+        // Swap in a fresh router, then repeat the set as a reconfiguration might
+        let router = RouterActor::new(None).start();
+        state_manager.send(SetRouter { router: router.clone() }).await.unwrap();
+        state_manager.send(SetRouter { router: router.clone() }).await.unwrap();
+        
+        router.send(ClientMessage::new(client_id, "to-agent").with_target_agent_id(Some("agent1".to_string())))
+            .await.unwrap();
+        router.send(AgentMessage::to_client(client_id, "to-client")).await.unwrap();
+        
+        let window = Duration::from_millis(100);
+        assert!(collect_frames(&mut agent_frames, window).await.concat().contains("to-agent"));
+        assert!(collect_frames(&mut client_frames, window).await.concat().contains("to-client"));
+    }
+    
+    #[actix::test]
+    async fn test_stopped_router_is_replaced_and_relearns_registrations() {
+        use common::test_support::{client_text_frame, collect_frames, start_ws_actor_with_input, start_ws_actor_with_sender};
+        use common::{AgentMessage, ClientMessage};
+        use std::sync::{Arc, Mutex};
+        
+        // The original router lives on its own arbiter so it can be stopped
+        let arbiter = actix::Arbiter::new();
+        let old_router = RouterActor::start_in_arbiter(&arbiter.handle(), |_| RouterActor::new(None));
+        let replacements = Arc::new(Mutex::new(Vec::new()));
+        let built = replacements.clone();
+        let mut actor = StateManagerActor::new().with_router_factory(move || {
+            let router = RouterActor::new(None).start();
+            built.lock().unwrap().push(router.clone());
+            router
+        });
+        actor.set_router(old_router.clone());
+        let state_manager = actor.start();
+        
+        // The agent serves one tenant, as declared to the original router
+        let client_id = Uuid::new_v4();
+        let mut client = ClientSessionActor::new(client_id);
+        client.set_router(old_router.clone());
+        client.set_tenant_id(Some("acme".to_string()));
+        let (client, client_input, mut client_frames) = start_ws_actor_with_sender(client);
+        let mut agent = AgentActor::new("agent1".to_string(), "token".to_string());
+        agent.set_router(old_router.clone());
+        let hello = client_text_frame(r#"{"type":"agent_hello","tenant_id":"acme"}"#);
+        let (agent, mut agent_frames) = start_ws_actor_with_input(agent, vec![hello]);
+        state_manager.send(RegisterClient { client_id, addr: client, authenticated: false, wallet_address: None }).await.unwrap();
+        state_manager.send(RegisterAgent { agent_id: "agent1".to_string(), addr: agent }).await.unwrap();
+        
+        arbiter.stop();
+        arbiter.join().unwrap();
+        assert!(!old_router.connected());
+        
+        // The next send to the router finds it gone; a later connection still
+        // holds the original, as the HTTP routes would hand it out
+        let late_id = Uuid::new_v4();
+        let mut late = ClientSessionActor::new(late_id);
+        late.set_router(old_router.clone());
+        late.set_tenant_id(Some("acme".to_string()));
+        let (late, late_input, _late_frames) = start_ws_actor_with_sender(late);
+        state_manager.send(RegisterClient { client_id: late_id, addr: late, authenticated: false, wallet_address: None }).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        
+        let router = {
+            let replacements = replacements.lock().unwrap();
+            assert_eq!(replacements.len(), 1, "exactly one replacement router");
+            replacements[0].clone()
+        };
+        
+        // The replacement knows the existing client, and every connection
+        // now sends through it
+        router.send(AgentMessage::to_client(client_id, "to-client")).await.unwrap();
+        client_input.send(client_text_frame("from-client")).unwrap();
+        late_input.send(client_text_frame("from-late-client")).unwrap();
+        // The agent is still kept apart from clients outside its tenant
+        router.send(ClientMessage::new(Uuid::new_v4(), "from-other-tenant")).await.unwrap();
+        
+        let window = Duration::from_millis(200);
+        assert!(collect_frames(&mut client_frames, window).await.concat().contains("to-client"));
+        let agent_output = collect_frames(&mut agent_frames, window).await.concat();
+        assert!(agent_output.contains("from-client"));
+        assert!(agent_output.contains("from-late-client"));
+        assert!(!agent_output.contains("from-other-tenant"));
+    }
+    
+    #[actix::test]
+    async fn test_reconciliation_evicts_router_entry_with_lost_unregister() {
+        use common::test_support::collect_frames;
+        use common::AgentMessage;
+        
+        let router = RouterActor::new(None).start();
+        let mut actor = StateManagerActor::new();
+        actor.set_router(router.clone());
+        actor.router_reconcile_interval = Duration::from_millis(20);
+        let state_manager = actor.start();
+        
+        let live_id = Uuid::new_v4();
+        let stale_id = Uuid::new_v4();
+        let (live, mut live_frames) = start_ws_actor(ClientSessionActor::new(live_id));
+        let (stale, mut stale_frames) = start_ws_actor(ClientSessionActor::new(stale_id));
+        state_manager.send(RegisterClient { client_id: live_id, addr: live, authenticated: false, wallet_address: None }).await.unwrap();
+        state_manager.send(RegisterClient { client_id: stale_id, addr: stale, authenticated: false, wallet_address: None }).await.unwrap();
+        
+        // The state manager sees the disconnect but the router never does,
+        // as if its unregister had been dropped
+        state_manager.send(UpdateClientState {
+            client_id: stale_id,
+            state: ConnectionState::Disconnected,
+            last_seen_update: true,
+        }).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        
+        for client_id in [live_id, stale_id] {
+            router.send(AgentMessage::to_client(client_id, format!("to-{}", client_id))).await.unwrap();
+        }
+        
+        let window = Duration::from_millis(100);
+        assert!(collect_frames(&mut live_frames, window).await.concat().contains(&format!("to-{}", live_id)));
+        assert!(!collect_frames(&mut stale_frames, window).await.concat().contains(&format!("to-{}", stale_id)));
+    }
+    
+    #[actix::test]
+    async fn test_agent_session_restored_on_reconnect() {
+        use common::test_support::{client_text_frame, collect_frames, start_ws_actor_with_input};
+        use common::ClientMessage;
+        
+        let state_manager = StateManagerActor::with_config(StateManagerConfig {
+            persist_agent_sessions: true,
+            ..StateManagerConfig::default()
+        }).start();
+        let router = RouterActor::new(None).start();
+        state_manager.send(SetRouter { router: router.clone() }).await.unwrap();
+        let agent = || {
+            let mut agent = AgentActor::new("agent1".to_string(), "token".to_string());
+            agent.set_state_manager(state_manager.clone());
+            agent.set_router(router.clone());
+            agent
+        };
+        
+        // The agent declares its tenant and follows a client, then disconnects
+        let watched = Uuid::new_v4();
+        let (first, _first_frames) = start_ws_actor_with_input(agent(), vec![
+            client_text_frame(r#"{"type":"agent_hello","tenant_id":"acme"}"#),
+            client_text_frame(&format!(r#"{{"type":"subscribe_presence","client_id":"{}"}}"#, watched)),
+        ]);
+        state_manager.send(RegisterAgent { agent_id: "agent1".to_string(), addr: first.clone() }).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        first.send(Shutdown { reason: "disconnect".to_string() }).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        
+        // Reconnecting without repeating either, it still has both
+        let (second, mut second_frames) = start_ws_actor(agent());
+        state_manager.send(RegisterAgent { agent_id: "agent1".to_string(), addr: second }).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        
+        let message = |content: &str, tenant_id: &str| {
+            ClientMessage::new(Uuid::new_v4(), content).with_tenant_id(Some(tenant_id.to_string()))
+        };
+        router.send(message("for-acme", "acme")).await.unwrap();
+        router.send(message("for-globex", "globex")).await.unwrap();
+        router.send(SystemMessage::ClientDisconnected { client_id: watched }).await.unwrap();
+        
+        let output = collect_frames(&mut second_frames, Duration::from_millis(100)).await.concat();
+        assert!(output.contains("for-acme"));
+        assert!(!output.contains("for-globex"));
+        assert!(output.contains("ClientDisconnected") && output.contains(&watched.to_string()));
+    }
+}
@@ -0,0 +1,63 @@
+// websocket-server/src/compression.rs
+// Deflate compression of large outbound text frames, for clients that enable it
+use actix_web::web::Bytes;
+use flate2::{write::DeflateEncoder, Compression};
+use std::io::Write;
+
+/// Once a client enables compression, every binary frame sent to it starts
+/// with one of these header bytes so it can tell the two kinds apart
+pub const RAW_FRAME: u8 = 0x00; // Payload follows unchanged
+pub const DEFLATE_TEXT_FRAME: u8 = 0x01; // Raw-deflated UTF-8 text follows
+
+/// A text frame compressed with raw deflate, behind its header byte
+pub fn deflate_text(text: &str) -> std::io::Result<Bytes> {
+    let mut encoder = DeflateEncoder::new(vec![DEFLATE_TEXT_FRAME], Compression::default());
+    encoder.write_all(text.as_bytes())?;
+    encoder.finish().map(Bytes::from)
+}
+
+/// An uncompressed binary payload behind its header byte
+pub fn mark_raw(data: &[u8]) -> Bytes {
+    let mut framed = Vec::with_capacity(data.len() + 1);
+    framed.push(RAW_FRAME);
+    framed.extend_from_slice(data);
+    Bytes::from(framed)
+}
+
+/// Undo `deflate_text` or `mark_raw`, as a client would
+#[cfg(test)]
+pub fn decode_frame(frame: &[u8]) -> std::io::Result<Vec<u8>> {
+    use flate2::read::DeflateDecoder;
+    use std::io::{Error, ErrorKind, Read};
+
+    match frame.split_first() {
+        Some((&RAW_FRAME, payload)) => Ok(payload.to_vec()),
+        Some((&DEFLATE_TEXT_FRAME, payload)) => {
+            let mut text = Vec::new();
+            DeflateDecoder::new(payload).read_to_end(&mut text)?;
+            Ok(text)
+        }
+        _ => Err(Error::new(ErrorKind::InvalidData, "unknown frame header")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_large_text_round_trips_through_deflate() {
+        let text = serde_json::json!({
+            "content": "the same broadcast sent to every client ".repeat(100),
+        }).to_string();
+
+        let frame = deflate_text(&text).unwrap();
+        assert_eq!(frame[0], DEFLATE_TEXT_FRAME);
+        assert!(frame.len() < text.len() / 4, "{} bytes compressed to {}", text.len(), frame.len());
+        assert_eq!(decode_frame(&frame).unwrap(), text.as_bytes());
+
+        let raw = mark_raw(b"\x01not compressed");
+        assert_eq!(decode_frame(&raw).unwrap(), b"\x01not compressed");
+        assert!(decode_frame(b"\x07junk").is_err());
+    }
+}
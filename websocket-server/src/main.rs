@@ -2,53 +2,140 @@
 // WebSocket Server - main.rs
 
 mod actors;
+mod compression;
+mod prometheus;
 mod routing;
 
 use actix_web::{web, App, HttpServer};
-use actors::state_manager::StateManagerActor;
-use actors::router_actor::RouterActor;
-use common::{setup_tracing, Config};
+use actors::state_manager::{DrainAndPersist, StateManagerActor};
+use actors::router_actor::{RouterActor, RoutingStrategy};
+use common::{setup_tracing, Config, ConnectionFilter};
 use routing::routes;
 use actix::Actor;
+use tokio::sync::Semaphore;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    // Setup tracing
-    setup_tracing();
+    // Setup tracing; the configured level applies once the config is loaded
+    let log_level = setup_tracing();
     
     // Load configuration
-    let config = Config::from_env();
+    let config = Config::from_env().inspect_err(|e| tracing::error!("Refusing to start: {}", e))?;
+    if let Err(e) = log_level.set(&config.log_level) {
+        tracing::warn!("{}, logging at info", e);
+    }
     
     // Save address before moving config into web::Data
     let server_addr = config.websocket_server_addr.clone();
     
-    // Initialize the router actor
-    let router = RouterActor::new().start();
+    let routing_strategy = config.routing_strategy.parse::<RoutingStrategy>()
+        .unwrap_or_else(|e| {
+            tracing::warn!("{}, falling back to default_agent", e);
+            RoutingStrategy::DefaultAgent
+        });
+    
+    // Agents register under `agent_id` or an `agents` id, so any other default never connects
+    if let Some(default_agent_id) = config.default_agent_id.as_ref()
+        .filter(|id| **id != config.agent_id && !config.agents.contains_key(*id))
+    {
+        tracing::warn!(
+            "default_agent_id {} is neither agent_id {} nor a configured agent; client messages will find no default agent",
+            default_agent_id, config.agent_id
+        );
+    }
+    
+    // Initialize the router actor. The state manager builds another the same
+    // way should it stop
+    let build_router = {
+        let config = config.clone();
+        move || RouterActor::new(config.default_agent_id.clone())
+            .with_required_acks(config.require_message_acks)
+            .with_server_timestamps(config.include_server_timestamp)
+            .with_strategy(routing_strategy)
+            .with_max_paused_messages(config.state_manager.client_buffer_size)
+            .with_slow_message_threshold(std::time::Duration::from_millis(config.slow_message_threshold_ms))
+            .with_shards(config.router_shards)
+            .start()
+    };
+    let router = build_router();
     
     // Initialize the state manager actor
-    let state_manager = StateManagerActor::new().start();
+    let state_manager = StateManagerActor::with_config(config.state_manager.clone())
+        .with_router_factory(build_router)
+        .start();
     
     // Make state manager aware of router
     state_manager.do_send(actors::state_manager::SetRouter {
         router: router.clone(),
     });
     
-    tracing::info!("Starting WebSocket Server on {}", server_addr);
+    // Load the certificate up front so a bad one fails startup
+    let tls = config.tls.as_ref().map(common::tls::server_config).transpose()?;
+    
+    tracing::info!("Starting WebSocket Server on {}{}", server_addr, if tls.is_some() { " (TLS)" } else { "" });
     
     // Create data references
+    let connection_filter_data = web::Data::new(ConnectionFilter::from_config(&config));
+    // One pool of restore permits shared by every client connection
+    let restore_permits_data = web::Data::new(Semaphore::new(match config.state_manager.max_concurrent_restores {
+        0 => Semaphore::MAX_PERMITS,
+        limit => limit,
+    }));
     let config_data = web::Data::new(config);
     let router_data = web::Data::new(router);
     let state_manager_data = web::Data::new(state_manager.clone());
     
     // Start HTTP server
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(state_manager_data.clone())
             .app_data(router_data.clone())
             .app_data(config_data.clone())
+            .app_data(connection_filter_data.clone())
+            .app_data(restore_permits_data.clone())
             .configure(routes)
-    })
-    .bind(&server_addr)?
-    .run()
-    .await
+    });
+    let server = match tls {
+        Some(tls) => server.bind_rustls(&server_addr, tls)?,
+        None => server.bind(&server_addr)?,
+    }
+    .disable_signals()
+    .run();
+    
+    // On shutdown, persist sessions while clients are still connected, then
+    // stop the server
+    let server_handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        tracing::info!("Shutdown requested, persisting sessions");
+        match state_manager.send(DrainAndPersist).await {
+            Ok(saved) => tracing::info!("Persisted {} sessions", saved),
+            Err(e) => tracing::error!("Failed to persist sessions on shutdown: {}", e),
+        }
+        server_handle.stop(true).await;
+    });
+    
+    server.await
+}
+
+// Resolves on Ctrl-C, or SIGTERM on Unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = terminate.recv() => {},
+                }
+                return;
+            },
+            Err(e) => tracing::error!("Failed to listen for SIGTERM: {}", e),
+        }
+    }
+    
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        tracing::error!("Failed to listen for Ctrl-C: {}", e);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,93 @@
+// websocket-server/src/prometheus.rs
+// Renders state manager metrics in the Prometheus text exposition format
+use std::fmt::{Display, Write};
+use crate::actors::state_manager::{AgentStatusResponse, ClientStatusResponse, ConnectionState, SystemMetrics};
+
+/// Content type of the text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+const STATES: [(ConnectionState, &str); 5] = [
+    (ConnectionState::Connected, "connected"),
+    (ConnectionState::Disconnected, "disconnected"),
+    (ConnectionState::Reconnecting, "reconnecting"),
+    (ConnectionState::Idle, "idle"),
+    (ConnectionState::Error, "error"),
+];
+
+// Escape a label value: backslash, double quote and newline
+fn escape_label(value: &str) -> String {
+    value.replace('\\', r"\\").replace('"', r#"\""#).replace('\n', r"\n")
+}
+
+#[derive(Default)]
+struct Exposition(String);
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {} {}", name, help);
+        let _ = writeln!(self.0, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        let _ = if labels.is_empty() {
+            writeln!(self.0, "{} {}", name, value)
+        } else {
+            let labels = labels.iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+                .collect::<Vec<_>>()
+                .join(",");
+            writeln!(self.0, "{}{{{}}} {}", name, labels, value)
+        };
+    }
+
+    // A family with a single unlabelled sample
+    fn single(&mut self, name: &str, kind: &str, help: &str, value: impl Display) {
+        self.family(name, kind, help);
+        self.sample(name, &[], value);
+    }
+
+    // One sample per agent, labelled with its id
+    fn per_agent<T: Display>(&mut self, name: &str, kind: &str, help: &str, agents: &[AgentStatusResponse], value: impl Fn(&AgentStatusResponse) -> T) {
+        self.family(name, kind, help);
+        for agent in agents {
+            self.sample(name, &[("agent_id", &agent.agent_id)], value(agent));
+        }
+    }
+}
+
+/// Render system, per-state client and per-agent metrics. Counters come from
+/// lifetime totals so they only ever grow; rates are exported as gauges.
+pub fn render(metrics: &SystemMetrics, clients: &[ClientStatusResponse], agents: &[AgentStatusResponse]) -> String {
+    let mut out = Exposition::default();
+
+    out.single("agent_bridge_active_clients", "gauge", "Clients currently connected", metrics.active_clients);
+    out.single("agent_bridge_active_agents", "gauge", "Agents currently connected", metrics.active_agents);
+    out.single("agent_bridge_messages_total", "counter", "Messages processed since first start", metrics.total_messages_processed);
+    out.single("agent_bridge_bytes_transferred_total", "counter", "Bytes transferred since first start", metrics.bytes_transferred);
+    out.single("agent_bridge_messages_per_second", "gauge", "Recent message rate", metrics.messages_per_second);
+
+    out.family("agent_bridge_agent_auth_attempts_total", "counter", "Agent pre-shared key checks since start, by result");
+    out.sample("agent_bridge_agent_auth_attempts_total", &[("result", "success")], metrics.agent_auth_successes);
+    out.sample("agent_bridge_agent_auth_attempts_total", &[("result", "failure")], metrics.agent_auth_failures);
+
+    out.family("agent_bridge_clients", "gauge", "Known clients by connection state");
+    for (state, label) in STATES {
+        let count = clients.iter().filter(|client| client.state == state).count();
+        out.sample("agent_bridge_clients", &[("state", label)], count);
+    }
+
+    out.per_agent("agent_bridge_agent_connected", "gauge", "Whether the agent is connected", agents,
+        |agent| u8::from(agent.state == ConnectionState::Connected));
+    out.per_agent("agent_bridge_agent_messages_sent_total", "counter", "Messages sent to the agent", agents,
+        |agent| agent.message_count_sent);
+    out.per_agent("agent_bridge_agent_messages_received_total", "counter", "Messages received from the agent", agents,
+        |agent| agent.message_count_received);
+    out.per_agent("agent_bridge_agent_bytes_sent_total", "counter", "Bytes sent to the agent", agents,
+        |agent| agent.bytes_sent);
+    out.per_agent("agent_bridge_agent_bytes_received_total", "counter", "Bytes received from the agent", agents,
+        |agent| agent.bytes_received);
+    out.per_agent("agent_bridge_agent_rate_limited_total", "counter", "Agent messages dropped by the rate limit", agents,
+        |agent| agent.messages_rate_limited);
+
+    out.0
+}
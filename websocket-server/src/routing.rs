@@ -1,16 +1,46 @@
 // websocket-server/src/routing.rs
-use actix_web::{web, HttpRequest, HttpResponse, Error};
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Error};
 use actix_web_actors::ws;
 use actix::Addr;
-use common::Config;
+use common::{AuthorizationHeader, Config, ConnectionFilter, JwtError, ProtocolVersion, TENANT_ID_HEADER, WALLET_ADDRESS_HEADER};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
 use uuid::Uuid;
+use crate::prometheus;
 use crate::actors::{
     agent_actor::AgentActor,
     client_session_actor::ClientSessionActor,
-    state_manager::{StateManagerActor, RegisterClient, RegisterAgent},
-    router_actor::RouterActor, // Import RouterActor
+    state_manager::{
+        StateManagerActor, RegisterClient, RegisterAgent, GetSystemMetrics, SystemMetricsResponse,
+        GetAllClients, GetAllAgents, ForceDisconnectClient, ForceDisconnectAgent, AgentAuthAttempt,
+    },
+    router_actor::{RouterActor, GetDefaultAgent, SetDefaultAgent}, // Import RouterActor
 };
 
+// Request body for changing the router's default agent
+#[derive(Deserialize)]
+pub struct SetDefaultAgentRequest {
+    pub agent_id: String,
+}
+
+// Optional reason given when an admin disconnects a client or agent
+#[derive(Deserialize)]
+pub struct ForceDisconnectQuery {
+    pub reason: Option<String>,
+}
+
+// Token a reconnecting client presents to resume its saved session
+#[derive(Deserialize)]
+pub struct ResumeQuery {
+    pub resume_token: Option<String>,
+}
+
+// Close reason sent when an admin gives none
+const DEFAULT_DISCONNECT_REASON: &str = "Disconnected by an administrator";
+
 /// Configure routes for the WebSocket server
 pub fn routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
@@ -19,7 +49,300 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
     ).service(
         web::resource("/ws/client/{client_id}")
             .route(web::get().to(client_ws_route))
-    );
+    ).service(
+        web::resource("/admin/default-agent")
+            .route(web::get().to(get_default_agent_route))
+            .route(web::put().to(set_default_agent_route))
+    ).service(
+        web::resource("/admin/clients/{client_id}")
+            .route(web::delete().to(disconnect_client_route))
+    ).service(
+        web::resource("/admin/agents/{agent_id}")
+            .route(web::delete().to(disconnect_agent_route))
+    ).service(
+        web::resource("/metrics")
+            .route(web::get().to(prometheus_metrics_route))
+    ).service(
+        web::resource("/admin/metrics")
+            .route(web::get().to(get_metrics_route))
+    ).app_data(web::Data::new(MetricsCache::default()));
+}
+
+// How long a metrics snapshot is served before the state manager is asked again
+const METRICS_CACHE_TTL: Duration = Duration::from_secs(1);
+
+/// Last metrics snapshot, so frequent polling can't flood the state manager's mailbox
+#[derive(Default)]
+struct MetricsCache(Mutex<Option<(Instant, SystemMetricsResponse)>>);
+
+impl MetricsCache {
+    fn fresh(&self) -> Option<SystemMetricsResponse> {
+        let cached = self.0.lock().unwrap();
+        cached.as_ref()
+            .filter(|(taken_at, _)| taken_at.elapsed() < METRICS_CACHE_TTL)
+            .map(|(_, metrics)| metrics.clone())
+    }
+
+    fn store(&self, metrics: SystemMetricsResponse) {
+        *self.0.lock().unwrap() = Some((Instant::now(), metrics));
+    }
+}
+
+/// Extract the token from the Authorization header: the agent pre-shared key
+/// or a client JWT. Accepts `Bearer <token>` as well as a bare token for older agents.
+fn token_from_request(req: &HttpRequest) -> Option<String> {
+    let header = req.headers().get("Authorization")?;
+    match AuthorizationHeader::from_header(header) {
+        Ok(auth) => Some(auth.token().to_string()),
+        Err(e) => {
+            tracing::warn!("Rejected malformed Authorization header: {}", e);
+            None
+        }
+    }
+}
+
+// Count and audit an agent's pre-shared key check against the address it
+// connected from. Agents connect directly, so forwarding headers aren't trusted
+fn record_agent_auth(state_manager: &Addr<StateManagerActor>, req: &HttpRequest, failure: Option<&'static str>) {
+    let ip = req.peer_addr().map(|addr| addr.ip().to_string()).unwrap_or_else(|| "unknown".to_string());
+    state_manager.do_send(AgentAuthAttempt { ip, failure });
+}
+
+/// Validate a client JWT with the configured algorithm, returning its client id and wallet
+fn validate_client_jwt(token: &str, config: &Config) -> Result<(Uuid, String), JwtError> {
+    let claims = config.validate_jwt(token, config.jwt_leeway_secs)?;
+    Ok((claims.client_id()?, claims.wallet))
+}
+
+/// Extract the client's tenant, forwarded by the web-server proxy
+fn tenant_id_from_request(req: &HttpRequest) -> Option<String> {
+    req.headers().get(TENANT_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|tenant_id| !tenant_id.is_empty())
+        .map(str::to_string)
+}
+
+/// Whether a client may connect as `client_id`. With `require_client_auth`
+/// the client must hold a JWT issued for that id, or the connection must come
+/// from the web-server proxy presenting the proxy token.
+fn is_client_authorized(req: &HttpRequest, client_id: Uuid, config: &Config) -> bool {
+    if !config.require_client_auth {
+        return true;
+    }
+
+    let Some(token) = token_from_request(req) else {
+        tracing::warn!("Client {} connected without credentials", client_id);
+        return false;
+    };
+    if token == config.proxy_token {
+        return true;
+    }
+
+    match validate_client_jwt(&token, config) {
+        Ok((token_client_id, _wallet_address)) if token_client_id == client_id => true,
+        Ok((token_client_id, _wallet_address)) => {
+            tracing::warn!(
+                "JWT client ID mismatch: token has {}, request for {}",
+                token_client_id, client_id
+            );
+            false
+        },
+        Err(e) => {
+            tracing::warn!("Invalid JWT for client {}: {}", client_id, e);
+            false
+        }
+    }
+}
+
+/// The wallet a client connection is authenticated as: taken from the
+/// client's own JWT, or vouched for by the web-server proxy presenting the
+/// proxy token. A wallet header without the token is ignored.
+fn authenticated_wallet(req: &HttpRequest, client_id: Uuid, config: &Config) -> Option<String> {
+    let token = token_from_request(req)?;
+    if token == config.proxy_token {
+        return req.headers().get(WALLET_ADDRESS_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|wallet_address| !wallet_address.is_empty())
+            .map(str::to_string);
+    }
+
+    match validate_client_jwt(&token, config) {
+        Ok((token_client_id, wallet_address)) if token_client_id == client_id => Some(wallet_address),
+        _ => None,
+    }
+}
+
+/// Whether the connection filter lets a client connect. Connections the
+/// web-server proxy vouches for with the proxy token carry the proxy's own
+/// User-Agent, and the proxy has already checked the client's
+fn is_client_connection_allowed(req: &HttpRequest, config: &Config, connection_filter: &ConnectionFilter) -> bool {
+    connection_filter.allows(req.headers())
+        || token_from_request(req).is_some_and(|token| token == config.proxy_token)
+}
+
+/// Admin routes are protected with the admin token
+fn is_admin_authorized(req: &HttpRequest, config: &Config) -> bool {
+    token_from_request(req)
+        .map(|token| token == config.admin_token)
+        .unwrap_or(false)
+}
+
+/// Admin route returning the router's current default agent
+async fn get_default_agent_route(
+    req: HttpRequest,
+    router: web::Data<Addr<RouterActor>>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    if !is_admin_authorized(&req, &config) {
+        tracing::warn!("Unauthorized default agent query");
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+
+    match router.send(GetDefaultAgent).await {
+        Ok(agent_id) => Ok(HttpResponse::Ok().json(json!({ "default_agent_id": agent_id }))),
+        Err(e) => {
+            tracing::error!("Failed to query default agent: {}", e);
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+/// Admin route switching the router's default agent at runtime
+async fn set_default_agent_route(
+    req: HttpRequest,
+    body: web::Json<SetDefaultAgentRequest>,
+    router: web::Data<Addr<RouterActor>>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    if !is_admin_authorized(&req, &config) {
+        tracing::warn!("Unauthorized default agent change attempt");
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+
+    let agent_id = body.into_inner().agent_id;
+    match router.send(SetDefaultAgent { agent_id: agent_id.clone() }).await {
+        Ok(Ok(())) => Ok(HttpResponse::Ok().json(json!({ "default_agent_id": agent_id }))),
+        Ok(Err(e)) => Ok(HttpResponse::NotFound().json(json!({ "error": e.to_string() }))),
+        Err(e) => {
+            tracing::error!("Failed to set default agent: {}", e);
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+/// Admin route closing a client's connection and forgetting it
+async fn disconnect_client_route(
+    req: HttpRequest,
+    path: web::Path<(Uuid,)>,
+    query: web::Query<ForceDisconnectQuery>,
+    state_manager: web::Data<Addr<StateManagerActor>>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    if !is_admin_authorized(&req, &config) {
+        tracing::warn!("Unauthorized client disconnect attempt");
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+
+    let client_id = path.0;
+    let reason = query.into_inner().reason.unwrap_or_else(|| DEFAULT_DISCONNECT_REASON.to_string());
+    match state_manager.send(ForceDisconnectClient { client_id, reason }).await {
+        Ok(true) => Ok(HttpResponse::Ok().json(json!({ "disconnected": client_id }))),
+        Ok(false) => Ok(HttpResponse::NotFound().json(json!({ "error": "Client not connected" }))),
+        Err(e) => {
+            tracing::error!("Failed to disconnect client {}: {}", client_id, e);
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+/// Admin route closing an agent's connection and forgetting it
+async fn disconnect_agent_route(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    query: web::Query<ForceDisconnectQuery>,
+    state_manager: web::Data<Addr<StateManagerActor>>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    if !is_admin_authorized(&req, &config) {
+        tracing::warn!("Unauthorized agent disconnect attempt");
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+
+    let agent_id = path.into_inner().0;
+    let reason = query.into_inner().reason.unwrap_or_else(|| DEFAULT_DISCONNECT_REASON.to_string());
+    match state_manager.send(ForceDisconnectAgent { agent_id: agent_id.clone(), reason }).await {
+        Ok(true) => Ok(HttpResponse::Ok().json(json!({ "disconnected": agent_id }))),
+        Ok(false) => Ok(HttpResponse::NotFound().json(json!({ "error": "Agent not connected" }))),
+        Err(e) => {
+            tracing::error!("Failed to disconnect agent {}: {}", agent_id, e);
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+/// Admin route returning a snapshot of system-wide metrics, at most
+/// `METRICS_CACHE_TTL` old
+async fn get_metrics_route(
+    req: HttpRequest,
+    state_manager: web::Data<Addr<StateManagerActor>>,
+    cache: web::Data<MetricsCache>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    if !is_admin_authorized(&req, &config) {
+        tracing::warn!("Unauthorized metrics query");
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+
+    if let Some(metrics) = cache.fresh() {
+        return Ok(HttpResponse::Ok().json(metrics));
+    }
+
+    match state_manager.send(GetSystemMetrics).await {
+        Ok(metrics) => {
+            let metrics = SystemMetricsResponse::from(metrics);
+            cache.store(metrics.clone());
+            Ok(HttpResponse::Ok().json(metrics))
+        },
+        Err(e) => {
+            tracing::error!("Failed to query system metrics: {}", e);
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+/// Prometheus scrape target, guarded like the admin routes. Requests that
+/// accept only JSON get the admin metrics snapshot instead.
+async fn prometheus_metrics_route(
+    req: HttpRequest,
+    state_manager: web::Data<Addr<StateManagerActor>>,
+    cache: web::Data<MetricsCache>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let wants_json = req.headers().get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"));
+    if wants_json {
+        return get_metrics_route(req, state_manager, cache, config).await;
+    }
+
+    if !is_admin_authorized(&req, &config) {
+        tracing::warn!("Unauthorized metrics scrape");
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+
+    let snapshot = async {
+        let metrics = state_manager.send(GetSystemMetrics).await?;
+        let clients = state_manager.send(GetAllClients { state_filter: None }).await?;
+        let agents = state_manager.send(GetAllAgents { state_filter: None }).await?;
+        Ok::<_, actix::MailboxError>(prometheus::render(&metrics, &clients, &agents))
+    };
+    match snapshot.await {
+        Ok(body) => Ok(HttpResponse::Ok().content_type(prometheus::CONTENT_TYPE).body(body)),
+        Err(e) => {
+            tracing::error!("Failed to query metrics for scrape: {}", e);
+            Ok(HttpResponse::InternalServerError().finish())
+        }
+    }
 }
 
 /// WebSocket route for agent connections
@@ -29,27 +352,44 @@ async fn agent_ws_route(
     state_manager: web::Data<Addr<StateManagerActor>>,
     router: web::Data<Addr<RouterActor>>, // <-- Get RouterActor address
     config: web::Data<Config>,
+    connection_filter: web::Data<ConnectionFilter>,
 ) -> Result<HttpResponse, Error> {
-    let auth_header = req.headers().get("Authorization");
-    let token = match auth_header {
-        Some(header) => header.to_str().unwrap_or_default(),
+    if !connection_filter.allows(req.headers()) {
+        tracing::warn!("Agent connection attempt refused by the connection filter");
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    if !req.headers().contains_key("Authorization") {
+        tracing::warn!("Agent connection attempt without Authorization header");
+        record_agent_auth(&state_manager, &req, Some("missing Authorization header"));
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+
+    let token = match token_from_request(&req) {
+        Some(token) => token,
         None => {
-            tracing::warn!("Agent connection attempt without Authorization header");
+            record_agent_auth(&state_manager, &req, Some("malformed Authorization header"));
             return Ok(HttpResponse::Unauthorized().finish());
         },
     };
 
-    if token != config.agent_token {
-        tracing::warn!("Agent connection attempt with invalid token");
-        return Ok(HttpResponse::Unauthorized().finish());
-    }
+    let agent_id = match config.agent_id_for_token(&token) {
+        Some(agent_id) => agent_id.to_string(),
+        None => {
+            tracing::warn!("Agent connection attempt with invalid token");
+            record_agent_auth(&state_manager, &req, Some("invalid token"));
+            return Ok(HttpResponse::Unauthorized().finish());
+        },
+    };
+    record_agent_auth(&state_manager, &req, None);
 
-    let agent_id = "agent1".to_string(); // Hardcoded for Phase 2
-    let mut agent = AgentActor::new(agent_id.clone(), token.to_string());
+    let mut agent = AgentActor::new(agent_id.clone(), token);
 
     // Inject dependencies
     agent.set_state_manager(state_manager.get_ref().clone());
     agent.set_router(router.get_ref().clone()); // <-- Inject Router address
+    agent.set_rate_limit(config.agent_rate_limit_per_sec, config.agent_rate_limit_burst);
+    agent.set_max_content_len(config.max_content_len);
 
     // Start WebSocket connection
     ws::start_with_addr(agent, &req, stream).map(|(addr, resp)| {
@@ -62,12 +402,23 @@ async fn agent_ws_route(
     })
 }
 
+// Refusal for a client requesting only envelope versions this server doesn't speak
+fn unsupported_protocol_response() -> HttpResponse {
+    let supported: Vec<&str> = ProtocolVersion::SUPPORTED.iter().map(|version| version.subprotocol()).collect();
+    HttpResponse::BadRequest().json(json!({
+        "error": "Unsupported WebSocket protocol",
+        "supported": supported,
+    }))
+}
+
 /// WebSocket route for client connections
 async fn client_ws_route(
     req: HttpRequest,
     stream: web::Payload,
     state_manager: web::Data<Addr<StateManagerActor>>,
     router: web::Data<Addr<RouterActor>>, // <-- Get RouterActor address
+    config: web::Data<Config>,
+    connection_filter: web::Data<ConnectionFilter>,
     path: web::Path<(String,)>,
 ) -> Result<HttpResponse, Error> {
     let client_id_str = &path.0;
@@ -79,21 +430,637 @@ async fn client_ws_route(
         },
     };
 
-    let mut client = ClientSessionActor::new(client_id);
+    if !is_client_connection_allowed(&req, &config, &connection_filter) {
+        tracing::warn!("Client {} connection attempt refused by the connection filter", client_id);
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    if !is_client_authorized(&req, client_id, &config) {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+
+    let requested_protocols = req.headers().get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok());
+    let protocol = match ProtocolVersion::negotiate(requested_protocols) {
+        Ok(protocol) => protocol,
+        Err(requested) => {
+            tracing::warn!("Client {} requested only unsupported protocols: {}", client_id, requested);
+            return Ok(unsupported_protocol_response());
+        },
+    };
+
+    let wallet_address = authenticated_wallet(&req, client_id, &config);
+    let mut client = ClientSessionActor::with_buffer_size(client_id, config.state_manager.client_buffer_size)
+        .with_auth(wallet_address.clone());
 
     // Inject dependencies
     client.set_state_manager(state_manager.get_ref().clone());
     client.set_router(router.get_ref().clone()); // <-- Inject Router address
+    client.set_require_acks(config.require_message_acks);
+    client.set_max_unacked_messages(config.max_unacked_messages);
+    client.set_flush_rate(config.state_manager.buffer_flush_rate);
+    client.set_message_ttl(Duration::from_secs(config.state_manager.buffered_message_ttl_secs));
+    client.set_tenant_id(tenant_id_from_request(&req));
+    client.set_compression_threshold(config.compression_threshold_bytes);
+    client.set_max_content_len(config.max_content_len);
+    client.set_slow_message_threshold(Duration::from_millis(config.slow_message_threshold_ms));
+    client.set_max_frame_bytes(config.max_frame_bytes);
+    client.set_protocol_version(protocol.unwrap_or_default());
+    if let Some(restore_permits) = req.app_data::<web::Data<Semaphore>>() {
+        client.set_restore_permits(restore_permits.clone().into_inner());
+    }
+    // Only the holder of the session's resume token gets its buffered messages
+    let resume_token = web::Query::<ResumeQuery>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.into_inner().resume_token);
+    client.set_claimed_resume_token(resume_token);
 
-    // Start WebSocket connection
-    ws::start_with_addr(client, &req, stream).map(|(addr, resp)| {
-        // Register client with state manager
-        state_manager.do_send(RegisterClient {
-            client_id,
-            addr, // This addr is the Addr<ClientSessionActor>
-            authenticated: false, // Phase 2 default
-            wallet_address: None, // Phase 2 default
+    // Start WebSocket connection, echoing the negotiated protocol when one
+    // was requested; the codec refuses frames over the same limit
+    let protocols: Vec<&str> = protocol.iter().map(|version| version.subprotocol()).collect();
+    ws::WsResponseBuilder::new(client, &req, stream)
+        .frame_size(config.max_frame_bytes)
+        .protocols(&protocols)
+        .start_with_addr()
+        .map(|(addr, resp)| {
+            // Register client with state manager
+            state_manager.do_send(RegisterClient {
+                client_id,
+                addr, // This addr is the Addr<ClientSessionActor>
+                authenticated: wallet_address.is_some(),
+                wallet_address,
+            });
+            resp
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::Actor;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+    use common::generate_jwt_token;
+    use crate::actors::state_manager::GetClientStatus;
+
+    fn enforcing_config() -> Config {
+        Config {
+            require_client_auth: true,
+            ..Config::default()
+        }
+    }
+
+    fn jwt_for(client_id: Uuid, config: &Config) -> String {
+        generate_jwt_token(&client_id, "0xwallet", config.jwt_secret.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_unenforced_mode_accepts_any_client() {
+        let req = TestRequest::default().to_http_request();
+        assert!(is_client_authorized(&req, Uuid::new_v4(), &Config::default()));
+    }
+
+    #[test]
+    fn test_enforced_mode_rejects_missing_or_invalid_credentials() {
+        let config = enforcing_config();
+        let client_id = Uuid::new_v4();
+
+        let req = TestRequest::default().to_http_request();
+        assert!(!is_client_authorized(&req, client_id, &config));
+
+        let req = TestRequest::default()
+            .insert_header(("Authorization", "Bearer not-a-jwt"))
+            .to_http_request();
+        assert!(!is_client_authorized(&req, client_id, &config));
+
+        // Agents can't connect as clients with their own token
+        let req = TestRequest::default()
+            .insert_header(("Authorization", format!("Bearer {}", config.agent_token)))
+            .to_http_request();
+        assert!(!is_client_authorized(&req, client_id, &config));
+
+        // A valid JWT issued to someone else
+        let other_jwt = jwt_for(Uuid::new_v4(), &config);
+        let req = TestRequest::default()
+            .insert_header(("Authorization", format!("Bearer {}", other_jwt)))
+            .to_http_request();
+        assert!(!is_client_authorized(&req, client_id, &config));
+    }
+
+    #[test]
+    fn test_enforced_mode_accepts_client_jwt_or_proxy_key() {
+        let config = enforcing_config();
+        let client_id = Uuid::new_v4();
+
+        let req = TestRequest::default()
+            .insert_header(("Authorization", format!("Bearer {}", jwt_for(client_id, &config))))
+            .to_http_request();
+        assert!(is_client_authorized(&req, client_id, &config));
+
+        let req = TestRequest::default()
+            .insert_header(("Authorization", format!("Bearer {}", config.proxy_token)))
+            .to_http_request();
+        assert!(is_client_authorized(&req, client_id, &config));
+    }
+
+    #[test]
+    fn test_authenticated_wallet_from_jwt_or_proxy() {
+        let config = Config::default();
+        let client_id = Uuid::new_v4();
+
+        let req = TestRequest::default()
+            .insert_header(("Authorization", format!("Bearer {}", jwt_for(client_id, &config))))
+            .to_http_request();
+        assert_eq!(authenticated_wallet(&req, client_id, &config).as_deref(), Some("0xwallet"));
+
+        let req = TestRequest::default()
+            .insert_header(("Authorization", format!("Bearer {}", config.proxy_token)))
+            .insert_header((WALLET_ADDRESS_HEADER, "0xproxied"))
+            .to_http_request();
+        assert_eq!(authenticated_wallet(&req, client_id, &config).as_deref(), Some("0xproxied"));
+
+        // The proxy connecting an anonymous client
+        let req = TestRequest::default()
+            .insert_header(("Authorization", format!("Bearer {}", config.proxy_token)))
+            .to_http_request();
+        assert_eq!(authenticated_wallet(&req, client_id, &config), None);
+    }
+
+    #[test]
+    fn test_wallet_header_without_proxy_key_is_ignored() {
+        let config = Config::default();
+        let client_id = Uuid::new_v4();
+
+        let req = TestRequest::default()
+            .insert_header((WALLET_ADDRESS_HEADER, "0xspoofed"))
+            .to_http_request();
+        assert_eq!(authenticated_wallet(&req, client_id, &config), None);
+
+        // Nor does an agent's token
+        let req = TestRequest::default()
+            .insert_header(("Authorization", format!("Bearer {}", config.agent_token)))
+            .insert_header((WALLET_ADDRESS_HEADER, "0xspoofed"))
+            .to_http_request();
+        assert_eq!(authenticated_wallet(&req, client_id, &config), None);
+
+        // Another client's JWT doesn't vouch for this one
+        let req = TestRequest::default()
+            .insert_header(("Authorization", format!("Bearer {}", jwt_for(Uuid::new_v4(), &config))))
+            .insert_header((WALLET_ADDRESS_HEADER, "0xspoofed"))
+            .to_http_request();
+        assert_eq!(authenticated_wallet(&req, client_id, &config), None);
+    }
+
+    #[actix_web::test]
+    async fn test_proxied_client_registers_as_authenticated() {
+        let config = Config::default();
+        let state_manager = StateManagerActor::new().start();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state_manager.clone()))
+                .app_data(web::Data::new(RouterActor::new(None).start()))
+                .app_data(web::Data::new(config.clone()))
+                .app_data(web::Data::new(ConnectionFilter::default()))
+                .configure(routes)
+        ).await;
+
+        let client_id = Uuid::new_v4();
+        let req = TestRequest::get()
+            .uri(&format!("/ws/client/{}", client_id))
+            .insert_header(("Upgrade", "websocket"))
+            .insert_header(("Connection", "upgrade"))
+            .insert_header(("Sec-WebSocket-Version", "13"))
+            .insert_header(("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="))
+            .insert_header(("Authorization", format!("Bearer {}", config.proxy_token)))
+            .insert_header((WALLET_ADDRESS_HEADER, "0xproxied"))
+            .to_request();
+        // Hold the upgrade response so the session stays connected
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), 101);
+
+        let status = state_manager.send(GetClientStatus { client_id }).await.unwrap().unwrap();
+        assert!(status.authenticated);
+        drop(res);
+    }
+
+    #[actix_web::test]
+    async fn test_client_protocol_is_negotiated() {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(StateManagerActor::new().start()))
+                .app_data(web::Data::new(RouterActor::new(None).start()))
+                .app_data(web::Data::new(Config::default()))
+                .app_data(web::Data::new(ConnectionFilter::default()))
+                .configure(routes)
+        ).await;
+        let upgrade = |protocols: Option<&str>| {
+            let mut req = TestRequest::get()
+                .uri(&format!("/ws/client/{}", Uuid::new_v4()))
+                .insert_header(("Upgrade", "websocket"))
+                .insert_header(("Connection", "upgrade"))
+                .insert_header(("Sec-WebSocket-Version", "13"))
+                .insert_header(("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="));
+            if let Some(protocols) = protocols {
+                req = req.insert_header((header::SEC_WEBSOCKET_PROTOCOL, protocols));
+            }
+            req.to_request()
+        };
+
+        let res = call_service(&app, upgrade(Some("agent-bridge.v1"))).await;
+        assert_eq!(res.status(), 101);
+        assert_eq!(res.headers().get(header::SEC_WEBSOCKET_PROTOCOL).unwrap(), "agent-bridge.v1");
+        drop(res);
+
+        // Clients that don't ask for a protocol get the default without an echo
+        let res = call_service(&app, upgrade(None)).await;
+        assert_eq!(res.status(), 101);
+        assert!(res.headers().get(header::SEC_WEBSOCKET_PROTOCOL).is_none());
+        drop(res);
+
+        let res = call_service(&app, upgrade(Some("agent-bridge.v9, graphql-ws"))).await;
+        assert_eq!(res.status(), 400);
+    }
+
+    // Open a client WebSocket on a running server, returning the frames it
+    // is sent within `window`
+    async fn client_frames(addr: std::net::SocketAddr, path: &str, window: Duration) -> Vec<(u8, Vec<u8>)> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let handshake = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+            path, addr,
+        );
+        stream.write_all(handshake.as_bytes()).await.unwrap();
+
+        let mut received = Vec::new();
+        let mut buf = [0u8; 4096];
+        let deadline = tokio::time::Instant::now() + window;
+        while let Ok(Ok(read @ 1..)) = tokio::time::timeout_at(deadline, stream.read(&mut buf)).await {
+            received.extend_from_slice(&buf[..read]);
+        }
+        let frames_start = received.windows(4).position(|window| window == b"\r\n\r\n").expect("no handshake response") + 4;
+        assert!(received.starts_with(b"HTTP/1.1 101"), "{}", String::from_utf8_lossy(&received));
+        common::test_support::decode_server_frames(&received[frames_start..])
+    }
+
+    #[actix_web::test]
+    async fn test_only_the_issued_resume_token_resumes_a_session() {
+        use crate::actors::client_session_actor::OutboundFrame;
+        use crate::actors::state_manager::{SaveSessionState, SessionState};
+        use common::SessionFrame;
+        use std::collections::HashMap;
+        use std::time::Instant;
+
+        let state_manager = StateManagerActor::new().start();
+        let server = {
+            let state_manager = state_manager.clone();
+            actix_web::HttpServer::new(move || {
+                App::new()
+                    .app_data(web::Data::new(state_manager.clone()))
+                    .app_data(web::Data::new(RouterActor::new(None).start()))
+                    .app_data(web::Data::new(Config::default()))
+                    .app_data(web::Data::new(ConnectionFilter::default()))
+                    .configure(routes)
+            })
+            .workers(1)
+            .bind("127.0.0.1:0")
+            .unwrap()
+        };
+        let addr = server.addrs()[0];
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let save_session = |client_id: Uuid| state_manager.send(SaveSessionState {
+            state: SessionState {
+                client_id,
+                authenticated: false,
+                wallet_address: None,
+                message_buffer: vec![(OutboundFrame::from("buffered-message".to_string()), Instant::now())],
+                last_seen: Instant::now(),
+                session_data: HashMap::new(),
+                next_sequence: 0,
+                resume_token: "issued-token".to_string(),
+            },
         });
-        resp
-    })
-}
\ No newline at end of file
+        let text_frames = |frames: Vec<(u8, Vec<u8>)>| -> Vec<String> {
+            frames.into_iter()
+                .filter(|(opcode, _)| *opcode == 0x1)
+                .map(|(_, payload)| String::from_utf8(payload).unwrap())
+                .collect()
+        };
+
+        // The holder of the token gets the buffered messages, and a new token
+        let client_id = Uuid::new_v4();
+        save_session(client_id).await.unwrap();
+        let frames = text_frames(client_frames(addr, &format!("/ws/client/{}?resume_token=issued-token", client_id), Duration::from_millis(300)).await);
+        let session = frames.iter().find_map(|frame| SessionFrame::parse(frame)).expect("a resume token should be issued");
+        assert_ne!(session.resume_token, "issued-token");
+        assert!(frames.iter().any(|frame| frame == "buffered-message"));
+
+        // A forged token, or none, starts a fresh session
+        for query in ["?resume_token=forged-token", ""] {
+            let client_id = Uuid::new_v4();
+            save_session(client_id).await.unwrap();
+            let frames = text_frames(client_frames(addr, &format!("/ws/client/{}{}", client_id, query), Duration::from_millis(300)).await);
+            assert!(frames.iter().any(|frame| SessionFrame::parse(frame).is_some()));
+            assert!(!frames.iter().any(|frame| frame.contains("buffered-message")), "{:?} resumed the session", query);
+        }
+
+        handle.stop(false).await;
+    }
+
+    #[actix_web::test]
+    async fn test_configured_agent_id_is_registered_and_routed_to() {
+        use crate::actors::state_manager::{GetAgentStatus, SetRouter};
+
+        // Set in a config file, without naming a default agent
+        let config_dir = std::env::temp_dir().join(format!("agent-bridge-config-{}", Uuid::new_v4()));
+        std::fs::create_dir(&config_dir).unwrap();
+        let custom = include_str!("../../config/default.toml")
+            .replace("\nagent_id = \"agent1\"", "\nagent_id = \"custom-agent\"");
+        std::fs::write(config_dir.join("default.toml"), custom).unwrap();
+        std::env::set_var("CONFIG_DIR", &config_dir);
+        let config = Config::load().unwrap();
+        std::env::remove_var("CONFIG_DIR");
+        std::fs::remove_dir_all(config_dir).unwrap();
+        let router = RouterActor::new(config.default_agent_id.clone()).start();
+        let state_manager = StateManagerActor::new().start();
+        state_manager.send(SetRouter { router: router.clone() }).await.unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state_manager.clone()))
+                .app_data(web::Data::new(router.clone()))
+                .app_data(web::Data::new(config.clone()))
+                .app_data(web::Data::new(ConnectionFilter::default()))
+                .configure(routes)
+        ).await;
+
+        let req = TestRequest::get()
+            .uri("/ws/agent")
+            .insert_header(("Upgrade", "websocket"))
+            .insert_header(("Connection", "upgrade"))
+            .insert_header(("Sec-WebSocket-Version", "13"))
+            .insert_header(("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="))
+            .insert_header(("Authorization", format!("Bearer {}", config.agent_token)))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), 101);
+
+        let status = state_manager.send(GetAgentStatus { agent_id: "custom-agent".to_string() }).await.unwrap();
+        assert!(status.is_some());
+        // The router only accepts a default it has registered
+        let default_agent_id = router.send(GetDefaultAgent).await.unwrap().unwrap();
+        assert_eq!(default_agent_id, "custom-agent");
+        assert!(router.send(SetDefaultAgent { agent_id: default_agent_id }).await.unwrap().is_ok());
+        drop(res);
+    }
+
+    #[actix_web::test]
+    async fn test_agent_tokens_register_distinct_agent_ids() {
+        use crate::actors::state_manager::GetAgentStatus;
+
+        let config = Config {
+            agents: [
+                ("agent2".to_string(), "agent2_token".to_string()),
+                ("agent3".to_string(), "agent3_token".to_string()),
+            ].into_iter().collect(),
+            ..Config::default()
+        };
+        let state_manager = StateManagerActor::new().start();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state_manager.clone()))
+                .app_data(web::Data::new(RouterActor::new(None).start()))
+                .app_data(web::Data::new(config.clone()))
+                .app_data(web::Data::new(ConnectionFilter::default()))
+                .configure(routes)
+        ).await;
+        let upgrade = |token: &str| TestRequest::get()
+            .uri("/ws/agent")
+            .insert_header(("Upgrade", "websocket"))
+            .insert_header(("Connection", "upgrade"))
+            .insert_header(("Sec-WebSocket-Version", "13"))
+            .insert_header(("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="))
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+
+        let agent2 = call_service(&app, upgrade("agent2_token")).await;
+        assert_eq!(agent2.status(), 101);
+        let agent3 = call_service(&app, upgrade("agent3_token")).await;
+        assert_eq!(agent3.status(), 101);
+        // The pre-shared token still registers under agent_id
+        let default_agent = call_service(&app, upgrade(&config.agent_token)).await;
+        assert_eq!(default_agent.status(), 101);
+        assert_eq!(call_service(&app, upgrade("unknown_token")).await.status(), 401);
+
+        for agent_id in ["agent2", "agent3", config.agent_id.as_str()] {
+            let status = state_manager.send(GetAgentStatus { agent_id: agent_id.to_string() }).await.unwrap();
+            assert!(status.is_some(), "{} is not registered", agent_id);
+        }
+        drop((agent2, agent3, default_agent));
+    }
+
+    #[actix_web::test]
+    async fn test_agent_auth_attempts_are_counted() {
+        let config = Config::default();
+        let state_manager = StateManagerActor::new().start();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state_manager.clone()))
+                .app_data(web::Data::new(RouterActor::new(None).start()))
+                .app_data(web::Data::new(config.clone()))
+                .app_data(web::Data::new(ConnectionFilter::default()))
+                .configure(routes)
+        ).await;
+        let upgrade = || TestRequest::get()
+            .uri("/ws/agent")
+            .peer_addr("203.0.113.7:40000".parse().unwrap())
+            .insert_header(("Upgrade", "websocket"))
+            .insert_header(("Connection", "upgrade"))
+            .insert_header(("Sec-WebSocket-Version", "13"))
+            .insert_header(("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="));
+
+        let res = call_service(&app, upgrade().to_request()).await;
+        assert_eq!(res.status(), 401);
+        let req = upgrade().insert_header(("Authorization", "Bearer wrong-token")).to_request();
+        assert_eq!(call_service(&app, req).await.status(), 401);
+        let req = upgrade().insert_header(("Authorization", format!("Bearer {}", config.agent_token))).to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), 101);
+
+        let metrics = state_manager.send(GetSystemMetrics).await.unwrap();
+        assert_eq!(metrics.agent_auth_successes, 1);
+        assert_eq!(metrics.agent_auth_failures, 2);
+        drop(res);
+    }
+
+    #[actix_web::test]
+    async fn test_connection_filter_refuses_unlisted_user_agents() {
+        let config = Config {
+            connection_filter_patterns: vec!["^AgentBridge/".to_string()],
+            ..Config::default()
+        };
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(StateManagerActor::new().start()))
+                .app_data(web::Data::new(RouterActor::new(None).start()))
+                .app_data(web::Data::new(ConnectionFilter::from_config(&config)))
+                .app_data(web::Data::new(config.clone()))
+                .configure(routes)
+        ).await;
+        let upgrade = |uri: &str, user_agent: &str| TestRequest::get()
+            .uri(uri)
+            .insert_header(("Upgrade", "websocket"))
+            .insert_header(("Connection", "upgrade"))
+            .insert_header(("Sec-WebSocket-Version", "13"))
+            .insert_header(("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="))
+            .insert_header(("User-Agent", user_agent));
+        let client_uri = format!("/ws/client/{}", Uuid::new_v4());
+
+        let res = call_service(&app, upgrade(&client_uri, "curl/8.5.0").to_request()).await;
+        assert_eq!(res.status(), 403);
+        let req = upgrade("/ws/agent", "curl/8.5.0")
+            .insert_header(("Authorization", format!("Bearer {}", config.agent_token)))
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), 403);
+
+        let res = call_service(&app, upgrade(&client_uri, "AgentBridge/1.0").to_request()).await;
+        assert_eq!(res.status(), 101);
+        drop(res);
+
+        // The proxy has checked the client it vouches for
+        let req = upgrade(&format!("/ws/client/{}", Uuid::new_v4()), "tungstenite-rs/0.20")
+            .insert_header(("Authorization", format!("Bearer {}", config.proxy_token)))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), 101);
+        drop(res);
+    }
+
+    #[actix_web::test]
+    async fn test_disconnect_routes_require_admin_key() {
+        let config = Config::default();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(StateManagerActor::new().start()))
+                .app_data(web::Data::new(RouterActor::new(None).start()))
+                .app_data(web::Data::new(config.clone()))
+                .configure(routes)
+        ).await;
+        let client_uri = format!("/admin/clients/{}?reason=spam", Uuid::new_v4());
+
+        let req = TestRequest::delete().uri(&client_uri).to_request();
+        assert_eq!(call_service(&app, req).await.status(), 401);
+
+        // An agent's token is not an admin credential
+        let req = TestRequest::delete()
+            .uri(&client_uri)
+            .insert_header(("Authorization", format!("Bearer {}", config.agent_token)))
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), 401);
+
+        for uri in [client_uri.as_str(), "/admin/agents/agent1"] {
+            let req = TestRequest::delete()
+                .uri(uri)
+                .insert_header(("Authorization", format!("Bearer {}", config.admin_token)))
+                .to_request();
+            assert_eq!(call_service(&app, req).await.status(), 404);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_metrics_route_requires_admin_key_and_returns_snapshot() {
+        use actix_web::test::read_body_json;
+
+        let config = Config::default();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(StateManagerActor::new().start()))
+                .app_data(web::Data::new(RouterActor::new(None).start()))
+                .app_data(web::Data::new(config.clone()))
+                .configure(routes)
+        ).await;
+        let get_metrics = |token: Option<&str>| {
+            let req = TestRequest::get().uri("/metrics").insert_header(("Accept", "application/json"));
+            match token {
+                Some(token) => req.insert_header(("Authorization", format!("Bearer {}", token))),
+                None => req,
+            }.to_request()
+        };
+
+        for token in [None, Some("not-the-admin-token"), Some(config.agent_token.as_str())] {
+            let res = call_service(&app, get_metrics(token)).await;
+            assert_eq!(res.status(), 401);
+        }
+
+        let res = call_service(&app, get_metrics(Some(&config.admin_token))).await;
+        assert_eq!(res.status(), 200);
+        let body: serde_json::Value = read_body_json(res).await;
+        for field in ["total_clients", "active_clients", "total_agents", "active_agents", "total_messages_processed", "bytes_transferred", "timestamp_ms"] {
+            assert!(body[field].is_u64(), "{} should be a count, got {}", field, body);
+        }
+        assert!(body["messages_per_second"].is_f64());
+
+        // Polling again straight away is served from the cached snapshot
+        let res = call_service(&app, get_metrics(Some(&config.admin_token))).await;
+        let again: serde_json::Value = read_body_json(res).await;
+        assert_eq!(again["timestamp_ms"], body["timestamp_ms"]);
+    }
+
+    #[actix_web::test]
+    async fn test_prometheus_scrape_exposes_expected_metrics() {
+        use common::test_support::start_ws_actor;
+        use std::collections::HashMap;
+
+        let config = Config::default();
+        let state_manager = StateManagerActor::new().start();
+        let (agent, _frames) = start_ws_actor(AgentActor::new("agent\"1".to_string(), "token".to_string()));
+        state_manager.send(RegisterAgent { agent_id: "agent\"1".to_string(), addr: agent }).await.unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state_manager))
+                .app_data(web::Data::new(RouterActor::new(None).start()))
+                .app_data(web::Data::new(config.clone()))
+                .configure(routes)
+        ).await;
+
+        let req = TestRequest::get().uri("/metrics").to_request();
+        assert_eq!(call_service(&app, req).await.status(), 401);
+
+        let req = TestRequest::get()
+            .uri("/metrics")
+            .insert_header(("Authorization", format!("Bearer {}", config.admin_token)))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+        assert!(res.headers().get(header::CONTENT_TYPE).unwrap().to_str().unwrap().starts_with("text/plain"));
+        let body = String::from_utf8(actix_web::test::read_body(res).await.to_vec()).unwrap();
+
+        // Every sample belongs to a family declared with HELP and TYPE, and has a numeric value
+        let mut types = HashMap::new();
+        let mut samples = HashMap::new();
+        for line in body.lines() {
+            if let Some(declaration) = line.strip_prefix("# TYPE ") {
+                let (name, kind) = declaration.split_once(' ').unwrap();
+                assert!(body.contains(&format!("# HELP {} ", name)));
+                types.insert(name.to_string(), kind.to_string());
+            } else if !line.starts_with('#') {
+                let (series, value) = line.rsplit_once(' ').unwrap();
+                let name = series.split('{').next().unwrap();
+                assert!(types.contains_key(name), "{} has no TYPE", name);
+                samples.insert(series.to_string(), value.parse::<f64>().unwrap());
+            }
+        }
+
+        assert_eq!(types["agent_bridge_active_clients"], "gauge");
+        assert_eq!(types["agent_bridge_messages_total"], "counter");
+        assert_eq!(types["agent_bridge_bytes_transferred_total"], "counter");
+        assert_eq!(types["agent_bridge_messages_per_second"], "gauge");
+        assert_eq!(samples[r#"agent_bridge_agent_connected{agent_id="agent\"1"}"#], 1.0);
+        assert_eq!(samples[r#"agent_bridge_clients{state="connected"}"#], 0.0);
+        assert_eq!(samples[r#"agent_bridge_agent_auth_attempts_total{result="failure"}"#], 0.0);
+    }
+}